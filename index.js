@@ -22,14 +22,43 @@ if (usingRust) {
     const OriginalBufferReader = protobuf.BufferReader;
     const OriginalBufferWriter = protobuf.BufferWriter;
     
+    const LongBits = protobuf.util.LongBits;
+
+    // Opcodes of the compact batch format, shared with the native module
+    const Opcode = nativeBinding.Opcode;
+    const OP_UINT32 = Opcode.Uint32,
+          OP_INT32 = Opcode.Int32,
+          OP_UINT64 = Opcode.Uint64,
+          OP_SINT64 = Opcode.Sint64,
+          OP_BOOL = Opcode.Bool,
+          OP_FIXED32 = Opcode.Fixed32,
+          OP_FIXED64 = Opcode.Fixed64,
+          OP_FLOAT = Opcode.Float,
+          OP_DOUBLE = Opcode.Double,
+          OP_BYTES = Opcode.Bytes,
+          OP_STRING = Opcode.String,
+          OP_FORK = Opcode.Fork,
+          OP_LDELIM = Opcode.Ldelim,
+          OP_RESET = Opcode.Reset;
+
+    function varint32Length(value) {
+        return value < 128 ? 1
+             : value < 16384 ? 2
+             : value < 2097152 ? 3
+             : value < 268435456 ? 4 : 5;
+    }
+
     // Wrap native Writer to ensure chainability and API compatibility
-    // Optimized batch operation approach: accumulate all operations in JS and send to Rust once
+    // Optimized batch operation approach: accumulate a compact opcode stream in JS and send to Rust once
     class RustWriter {
         constructor() {
-            // Accumulate operations instead of calling Rust immediately
-            this._operations = [];
+            // Opcodes plus their operand side tables, see Writer.encodeOps
+            this._ops = [];
+            this._nums = [];
+            this._strs = [];
+            this._bufs = [];
             this._len = 0;
-            this._states = [];  // Support fork/ldelim
+            this._states = [];  // Lengths of the enclosing forks
         }
         
         static create() {
@@ -38,19 +67,18 @@ if (usingRust) {
         
         uint32(value) {
             value = value >>> 0;
-            this._operations.push(['u32', value]);
+            this._ops.push(OP_UINT32);
+            this._nums.push(value);
             // Pre-calculate length (same as original JS implementation)
-            this._len += value < 128 ? 1
-                      : value < 16384 ? 2
-                      : value < 2097152 ? 3
-                      : value < 268435456 ? 4 : 5;
+            this._len += varint32Length(value);
             return this;
         }
         
         int32(value) {
             if (value < 0) {
                 // Negative numbers encode to 10 bytes
-                this._operations.push(['i32', value]);
+                this._ops.push(OP_INT32);
+                this._nums.push(value | 0);
                 this._len += 10;
             } else {
                 return this.uint32(value);
@@ -63,33 +91,40 @@ if (usingRust) {
             return this.uint32(encoded);
         }
         
+        _long(op, value) {
+            const bits = typeof value === 'bigint'
+                ? new LongBits(Number(BigInt.asUintN(32, value)), Number(BigInt.asUintN(32, value >> BigInt(32))))
+                : LongBits.from(value);
+            this._ops.push(op);
+            this._nums.push(bits.lo >>> 0, bits.hi >>> 0);
+            return bits;
+        }
+        
         uint64(value) {
-            this._operations.push(['u64', value]);
-            // Simplified: max 10 bytes
-            this._len += 10;
+            this._len += this._long(OP_UINT64, value).length();
             return this;
         }
         
         int64(value) {
-            this._operations.push(['i64', value]);
-            this._len += 10;
-            return this;
+            return this.uint64(value);
         }
         
         sint64(value) {
-            this._operations.push(['s64', value]);
-            this._len += 10;
+            // The native side zig-zag encodes the raw bits, this only sizes the result
+            this._len += this._long(OP_SINT64, value).zzEncode().length();
             return this;
         }
         
         bool(value) {
-            this._operations.push(['bool', value]);
+            this._ops.push(OP_BOOL);
+            this._nums.push(value ? 1 : 0);
             this._len += 1;
             return this;
         }
         
         fixed32(value) {
-            this._operations.push(['f32', value >>> 0]);
+            this._ops.push(OP_FIXED32);
+            this._nums.push(value >>> 0);
             this._len += 4;
             return this;
         }
@@ -99,7 +134,7 @@ if (usingRust) {
         }
         
         fixed64(value) {
-            this._operations.push(['f64', value]);
+            this._long(OP_FIXED64, value);
             this._len += 8;
             return this;
         }
@@ -109,13 +144,15 @@ if (usingRust) {
         }
         
         float(value) {
-            this._operations.push(['float', value]);
+            this._ops.push(OP_FLOAT);
+            this._nums.push(value);
             this._len += 4;
             return this;
         }
         
         double(value) {
-            this._operations.push(['double', value]);
+            this._ops.push(OP_DOUBLE);
+            this._nums.push(value);
             this._len += 8;
             return this;
         }
@@ -134,61 +171,56 @@ if (usingRust) {
                 buffer = value;
             }
             
-            this._operations.push(['bytes', buffer]);
+            this._ops.push(OP_BYTES);
+            this._bufs.push(buffer);
             const len = buffer.length;
-            this._len += (len < 128 ? 1 : len < 16384 ? 2 : len < 2097152 ? 3 : len < 268435456 ? 4 : 5) + len;
+            this._len += varint32Length(len) + len;
             return this;
         }
         
         string(value) {
-            const len = Buffer.byteLength(value, 'utf8');
+            // Encoded one by one: joining first could pair up lone surrogates of neighbouring strings
+            const buffer = Buffer.from(value, 'utf8');
+            const len = buffer.length;
             if (len === 0) {
                 return this.uint32(0);
             }
-            this._operations.push(['string', value]);
-            this._len += (len < 128 ? 1 : len < 16384 ? 2 : len < 2097152 ? 3 : len < 268435456 ? 4 : 5) + len;
+            this._ops.push(OP_STRING);
+            this._nums.push(len);
+            this._strs.push(buffer);
+            this._len += varint32Length(len) + len;
             return this;
         }
         
+        // Nesting is encoded natively: only the lengths are tracked here for `len`
         fork() {
-            this._states.push({
-                operations: this._operations.slice(),
-                len: this._len
-            });
-            this._operations = [];
+            this._ops.push(OP_FORK);
+            this._states.push(this._len);
             this._len = 0;
             return this;
         }
         
         reset() {
-            if (this._states.length > 0) {
-                const state = this._states.pop();
-                this._operations = state.operations;
-                this._len = state.len;
-            } else {
-                this._operations = [];
-                this._len = 0;
-            }
+            this._ops.push(OP_RESET);
+            this._len = this._states.length > 0 ? this._states.pop() : 0;
             return this;
         }
         
         ldelim() {
-            const forkOps = this._operations;
+            this._ops.push(OP_LDELIM);
             const forkLen = this._len;
-            
-            this.reset();
-            this.uint32(forkLen);
-            
-            // Merge fork operations
-            this._operations.push(...forkOps);
-            this._len += forkLen;
-            
+            this._len = (this._states.length > 0 ? this._states.pop() : 0) + varint32Length(forkLen) + forkLen;
             return this;
         }
         
         finish() {
-            // 💥 Key optimization: only cross FFI boundary once!
-            return nativeBinding.Writer.encodeAll(this._operations);
+            // 💥 Key optimization: only cross FFI boundary once, without per-op JS arrays
+            return nativeBinding.Writer.encodeOps(
+                Uint8Array.from(this._ops),
+                Float64Array.from(this._nums),
+                Buffer.concat(this._strs),
+                this._bufs
+            );
         }
         
         get len() {
@@ -204,6 +236,14 @@ if (usingRust) {
         }
     }
     
+    // Same result as the JavaScript reader: a Long if available, otherwise a number
+    function toLong(bits) {
+        if (protobuf.util.Long) {
+            return protobuf.util.Long.fromBits(bits.low, bits.high, bits.unsigned);
+        }
+        return new LongBits(bits.low >>> 0, bits.high >>> 0).toNumber(bits.unsigned);
+    }
+    
     // Wrap native Reader to ensure API compatibility
     class RustReader {
         constructor(buffer) {
@@ -211,7 +251,8 @@ if (usingRust) {
             if (Array.isArray(buffer)) {
                 buffer = Buffer.from(buffer);
             }
-            this._native = new nativeBinding.Reader(buffer);
+            // 64-bit values come back as { low, high, unsigned }, see toLong
+            this._native = new nativeBinding.Reader(buffer, { longs: "long" });
         }
         
         static create(buffer) {
@@ -235,34 +276,15 @@ if (usingRust) {
         }
         
         uint64() {
-            const value = this._native.uint64();
-            // Convert to Long if available
-            if (protobuf.util.Long) {
-                return protobuf.util.Long.fromBits(value & 0xFFFFFFFF, Math.floor(value / 0x100000000), true);
-            }
-            return value;
+            return toLong(this._native.uint64());
         }
         
         int64() {
-            const value = this._native.int64();
-            // Convert to Long if available
-            if (protobuf.util.Long) {
-                const low = value & 0xFFFFFFFF;
-                const high = Math.floor(value / 0x100000000);
-                return protobuf.util.Long.fromBits(low, high, false);
-            }
-            return value;
+            return toLong(this._native.int64());
         }
         
         sint64() {
-            const value = this._native.sint64();
-            // Convert to Long if available
-            if (protobuf.util.Long) {
-                const low = value & 0xFFFFFFFF;
-                const high = Math.floor(value / 0x100000000);
-                return protobuf.util.Long.fromBits(low, high, false);
-            }
-            return value;
+            return toLong(this._native.sint64());
         }
         
         bool() {
@@ -278,25 +300,11 @@ if (usingRust) {
         }
         
         fixed64() {
-            const value = this._native.fixed64();
-            // Convert to Long if available
-            if (protobuf.util.Long) {
-                const low = value & 0xFFFFFFFF;
-                const high = Math.floor(value / 0x100000000);
-                return protobuf.util.Long.fromBits(low, high, true);
-            }
-            return value;
+            return toLong(this._native.fixed64());
         }
         
         sfixed64() {
-            const value = this._native.sfixed64();
-            // Convert to Long if available
-            if (protobuf.util.Long) {
-                const low = value & 0xFFFFFFFF;
-                const high = Math.floor(value / 0x100000000);
-                return protobuf.util.Long.fromBits(low, high, false);
-            }
-            return value;
+            return toLong(this._native.sfixed64());
         }
         
         float() {
@@ -354,4 +362,7 @@ if (usingRust) {
 // Export flag for testing/debugging
 protobuf.__usingRust = usingRust;
 
+// Native bindings (schema-aware codec etc.), null when falling back to JavaScript
+protobuf.native = nativeBinding;
+
 module.exports = protobuf;
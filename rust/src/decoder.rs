@@ -0,0 +1,834 @@
+use napi::Env;
+use napi::Error;
+use napi::JsObject;
+use napi::JsUnknown;
+use napi::Status;
+use napi_derive::napi;
+use serde_json::Value;
+
+use crate::base64;
+use crate::descriptor::{
+    FieldDescriptor, FieldType, Label, MessageDescriptor, Registry, ScalarType, WellKnown,
+};
+use crate::error::{ErrorKind, ProtoError, Result};
+use crate::json;
+use crate::long::LongRepr;
+use crate::reader::{DecodeLimits, ReaderImpl};
+use crate::utf8::Utf8Policy;
+use crate::wkt;
+
+/// Conversion options for decoded objects, modelled after protobuf.js `IConversionOptions`
+#[napi(object)]
+#[derive(Default)]
+pub struct DecodeOptions {
+    /// `"number"` (default), `"string"`, `"bigint"` or `"long"` for 64-bit values
+    pub longs: Option<String>,
+    /// `"number"` (default) or `"string"` for enum values
+    pub enums: Option<String>,
+    /// `"buffer"` (default) or `"base64"` for bytes values
+    pub bytes: Option<String>,
+    /// Set default values on missing fields
+    pub defaults: Option<bool>,
+    /// Set empty arrays for missing repeated fields even if `defaults` is false
+    pub arrays: Option<bool>,
+    /// Set empty objects for missing map fields even if `defaults` is false
+    pub objects: Option<bool>,
+    /// Set virtual oneof properties to the present field's name
+    pub oneofs: Option<bool>,
+    /// Deepest nesting of sub-messages and groups, 100 by default
+    pub max_depth: Option<u32>,
+    /// Largest input accepted, unlimited by default
+    pub max_message_size: Option<u32>,
+    /// Longest bytes or string value, unlimited by default
+    pub max_bytes_length: Option<u32>,
+    /// Most fields read, unlimited by default
+    pub max_fields: Option<u32>,
+    /// Reject input that isn't canonically encoded
+    pub strict: Option<bool>,
+    /// `"strict"` (default), `"replace"` or `"bytes"` for strings that aren't valid UTF-8
+    pub utf8: Option<String>,
+    /// Decode fields of well-known types as native JS values: Timestamp as a Date,
+    /// Duration as milliseconds, wrappers as their value, Struct, Value and ListValue
+    /// as plain objects, values and arrays, and FieldMask as its paths
+    pub well_known_types: Option<bool>,
+    /// `"date"` (default) or `"string"` for RFC 3339 Timestamps with `wellKnownTypes`
+    pub timestamps: Option<String>,
+    /// `"number"` (default) of milliseconds or `"string"` like `"1.5s"` for Durations with `wellKnownTypes`
+    pub durations: Option<String>,
+    /// Decode `Any` values of registered types as their payload with an `@type` property
+    pub unpack_anys: Option<bool>,
+    /// `"keep"` (default) to leave `Any` values of unknown types as they are, with their
+    /// raw bytes, or `"error"` to throw when unpacking them
+    pub unknown_anys: Option<String>,
+}
+
+#[derive(Clone, Copy)]
+pub struct Options {
+    longs: LongRepr,
+    enum_names: bool,
+    bytes_base64: bool,
+    defaults: bool,
+    arrays: bool,
+    objects: bool,
+    oneofs: bool,
+    well_known_types: bool,
+    timestamp_strings: bool,
+    duration_strings: bool,
+    pub unpack_anys: bool,
+    unknown_anys_error: bool,
+    pub limits: DecodeLimits,
+    pub strict: bool,
+    pub utf8: Utf8Policy,
+}
+
+impl Options {
+    pub fn from_js(options: Option<DecodeOptions>) -> napi::Result<Options> {
+        let options = options.unwrap_or_default();
+        let longs = LongRepr::from_option(options.longs.as_deref())?;
+        let enum_names = match options.enums.as_deref() {
+            None | Some("number") => false,
+            Some("string") => true,
+            Some(other) => return Err(invalid_option("enums", other)),
+        };
+        let bytes_base64 = match options.bytes.as_deref() {
+            None | Some("buffer") => false,
+            Some("base64") => true,
+            Some(other) => return Err(invalid_option("bytes", other)),
+        };
+        let timestamp_strings = match options.timestamps.as_deref() {
+            None | Some("date") => false,
+            Some("string") => true,
+            Some(other) => return Err(invalid_option("timestamps", other)),
+        };
+        let duration_strings = match options.durations.as_deref() {
+            None | Some("number") => false,
+            Some("string") => true,
+            Some(other) => return Err(invalid_option("durations", other)),
+        };
+        let unknown_anys_error = match options.unknown_anys.as_deref() {
+            None | Some("keep") => false,
+            Some("error") => true,
+            Some(other) => return Err(invalid_option("unknownAnys", other)),
+        };
+        let defaults = options.defaults.unwrap_or(false);
+        Ok(Options {
+            longs,
+            enum_names,
+            bytes_base64,
+            defaults,
+            arrays: defaults || options.arrays.unwrap_or(false),
+            objects: defaults || options.objects.unwrap_or(false),
+            oneofs: options.oneofs.unwrap_or(false),
+            well_known_types: options.well_known_types.unwrap_or(false),
+            timestamp_strings,
+            duration_strings,
+            unpack_anys: options.unpack_anys.unwrap_or(false),
+            unknown_anys_error,
+            limits: DecodeLimits::from_options(
+                options.max_depth,
+                options.max_message_size,
+                options.max_bytes_length,
+                options.max_fields,
+            ),
+            strict: options.strict.unwrap_or(false),
+            utf8: Utf8Policy::from_option(options.utf8.as_deref())?,
+        })
+    }
+}
+
+fn invalid_option(name: &str, value: &str) -> Error {
+    Error::new(
+        Status::InvalidArg,
+        format!("invalid {} option: {}", name, value),
+    )
+}
+
+fn wire_type_error(field: &FieldDescriptor, wire_type: u32, offset: usize) -> ProtoError {
+    ProtoError::at(
+        ErrorKind::InvalidWireType,
+        offset,
+        format!(
+            "invalid wire type {} for field {} at offset {}",
+            wire_type, field.name, offset
+        ),
+    )
+}
+
+/// The strict mode rules that need the schema: a field must use the wire type
+/// the schema writes it with and a singular field must occur only once
+fn check_canonical(
+    field: &FieldDescriptor,
+    index: usize,
+    wire_type: u32,
+    collected: &Collected,
+    offset: usize,
+) -> Result<()> {
+    let expected = if field.is_map() {
+        2
+    } else if field.is_repeated() && field.is_packable() {
+        if field.packed {
+            2
+        } else {
+            field.value_wire_type()
+        }
+    } else if let FieldType::Message(_) = field.field_type {
+        if field.delimited {
+            3
+        } else {
+            2
+        }
+    } else {
+        field.value_wire_type()
+    };
+    if wire_type != expected {
+        return Err(wire_type_mismatch(&format!("field {}", field.name), wire_type, expected, offset));
+    }
+    let duplicate = if field.is_map() || field.is_repeated() {
+        false
+    } else if let Some(oneof) = field.oneof {
+        collected.oneofs[oneof].is_some()
+    } else {
+        collected.seen[index]
+    };
+    if duplicate {
+        return Err(duplicate_field(&format!("field {}", field.name), offset));
+    }
+    Ok(())
+}
+
+fn wire_type_mismatch(what: &str, wire_type: u32, expected: u32, offset: usize) -> ProtoError {
+    ProtoError::non_canonical(
+        "wire_type_mismatch",
+        offset,
+        format!(
+            "{} has wire type {} instead of {} at offset {}",
+            what, wire_type, expected, offset
+        ),
+    )
+}
+
+fn duplicate_field(what: &str, offset: usize) -> ProtoError {
+    ProtoError::non_canonical(
+        "duplicate_field",
+        offset,
+        format!("{} occurs more than once at offset {}", what, offset),
+    )
+}
+
+pub struct Decoder<'a> {
+    env: &'a Env,
+    registry: &'a Registry,
+    options: Options,
+}
+
+/// Per-message decoding state for fields that accumulate values
+struct Collected {
+    seen: Vec<bool>,
+    arrays: Vec<Option<(JsObject, u32)>>,
+    maps: Vec<Option<JsObject>>,
+    oneofs: Vec<Option<usize>>,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(env: &'a Env, registry: &'a Registry, options: Options) -> Self {
+        Decoder {
+            env,
+            registry,
+            options,
+        }
+    }
+
+    /// Decodes a message up to the reader's limit, or until the matching end group tag when `group` is set
+    pub fn decode_message(
+        &self,
+        reader: &mut ReaderImpl,
+        message: &MessageDescriptor,
+        group: Option<u32>,
+    ) -> Result<JsObject> {
+        let mut obj = self.env.create_object()?;
+        let count = message.fields.len();
+        let mut collected = Collected {
+            seen: vec![false; count],
+            arrays: (0..count).map(|_| None).collect(),
+            maps: (0..count).map(|_| None).collect(),
+            oneofs: vec![None; message.oneofs.len()],
+        };
+
+        let mut ended = group.is_none();
+        while !reader.at_end() {
+            let start = reader.pos();
+            let tag = reader.read_varint32()?;
+            if Some(tag) == group {
+                ended = true;
+                break;
+            }
+            reader.count_field()?;
+            let wire_type = tag & 7;
+            if reader.is_strict() && (tag >> 3 == 0 || wire_type == 4) {
+                return Err(ProtoError::non_canonical(
+                    "trailing_garbage",
+                    start,
+                    format!("unexpected tag {} at offset {}", tag, start),
+                ));
+            }
+            let index = match message.field_index(tag >> 3) {
+                Some(index) => index,
+                None => {
+                    reader.skip_field(tag >> 3, wire_type)?;
+                    continue;
+                }
+            };
+            let field = &message.fields[index];
+            if reader.is_strict() {
+                check_canonical(field, index, wire_type, &collected, start)
+                    .map_err(|e| e.in_field(&field.name))?;
+            }
+            collected.seen[index] = true;
+            if let Some(oneof) = field.oneof {
+                // The last member of a oneof on the wire wins
+                if let Some(previous) = collected.oneofs[oneof].replace(index) {
+                    if previous != index {
+                        obj.delete_named_property(&message.fields[previous].name)?;
+                        collected.seen[previous] = false;
+                    }
+                }
+            }
+
+            self.decode_field(reader, field, index, wire_type, &mut obj, &mut collected)
+                .map_err(|e| e.in_field(&field.name))?;
+        }
+        if !ended {
+            return Err(ProtoError::at(
+                ErrorKind::Malformed,
+                reader.pos(),
+                format!("missing end group tag in {}", message.full_name),
+            ));
+        }
+
+        for (index, field) in message.fields.iter().enumerate() {
+            if let Some((array, _)) = collected.arrays[index].take() {
+                obj.set_named_property(&field.name, array)?;
+            } else if let Some(map) = collected.maps[index].take() {
+                obj.set_named_property(&field.name, map)?;
+            } else if !collected.seen[index] {
+                if field.label == Label::Required {
+                    return Err(ProtoError::new(
+                        ErrorKind::MissingRequired,
+                        format!("missing required '{}'", field.name),
+                    ));
+                }
+                self.set_default(&mut obj, field)?;
+            }
+        }
+        if self.options.oneofs {
+            for (oneof, present) in message.oneofs.iter().zip(&collected.oneofs) {
+                if let Some(index) = present {
+                    obj.set_named_property(
+                        &oneof.name,
+                        self.env.create_string(&message.fields[*index].name)?,
+                    )?;
+                }
+            }
+        }
+        Ok(obj)
+    }
+
+    /// Decodes one occurrence of `field`, adding it to `obj` or to the collected values
+    fn decode_field(
+        &self,
+        reader: &mut ReaderImpl,
+        field: &FieldDescriptor,
+        index: usize,
+        wire_type: u32,
+        obj: &mut JsObject,
+        collected: &mut Collected,
+    ) -> Result<()> {
+        if field.is_map() {
+            if wire_type != 2 {
+                return Err(wire_type_error(field, wire_type, reader.pos()));
+            }
+            let map = match collected.maps[index].take() {
+                Some(map) => map,
+                None => self.env.create_object()?,
+            };
+            let map = self.decode_map_entry(reader, field, map)?;
+            collected.maps[index] = Some(map);
+        } else if field.is_repeated() {
+            let (mut array, mut len) = match collected.arrays[index].take() {
+                Some(entry) => entry,
+                None => (self.env.create_array_with_length(0)?, 0),
+            };
+            // Packable fields accept both encodings for forward and backward compatibility
+            if wire_type == 2 && field.is_packable() {
+                let packed_len = reader.read_varint32()? as usize;
+                reader.push_limit(packed_len)?;
+                while !reader.at_end() {
+                    let value = self
+                        .read_value(reader, field, field.value_wire_type())
+                        .map_err(|e| e.in_index(len))?;
+                    array.set_element(len, value)?;
+                    len += 1;
+                }
+                reader.pop_limit()?;
+            } else {
+                let value = self
+                    .read_value(reader, field, wire_type)
+                    .map_err(|e| e.in_index(len))?;
+                array.set_element(len, value)?;
+                len += 1;
+            }
+            collected.arrays[index] = Some((array, len));
+        } else {
+            let value = self.read_value(reader, field, wire_type)?;
+            obj.set_named_property(&field.name, value)?;
+        }
+        Ok(())
+    }
+
+    fn decode_map_entry(
+        &self,
+        reader: &mut ReaderImpl,
+        field: &FieldDescriptor,
+        mut map: JsObject,
+    ) -> Result<JsObject> {
+        let key_type = field.key_type.unwrap_or(ScalarType::String);
+        let len = reader.read_varint32()? as usize;
+        reader.push_limit(len)?;
+        let mut key: Option<String> = None;
+        let mut value: Option<JsUnknown> = None;
+        while !reader.at_end() {
+            let start = reader.pos();
+            let tag = reader.read_varint32()?;
+            reader.count_field()?;
+            let wire_type = tag & 7;
+            match tag >> 3 {
+                1 => {
+                    let expected = key_type.wire_type();
+                    if reader.is_strict() {
+                        let what = format!("key of field {}", field.name);
+                        if wire_type != expected {
+                            return Err(wire_type_mismatch(&what, wire_type, expected, start));
+                        }
+                        if key.is_some() {
+                            return Err(duplicate_field(&what, start));
+                        }
+                    }
+                    if wire_type != expected {
+                        return Err(wire_type_error(field, wire_type, start));
+                    }
+                    key = Some(self.read_map_key(reader, key_type)?);
+                }
+                2 => {
+                    if reader.is_strict() {
+                        let what = format!("value of field {}", field.name);
+                        let expected = field.value_wire_type();
+                        if wire_type != expected {
+                            return Err(wire_type_mismatch(&what, wire_type, expected, start));
+                        }
+                        if value.is_some() {
+                            return Err(duplicate_field(&what, start));
+                        }
+                    }
+                    let read = self.read_value(reader, field, wire_type);
+                    value = Some(read.map_err(|e| match &key {
+                        Some(key) if key_type == ScalarType::String => e.in_index(format!("\"{}\"", key)),
+                        Some(key) => e.in_index(key),
+                        None => e,
+                    })?);
+                }
+                number => reader.skip_field(number, wire_type)?,
+            }
+        }
+        reader.pop_limit()?;
+        let key = match key {
+            Some(key) => key,
+            None => match key_type {
+                ScalarType::String => String::new(),
+                ScalarType::Bool => "false".to_string(),
+                _ => "0".to_string(),
+            },
+        };
+        let value = match value {
+            Some(value) => value,
+            None => self.default_value(field)?,
+        };
+        map.set_named_property(&key, value)?;
+        Ok(map)
+    }
+
+    /// Map keys become JS property names, so every key type is rendered as a string
+    fn read_map_key(&self, reader: &mut ReaderImpl, key_type: ScalarType) -> Result<String> {
+        Ok(match key_type {
+            ScalarType::String => {
+                // Keys must be property names, so the `bytes` policy falls back to replacing
+                let len = reader.read_varint32()? as usize;
+                let policy = reader.utf8();
+                reader.read_text(len, policy)?.into_lossy()
+            }
+            ScalarType::Bool => (reader.read_varint64()? != 0).to_string(),
+            ScalarType::Int32 => reader.read_int32()?.to_string(),
+            ScalarType::Uint32 => reader.read_varint32()?.to_string(),
+            ScalarType::Sint32 => reader.read_sint32()?.to_string(),
+            ScalarType::Fixed32 => reader.read_fixed32()?.to_string(),
+            ScalarType::Sfixed32 => reader.read_sfixed32()?.to_string(),
+            ScalarType::Int64 => (reader.read_varint64()? as i64).to_string(),
+            ScalarType::Uint64 => reader.read_varint64()?.to_string(),
+            ScalarType::Sint64 => reader.read_sint64()?.to_string(),
+            ScalarType::Fixed64 => reader.read_fixed64()?.to_string(),
+            ScalarType::Sfixed64 => reader.read_sfixed64()?.to_string(),
+            ScalarType::Double | ScalarType::Float | ScalarType::Bytes => {
+                return Err(ProtoError::new(
+                    ErrorKind::Malformed,
+                    format!("invalid key type: {}", key_type.name()),
+                ))
+            }
+        })
+    }
+
+    /// Reads a single value of `field` encoded with `wire_type`
+    fn read_value(
+        &self,
+        reader: &mut ReaderImpl,
+        field: &FieldDescriptor,
+        wire_type: u32,
+    ) -> Result<JsUnknown> {
+        let expected = field.value_wire_type();
+        let group_encoded =
+            matches!(field.field_type, FieldType::Message(_)) && (wire_type == 2 || wire_type == 3);
+        if wire_type != expected && !group_encoded {
+            return Err(wire_type_error(field, wire_type, reader.pos()));
+        }
+        match field.field_type {
+            FieldType::Scalar(scalar) => self.read_scalar(reader, scalar),
+            FieldType::Enum(index) => {
+                let value = reader.read_int32()?;
+                if self.options.enum_names {
+                    if let Some(name) = self.registry.enum_at(index).name_of(value) {
+                        return Ok(self.env.create_string(name)?.into_unknown());
+                    }
+                }
+                Ok(self.env.create_int32(value)?.into_unknown())
+            }
+            FieldType::Message(index) => {
+                let message = self.registry.message_at(index);
+                reader.descend()?;
+                let value = if wire_type == 3 {
+                    self.decode_value(reader, message, Some(field.number << 3 | 4))?
+                } else {
+                    let len = reader.read_varint32()? as usize;
+                    reader.push_limit(len)?;
+                    let value = self.decode_value(reader, message, None)?;
+                    reader.pop_limit()?;
+                    value
+                };
+                reader.ascend();
+                Ok(value)
+            }
+        }
+    }
+
+    /// Decodes a message value, which is the native JS value of its type with
+    /// `wellKnownTypes` or, for an `Any` with `unpackAnys`, its payload
+    pub fn decode_value(
+        &self,
+        reader: &mut ReaderImpl,
+        message: &MessageDescriptor,
+        group: Option<u32>,
+    ) -> Result<JsUnknown> {
+        let well_known = match message.well_known {
+            Some(WellKnown::Any) if self.options.unpack_anys => return self.unpack_any(reader, message, group),
+            Some(well_known) if self.options.well_known_types => well_known,
+            _ => return Ok(self.decode_message(reader, message, group)?.into_unknown()),
+        };
+        if let WellKnown::Timestamp | WellKnown::Duration = well_known {
+            let (seconds, nanos) = read_seconds_nanos(reader, message, group)?;
+            return self.time(message, well_known, seconds, nanos);
+        }
+        let obj = self.decode_message(reader, message, group)?;
+        let first = match message.fields.first() {
+            Some(first) => first,
+            None => return Ok(obj.into_unknown()),
+        };
+        let env = self.env;
+        Ok(match well_known {
+            WellKnown::Wrapper => match obj.get::<_, JsUnknown>(&first.name)? {
+                Some(value) => value,
+                None => self.default_value(first)?,
+            },
+            WellKnown::Struct => match obj.get::<_, JsUnknown>(&first.name)? {
+                Some(fields) => fields,
+                None => env.create_object()?.into_unknown(),
+            },
+            WellKnown::ListValue | WellKnown::FieldMask => match obj.get::<_, JsUnknown>(&first.name)? {
+                Some(values) => values,
+                None => env.create_array_with_length(0)?.into_unknown(),
+            },
+            WellKnown::Value => {
+                // The member of the `kind` oneof that is set, or null for `null_value` or none
+                let mut value = env.get_null()?.into_unknown();
+                for field in &message.fields {
+                    if field.number != 1 && obj.has_own_property(&field.name)? {
+                        value = obj.get_named_property(&field.name)?;
+                        break;
+                    }
+                }
+                value
+            }
+            _ => obj.into_unknown(),
+        })
+    }
+
+    /// Decodes an `Any` as its payload with an `@type` property, or as is when its type isn't registered
+    fn unpack_any(&self, reader: &mut ReaderImpl, message: &MessageDescriptor, group: Option<u32>) -> Result<JsUnknown> {
+        let start = reader.pos();
+        let fields = reader.fields_read();
+        let mut type_url = String::new();
+        let mut value = (start, 0);
+        for_each_field(reader, message, group, |reader, tag| {
+            match tag {
+                10 => {
+                    let len = reader.read_varint32()? as usize;
+                    type_url = reader.read_text(len, Utf8Policy::Replace)?.into_lossy();
+                }
+                18 => {
+                    let len = reader.read_varint32()? as usize;
+                    value = (reader.pos(), len);
+                    reader.skip(len)?;
+                }
+                _ => return Ok(false),
+            }
+            Ok(true)
+        })?;
+        let payload = match json::any_type(self.registry, &type_url) {
+            Ok(payload) => payload,
+            Err(e) if self.options.unknown_anys_error => return Err(e),
+            Err(_) => {
+                // Read again as is, counting each field once
+                reader.seek(start);
+                reader.set_fields_read(fields);
+                return Ok(self.decode_message(reader, message, group)?.into_unknown());
+            }
+        };
+        let end = reader.pos();
+        reader.seek(value.0);
+        reader.push_limit(value.1)?;
+        reader.descend()?;
+        let obj = self.decode_message(reader, payload, None);
+        reader.ascend();
+        reader.pop_limit()?;
+        reader.seek(end);
+        let mut obj = obj?;
+        obj.set_named_property("@type", self.env.create_string(&type_url)?)?;
+        Ok(obj.into_unknown())
+    }
+
+    /// Converts the fields of a Timestamp or Duration to a Date, milliseconds or a string
+    fn time(&self, message: &MessageDescriptor, well_known: WellKnown, seconds: i64, nanos: i32) -> Result<JsUnknown> {
+        let env = self.env;
+        let text = if well_known == WellKnown::Timestamp {
+            wkt::format_timestamp(seconds, nanos)
+        } else {
+            wkt::format_duration(seconds, nanos)
+        };
+        let text = text.ok_or_else(|| {
+            ProtoError::new(
+                ErrorKind::Malformed,
+                format!("invalid {}: seconds {} nanos {}", message.full_name, seconds, nanos),
+            )
+        })?;
+        Ok(match well_known {
+            WellKnown::Timestamp if !self.options.timestamp_strings => {
+                // Whole milliseconds rounded down, so instants before 1970 keep their second
+                let millis = seconds * 1000 + nanos.div_euclid(1_000_000) as i64;
+                env.create_date(millis as f64)?.into_unknown()
+            }
+            WellKnown::Duration if !self.options.duration_strings => env
+                .create_double(seconds as f64 * 1000.0 + nanos as f64 / 1e6)?
+                .into_unknown(),
+            _ => env.create_string(&text)?.into_unknown(),
+        })
+    }
+
+    fn read_scalar(&self, reader: &mut ReaderImpl, scalar: ScalarType) -> Result<JsUnknown> {
+        let env = self.env;
+        Ok(match scalar {
+            ScalarType::Int32 => env
+                .create_int32(reader.read_int32()?)?
+                .into_unknown(),
+            ScalarType::Uint32 => env.create_uint32(reader.read_varint32()?)?.into_unknown(),
+            ScalarType::Sint32 => env.create_int32(reader.read_sint32()?)?.into_unknown(),
+            ScalarType::Fixed32 => env.create_uint32(reader.read_fixed32()?)?.into_unknown(),
+            ScalarType::Sfixed32 => env.create_int32(reader.read_sfixed32()?)?.into_unknown(),
+            ScalarType::Int64 => self.long(reader.read_varint64()?, false)?,
+            ScalarType::Uint64 => self.long(reader.read_varint64()?, true)?,
+            ScalarType::Sint64 => self.long(reader.read_sint64()? as u64, false)?,
+            ScalarType::Fixed64 => self.long(reader.read_fixed64()?, true)?,
+            ScalarType::Sfixed64 => self.long(reader.read_sfixed64()? as u64, false)?,
+            ScalarType::Bool => env
+                .get_boolean(reader.read_varint64()? != 0)?
+                .into_unknown(),
+            ScalarType::Float => env
+                .create_double(reader.read_float()? as f64)?
+                .into_unknown(),
+            ScalarType::Double => env.create_double(reader.read_double()?)?.into_unknown(),
+            ScalarType::String => {
+                let len = reader.read_varint32()? as usize;
+                let policy = reader.utf8();
+                reader.read_text(len, policy)?.to_js(env)?
+            }
+            ScalarType::Bytes => {
+                let len = reader.read_varint32()? as usize;
+                self.bytes(reader.read_slice(len)?)?
+            }
+        })
+    }
+
+    fn long(&self, bits: u64, unsigned: bool) -> Result<JsUnknown> {
+        Ok(self.options.longs.to_js(self.env, bits, unsigned)?)
+    }
+
+    fn bytes(&self, data: &[u8]) -> Result<JsUnknown> {
+        if self.options.bytes_base64 {
+            Ok(self
+                .env
+                .create_string(&base64::encode(data))?
+                .into_unknown())
+        } else {
+            Ok(self
+                .env
+                .create_buffer_with_data(data.to_vec())?
+                .into_raw()
+                .into_unknown())
+        }
+    }
+
+    fn set_default(&self, obj: &mut JsObject, field: &FieldDescriptor) -> Result<()> {
+        if field.is_map() {
+            if self.options.objects {
+                obj.set_named_property(&field.name, self.env.create_object()?)?;
+            }
+        } else if field.is_repeated() {
+            if self.options.arrays {
+                obj.set_named_property(&field.name, self.env.create_array_with_length(0)?)?;
+            }
+        } else if self.options.defaults && field.oneof.is_none() {
+            let value = match field.field_type {
+                FieldType::Message(_) => self.env.get_null()?.into_unknown(),
+                _ => self.default_value(field)?,
+            };
+            obj.set_named_property(&field.name, value)?;
+        }
+        Ok(())
+    }
+
+    /// What `decode_value` gives for a message without fields, built directly so
+    /// that missing required fields don't fail
+    fn empty_value(&self, message: &MessageDescriptor) -> Result<JsUnknown> {
+        let env = self.env;
+        let well_known = message.well_known.filter(|_| self.options.well_known_types);
+        Ok(match (well_known, message.fields.first()) {
+            (Some(well_known @ (WellKnown::Timestamp | WellKnown::Duration)), _) => self.time(message, well_known, 0, 0)?,
+            (Some(WellKnown::Wrapper), Some(first)) => self.default_value(first)?,
+            (Some(WellKnown::Struct), Some(_)) => env.create_object()?.into_unknown(),
+            (Some(WellKnown::ListValue | WellKnown::FieldMask), Some(_)) => env.create_array_with_length(0)?.into_unknown(),
+            (Some(WellKnown::Value), _) => env.get_null()?.into_unknown(),
+            _ => {
+                let mut obj = env.create_object()?;
+                for field in &message.fields {
+                    self.set_default(&mut obj, field)?;
+                }
+                obj.into_unknown()
+            }
+        })
+    }
+
+    /// Default value of a single (non-repeated) value of `field`
+    fn default_value(&self, field: &FieldDescriptor) -> Result<JsUnknown> {
+        let env = self.env;
+        let declared = field.default_value.as_ref();
+        Ok(match field.field_type {
+            FieldType::Message(index) => self.empty_value(self.registry.message_at(index))?,
+            FieldType::Enum(index) => {
+                let descriptor = self.registry.enum_at(index);
+                let value = match declared {
+                    Some(Value::String(name)) => descriptor.value_of(name).unwrap_or(0),
+                    Some(Value::Number(n)) => n.as_i64().unwrap_or(0) as i32,
+                    _ => descriptor.default_value(),
+                };
+                match descriptor.name_of(value) {
+                    Some(name) if self.options.enum_names => {
+                        env.create_string(name)?.into_unknown()
+                    }
+                    _ => env.create_int32(value)?.into_unknown(),
+                }
+            }
+            FieldType::Scalar(ScalarType::Bool) => env
+                .get_boolean(declared.and_then(Value::as_bool).unwrap_or(false))?
+                .into_unknown(),
+            FieldType::Scalar(ScalarType::String) => env
+                .create_string(declared.and_then(Value::as_str).unwrap_or(""))?
+                .into_unknown(),
+            FieldType::Scalar(ScalarType::Bytes) => {
+                let data = declared
+                    .and_then(Value::as_str)
+                    .map(str::as_bytes)
+                    .unwrap_or(&[]);
+                self.bytes(data)?
+            }
+            FieldType::Scalar(scalar) => match scalar {
+                ScalarType::Int64 | ScalarType::Sint64 | ScalarType::Sfixed64 => {
+                    self.long(declared.and_then(Value::as_i64).unwrap_or(0) as u64, false)?
+                }
+                ScalarType::Uint64 | ScalarType::Fixed64 => {
+                    self.long(declared.and_then(Value::as_u64).unwrap_or(0), true)?
+                }
+                _ => env
+                    .create_double(declared.and_then(Value::as_f64).unwrap_or(0.0))?
+                    .into_unknown(),
+            },
+        })
+    }
+}
+
+
+/// Passes each tag of a message up to the reader's limit or the end group tag
+/// to `field`, which reads the value or returns false to skip it
+fn for_each_field(
+    reader: &mut ReaderImpl,
+    message: &MessageDescriptor,
+    group: Option<u32>,
+    mut field: impl FnMut(&mut ReaderImpl, u32) -> Result<bool>,
+) -> Result<()> {
+    while !reader.at_end() {
+        let tag = reader.read_varint32()?;
+        if Some(tag) == group {
+            return Ok(());
+        }
+        reader.count_field()?;
+        if !field(reader, tag)? {
+            reader.skip_field(tag >> 3, tag & 7)?;
+        }
+    }
+    if group.is_some() {
+        return Err(ProtoError::at(
+            ErrorKind::Malformed,
+            reader.pos(),
+            format!("missing end group tag in {}", message.full_name),
+        ));
+    }
+    Ok(())
+}
+
+/// Reads the `seconds` and `nanos` of a Timestamp or Duration
+fn read_seconds_nanos(reader: &mut ReaderImpl, message: &MessageDescriptor, group: Option<u32>) -> Result<(i64, i32)> {
+    let (mut seconds, mut nanos) = (0, 0);
+    for_each_field(reader, message, group, |reader, tag| {
+        match tag {
+            8 => seconds = reader.read_varint64()? as i64,
+            16 => nanos = reader.read_int32()?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?;
+    Ok((seconds, nanos))
+}
@@ -0,0 +1,1078 @@
+use std::collections::HashMap;
+use std::sync::{OnceLock, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use napi::Error;
+use napi::Result;
+use napi::Status;
+use serde_json::{Map, Value};
+
+/// Scalar field types as named by protobuf.js.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScalarType {
+    Double,
+    Float,
+    Int32,
+    Uint32,
+    Sint32,
+    Fixed32,
+    Sfixed32,
+    Int64,
+    Uint64,
+    Sint64,
+    Fixed64,
+    Sfixed64,
+    Bool,
+    String,
+    Bytes,
+}
+
+impl ScalarType {
+    pub fn from_name(name: &str) -> Option<ScalarType> {
+        Some(match name {
+            "double" => ScalarType::Double,
+            "float" => ScalarType::Float,
+            "int32" => ScalarType::Int32,
+            "uint32" => ScalarType::Uint32,
+            "sint32" => ScalarType::Sint32,
+            "fixed32" => ScalarType::Fixed32,
+            "sfixed32" => ScalarType::Sfixed32,
+            "int64" => ScalarType::Int64,
+            "uint64" => ScalarType::Uint64,
+            "sint64" => ScalarType::Sint64,
+            "fixed64" => ScalarType::Fixed64,
+            "sfixed64" => ScalarType::Sfixed64,
+            "bool" => ScalarType::Bool,
+            "string" => ScalarType::String,
+            "bytes" => ScalarType::Bytes,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ScalarType::Double => "double",
+            ScalarType::Float => "float",
+            ScalarType::Int32 => "int32",
+            ScalarType::Uint32 => "uint32",
+            ScalarType::Sint32 => "sint32",
+            ScalarType::Fixed32 => "fixed32",
+            ScalarType::Sfixed32 => "sfixed32",
+            ScalarType::Int64 => "int64",
+            ScalarType::Uint64 => "uint64",
+            ScalarType::Sint64 => "sint64",
+            ScalarType::Fixed64 => "fixed64",
+            ScalarType::Sfixed64 => "sfixed64",
+            ScalarType::Bool => "bool",
+            ScalarType::String => "string",
+            ScalarType::Bytes => "bytes",
+        }
+    }
+
+    pub fn wire_type(self) -> u32 {
+        match self {
+            ScalarType::Double | ScalarType::Fixed64 | ScalarType::Sfixed64 => 1,
+            ScalarType::Float | ScalarType::Fixed32 | ScalarType::Sfixed32 => 5,
+            ScalarType::String | ScalarType::Bytes => 2,
+            _ => 0,
+        }
+    }
+
+    pub fn is_packable(self) -> bool {
+        !matches!(self, ScalarType::String | ScalarType::Bytes)
+    }
+
+    pub fn is_valid_map_key(self) -> bool {
+        !matches!(
+            self,
+            ScalarType::Double | ScalarType::Float | ScalarType::Bytes
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FieldType {
+    Scalar(ScalarType),
+    /// Index into the registry's enums
+    Enum(usize),
+    /// Index into the registry's messages
+    Message(usize),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Label {
+    Optional,
+    Required,
+    Repeated,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct FieldDescriptor {
+    /// Property name on JS objects (`.pkg.ext` for extension fields)
+    pub name: String,
+    /// Name in proto3 JSON, `json_name` or the lowerCamelCase name (`[pkg.ext]` for extension fields)
+    pub json_name: String,
+    pub number: u32,
+    pub label: Label,
+    pub field_type: FieldType,
+    /// Key type of map fields
+    pub key_type: Option<ScalarType>,
+    pub packed: bool,
+    /// Message encoded as a group (wire types 3/4)
+    pub delimited: bool,
+    pub has_presence: bool,
+    /// Index into the owning message's `oneofs`
+    pub oneof: Option<usize>,
+    /// Explicit proto2 default value
+    pub default_value: Option<Value>,
+    /// Precomputed `number << 3 | wire_type` of an unpacked value
+    pub tag: u32,
+    /// `options` of the field's JSON, custom options included
+    pub options: Map<String, Value>,
+}
+
+impl FieldDescriptor {
+    pub fn is_map(&self) -> bool {
+        self.key_type.is_some()
+    }
+
+    pub fn is_repeated(&self) -> bool {
+        self.label == Label::Repeated
+    }
+
+    /// Wire type of a single, unpacked value
+    pub fn value_wire_type(&self) -> u32 {
+        match self.field_type {
+            FieldType::Scalar(scalar) => scalar.wire_type(),
+            FieldType::Enum(_) => 0,
+            FieldType::Message(_) => {
+                if self.delimited {
+                    3
+                } else {
+                    2
+                }
+            }
+        }
+    }
+
+    /// Whether repeated values may be written as one length-delimited run
+    pub fn is_packable(&self) -> bool {
+        match self.field_type {
+            FieldType::Scalar(scalar) => scalar.is_packable(),
+            FieldType::Enum(_) => true,
+            FieldType::Message(_) => false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct OneofDescriptor {
+    pub name: String,
+    /// Indices into the owning message's `fields`
+    pub fields: Vec<usize>,
+}
+
+/// Types of `google/protobuf` with a special JSON representation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WellKnown {
+    Any,
+    Timestamp,
+    Duration,
+    FieldMask,
+    Struct,
+    Value,
+    ListValue,
+    /// `DoubleValue`, `Int64Value` and the other wrappers of a single `value = 1`
+    Wrapper,
+}
+
+impl WellKnown {
+    fn from_name(full_name: &str) -> Option<WellKnown> {
+        Some(match full_name.strip_prefix("google.protobuf.")? {
+            "Any" => WellKnown::Any,
+            "Timestamp" => WellKnown::Timestamp,
+            "Duration" => WellKnown::Duration,
+            "FieldMask" => WellKnown::FieldMask,
+            "Struct" => WellKnown::Struct,
+            "Value" => WellKnown::Value,
+            "ListValue" => WellKnown::ListValue,
+            "DoubleValue" | "FloatValue" | "Int64Value" | "UInt64Value" | "Int32Value"
+            | "UInt32Value" | "BoolValue" | "StringValue" | "BytesValue" => WellKnown::Wrapper,
+            _ => return None,
+        })
+    }
+}
+
+/// protoc's default JSON name: underscores are dropped and the letter after them capitalized
+pub fn json_name_of(name: &str) -> String {
+    let mut json_name = String::with_capacity(name.len());
+    let mut upper = false;
+    for c in name.chars() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            json_name.push(c.to_ascii_uppercase());
+            upper = false;
+        } else {
+            json_name.push(c);
+        }
+    }
+    json_name
+}
+
+#[derive(Debug)]
+pub struct MessageDescriptor {
+    pub full_name: String,
+    pub well_known: Option<WellKnown>,
+    /// Fields sorted by number, extension fields included
+    pub fields: Vec<FieldDescriptor>,
+    pub oneofs: Vec<OneofDescriptor>,
+    pub group: bool,
+    pub options: Map<String, Value>,
+    by_number: HashMap<u32, usize>,
+}
+
+impl MessageDescriptor {
+    pub fn field_index(&self, number: u32) -> Option<usize> {
+        self.by_number.get(&number).copied()
+    }
+
+    fn insert_field(&mut self, field: FieldDescriptor) {
+        let pos = self.fields.partition_point(|f| f.number < field.number);
+        self.fields.insert(pos, field);
+        self.reindex();
+    }
+
+    fn reindex(&mut self) {
+        self.by_number = self
+            .fields
+            .iter()
+            .enumerate()
+            .map(|(i, f)| (f.number, i))
+            .collect();
+        for oneof in &mut self.oneofs {
+            oneof.fields.clear();
+        }
+        for (i, field) in self.fields.iter().enumerate() {
+            if let Some(oneof) = field.oneof {
+                self.oneofs[oneof].fields.push(i);
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct EnumDescriptor {
+    pub full_name: String,
+    pub values: Vec<(String, i32)>,
+    pub options: Map<String, Value>,
+    /// Options of the values by name, for those that have some
+    pub values_options: Map<String, Value>,
+}
+
+impl EnumDescriptor {
+    pub fn value_of(&self, name: &str) -> Option<i32> {
+        self.values.iter().find(|(n, _)| n == name).map(|(_, v)| *v)
+    }
+
+    pub fn name_of(&self, value: i32) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|(_, v)| *v == value)
+            .map(|(n, _)| n.as_str())
+    }
+
+    /// protobuf.js uses the first declared value as the enum's default
+    pub fn default_value(&self) -> i32 {
+        self.values.first().map(|(_, v)| *v).unwrap_or(0)
+    }
+}
+
+#[derive(Debug)]
+pub struct MethodDescriptor {
+    pub name: String,
+    /// Fully qualified name of the request type, or the name as declared when it isn't registered
+    pub input_type: String,
+    pub output_type: String,
+    pub client_streaming: bool,
+    pub server_streaming: bool,
+    pub options: Map<String, Value>,
+}
+
+#[derive(Debug)]
+pub struct ServiceDescriptor {
+    pub full_name: String,
+    pub methods: Vec<MethodDescriptor>,
+    pub options: Map<String, Value>,
+}
+
+/// What an element of `Registry::nested` is, `None` standing for a namespace
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NestedKind {
+    Message,
+    Enum,
+    Service,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TypeRef {
+    Message(usize),
+    Enum(usize),
+}
+
+/// Edition features relevant to the wire format, resolved per element.
+#[derive(Clone, Copy, Debug)]
+struct Features {
+    packed: bool,
+    implicit_presence: bool,
+    delimited: bool,
+}
+
+impl Features {
+    fn for_edition(edition: &str) -> Result<Features> {
+        match edition {
+            "proto2" => Ok(Features {
+                packed: false,
+                implicit_presence: false,
+                delimited: false,
+            }),
+            "proto3" => Ok(Features {
+                packed: true,
+                implicit_presence: true,
+                delimited: false,
+            }),
+            "2023" | "2024" => Ok(Features {
+                packed: true,
+                implicit_presence: false,
+                delimited: false,
+            }),
+            _ => Err(Error::new(
+                Status::InvalidArg,
+                format!("unknown edition: {}", edition),
+            )),
+        }
+    }
+
+    /// Applies `options.features` overrides of a reflection object
+    fn apply(mut self, options: Option<&Map<String, Value>>) -> Features {
+        let features = match options
+            .and_then(|o| o.get("features"))
+            .and_then(Value::as_object)
+        {
+            Some(features) => features,
+            None => return self,
+        };
+        if let Some(v) = features
+            .get("repeated_field_encoding")
+            .and_then(Value::as_str)
+        {
+            self.packed = v == "PACKED";
+        }
+        if let Some(v) = features.get("field_presence").and_then(Value::as_str) {
+            self.implicit_presence = v == "IMPLICIT";
+        }
+        if let Some(v) = features.get("message_encoding").and_then(Value::as_str) {
+            self.delimited = v == "DELIMITED";
+        }
+        self
+    }
+}
+
+struct PendingMessage<'a> {
+    full_name: String,
+    json: &'a Map<String, Value>,
+    features: Features,
+}
+
+struct PendingEnum<'a> {
+    full_name: String,
+    json: &'a Map<String, Value>,
+}
+
+struct PendingService<'a> {
+    full_name: String,
+    json: &'a Map<String, Value>,
+}
+
+struct PendingExtension<'a> {
+    scope: String,
+    full_name: String,
+    json: &'a Map<String, Value>,
+    features: Features,
+}
+
+#[derive(Default)]
+struct Pending<'a> {
+    messages: Vec<PendingMessage<'a>>,
+    enums: Vec<PendingEnum<'a>>,
+    services: Vec<PendingService<'a>>,
+    extensions: Vec<PendingExtension<'a>>,
+}
+
+fn join_name(scope: &str, name: &str) -> String {
+    if scope.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", scope, name)
+    }
+}
+
+fn object_options(json: &Map<String, Value>) -> Option<&Map<String, Value>> {
+    json.get("options").and_then(Value::as_object)
+}
+
+fn owned_options(json: &Map<String, Value>) -> Map<String, Value> {
+    object_options(json).cloned().unwrap_or_default()
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(Status::InvalidArg, message)
+}
+
+/// Registered message and enum types, shared by every native codec entry point,
+/// and the services declared with them.
+#[derive(Default)]
+pub struct Registry {
+    messages: Vec<MessageDescriptor>,
+    enums: Vec<EnumDescriptor>,
+    names: HashMap<String, TypeRef>,
+    services: Vec<ServiceDescriptor>,
+    service_names: HashMap<String, usize>,
+    /// `Any` type URLs registered for messages, besides `type.googleapis.com/<name>`
+    type_urls: HashMap<String, usize>,
+    /// The URL `packAny` writes for a message, the last one registered for it
+    preferred_urls: HashMap<usize, String>,
+}
+
+fn registry() -> &'static RwLock<Registry> {
+    static REGISTRY: OnceLock<RwLock<Registry>> = OnceLock::new();
+    REGISTRY.get_or_init(|| RwLock::new(Registry::default()))
+}
+
+pub fn read_registry() -> Result<RwLockReadGuard<'static, Registry>> {
+    registry()
+        .read()
+        .map_err(|_| Error::new(Status::GenericFailure, "type registry is poisoned"))
+}
+
+pub fn write_registry() -> Result<RwLockWriteGuard<'static, Registry>> {
+    registry()
+        .write()
+        .map_err(|_| Error::new(Status::GenericFailure, "type registry is poisoned"))
+}
+
+impl Registry {
+    pub fn message(&self, handle: u32) -> Result<&MessageDescriptor> {
+        self.messages
+            .get(handle as usize)
+            .ok_or_else(|| invalid(format!("unknown type handle {}", handle)))
+    }
+
+    pub fn message_at(&self, index: usize) -> &MessageDescriptor {
+        &self.messages[index]
+    }
+
+    pub fn enum_at(&self, index: usize) -> &EnumDescriptor {
+        &self.enums[index]
+    }
+
+    /// Looks up a registered type by its fully qualified name
+    pub fn lookup(&self, name: &str) -> Option<TypeRef> {
+        self.names
+            .get(name.strip_prefix('.').unwrap_or(name))
+            .copied()
+    }
+
+    /// Looks up a registered service by its fully qualified name
+    pub fn lookup_service(&self, name: &str) -> Option<&ServiceDescriptor> {
+        let index = self.service_names.get(name.strip_prefix('.').unwrap_or(name))?;
+        Some(&self.services[*index])
+    }
+
+    /// The registered types, services and namespaces directly within `scope`, which
+    /// is a fully qualified name or empty for the root, sorted by name. Namespaces
+    /// are the packages leading to a registered name.
+    pub fn nested(&self, scope: &str) -> Vec<(String, Option<NestedKind>)> {
+        let scope = scope.strip_prefix('.').unwrap_or(scope);
+        let names = self
+            .names
+            .iter()
+            .map(|(name, found)| {
+                let kind = match found {
+                    TypeRef::Message(_) => NestedKind::Message,
+                    TypeRef::Enum(_) => NestedKind::Enum,
+                };
+                (name, kind)
+            })
+            .chain(self.service_names.keys().map(|name| (name, NestedKind::Service)));
+        let mut nested: Vec<(String, Option<NestedKind>)> = Vec::new();
+        for (name, kind) in names {
+            let rest = match scope {
+                "" => name.as_str(),
+                _ => match name.strip_prefix(scope).and_then(|rest| rest.strip_prefix('.')) {
+                    Some(rest) => rest,
+                    None => continue,
+                },
+            };
+            let (child, kind) = match rest.split_once('.') {
+                Some((child, _)) => (child, None),
+                None => (rest, Some(kind)),
+            };
+            match nested.iter_mut().find(|(name, _)| name == child) {
+                Some(existing) => existing.1 = existing.1.or(kind),
+                None => nested.push((child.to_string(), kind)),
+            }
+        }
+        nested.sort_by(|a, b| a.0.cmp(&b.0));
+        nested
+    }
+
+    /// Maps an `Any` type URL to a registered message, which is then packed with it
+    pub fn register_type_url(&mut self, type_url: &str, handle: u32) -> Result<()> {
+        self.message(handle)?;
+        self.type_urls.insert(type_url.to_string(), handle as usize);
+        self.preferred_urls.insert(handle as usize, type_url.to_string());
+        Ok(())
+    }
+
+    /// Resolves an `Any` type URL: a registered one, or else the type named after the last `/`
+    pub fn resolve_type_url(&self, type_url: &str) -> Option<usize> {
+        if let Some(index) = self.type_urls.get(type_url) {
+            return Some(*index);
+        }
+        let name = type_url.rsplit('/').next().unwrap_or(type_url);
+        match self.lookup(name) {
+            Some(TypeRef::Message(index)) => Some(index),
+            _ => None,
+        }
+    }
+
+    /// The type URL of a message, `None` for anonymous types without a registered one
+    pub fn type_url(&self, index: usize) -> Option<String> {
+        if let Some(type_url) = self.preferred_urls.get(&index) {
+            return Some(type_url.clone());
+        }
+        let full_name = &self.messages.get(index)?.full_name;
+        (!full_name.is_empty()).then(|| format!("type.googleapis.com/{}", full_name))
+    }
+
+    /// Registers every type found in a protobuf.js `Type.toJSON()` or `Root.toJSON()`
+    /// descriptor and returns the handle of `type_name`. A bare type descriptor may be
+    /// registered without a name, in which case it is only reachable through its handle.
+    pub fn register_json(&mut self, json: &Value, type_name: Option<&str>) -> Result<u32> {
+        let root = json
+            .as_object()
+            .ok_or_else(|| invalid("descriptor must be an object".to_string()))?;
+        let type_name = type_name.map(|n| n.strip_prefix('.').unwrap_or(n));
+        if type_name.is_none() && !root.contains_key("fields") {
+            return Err(invalid(
+                "a type name is required to register a namespace descriptor".to_string(),
+            ));
+        }
+        let message_base = self.add(root, type_name)?;
+
+        match type_name {
+            None => Ok(message_base as u32),
+            Some(name) => match self.lookup(name) {
+                Some(TypeRef::Message(index)) => Ok(index as u32),
+                Some(TypeRef::Enum(_)) => Err(invalid(format!("{} is not a message type", name))),
+                None => Err(invalid(format!("no such type: {}", name))),
+            },
+        }
+    }
+
+    /// Registers every type found in a protobuf.js `Root.toJSON()` descriptor
+    pub fn register_root(&mut self, json: &Value) -> Result<()> {
+        let root = json
+            .as_object()
+            .filter(|root| !root.contains_key("fields"))
+            .ok_or_else(|| invalid("descriptor must be a namespace".to_string()))?;
+        self.add(root, Some("")).map(|_| ())
+    }
+
+    /// Adds the types of a descriptor, named `type_name` if it is a type itself, and
+    /// returns the index of the first one. Their names are only registered when
+    /// `type_name` is given, and names that are already registered keep their types.
+    /// Nothing is added when any part of the descriptor fails to build.
+    fn add(&mut self, root: &Map<String, Value>, type_name: Option<&str>) -> Result<usize> {
+        let anonymous = type_name.is_none();
+        let mut pending = Pending::default();
+        let features = Features::for_edition(
+            root.get("edition")
+                .and_then(Value::as_str)
+                .unwrap_or("proto3"),
+        )?;
+        if root.contains_key("fields") {
+            let name = type_name.unwrap_or("");
+            collect_message(&mut pending, name.to_string(), root, features)?;
+        } else {
+            collect_nested(&mut pending, "", root, features)?;
+        }
+
+        let mut local: HashMap<String, TypeRef> = HashMap::new();
+        if !anonymous {
+            let registered = |name: &str| self.names.get(name).copied();
+            pending.messages.retain(|m| match registered(&m.full_name) {
+                Some(found @ TypeRef::Message(_)) => {
+                    local.insert(m.full_name.clone(), found);
+                    false
+                }
+                _ => true,
+            });
+            pending.enums.retain(|e| match registered(&e.full_name) {
+                Some(found @ TypeRef::Enum(_)) => {
+                    local.insert(e.full_name.clone(), found);
+                    false
+                }
+                _ => true,
+            });
+            pending
+                .services
+                .retain(|service| !self.service_names.contains_key(&service.full_name));
+        }
+
+        // Local names take precedence over previously registered types
+        let message_base = self.messages.len();
+        let enum_base = self.enums.len();
+        for (i, m) in pending.messages.iter().enumerate() {
+            local.insert(m.full_name.clone(), TypeRef::Message(message_base + i));
+        }
+        for (i, e) in pending.enums.iter().enumerate() {
+            local.insert(e.full_name.clone(), TypeRef::Enum(enum_base + i));
+        }
+
+        let mut enums = Vec::with_capacity(pending.enums.len());
+        for e in &pending.enums {
+            enums.push(build_enum(e)?);
+        }
+
+        let mut messages = Vec::with_capacity(pending.messages.len());
+        for m in &pending.messages {
+            messages.push(self.build_message(m, &local, &pending)?);
+        }
+
+        let services: Vec<_> = pending
+            .services
+            .iter()
+            .map(|service| self.build_service(service, &local, &messages))
+            .collect();
+
+        let mut extensions: Vec<(usize, FieldDescriptor)> = Vec::new();
+        for ext in &pending.extensions {
+            let extendee_name = ext.json.get("extend").and_then(Value::as_str).unwrap_or("");
+            let extendee = match self.resolve(&ext.scope, extendee_name, &local) {
+                Some(TypeRef::Message(index)) => index,
+                _ => {
+                    return Err(invalid(format!(
+                        "no such extended type: {} in {}",
+                        extendee_name, ext.full_name
+                    )))
+                }
+            };
+            let mut field = self.build_field(
+                &format!(".{}", ext.full_name),
+                ext.json,
+                &ext.scope,
+                ext.features,
+                None,
+                &local,
+                &pending,
+            )?;
+            field.has_presence = !field.is_repeated();
+            let message = match extendee.checked_sub(message_base) {
+                Some(i) => &messages[i],
+                None => &self.messages[extendee],
+            };
+            // Registering the same extension again is a no-op
+            let present = message
+                .field_index(field.number)
+                .map(|i| &message.fields[i])
+                .or_else(|| {
+                    extensions
+                        .iter()
+                        .find(|(m, f)| *m == extendee && f.number == field.number)
+                        .map(|(_, f)| f)
+                });
+            match present {
+                Some(present) if *present == field => {}
+                Some(_) => {
+                    return Err(invalid(format!(
+                        "duplicate id {} in {}",
+                        field.number, message.full_name
+                    )))
+                }
+                None => extensions.push((extendee, field)),
+            }
+        }
+
+        self.enums.extend(enums);
+        self.messages.extend(messages);
+        for (extendee, field) in extensions {
+            self.messages[extendee].insert_field(field);
+        }
+        if !anonymous {
+            self.names.extend(local);
+            for service in services {
+                self.service_names
+                    .insert(service.full_name.clone(), self.services.len());
+                self.services.push(service);
+            }
+        }
+        Ok(message_base)
+    }
+
+    /// Resolves a type reference the way protobuf.js does, walking from the innermost scope outwards
+    fn resolve(
+        &self,
+        scope: &str,
+        name: &str,
+        local: &HashMap<String, TypeRef>,
+    ) -> Option<TypeRef> {
+        let find = |full: &str| {
+            local
+                .get(full)
+                .copied()
+                .or_else(|| self.names.get(full).copied())
+        };
+        if let Some(absolute) = name.strip_prefix('.') {
+            return find(absolute);
+        }
+        let mut scope = scope;
+        loop {
+            if let Some(found) = find(&join_name(scope, name)) {
+                return Some(found);
+            }
+            if scope.is_empty() {
+                return None;
+            }
+            scope = scope.rfind('.').map(|i| &scope[..i]).unwrap_or("");
+        }
+    }
+
+    fn build_message(
+        &self,
+        pending: &PendingMessage,
+        local: &HashMap<String, TypeRef>,
+        all: &Pending,
+    ) -> Result<MessageDescriptor> {
+        let json = pending.json;
+        let mut oneofs = Vec::new();
+        let mut oneof_of: HashMap<&str, usize> = HashMap::new();
+        if let Some(defs) = json.get("oneofs").and_then(Value::as_object) {
+            for (name, def) in defs {
+                let members = def
+                    .get("oneof")
+                    .and_then(Value::as_array)
+                    .map(Vec::as_slice)
+                    .unwrap_or(&[]);
+                for member in members.iter().filter_map(Value::as_str) {
+                    oneof_of.insert(member, oneofs.len());
+                }
+                oneofs.push(OneofDescriptor {
+                    name: name.clone(),
+                    fields: Vec::new(),
+                });
+            }
+        }
+
+        let mut fields = Vec::new();
+        if let Some(defs) = json.get("fields").and_then(Value::as_object) {
+            for (name, def) in defs {
+                let def = def.as_object().ok_or_else(|| {
+                    invalid(format!("invalid field {} in {}", name, pending.full_name))
+                })?;
+                if def.contains_key("extend") {
+                    continue;
+                }
+                fields.push(self.build_field(
+                    name,
+                    def,
+                    &pending.full_name,
+                    pending.features,
+                    oneof_of.get(name.as_str()).copied(),
+                    local,
+                    all,
+                )?);
+            }
+        }
+        fields.sort_by_key(|f| f.number);
+
+        let mut message = MessageDescriptor {
+            full_name: pending.full_name.clone(),
+            well_known: WellKnown::from_name(&pending.full_name),
+            fields,
+            oneofs,
+            group: json.get("group").and_then(Value::as_bool).unwrap_or(false),
+            options: owned_options(json),
+            by_number: HashMap::new(),
+        };
+        message.reindex();
+        if message.by_number.len() != message.fields.len() {
+            return Err(invalid(format!(
+                "duplicate field id in {}",
+                message.full_name
+            )));
+        }
+        Ok(message)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn build_field(
+        &self,
+        name: &str,
+        json: &Map<String, Value>,
+        scope: &str,
+        parent: Features,
+        oneof: Option<usize>,
+        local: &HashMap<String, TypeRef>,
+        all: &Pending,
+    ) -> Result<FieldDescriptor> {
+        let number = json.get("id").and_then(Value::as_u64).unwrap_or(0);
+        if number == 0 || number > 0x1FFF_FFFF {
+            return Err(invalid(format!(
+                "invalid id {} for field {} in {}",
+                number, name, scope
+            )));
+        }
+        let number = number as u32;
+        let options = object_options(json);
+        let features = parent.apply(options);
+
+        let type_name = json.get("type").and_then(Value::as_str).unwrap_or("");
+        let field_type = match ScalarType::from_name(type_name) {
+            Some(scalar) => FieldType::Scalar(scalar),
+            None => match self.resolve(scope, type_name, local) {
+                Some(TypeRef::Message(index)) => FieldType::Message(index),
+                Some(TypeRef::Enum(index)) => FieldType::Enum(index),
+                None => return Err(invalid(format!("no such type: {} in {}", type_name, scope))),
+            },
+        };
+
+        let key_type = match json.get("keyType").and_then(Value::as_str) {
+            Some(key) => match ScalarType::from_name(key) {
+                Some(scalar) if scalar.is_valid_map_key() => Some(scalar),
+                _ => return Err(invalid(format!("invalid key type: {}", key))),
+            },
+            None => None,
+        };
+
+        let label = match json.get("rule").and_then(Value::as_str) {
+            _ if key_type.is_some() => Label::Repeated,
+            Some("repeated") => Label::Repeated,
+            Some("required") => Label::Required,
+            _ => Label::Optional,
+        };
+
+        let mut packed = features.packed;
+        if let Some(explicit) = options
+            .and_then(|o| o.get("packed"))
+            .and_then(Value::as_bool)
+        {
+            packed = explicit;
+        }
+
+        let delimited = match field_type {
+            FieldType::Message(index) => features.delimited || self.is_group(index, all),
+            _ => false,
+        };
+
+        let proto3_optional = options
+            .and_then(|o| o.get("proto3_optional"))
+            .and_then(Value::as_bool)
+            .unwrap_or(false);
+        let has_presence = label != Label::Repeated
+            && (oneof.is_some()
+                || proto3_optional
+                || label == Label::Required
+                || !features.implicit_presence
+                || matches!(field_type, FieldType::Message(_)));
+
+        let json_name = match name.strip_prefix('.') {
+            Some(extension) => format!("[{}]", extension),
+            None => options
+                .and_then(|o| o.get("json_name"))
+                .and_then(Value::as_str)
+                .map_or_else(|| json_name_of(name), str::to_string),
+        };
+        let mut field = FieldDescriptor {
+            name: name.to_string(),
+            json_name,
+            number,
+            label,
+            field_type,
+            key_type,
+            packed: false,
+            delimited,
+            has_presence,
+            oneof,
+            default_value: options.and_then(|o| o.get("default")).cloned(),
+            tag: 0,
+            options: options.cloned().unwrap_or_default(),
+        };
+        field.packed =
+            label == Label::Repeated && key_type.is_none() && packed && field.is_packable();
+        field.tag = number << 3
+            | if field.is_map() {
+                2
+            } else {
+                field.value_wire_type()
+            };
+        Ok(field)
+    }
+
+    /// Builds a service, naming method types by their fully qualified name when they resolve
+    fn build_service(
+        &self,
+        pending: &PendingService,
+        local: &HashMap<String, TypeRef>,
+        added: &[MessageDescriptor],
+    ) -> ServiceDescriptor {
+        let type_name = |json: &Map<String, Value>, key: &str| {
+            let name = json.get(key).and_then(Value::as_str).unwrap_or("");
+            match self.resolve(&pending.full_name, name, local) {
+                Some(TypeRef::Message(index)) => match self.messages.get(index) {
+                    Some(message) => message.full_name.clone(),
+                    None => added[index - self.messages.len()].full_name.clone(),
+                },
+                _ => name.to_string(),
+            }
+        };
+        let methods = pending
+            .json
+            .get("methods")
+            .and_then(Value::as_object)
+            .into_iter()
+            .flatten()
+            .filter_map(|(name, json)| {
+                let json = json.as_object()?;
+                Some(MethodDescriptor {
+                    name: name.clone(),
+                    input_type: type_name(json, "requestType"),
+                    output_type: type_name(json, "responseType"),
+                    client_streaming: json.get("requestStream").and_then(Value::as_bool).unwrap_or(false),
+                    server_streaming: json.get("responseStream").and_then(Value::as_bool).unwrap_or(false),
+                    options: owned_options(json),
+                })
+            })
+            .collect();
+        ServiceDescriptor {
+            full_name: pending.full_name.clone(),
+            methods,
+            options: owned_options(pending.json),
+        }
+    }
+
+    fn is_group(&self, index: usize, all: &Pending) -> bool {
+        match self.messages.get(index) {
+            Some(existing) => existing.group,
+            None => all.messages[index - self.messages.len()]
+                .json
+                .get("group")
+                .and_then(Value::as_bool)
+                .unwrap_or(false),
+        }
+    }
+}
+
+fn build_enum(pending: &PendingEnum) -> Result<EnumDescriptor> {
+    let values = pending
+        .json
+        .get("values")
+        .and_then(Value::as_object)
+        .ok_or_else(|| invalid(format!("invalid enum {}", pending.full_name)))?;
+    let mut parsed = Vec::with_capacity(values.len());
+    for (name, value) in values {
+        // protobuf.js enum values inherit `valuesById` on their prototype, which shows
+        // up as numeric keys mapping to names when the object is passed in directly
+        if value.is_string() && name.parse::<i32>().is_ok() {
+            continue;
+        }
+        let value = value
+            .as_i64()
+            .and_then(|v| i32::try_from(v).ok())
+            .ok_or_else(|| invalid(format!("invalid value for {}.{}", pending.full_name, name)))?;
+        parsed.push((name.clone(), value));
+    }
+    Ok(EnumDescriptor {
+        full_name: pending.full_name.clone(),
+        values: parsed,
+        options: owned_options(pending.json),
+        values_options: pending
+            .json
+            .get("valuesOptions")
+            .and_then(Value::as_object)
+            .cloned()
+            .unwrap_or_default(),
+    })
+}
+
+fn element_features(json: &Map<String, Value>, inherited: Features) -> Result<Features> {
+    let base = match json.get("edition").and_then(Value::as_str) {
+        Some(edition) => Features::for_edition(edition)?,
+        None => inherited,
+    };
+    Ok(base.apply(object_options(json)))
+}
+
+fn collect_message<'a>(
+    pending: &mut Pending<'a>,
+    full_name: String,
+    json: &'a Map<String, Value>,
+    inherited: Features,
+) -> Result<()> {
+    let features = element_features(json, inherited)?;
+    pending.messages.push(PendingMessage {
+        full_name: full_name.clone(),
+        json,
+        features,
+    });
+    collect_nested(pending, &full_name, json, features)
+}
+
+fn collect_nested<'a>(
+    pending: &mut Pending<'a>,
+    scope: &str,
+    json: &'a Map<String, Value>,
+    features: Features,
+) -> Result<()> {
+    let nested = match json.get("nested").and_then(Value::as_object) {
+        Some(nested) => nested,
+        None => return Ok(()),
+    };
+    for (name, child) in nested {
+        let child = match child.as_object() {
+            Some(child) => child,
+            None => continue,
+        };
+        let full_name = join_name(scope, name);
+        if child.contains_key("id") {
+            if child.contains_key("extend") {
+                let features = element_features(child, features)?;
+                pending.extensions.push(PendingExtension {
+                    scope: scope.to_string(),
+                    full_name,
+                    json: child,
+                    features,
+                });
+            }
+        } else if child.contains_key("fields") {
+            collect_message(pending, full_name, child, features)?;
+        } else if child.contains_key("values") {
+            pending.enums.push(PendingEnum {
+                full_name,
+                json: child,
+            });
+        } else if child.contains_key("methods") {
+            pending.services.push(PendingService {
+                full_name: full_name.clone(),
+                json: child,
+            });
+            let features = element_features(child, features)?;
+            collect_nested(pending, &full_name, child, features)?;
+        } else {
+            // Namespaces and services only contribute nested types
+            let features = element_features(child, features)?;
+            collect_nested(pending, &full_name, child, features)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,889 @@
+use std::collections::{HashMap, HashSet};
+
+use serde_json::{Map, Number, Value};
+
+use crate::descriptor::json_name_of;
+use crate::error::{ErrorKind, ProtoError, Result};
+use crate::reader::ReaderImpl;
+use crate::text::{Token, Tokenizer};
+use crate::utf8::Utf8Policy;
+
+/// How an option field is read and shown in the JSON
+#[derive(Clone, Copy)]
+enum OptionKind {
+    Bool,
+    Str,
+    /// Enum value names by number, or the number when it isn't listed
+    Enum(&'static [&'static str]),
+    /// A nested `FeatureSet`
+    Features,
+}
+
+use OptionKind::*;
+
+const FILE_OPTIONS: &[(u32, &str, OptionKind)] = &[
+    (1, "java_package", Str),
+    (8, "java_outer_classname", Str),
+    (9, "optimize_for", Enum(&["", "SPEED", "CODE_SIZE", "LITE_RUNTIME"])),
+    (10, "java_multiple_files", Bool),
+    (11, "go_package", Str),
+    (16, "cc_generic_services", Bool),
+    (17, "java_generic_services", Bool),
+    (18, "py_generic_services", Bool),
+    (20, "java_generate_equals_and_hash", Bool),
+    (23, "deprecated", Bool),
+    (27, "java_string_check_utf8", Bool),
+    (31, "cc_enable_arenas", Bool),
+    (36, "objc_class_prefix", Str),
+    (37, "csharp_namespace", Str),
+    (39, "swift_prefix", Str),
+    (40, "php_class_prefix", Str),
+    (41, "php_namespace", Str),
+    (44, "php_metadata_namespace", Str),
+    (45, "ruby_package", Str),
+    (50, "features", Features),
+];
+
+const MESSAGE_OPTIONS: &[(u32, &str, OptionKind)] = &[
+    (1, "message_set_wire_format", Bool),
+    (2, "no_standard_descriptor_accessor", Bool),
+    (3, "deprecated", Bool),
+    (7, "map_entry", Bool),
+    (12, "features", Features),
+];
+
+const FIELD_OPTIONS: &[(u32, &str, OptionKind)] = &[
+    (1, "ctype", Enum(&["STRING", "CORD", "STRING_PIECE"])),
+    (2, "packed", Bool),
+    (3, "deprecated", Bool),
+    (5, "lazy", Bool),
+    (6, "jstype", Enum(&["JS_NORMAL", "JS_STRING", "JS_NUMBER"])),
+    (10, "weak", Bool),
+    (15, "unverified_lazy", Bool),
+    (16, "debug_redact", Bool),
+    (21, "features", Features),
+];
+
+const ONEOF_OPTIONS: &[(u32, &str, OptionKind)] = &[(1, "features", Features)];
+
+const ENUM_OPTIONS: &[(u32, &str, OptionKind)] = &[
+    (2, "allow_alias", Bool),
+    (3, "deprecated", Bool),
+    (7, "features", Features),
+];
+
+const ENUM_VALUE_OPTIONS: &[(u32, &str, OptionKind)] = &[
+    (1, "deprecated", Bool),
+    (2, "features", Features),
+    (3, "debug_redact", Bool),
+];
+
+const SERVICE_OPTIONS: &[(u32, &str, OptionKind)] = &[(33, "deprecated", Bool), (34, "features", Features)];
+
+const METHOD_OPTIONS: &[(u32, &str, OptionKind)] = &[
+    (33, "deprecated", Bool),
+    (
+        34,
+        "idempotency_level",
+        Enum(&["IDEMPOTENCY_UNKNOWN", "NO_SIDE_EFFECTS", "IDEMPOTENT"]),
+    ),
+    (35, "features", Features),
+];
+
+const FEATURES: &[(u32, &str, OptionKind)] = &[
+    (
+        1,
+        "field_presence",
+        Enum(&["FIELD_PRESENCE_UNKNOWN", "EXPLICIT", "IMPLICIT", "LEGACY_REQUIRED"]),
+    ),
+    (2, "enum_type", Enum(&["ENUM_TYPE_UNKNOWN", "OPEN", "CLOSED"])),
+    (
+        3,
+        "repeated_field_encoding",
+        Enum(&["REPEATED_FIELD_ENCODING_UNKNOWN", "PACKED", "EXPANDED"]),
+    ),
+    (
+        4,
+        "utf8_validation",
+        Enum(&["UTF8_VALIDATION_UNKNOWN", "", "VERIFY", "NONE"]),
+    ),
+    (
+        5,
+        "message_encoding",
+        Enum(&["MESSAGE_ENCODING_UNKNOWN", "LENGTH_PREFIXED", "DELIMITED"]),
+    ),
+    (6, "json_format", Enum(&["JSON_FORMAT_UNKNOWN", "ALLOW", "LEGACY_BEST_EFFORT"])),
+];
+
+/// Names of the `FieldDescriptorProto.Type` values, `None` for groups, messages and enums
+const SCALAR_TYPES: [Option<&str>; 19] = [
+    None,
+    Some("double"),
+    Some("float"),
+    Some("int64"),
+    Some("uint64"),
+    Some("int32"),
+    Some("fixed64"),
+    Some("fixed32"),
+    Some("bool"),
+    Some("string"),
+    None,
+    None,
+    Some("bytes"),
+    Some("uint32"),
+    None,
+    Some("sfixed32"),
+    Some("sfixed64"),
+    Some("sint32"),
+    Some("sint64"),
+];
+
+const TYPE_GROUP: i32 = 10;
+const LABEL_REQUIRED: i32 = 2;
+const LABEL_REPEATED: i32 = 3;
+
+#[derive(Default)]
+struct FileProto {
+    name: String,
+    package: String,
+    dependencies: Vec<String>,
+    messages: Vec<MessageProto>,
+    enums: Vec<EnumProto>,
+    services: Vec<ServiceProto>,
+    extensions: Vec<FieldProto>,
+    options: Map<String, Value>,
+    syntax: String,
+    edition: Option<i32>,
+}
+
+#[derive(Default)]
+struct MessageProto {
+    name: String,
+    fields: Vec<FieldProto>,
+    nested: Vec<MessageProto>,
+    enums: Vec<EnumProto>,
+    extension_ranges: Vec<Value>,
+    extensions: Vec<FieldProto>,
+    options: Map<String, Value>,
+    oneofs: Vec<(String, Map<String, Value>)>,
+    reserved: Vec<Value>,
+}
+
+#[derive(Default)]
+struct FieldProto {
+    name: String,
+    extendee: String,
+    number: i32,
+    label: i32,
+    kind: i32,
+    type_name: String,
+    default_value: Option<String>,
+    options: Map<String, Value>,
+    oneof_index: Option<usize>,
+    json_name: Option<String>,
+    proto3_optional: bool,
+}
+
+#[derive(Default)]
+struct EnumProto {
+    name: String,
+    values: Vec<(String, i32, Map<String, Value>)>,
+    options: Map<String, Value>,
+    reserved: Vec<Value>,
+}
+
+#[derive(Default)]
+struct ServiceProto {
+    name: String,
+    methods: Vec<MethodProto>,
+    options: Map<String, Value>,
+}
+
+#[derive(Default)]
+struct MethodProto {
+    name: String,
+    input_type: String,
+    output_type: String,
+    options: Map<String, Value>,
+    client_streaming: bool,
+    server_streaming: bool,
+}
+
+/// Reads the fields up to the reader's limit, passing each field number and wire
+/// type to `field`, which reads the value or returns false to skip it
+fn for_each_field(reader: &mut ReaderImpl, mut field: impl FnMut(&mut ReaderImpl, u32, u32) -> Result<bool>) -> Result<()> {
+    while !reader.at_end() {
+        let (number, wire_type) = reader.read_tag()?;
+        if !field(reader, number, wire_type)? {
+            reader.skip_field(number, wire_type)?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads a length-delimited submessage with `read`
+fn nested<T>(reader: &mut ReaderImpl, read: impl FnOnce(&mut ReaderImpl) -> Result<T>) -> Result<T> {
+    let len = reader.read_varint32()? as usize;
+    reader.push_limit(len)?;
+    reader.descend()?;
+    let value = read(reader);
+    reader.ascend();
+    reader.pop_limit()?;
+    value
+}
+
+fn string(reader: &mut ReaderImpl) -> Result<String> {
+    let len = reader.read_varint32()? as usize;
+    Ok(reader.read_text(len, Utf8Policy::Strict)?.into_lossy())
+}
+
+fn int32(reader: &mut ReaderImpl) -> Result<i32> {
+    Ok(reader.read_varint64()? as i32)
+}
+
+/// Reads the options listed in `known`, skipping others like custom options
+fn options(reader: &mut ReaderImpl, known: &[(u32, &str, OptionKind)]) -> Result<Map<String, Value>> {
+    nested(reader, |reader| {
+        let mut values = Map::new();
+        for_each_field(reader, |reader, number, wire_type| {
+            let (name, kind) = match known.iter().find(|(n, _, _)| *n == number) {
+                Some((_, name, kind)) => (*name, *kind),
+                None => return Ok(false),
+            };
+            let value = match (kind, wire_type) {
+                (Bool, 0) => Value::Bool(reader.read_varint64()? != 0),
+                (Str, 2) => Value::String(string(reader)?),
+                (Enum(names), 0) => {
+                    let number = int32(reader)?;
+                    match names.get(number as usize) {
+                        Some(name) if number >= 0 && !name.is_empty() => Value::String(name.to_string()),
+                        _ => Value::from(number),
+                    }
+                }
+                (Features, 2) => Value::Object(options(reader, FEATURES)?),
+                _ => return Ok(false),
+            };
+            values.insert(name.to_string(), value);
+            Ok(true)
+        })?;
+        Ok(values)
+    })
+}
+
+/// Reads a `[start, end)` range as protobuf.js' inclusive `[start, end]`
+fn range(reader: &mut ReaderImpl, exclusive: bool) -> Result<Value> {
+    nested(reader, |reader| {
+        let (mut start, mut end) = (0, 0);
+        for_each_field(reader, |reader, number, wire_type| {
+            match (number, wire_type) {
+                (1, 0) => start = int32(reader)?,
+                (2, 0) => end = int32(reader)?,
+                _ => return Ok(false),
+            }
+            Ok(true)
+        })?;
+        if exclusive {
+            end -= 1;
+        }
+        Ok(Value::from(vec![start, end]))
+    })
+}
+
+fn read_file(reader: &mut ReaderImpl) -> Result<FileProto> {
+    nested(reader, |reader| {
+        let mut file = FileProto::default();
+        for_each_field(reader, |reader, number, wire_type| {
+            match (number, wire_type) {
+                (1, 2) => file.name = string(reader)?,
+                (2, 2) => file.package = string(reader)?,
+                (3, 2) => file.dependencies.push(string(reader)?),
+                (4, 2) => file.messages.push(read_message(reader)?),
+                (5, 2) => file.enums.push(read_enum(reader)?),
+                (6, 2) => file.services.push(read_service(reader)?),
+                (7, 2) => file.extensions.push(read_field(reader)?),
+                (8, 2) => file.options = options(reader, FILE_OPTIONS)?,
+                (12, 2) => file.syntax = string(reader)?,
+                (14, 0) => file.edition = Some(int32(reader)?),
+                _ => return Ok(false),
+            }
+            Ok(true)
+        })?;
+        Ok(file)
+    })
+}
+
+fn read_message(reader: &mut ReaderImpl) -> Result<MessageProto> {
+    nested(reader, |reader| {
+        let mut message = MessageProto::default();
+        for_each_field(reader, |reader, number, wire_type| {
+            match (number, wire_type) {
+                (1, 2) => message.name = string(reader)?,
+                (2, 2) => message.fields.push(read_field(reader)?),
+                (3, 2) => message.nested.push(read_message(reader)?),
+                (4, 2) => message.enums.push(read_enum(reader)?),
+                (5, 2) => message.extension_ranges.push(range(reader, true)?),
+                (6, 2) => message.extensions.push(read_field(reader)?),
+                (7, 2) => message.options = options(reader, MESSAGE_OPTIONS)?,
+                (8, 2) => message.oneofs.push(nested(reader, |reader| {
+                    let mut oneof = (String::new(), Map::new());
+                    for_each_field(reader, |reader, number, wire_type| {
+                        match (number, wire_type) {
+                            (1, 2) => oneof.0 = string(reader)?,
+                            (2, 2) => oneof.1 = options(reader, ONEOF_OPTIONS)?,
+                            _ => return Ok(false),
+                        }
+                        Ok(true)
+                    })?;
+                    Ok(oneof)
+                })?),
+                (9, 2) => message.reserved.push(range(reader, true)?),
+                (10, 2) => message.reserved.push(Value::String(string(reader)?)),
+                _ => return Ok(false),
+            }
+            Ok(true)
+        })?;
+        Ok(message)
+    })
+}
+
+fn read_field(reader: &mut ReaderImpl) -> Result<FieldProto> {
+    nested(reader, |reader| {
+        let mut field = FieldProto::default();
+        for_each_field(reader, |reader, number, wire_type| {
+            match (number, wire_type) {
+                (1, 2) => field.name = string(reader)?,
+                (2, 2) => field.extendee = string(reader)?,
+                (3, 0) => field.number = int32(reader)?,
+                (4, 0) => field.label = int32(reader)?,
+                (5, 0) => field.kind = int32(reader)?,
+                (6, 2) => field.type_name = string(reader)?,
+                (7, 2) => field.default_value = Some(string(reader)?),
+                (8, 2) => field.options = options(reader, FIELD_OPTIONS)?,
+                (9, 0) => field.oneof_index = Some(int32(reader)? as usize),
+                (10, 2) => field.json_name = Some(string(reader)?),
+                (17, 0) => field.proto3_optional = reader.read_varint64()? != 0,
+                _ => return Ok(false),
+            }
+            Ok(true)
+        })?;
+        Ok(field)
+    })
+}
+
+fn read_enum(reader: &mut ReaderImpl) -> Result<EnumProto> {
+    nested(reader, |reader| {
+        let mut enumeration = EnumProto::default();
+        for_each_field(reader, |reader, number, wire_type| {
+            match (number, wire_type) {
+                (1, 2) => enumeration.name = string(reader)?,
+                (2, 2) => enumeration.values.push(nested(reader, |reader| {
+                    let mut value = (String::new(), 0, Map::new());
+                    for_each_field(reader, |reader, number, wire_type| {
+                        match (number, wire_type) {
+                            (1, 2) => value.0 = string(reader)?,
+                            (2, 0) => value.1 = int32(reader)?,
+                            (3, 2) => value.2 = options(reader, ENUM_VALUE_OPTIONS)?,
+                            _ => return Ok(false),
+                        }
+                        Ok(true)
+                    })?;
+                    Ok(value)
+                })?),
+                (3, 2) => enumeration.options = options(reader, ENUM_OPTIONS)?,
+                (4, 2) => enumeration.reserved.push(range(reader, false)?),
+                (5, 2) => enumeration.reserved.push(Value::String(string(reader)?)),
+                _ => return Ok(false),
+            }
+            Ok(true)
+        })?;
+        Ok(enumeration)
+    })
+}
+
+fn read_service(reader: &mut ReaderImpl) -> Result<ServiceProto> {
+    nested(reader, |reader| {
+        let mut service = ServiceProto::default();
+        for_each_field(reader, |reader, number, wire_type| {
+            match (number, wire_type) {
+                (1, 2) => service.name = string(reader)?,
+                (2, 2) => service.methods.push(nested(reader, |reader| {
+                    let mut method = MethodProto::default();
+                    for_each_field(reader, |reader, number, wire_type| {
+                        match (number, wire_type) {
+                            (1, 2) => method.name = string(reader)?,
+                            (2, 2) => method.input_type = string(reader)?,
+                            (3, 2) => method.output_type = string(reader)?,
+                            (4, 2) => method.options = options(reader, METHOD_OPTIONS)?,
+                            (5, 0) => method.client_streaming = reader.read_varint64()? != 0,
+                            (6, 0) => method.server_streaming = reader.read_varint64()? != 0,
+                            _ => return Ok(false),
+                        }
+                        Ok(true)
+                    })?;
+                    Ok(method)
+                })?),
+                (3, 2) => service.options = options(reader, SERVICE_OPTIONS)?,
+                _ => return Ok(false),
+            }
+            Ok(true)
+        })?;
+        Ok(service)
+    })
+}
+
+fn malformed(message: String) -> ProtoError {
+    ProtoError::new(ErrorKind::Malformed, message)
+}
+
+/// Orders files after the files they import
+fn sort_files(files: Vec<FileProto>) -> Result<Vec<FileProto>> {
+    let mut by_name = HashMap::new();
+    for (i, file) in files.iter().enumerate() {
+        if by_name.insert(file.name.as_str(), i).is_some() {
+            return Err(malformed(format!("duplicate file {} in descriptor set", file.name)));
+        }
+    }
+    // 0 unvisited, 1 in progress, 2 done
+    let mut state = vec![0u8; files.len()];
+    let mut order = Vec::with_capacity(files.len());
+    fn visit(
+        i: usize,
+        files: &[FileProto],
+        by_name: &HashMap<&str, usize>,
+        state: &mut [u8],
+        order: &mut Vec<usize>,
+    ) -> Result<()> {
+        match state[i] {
+            1 => return Err(malformed(format!("import cycle at {}", files[i].name))),
+            2 => return Ok(()),
+            _ => state[i] = 1,
+        }
+        for dependency in &files[i].dependencies {
+            let index = by_name.get(dependency.as_str()).ok_or_else(|| {
+                malformed(format!(
+                    "missing {}, imported by {} (build the set with its imports)",
+                    dependency, files[i].name
+                ))
+            })?;
+            visit(*index, files, by_name, state, order)?;
+        }
+        state[i] = 2;
+        order.push(i);
+        Ok(())
+    }
+    for i in 0..files.len() {
+        visit(i, &files, &by_name, &mut state, &mut order)?;
+    }
+    let mut files: Vec<Option<FileProto>> = files.into_iter().map(Some).collect();
+    Ok(order.into_iter().filter_map(|i| files[i].take()).collect())
+}
+
+/// Reads a serialized `google.protobuf.FileDescriptorSet` and converts it to protobuf.js
+/// `Root.toJSON()` JSON, with the files ordered after their imports and type references
+/// kept fully qualified
+pub fn to_json(reader: &mut ReaderImpl) -> Result<Value> {
+    let mut files = Vec::new();
+    for_each_field(reader, |reader, number, wire_type| {
+        if (number, wire_type) != (1, 2) {
+            return Ok(false);
+        }
+        files.push(read_file(reader)?);
+        Ok(true)
+    })?;
+    let files = sort_files(files)?;
+
+    let mut root = Map::new();
+    for file in &files {
+        Converter::new(file)?
+            .file(&mut root, file)
+            .map_err(|e| e.prefixed(&file.name))?;
+    }
+    Ok(Value::Object(root))
+}
+
+struct Converter {
+    /// `edition` of the file's top-level elements, `None` for proto3
+    edition: Option<&'static str>,
+    /// Edition features of the file, which protobuf.js keeps on its top-level elements
+    features: Option<Value>,
+    /// Messages used as proto2 groups
+    groups: HashSet<String>,
+}
+
+impl Converter {
+    fn new(file: &FileProto) -> Result<Converter> {
+        let edition = match (file.syntax.as_str(), file.edition) {
+            ("" | "proto2", _) => Some("proto2"),
+            ("proto3", _) => None,
+            ("editions", Some(1000)) => Some("2023"),
+            ("editions", Some(1001)) => Some("2024"),
+            ("editions", edition) => {
+                return Err(malformed(format!(
+                    "unsupported edition {} in {}",
+                    edition.unwrap_or(0),
+                    file.name
+                )))
+            }
+            (syntax, _) => return Err(malformed(format!("unknown syntax {} in {}", syntax, file.name))),
+        };
+        let mut groups = HashSet::new();
+        if edition == Some("proto2") {
+            let mut fields: Vec<&FieldProto> = file.extensions.iter().collect();
+            let mut messages: Vec<&MessageProto> = file.messages.iter().collect();
+            while let Some(message) = messages.pop() {
+                fields.extend(&message.fields);
+                fields.extend(&message.extensions);
+                messages.extend(&message.nested);
+            }
+            groups.extend(
+                fields
+                    .into_iter()
+                    .filter(|f| f.kind == TYPE_GROUP)
+                    .map(|f| f.type_name.clone()),
+            );
+        }
+        Ok(Converter {
+            edition,
+            features: file.options.get("features").cloned(),
+            groups,
+        })
+    }
+
+    fn file(&self, root: &mut Map<String, Value>, file: &FileProto) -> Result<()> {
+        let namespace = namespace(root, &file.package);
+        let mut options = file.options.clone();
+        options.remove("features");
+        if !options.is_empty() {
+            let existing = namespace
+                .entry("options")
+                .or_insert_with(|| Value::Object(Map::new()));
+            if let Value::Object(existing) = existing {
+                existing.extend(options);
+            }
+        }
+        let scope = match file.package.as_str() {
+            "" => String::new(),
+            package => format!(".{}", package),
+        };
+        let mut nested = Map::new();
+        for message in &file.messages {
+            let json = self.message(&scope, message)?;
+            insert(&mut nested, &message.name, self.top_level(json))?;
+        }
+        for enumeration in &file.enums {
+            insert(&mut nested, &enumeration.name, self.top_level(enumeration_json(enumeration)))?;
+        }
+        for extension in &file.extensions {
+            insert(&mut nested, &extension.name, self.top_level(self.field(extension, None)?))?;
+        }
+        for service in &file.services {
+            insert(&mut nested, &service.name, self.top_level(service_json(service)))?;
+        }
+        if nested.is_empty() {
+            return Ok(());
+        }
+        let existing = namespace
+            .entry("nested")
+            .or_insert_with(|| Value::Object(Map::new()));
+        if let Value::Object(existing) = existing {
+            for (name, value) in nested {
+                insert(existing, &name, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds the file's `edition` and features to a top-level element
+    fn top_level(&self, json: Map<String, Value>) -> Value {
+        let mut out = Map::new();
+        if let Some(edition) = self.edition {
+            out.insert("edition".to_string(), Value::from(edition));
+        }
+        if let Some(Value::Object(features)) = &self.features {
+            let mut merged = features.clone();
+            if let Some(Value::Object(own)) = json.get("options").and_then(|o| o.get("features")) {
+                merged.extend(own.clone());
+            }
+            let mut options = match json.get("options") {
+                Some(Value::Object(options)) => options.clone(),
+                _ => Map::new(),
+            };
+            options.insert("features".to_string(), Value::Object(merged));
+            out.insert("options".to_string(), Value::Object(options));
+        }
+        for (key, value) in json {
+            out.entry(key).or_insert(value);
+        }
+        Value::Object(out)
+    }
+
+    fn message(&self, scope: &str, message: &MessageProto) -> Result<Map<String, Value>> {
+        let full_name = format!("{}.{}", scope, message.name);
+        let map_entries: HashMap<String, &MessageProto> = message
+            .nested
+            .iter()
+            .filter(|m| m.options.get("map_entry") == Some(&Value::Bool(true)))
+            .map(|m| (format!("{}.{}", full_name, m.name), m))
+            .collect();
+
+        let mut json = Map::new();
+        let mut options = message.options.clone();
+        options.remove("map_entry");
+        if !options.is_empty() {
+            json.insert("options".to_string(), Value::Object(options));
+        }
+        if !message.oneofs.is_empty() {
+            let mut oneofs = Map::new();
+            for (i, (name, options)) in message.oneofs.iter().enumerate() {
+                let members: Vec<Value> = message
+                    .fields
+                    .iter()
+                    .filter(|f| f.oneof_index == Some(i))
+                    .map(|f| Value::from(f.name.as_str()))
+                    .collect();
+                let mut oneof = Map::new();
+                oneof.insert("oneof".to_string(), Value::Array(members));
+                if !options.is_empty() {
+                    oneof.insert("options".to_string(), Value::Object(options.clone()));
+                }
+                oneofs.insert(name.clone(), Value::Object(oneof));
+            }
+            json.insert("oneofs".to_string(), Value::Object(oneofs));
+        }
+        let mut fields = Map::new();
+        for field in &message.fields {
+            let entry = match field.label {
+                LABEL_REPEATED => map_entries.get(&field.type_name).copied(),
+                _ => None,
+            };
+            if fields.insert(field.name.clone(), Value::Object(self.field(field, entry)?)).is_some() {
+                return Err(malformed(format!("duplicate field {} in {}", field.name, &full_name[1..])));
+            }
+        }
+        json.insert("fields".to_string(), Value::Object(fields));
+        if !message.extension_ranges.is_empty() {
+            json.insert("extensions".to_string(), Value::Array(message.extension_ranges.clone()));
+        }
+        if !message.reserved.is_empty() {
+            json.insert("reserved".to_string(), Value::Array(message.reserved.clone()));
+        }
+        if self.groups.contains(&full_name) {
+            json.insert("group".to_string(), Value::Bool(true));
+        }
+
+        let mut nested = Map::new();
+        for child in &message.nested {
+            if !map_entries.contains_key(&format!("{}.{}", full_name, child.name)) {
+                insert(&mut nested, &child.name, Value::Object(self.message(&full_name, child)?))?;
+            }
+        }
+        for enumeration in &message.enums {
+            insert(&mut nested, &enumeration.name, Value::Object(enumeration_json(enumeration)))?;
+        }
+        for extension in &message.extensions {
+            insert(&mut nested, &extension.name, Value::Object(self.field(extension, None)?))?;
+        }
+        if !nested.is_empty() {
+            json.insert("nested".to_string(), Value::Object(nested));
+        }
+        Ok(json)
+    }
+
+    /// Converts a field, which is a map field when `entry` is its map entry message
+    fn field(&self, field: &FieldProto, entry: Option<&MessageProto>) -> Result<Map<String, Value>> {
+        let mut json = Map::new();
+        let value = match entry {
+            Some(entry) => {
+                let key = entry.fields.iter().find(|f| f.number == 1);
+                let value = entry.fields.iter().find(|f| f.number == 2);
+                let (key, value) = key.zip(value).ok_or_else(|| {
+                    malformed(format!("invalid map entry {}", field.type_name))
+                })?;
+                json.insert("keyType".to_string(), Value::from(type_of(key)?));
+                value
+            }
+            None => {
+                match field.label {
+                    LABEL_REQUIRED => json.insert("rule".to_string(), Value::from("required")),
+                    LABEL_REPEATED => json.insert("rule".to_string(), Value::from("repeated")),
+                    _ => None,
+                };
+                field
+            }
+        };
+        json.insert("type".to_string(), Value::from(type_of(value)?));
+        json.insert("id".to_string(), Value::from(field.number));
+        if !field.extendee.is_empty() {
+            json.insert("extend".to_string(), Value::from(field.extendee.as_str()));
+        }
+
+        let mut options = field.options.clone();
+        if field.proto3_optional {
+            options.insert("proto3_optional".to_string(), Value::Bool(true));
+        }
+        if let Some(json_name) = &field.json_name {
+            if *json_name != json_name_of(&field.name) {
+                options.insert("json_name".to_string(), Value::from(json_name.as_str()));
+            }
+        }
+        if let Some(default) = &field.default_value {
+            options.insert("default".to_string(), default_value(field.kind, default));
+        }
+        if field.kind == TYPE_GROUP && self.edition.is_some_and(|e| e != "proto2") {
+            let features = options
+                .entry("features")
+                .or_insert_with(|| Value::Object(Map::new()));
+            if let Value::Object(features) = features {
+                features
+                    .entry("message_encoding")
+                    .or_insert_with(|| Value::from("DELIMITED"));
+            }
+        }
+        if !options.is_empty() {
+            json.insert("options".to_string(), Value::Object(options));
+        }
+        Ok(json)
+    }
+}
+
+/// The protobuf.js type of a field: a scalar name or the referenced type's full name
+fn type_of(field: &FieldProto) -> Result<String> {
+    match SCALAR_TYPES.get(field.kind as usize) {
+        Some(Some(scalar)) if field.type_name.is_empty() => Ok(scalar.to_string()),
+        _ if !field.type_name.is_empty() => Ok(field.type_name.clone()),
+        _ => Err(malformed(format!("invalid type {} of field {}", field.kind, field.name))),
+    }
+}
+
+/// Converts the text of a `default_value` to the JSON protobuf.js parses it to
+fn default_value(kind: i32, text: &str) -> Value {
+    match SCALAR_TYPES.get(kind as usize).copied().flatten() {
+        Some("bool") => Value::Bool(text == "true"),
+        Some("string") => Value::from(text),
+        Some("bytes") => {
+            // The C escapes of protoc, resolved like text format string literals
+            match Tokenizer::new(&format!("\"{}\"", text)).next() {
+                Ok(Token::Str(data)) => match String::from_utf8(data) {
+                    Ok(text) => Value::from(text),
+                    Err(_) => Value::from(text),
+                },
+                _ => Value::from(text),
+            }
+        }
+        Some(_) => {
+            if let Ok(value) = text.parse::<i64>() {
+                Value::from(value)
+            } else if let Ok(value) = text.parse::<u64>() {
+                Value::from(value)
+            } else {
+                match text.parse::<f64>().ok().and_then(Number::from_f64) {
+                    Some(number) => Value::Number(number),
+                    None => Value::from(text),
+                }
+            }
+        }
+        // Enum value names
+        None => Value::from(text),
+    }
+}
+
+fn enumeration_json(enumeration: &EnumProto) -> Map<String, Value> {
+    let mut json = Map::new();
+    if !enumeration.options.is_empty() {
+        json.insert("options".to_string(), Value::Object(enumeration.options.clone()));
+    }
+    let mut values = Map::new();
+    let mut values_options = Map::new();
+    for (name, number, options) in &enumeration.values {
+        values.insert(name.clone(), Value::from(*number));
+        if !options.is_empty() {
+            values_options.insert(name.clone(), Value::Object(options.clone()));
+        }
+    }
+    if !values_options.is_empty() {
+        json.insert("valuesOptions".to_string(), Value::Object(values_options));
+    }
+    json.insert("values".to_string(), Value::Object(values));
+    if !enumeration.reserved.is_empty() {
+        json.insert("reserved".to_string(), Value::Array(enumeration.reserved.clone()));
+    }
+    json
+}
+
+fn service_json(service: &ServiceProto) -> Map<String, Value> {
+    let mut json = Map::new();
+    if !service.options.is_empty() {
+        json.insert("options".to_string(), Value::Object(service.options.clone()));
+    }
+    let mut methods = Map::new();
+    for method in &service.methods {
+        let mut json = Map::new();
+        json.insert("requestType".to_string(), Value::from(method.input_type.as_str()));
+        if method.client_streaming {
+            json.insert("requestStream".to_string(), Value::Bool(true));
+        }
+        json.insert("responseType".to_string(), Value::from(method.output_type.as_str()));
+        if method.server_streaming {
+            json.insert("responseStream".to_string(), Value::Bool(true));
+        }
+        if !method.options.is_empty() {
+            json.insert("options".to_string(), Value::Object(method.options.clone()));
+        }
+        methods.insert(method.name.clone(), Value::Object(json));
+    }
+    json.insert("methods".to_string(), Value::Object(methods));
+    json
+}
+
+/// The namespace object of a package, created as needed
+pub fn namespace<'a>(root: &'a mut Map<String, Value>, package: &str) -> &'a mut Map<String, Value> {
+    let mut namespace = root;
+    for part in package.split('.').filter(|p| !p.is_empty()) {
+        let nested = namespace
+            .entry("nested")
+            .or_insert_with(|| Value::Object(Map::new()));
+        let child = match nested {
+            Value::Object(nested) => nested
+                .entry(part)
+                .or_insert_with(|| Value::Object(Map::new())),
+            _ => unreachable!("nested is always an object"),
+        };
+        namespace = match child {
+            Value::Object(child) => child,
+            _ => unreachable!("namespaces are always objects"),
+        };
+    }
+    namespace
+}
+
+/// Adds an element to a `nested` object, merging namespaces with a type of the same name
+pub fn insert(nested: &mut Map<String, Value>, name: &str, value: Value) -> Result<()> {
+    match nested.get_mut(name) {
+        None => {
+            nested.insert(name.to_string(), value);
+        }
+        Some(Value::Object(existing)) if is_namespace(existing) => {
+            let mut value = value;
+            if let (Value::Object(value), Some(Value::Object(inner))) = (&mut value, existing.get("nested")) {
+                let merged = value
+                    .entry("nested")
+                    .or_insert_with(|| Value::Object(Map::new()));
+                if let Value::Object(merged) = merged {
+                    for (key, child) in inner {
+                        insert(merged, key, child.clone())?;
+                    }
+                }
+            }
+            nested.insert(name.to_string(), value);
+        }
+        Some(_) => return Err(malformed(format!("duplicate name {}", name))),
+    }
+    Ok(())
+}
+
+/// Whether an element of `nested` is a plain namespace rather than a type, field or service
+fn is_namespace(json: &Map<String, Value>) -> bool {
+    !["fields", "values", "id", "methods"]
+        .iter()
+        .any(|key| json.contains_key(*key))
+}
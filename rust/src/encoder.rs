@@ -0,0 +1,515 @@
+use napi::JsBuffer;
+use napi::JsDate;
+use napi::JsNumber;
+use napi::JsObject;
+use napi::JsString;
+use napi::JsTypedArray;
+use napi::JsUnknown;
+use napi::ValueType;
+use napi_derive::napi;
+
+use crate::base64;
+use crate::error::{ErrorKind, ProtoError, Result};
+use crate::json;
+use crate::long;
+use crate::utf8;
+use crate::wkt;
+use crate::descriptor::{FieldDescriptor, FieldType, MessageDescriptor, Registry, ScalarType, WellKnown};
+use crate::writer::WriterImpl;
+
+/// Options of `encode`
+#[napi(object)]
+#[derive(Default)]
+pub struct EncodeOptions {
+    /// `"strict"` (default), `"replace"` or `"bytes"` for strings with lone surrogates
+    pub utf8: Option<String>,
+    /// Take well-known types as the native JS values `decode` gives with `wellKnownTypes`,
+    /// as well as RFC 3339 strings for Timestamp and strings like `"1.5s"` for Duration
+    pub well_known_types: Option<bool>,
+}
+
+/// Errors are raised without the field name, which is added to the path by `encode_message`
+fn type_error(expected: &str) -> ProtoError {
+    ProtoError::new(ErrorKind::TypeMismatch, format!("{} expected", expected))
+}
+
+/// Serializes a plain JS object (or protobuf.js message instance) as `message`
+pub fn encode_message(
+    writer: &mut WriterImpl,
+    registry: &Registry,
+    message: &MessageDescriptor,
+    obj: &JsObject,
+) -> Result<()> {
+    // Fields are kept sorted by number, which is the order the wire format asks for
+    for field in &message.fields {
+        let value = match obj.get::<_, JsUnknown>(&field.name)? {
+            Some(value) => value,
+            None => continue,
+        };
+        if value.get_type()? == ValueType::Null {
+            continue;
+        }
+
+        let result = if field.is_map() {
+            if obj.has_own_property(&field.name)? {
+                encode_map(writer, registry, field, value)
+            } else {
+                Ok(())
+            }
+        } else if field.is_repeated() {
+            encode_repeated(writer, registry, field, value)
+        } else if obj.has_own_property(&field.name)? {
+            write_field(writer, registry, field, value)
+        } else {
+            Ok(())
+        };
+        result.map_err(|e| e.in_field(&field.name))?;
+    }
+    Ok(())
+}
+
+fn encode_repeated(
+    writer: &mut WriterImpl,
+    registry: &Registry,
+    field: &FieldDescriptor,
+    value: JsUnknown,
+) -> Result<()> {
+    if !value.is_array()? {
+        return Err(type_error("array"));
+    }
+    let array: JsObject = unsafe { value.cast() };
+    let len = array.get_array_length()?;
+    if len == 0 {
+        return Ok(());
+    }
+
+    if field.packed {
+        writer.write_varint32(field.number << 3 | 2);
+        writer.fork();
+        for i in 0..len {
+            write_value(writer, registry, field, array.get_element::<JsUnknown>(i)?)
+                .map_err(|e| e.in_index(i))?;
+        }
+        writer.ldelim();
+    } else {
+        for i in 0..len {
+            write_field(writer, registry, field, array.get_element::<JsUnknown>(i)?)
+                .map_err(|e| e.in_index(i))?;
+        }
+    }
+    Ok(())
+}
+
+fn encode_map(
+    writer: &mut WriterImpl,
+    registry: &Registry,
+    field: &FieldDescriptor,
+    value: JsUnknown,
+) -> Result<()> {
+    let key_type = field.key_type.unwrap_or(ScalarType::String);
+    let map = value.coerce_to_object()?;
+    for key in JsObject::keys(&map)? {
+        let entry: JsUnknown = map.get_named_property(&key)?;
+        encode_map_entry(writer, registry, field, key_type, &key, entry).map_err(|e| {
+            if key_type == ScalarType::String {
+                e.in_index(format!("\"{}\"", key))
+            } else {
+                e.in_index(&key)
+            }
+        })?;
+    }
+    Ok(())
+}
+
+fn encode_map_entry(
+    writer: &mut WriterImpl,
+    registry: &Registry,
+    field: &FieldDescriptor,
+    key_type: ScalarType,
+    key: &str,
+    entry: JsUnknown,
+) -> Result<()> {
+    writer.write_varint32(field.tag);
+    writer.fork();
+    writer.write_varint32(8 | key_type.wire_type());
+    write_map_key(writer, key_type, key)?;
+    match field.field_type {
+        FieldType::Message(index) => {
+            // Map values can't be groups
+            writer.write_varint32(18);
+            writer.fork();
+            let message = registry.message_at(index);
+            nested(writer, |writer| {
+                if !encode_well_known(writer, registry, message, &entry)? {
+                    encode_message(writer, registry, message, &entry.coerce_to_object()?)?;
+                }
+                Ok(())
+            })?;
+            writer.ldelim();
+        }
+        _ => {
+            writer.write_varint32(16 | field.value_wire_type());
+            write_value(writer, registry, field, entry)?;
+        }
+    }
+    writer.ldelim();
+    Ok(())
+}
+
+/// Writes the contents of an `Any` of `message`
+pub fn encode_any(
+    writer: &mut WriterImpl,
+    registry: &Registry,
+    type_url: &str,
+    message: &MessageDescriptor,
+    obj: &JsObject,
+) -> Result<()> {
+    writer.write_varint32(10);
+    writer.write_varint32(type_url.len() as u32);
+    writer.write_bytes(type_url.as_bytes());
+    writer.write_varint32(18);
+    writer.fork();
+    nested(writer, |writer| encode_message(writer, registry, message, obj))?;
+    writer.ldelim();
+    Ok(())
+}
+
+/// Runs `encode` for a nested message, failing past the maximum depth
+fn nested(writer: &mut WriterImpl, encode: impl FnOnce(&mut WriterImpl) -> Result<()>) -> Result<()> {
+    writer.descend()?;
+    let result = encode(writer);
+    writer.ascend();
+    result
+}
+
+/// Writes the contents of a well-known type given as a native JS value, or returns
+/// false when `value` has the message's shape. An `Any` is packed from an object with
+/// an `@type`; the other types need a writer that takes native values.
+fn encode_well_known(
+    writer: &mut WriterImpl,
+    registry: &Registry,
+    message: &MessageDescriptor,
+    value: &JsUnknown,
+) -> Result<bool> {
+    let value_type = value.get_type()?;
+    let well_known = match message.well_known {
+        Some(WellKnown::Any) if value_type == ValueType::Object => {
+            let obj: JsObject = unsafe { value.cast() };
+            let type_url = match obj.get::<_, JsUnknown>("@type")? {
+                Some(type_url) if type_url.get_type()? == ValueType::String => string_of(type_url)?,
+                _ => return Ok(false),
+            };
+            let payload = json::any_type(registry, &type_url)?;
+            encode_any(writer, registry, &type_url, payload, &obj)?;
+            return Ok(true);
+        }
+        Some(well_known) if writer.well_known_types() => well_known,
+        _ => return Ok(false),
+    };
+    let first = match message.fields.first() {
+        Some(first) => first,
+        None => return Ok(false),
+    };
+    match well_known {
+        WellKnown::Timestamp => {
+            let (seconds, nanos) = if value.is_date()? {
+                let millis = unsafe { value.cast::<JsDate>() }.value_of()?;
+                if !millis.is_finite() {
+                    return Err(type_error("valid Date"));
+                }
+                let seconds = (millis / 1000.0).floor();
+                (seconds as i64, ((millis - seconds * 1000.0) * 1e6).round() as i32)
+            } else if value_type == ValueType::String {
+                wkt::parse_timestamp(&string_of(unsafe { value.cast() })?)
+                    .ok_or_else(|| type_error("RFC 3339 timestamp"))?
+            } else {
+                return Ok(false);
+            };
+            if !(wkt::TIMESTAMP_MIN_SECONDS..=wkt::TIMESTAMP_MAX_SECONDS).contains(&seconds) {
+                return Err(type_error("timestamp between years 1 and 9999"));
+            }
+            write_seconds_nanos(writer, seconds, nanos);
+        }
+        WellKnown::Duration => {
+            let (seconds, nanos) = match value_type {
+                ValueType::Number => {
+                    let millis = unsafe { value.cast::<JsNumber>() }.get_double()?;
+                    let seconds = (millis / 1000.0).trunc();
+                    if seconds.is_nan() || seconds.abs() > wkt::DURATION_MAX_SECONDS as f64 {
+                        return Err(type_error("duration within 10000 years"));
+                    }
+                    (seconds as i64, ((millis - seconds * 1000.0) * 1e6).round() as i32)
+                }
+                ValueType::String => wkt::parse_duration(&string_of(unsafe { value.cast() })?)
+                    .ok_or_else(|| type_error("duration like \"1.5s\""))?,
+                _ => return Ok(false),
+            };
+            write_seconds_nanos(writer, seconds, nanos);
+        }
+        WellKnown::Wrapper => {
+            if value_type == ValueType::Object && unsafe { value.cast::<JsObject>() }.has_own_property(&first.name)? {
+                return Ok(false);
+            }
+            // Written like an implicit presence field, so zero values are left out
+            let mut inner = WriterImpl::new();
+            inner.set_utf8(writer.utf8());
+            write_value(&mut inner, registry, first, unsafe { value.cast() })?;
+            let data = inner.finish();
+            if data.iter().any(|&byte| byte != 0) {
+                writer.write_varint32(first.tag);
+                writer.write_bytes(&data);
+            }
+        }
+        WellKnown::Struct => {
+            if value_type != ValueType::Object || value.is_array()? {
+                return Err(type_error("object"));
+            }
+            encode_map(writer, registry, first, unsafe { value.cast() })?;
+        }
+        WellKnown::ListValue | WellKnown::FieldMask => {
+            if well_known == WellKnown::FieldMask && value_type == ValueType::String {
+                let paths = string_of(unsafe { value.cast() })?;
+                for path in paths.split(',').filter(|path| !path.is_empty()) {
+                    writer.write_varint32(first.tag);
+                    writer.write_varint32(path.len() as u32);
+                    writer.write_bytes(path.as_bytes());
+                }
+            } else if value.is_array()? {
+                encode_repeated(writer, registry, first, unsafe { value.cast() })?;
+            } else {
+                return Ok(false);
+            }
+        }
+        WellKnown::Value => {
+            let number = match value_type {
+                ValueType::Null | ValueType::Undefined => {
+                    // null_value = 1 with the only NullValue
+                    writer.write_varint32(8);
+                    writer.write_varint32(0);
+                    return Ok(true);
+                }
+                ValueType::Number => 2,
+                ValueType::String => 3,
+                ValueType::Boolean => 4,
+                ValueType::Object if value.is_array()? => 6,
+                ValueType::Object => 5,
+                _ => return Err(type_error("JSON value")),
+            };
+            let field = message
+                .field_index(number)
+                .map(|index| &message.fields[index])
+                .ok_or_else(|| type_error("JSON value"))?;
+            write_field(writer, registry, field, unsafe { value.cast() })?;
+        }
+        WellKnown::Any => return Ok(false),
+    }
+    Ok(true)
+}
+
+/// Writes the fields of a Timestamp or Duration, leaving out zeros
+fn write_seconds_nanos(writer: &mut WriterImpl, seconds: i64, nanos: i32) {
+    if seconds != 0 {
+        writer.write_varint32(8);
+        writer.write_varint64(seconds as u64);
+    }
+    if nanos != 0 {
+        writer.write_varint32(16);
+        writer.write_varint64(nanos as i64 as u64);
+    }
+}
+
+/// Writes the field's tag followed by its value
+fn write_field(
+    writer: &mut WriterImpl,
+    registry: &Registry,
+    field: &FieldDescriptor,
+    value: JsUnknown,
+) -> Result<()> {
+    if let FieldType::Message(index) = field.field_type {
+        let message = registry.message_at(index);
+        if field.delimited {
+            writer.write_varint32(field.number << 3 | 3);
+        } else {
+            writer.write_varint32(field.number << 3 | 2);
+            writer.fork();
+        }
+        nested(writer, |writer| {
+            if !encode_well_known(writer, registry, message, &value)? {
+                let obj = match value.get_type()? {
+                    ValueType::Object => value.coerce_to_object()?,
+                    _ => return Err(type_error("object")),
+                };
+                encode_message(writer, registry, message, &obj)?;
+            }
+            Ok(())
+        })?;
+        if field.delimited {
+            writer.write_varint32(field.number << 3 | 4);
+        } else {
+            writer.ldelim();
+        }
+        return Ok(());
+    }
+    writer.write_varint32(field.tag);
+    write_value(writer, registry, field, value)
+}
+
+/// Writes a single scalar or enum value without its tag
+fn write_value(
+    writer: &mut WriterImpl,
+    registry: &Registry,
+    field: &FieldDescriptor,
+    value: JsUnknown,
+) -> Result<()> {
+    match field.field_type {
+        FieldType::Scalar(scalar) => write_scalar(writer, scalar, value),
+        FieldType::Enum(index) => {
+            let number = if value.get_type()? == ValueType::String {
+                let name = string_of(value)?;
+                registry.enum_at(index).value_of(&name).ok_or_else(|| {
+                    ProtoError::new(
+                        ErrorKind::TypeMismatch,
+                        format!("enum value expected, got '{}'", name),
+                    )
+                })?
+            } else {
+                number_of(value)?.get_int32()?
+            };
+            write_int32(writer, number);
+            Ok(())
+        }
+        FieldType::Message(_) => Err(type_error("scalar")),
+    }
+}
+
+fn write_scalar(writer: &mut WriterImpl, scalar: ScalarType, value: JsUnknown) -> Result<()> {
+    match scalar {
+        ScalarType::Int32 => write_int32(writer, number_of(value)?.get_int32()?),
+        ScalarType::Uint32 => writer.write_varint32(number_of(value)?.get_uint32()?),
+        ScalarType::Sint32 => writer.write_sint32(number_of(value)?.get_int32()?),
+        ScalarType::Fixed32 => writer.write_fixed32(number_of(value)?.get_uint32()?),
+        ScalarType::Sfixed32 => writer.write_fixed32(number_of(value)?.get_int32()? as u32),
+        ScalarType::Int64 | ScalarType::Uint64 => writer.write_varint64(long_bits(value)?),
+        ScalarType::Sint64 => writer.write_sint64(long_bits(value)? as i64),
+        ScalarType::Fixed64 | ScalarType::Sfixed64 => {
+            writer.write_fixed64(long_bits(value)?)
+        }
+        ScalarType::Bool => {
+            let flag = value.coerce_to_bool()?.get_value()?;
+            writer.write_varint32(if flag { 1 } else { 0 });
+        }
+        ScalarType::Float => writer.write_float(number_of(value)?.get_double()? as f32),
+        ScalarType::Double => writer.write_double(number_of(value)?.get_double()?),
+        ScalarType::String => {
+            if value.get_type()? != ValueType::String {
+                return Err(type_error("string"));
+            }
+            let bytes = utf8::encode(unsafe { value.cast() }, writer.utf8())?;
+            writer.write_varint32(bytes.len() as u32);
+            writer.write_bytes(&bytes);
+        }
+        ScalarType::Bytes => write_bytes_value(writer, value)?,
+    }
+    Ok(())
+}
+
+#[inline]
+fn write_int32(writer: &mut WriterImpl, value: i32) {
+    if value < 0 {
+        // Negative int32 is encoded as 10-byte varint
+        writer.write_varint64(value as i64 as u64);
+    } else {
+        writer.write_varint32(value as u32);
+    }
+}
+
+fn number_of(value: JsUnknown) -> Result<JsNumber> {
+    match value.get_type()? {
+        ValueType::Number => Ok(unsafe { value.cast() }),
+        ValueType::Boolean | ValueType::String => Ok(value.coerce_to_number()?),
+        _ => Err(type_error("number")),
+    }
+}
+
+fn string_of(value: JsUnknown) -> Result<String> {
+    let value: JsString = unsafe { value.cast() };
+    Ok(value.into_utf8()?.into_owned()?)
+}
+
+/// Reads a 64-bit value from a number, BigInt, decimal string or Long-like `{ low, high }` object
+fn long_bits(value: JsUnknown) -> Result<u64> {
+    long::bits_of(&value)?.ok_or_else(|| type_error("integer|Long|BigInt"))
+}
+
+fn write_bytes_value(writer: &mut WriterImpl, value: JsUnknown) -> Result<()> {
+    if value.is_buffer()? {
+        let buffer: JsBuffer = unsafe { value.cast() };
+        let data = buffer.into_value()?;
+        writer.write_varint32(data.len() as u32);
+        writer.write_bytes(&data);
+    } else if value.is_typedarray()? {
+        let array: JsTypedArray = unsafe { value.cast() };
+        let data = array.into_value()?;
+        let bytes: &[u8] = data.as_ref();
+        writer.write_varint32(bytes.len() as u32);
+        writer.write_bytes(bytes);
+    } else if value.get_type()? == ValueType::String {
+        let bytes =
+            base64::decode(&string_of(value)?).ok_or_else(|| type_error("base64 string"))?;
+        writer.write_varint32(bytes.len() as u32);
+        writer.write_bytes(&bytes);
+    } else if value.is_array()? {
+        let array: JsObject = unsafe { value.cast() };
+        let len = array.get_array_length()?;
+        let mut bytes = Vec::with_capacity(len as usize);
+        for i in 0..len {
+            bytes.push(array.get_element::<JsNumber>(i)?.get_uint32()? as u8);
+        }
+        writer.write_varint32(bytes.len() as u32);
+        writer.write_bytes(&bytes);
+    } else {
+        return Err(type_error("buffer"));
+    }
+    Ok(())
+}
+
+/// Map keys arrive as JS property names and are converted back to the declared key type
+fn write_map_key(writer: &mut WriterImpl, key_type: ScalarType, key: &str) -> Result<()> {
+    let invalid = || {
+        ProtoError::new(
+            ErrorKind::TypeMismatch,
+            format!("invalid {} map key '{}'", key_type.name(), key),
+        )
+    };
+    match key_type {
+        ScalarType::String => {
+            writer.write_varint32(key.len() as u32);
+            writer.write_bytes(key.as_bytes());
+        }
+        ScalarType::Bool => writer.write_varint32(if key == "true" { 1 } else { 0 }),
+        ScalarType::Int32 => write_int32(writer, key.parse().map_err(|_| invalid())?),
+        ScalarType::Sint32 => writer.write_sint32(key.parse().map_err(|_| invalid())?),
+        ScalarType::Sfixed32 => {
+            writer.write_fixed32(key.parse::<i32>().map_err(|_| invalid())? as u32)
+        }
+        ScalarType::Uint32 => writer.write_varint32(key.parse().map_err(|_| invalid())?),
+        ScalarType::Fixed32 => writer.write_fixed32(key.parse().map_err(|_| invalid())?),
+        ScalarType::Int64
+        | ScalarType::Uint64
+        | ScalarType::Fixed64
+        | ScalarType::Sfixed64
+        | ScalarType::Sint64 => {
+            let bits = key
+                .parse::<u64>()
+                .or_else(|_| key.parse::<i64>().map(|v| v as u64))
+                .map_err(|_| invalid())?;
+            match key_type {
+                ScalarType::Sint64 => writer.write_sint64(bits as i64),
+                ScalarType::Fixed64 | ScalarType::Sfixed64 => writer.write_fixed64(bits),
+                _ => writer.write_varint64(bits),
+            }
+        }
+        ScalarType::Double | ScalarType::Float | ScalarType::Bytes => return Err(invalid()),
+    }
+    Ok(())
+}
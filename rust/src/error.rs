@@ -0,0 +1,207 @@
+use napi::Env;
+use napi::JsError;
+use napi::JsObject;
+use napi::JsRangeError;
+use napi::JsUnknown;
+use napi::Status;
+
+/// What went wrong, exposed to JS as the error's `code`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// A read past the end of the buffer or of the current limit, thrown as a `RangeError` like protobuf.js does
+    Truncated,
+    InvalidWireType,
+    InvalidFieldNumber,
+    InvalidUtf8,
+    /// A varint longer than 10 bytes
+    VarintOverflow,
+    /// One of the `ReaderOptions` safety limits
+    LimitExceeded,
+    /// A JS value of the wrong type for the field or operand
+    TypeMismatch,
+    MissingRequired,
+    /// Input rejected by strict mode, which names the `rule` that failed
+    NonCanonical,
+    /// Input that is otherwise structurally invalid, like a missing end group tag
+    Malformed,
+    /// A failure of a napi call or of JS code, which is rethrown as is
+    Js,
+}
+
+impl ErrorKind {
+    pub fn code(self) -> &'static str {
+        match self {
+            ErrorKind::Truncated => "TRUNCATED",
+            ErrorKind::InvalidWireType => "INVALID_WIRE_TYPE",
+            ErrorKind::InvalidFieldNumber => "INVALID_FIELD_NUMBER",
+            ErrorKind::InvalidUtf8 => "INVALID_UTF8",
+            ErrorKind::VarintOverflow => "VARINT_OVERFLOW",
+            ErrorKind::LimitExceeded => "LIMIT_EXCEEDED",
+            ErrorKind::TypeMismatch => "TYPE_MISMATCH",
+            ErrorKind::MissingRequired => "MISSING_REQUIRED",
+            ErrorKind::NonCanonical => "NON_CANONICAL",
+            ErrorKind::Malformed => "MALFORMED",
+            ErrorKind::Js => "JS",
+        }
+    }
+}
+
+/// One step of a field path
+#[derive(Debug)]
+enum Segment {
+    Field(String),
+    /// A repeated field index or a map key, rendered in brackets
+    Index(String),
+}
+
+/// An error of the native codec. It carries the offset in the input where it
+/// occurred and, on schema-aware paths, the field path leading to it.
+#[derive(Debug)]
+pub struct ProtoError {
+    pub kind: ErrorKind,
+    message: String,
+    offset: Option<usize>,
+    /// The strict mode rule that failed
+    rule: Option<&'static str>,
+    /// Innermost segment first, as they are added while the error propagates
+    path: Vec<Segment>,
+    source: Option<Box<napi::Error>>,
+}
+
+pub type Result<T> = std::result::Result<T, ProtoError>;
+
+impl ProtoError {
+    pub fn new(kind: ErrorKind, message: String) -> Self {
+        ProtoError {
+            kind,
+            message,
+            offset: None,
+            rule: None,
+            path: Vec::new(),
+            source: None,
+        }
+    }
+
+    pub fn at(kind: ErrorKind, offset: usize, message: String) -> Self {
+        ProtoError {
+            offset: Some(offset),
+            ..ProtoError::new(kind, message)
+        }
+    }
+
+    /// A strict mode failure of `rule`, one of `non_minimal_varint`, `overlong_varint`,
+    /// `wire_type_mismatch`, `duplicate_field` or `trailing_garbage`
+    pub fn non_canonical(rule: &'static str, offset: usize, message: String) -> Self {
+        ProtoError {
+            rule: Some(rule),
+            ..ProtoError::at(ErrorKind::NonCanonical, offset, message)
+        }
+    }
+
+    /// Sets the offset if the error doesn't have one yet
+    pub fn with_offset(mut self, offset: usize) -> Self {
+        self.offset.get_or_insert(offset);
+        self
+    }
+
+    /// Prefixes the message with the operation or context it occurred in
+    pub fn prefixed(mut self, context: &str) -> Self {
+        self.message = format!("{}: {}", context, self.message);
+        self
+    }
+
+    /// Prefixes the path with a field name
+    pub fn in_field(mut self, name: &str) -> Self {
+        self.path.push(Segment::Field(name.to_owned()));
+        self
+    }
+
+    /// Prefixes the path with an element index or map key
+    pub fn in_index(mut self, index: impl ToString) -> Self {
+        self.path.push(Segment::Index(index.to_string()));
+        self
+    }
+
+    /// The field path like `order.items[3].sku`, empty outside schema-aware paths
+    pub fn path(&self) -> String {
+        let mut path = String::new();
+        for segment in self.path.iter().rev() {
+            match segment {
+                Segment::Field(name) => {
+                    if !path.is_empty() {
+                        path.push('.');
+                    }
+                    path.push_str(name);
+                }
+                Segment::Index(index) => {
+                    path.push('[');
+                    path.push_str(index);
+                    path.push(']');
+                }
+            }
+        }
+        path
+    }
+
+    /// The message thrown to JS, prefixed with the path when there is one
+    pub fn message(&self) -> String {
+        if self.path.is_empty() {
+            self.message.clone()
+        } else {
+            format!("{}: {}", self.path(), self.message)
+        }
+    }
+
+    /// Converts to a JS error object with `code`, `offset` and `path` properties.
+    /// Napi and JS failures are returned as the error.
+    pub fn into_js(self, env: &Env) -> napi::Result<JsUnknown> {
+        if let Some(source) = self.source {
+            return Err(*source);
+        }
+        let error = napi::Error::new(Status::GenericFailure, self.message());
+        let object = match self.kind {
+            ErrorKind::Truncated => JsRangeError::from(error).into_unknown(*env),
+            _ => JsError::from(error).into_unknown(*env),
+        };
+        let mut object: JsObject = unsafe { object.cast() };
+        object.set_named_property("code", env.create_string(self.kind.code())?)?;
+        if let Some(offset) = self.offset {
+            object.set_named_property("offset", env.create_uint32(offset as u32)?)?;
+        }
+        if !self.path.is_empty() {
+            object.set_named_property("path", env.create_string(&self.path())?)?;
+        }
+        if let Some(rule) = self.rule {
+            object.set_named_property("rule", env.create_string(rule)?)?;
+        }
+        Ok(object.into_unknown())
+    }
+
+    /// Converts to a napi error that throws the object of `into_js`
+    pub fn into_napi(self, env: &Env) -> napi::Error {
+        match self.into_js(env) {
+            Ok(object) => napi::Error::from(object),
+            Err(error) => error,
+        }
+    }
+}
+
+impl From<napi::Error> for ProtoError {
+    fn from(error: napi::Error) -> Self {
+        ProtoError {
+            source: Some(Box::new(error)),
+            ..ProtoError::new(ErrorKind::Js, String::new())
+        }
+    }
+}
+
+/// Converts results of the native codec at the napi boundary
+pub trait IntoNapi<T> {
+    fn into_napi(self, env: &Env) -> napi::Result<T>;
+}
+
+impl<T> IntoNapi<T> for Result<T> {
+    fn into_napi(self, env: &Env) -> napi::Result<T> {
+        self.map_err(|error| error.into_napi(env))
+    }
+}
@@ -0,0 +1,1192 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fmt::LowerExp;
+use std::fmt::Write as _;
+
+use napi_derive::napi;
+use serde_json::{Map, Value};
+
+use crate::base64;
+use crate::descriptor::{
+    json_name_of, FieldDescriptor, FieldType, MessageDescriptor, Registry, ScalarType,
+    WellKnown,
+};
+use crate::error::{ErrorKind, ProtoError, Result};
+use crate::reader::ReaderImpl;
+use crate::scan;
+use crate::utf8::Utf8Policy;
+use crate::wkt;
+use crate::writer::WriterImpl;
+
+/// Options of `binaryToJson`
+#[napi(object)]
+#[derive(Default)]
+pub struct BinaryToJsonOptions {
+    /// Also print fields without presence that hold their default value, and empty repeated and map fields
+    pub emit_defaults: Option<bool>,
+    /// Print enum values as numbers instead of names
+    pub enums_as_ints: Option<bool>,
+    /// Use the field names of the schema instead of the JSON names
+    pub use_proto_names: Option<bool>,
+    /// Deepest nesting of sub-messages and groups, 100 by default
+    pub max_depth: Option<u32>,
+    /// Largest input accepted, unlimited by default
+    pub max_message_size: Option<u32>,
+    /// Longest bytes or string value, unlimited by default
+    pub max_bytes_length: Option<u32>,
+    /// Most fields read, unlimited by default
+    pub max_fields: Option<u32>,
+    /// `"strict"` (default) or `"replace"` for strings that aren't valid UTF-8.
+    /// JSON strings can't hold raw bytes, so `"bytes"` replaces as well.
+    pub utf8: Option<String>,
+}
+
+/// Options of `jsonToBinary`
+#[napi(object)]
+#[derive(Default)]
+pub struct JsonToBinaryOptions {
+    /// Skip fields and enum value names the schema doesn't know instead of failing
+    pub ignore_unknown_fields: Option<bool>,
+    /// `"strict"` (default) rejects lone surrogates in the JSON text, `"replace"` and `"bytes"` replace them
+    pub utf8: Option<String>,
+}
+
+const NULL_VALUE: &str = "google.protobuf.NullValue";
+
+fn type_error(expected: &str, value: &Value) -> ProtoError {
+    ProtoError::new(
+        ErrorKind::TypeMismatch,
+        format!("{} expected, got {}", expected, value),
+    )
+}
+
+pub fn wire_type_error(field: &FieldDescriptor, wire_type: u32, offset: usize) -> ProtoError {
+    ProtoError::at(
+        ErrorKind::InvalidWireType,
+        offset,
+        format!(
+            "invalid wire type {} for field {} at offset {}",
+            wire_type, field.name, offset
+        ),
+    )
+}
+
+/// A value located by `scan`: the encoded bytes of varints and fixed values,
+/// the payload of length-delimited values and the contents of groups
+#[derive(Clone, Copy)]
+pub struct Span {
+    pub wire_type: u32,
+    pub offset: usize,
+    pub length: usize,
+}
+
+impl Span {
+    /// The contents of a message given by its payload
+    pub fn payload(offset: usize, length: usize) -> Span {
+        Span {
+            wire_type: 2,
+            offset,
+            length,
+        }
+    }
+}
+
+/// Groups the fields found in `spans`, which are merged like repeated occurrences
+/// of a message on the wire, by field index. Only the last member of a oneof is
+/// kept. Fields the message doesn't know go to `unknown` when given.
+pub fn collect(
+    reader: &mut ReaderImpl,
+    message: &MessageDescriptor,
+    spans: &[Span],
+    mut unknown: Option<&mut Vec<(u32, Span)>>,
+) -> Result<Vec<Vec<Span>>> {
+    let mut table = Vec::new();
+    for span in spans {
+        reader.seek(span.offset);
+        reader.push_limit(span.length)?;
+        let result = scan::scan(reader, false, &mut table);
+        reader.pop_limit()?;
+        result?;
+    }
+    let mut fields = vec![Vec::new(); message.fields.len()];
+    let mut oneofs = vec![None; message.oneofs.len()];
+    for row in table.chunks_exact(4) {
+        let span = Span {
+            wire_type: row[1],
+            offset: row[2] as usize,
+            length: row[3] as usize,
+        };
+        match message.field_index(row[0]) {
+            Some(index) => {
+                if let Some(oneof) = message.fields[index].oneof {
+                    oneofs[oneof] = Some(index);
+                }
+                fields[index].push(span);
+            }
+            None => {
+                if let Some(unknown) = unknown.as_deref_mut() {
+                    unknown.push((row[0], span));
+                }
+            }
+        }
+    }
+    for (index, field) in message.fields.iter().enumerate() {
+        if let Some(oneof) = field.oneof {
+            if oneofs[oneof] != Some(index) {
+                fields[index].clear();
+            }
+        }
+    }
+    Ok(fields)
+}
+
+/// Whether a value holds the zero default of implicit presence fields
+pub fn is_zero(reader: &mut ReaderImpl, span: &Span) -> Result<bool> {
+    Ok(match span.wire_type {
+        0 => {
+            reader.seek(span.offset);
+            reader.read_varint64()? == 0
+        }
+        1 => {
+            reader.seek(span.offset);
+            reader.read_fixed64()? == 0
+        }
+        5 => {
+            reader.seek(span.offset);
+            reader.read_fixed32()? == 0
+        }
+        _ => span.length == 0,
+    })
+}
+
+/// Appends `text` as a quoted JSON string
+fn push_string(out: &mut String, text: &str) {
+    out.push_str(&Value::from(text).to_string());
+}
+
+/// Finite numbers print as JSON numbers, with an exponent outside [1e-7, 1e21) like JS does
+fn push_float<T: Copy + Into<f64> + Display + LowerExp>(out: &mut String, value: T) {
+    let double: f64 = value.into();
+    if double.is_nan() {
+        out.push_str("\"NaN\"");
+    } else if double.is_infinite() {
+        out.push_str(if double > 0.0 { "\"Infinity\"" } else { "\"-Infinity\"" });
+    } else if double != 0.0 && (double.abs() < 1e-7 || double.abs() >= 1e21) {
+        let _ = write!(out, "{:e}", value);
+    } else {
+        let _ = write!(out, "{}", value);
+    }
+}
+
+/// Prints binary messages as proto3 JSON
+pub struct Printer<'a> {
+    registry: &'a Registry,
+    emit_defaults: bool,
+    enums_as_ints: bool,
+    proto_names: bool,
+    utf8: Utf8Policy,
+    out: String,
+}
+
+impl<'a> Printer<'a> {
+    pub fn new(registry: &'a Registry, options: BinaryToJsonOptions) -> napi::Result<Self> {
+        let utf8 = match Utf8Policy::from_option(options.utf8.as_deref())? {
+            Utf8Policy::Strict => Utf8Policy::Strict,
+            _ => Utf8Policy::Replace,
+        };
+        Ok(Printer {
+            registry,
+            emit_defaults: options.emit_defaults.unwrap_or(false),
+            enums_as_ints: options.enums_as_ints.unwrap_or(false),
+            proto_names: options.use_proto_names.unwrap_or(false),
+            utf8,
+            out: String::new(),
+        })
+    }
+
+    /// Prints the message between the reader's position and its end
+    pub fn print(mut self, reader: &mut ReaderImpl, message: &MessageDescriptor) -> Result<String> {
+        let span = Span::payload(reader.pos(), reader.len() - reader.pos());
+        self.contents(reader, message, &[span])?;
+        Ok(self.out)
+    }
+
+    /// Prints a nested message
+    fn message(&mut self, reader: &mut ReaderImpl, message: &MessageDescriptor, spans: &[Span]) -> Result<()> {
+        reader.descend()?;
+        let result = self.contents(reader, message, spans);
+        reader.ascend();
+        result
+    }
+
+    fn contents(&mut self, reader: &mut ReaderImpl, message: &MessageDescriptor, spans: &[Span]) -> Result<()> {
+        match message.well_known {
+            Some(well_known) => self.well_known(reader, message, well_known, spans),
+            None => {
+                self.out.push('{');
+                let result = self.fields(reader, message, spans, &mut true);
+                self.out.push('}');
+                result
+            }
+        }
+    }
+
+    /// Prints the members of a JSON object without the braces
+    fn fields(
+        &mut self,
+        reader: &mut ReaderImpl,
+        message: &MessageDescriptor,
+        spans: &[Span],
+        first: &mut bool,
+    ) -> Result<()> {
+        let fields = collect(reader, message, spans, None)?;
+        for (field, spans) in message.fields.iter().zip(&fields) {
+            self.field(reader, field, spans, first)
+                .map_err(|e| e.in_field(&field.name))?;
+        }
+        Ok(())
+    }
+
+    fn key(&mut self, field: &FieldDescriptor, first: &mut bool) {
+        if !*first {
+            self.out.push(',');
+        }
+        *first = false;
+        // Extension fields keep their `[pkg.ext]` form either way
+        let name = if self.proto_names && !field.name.starts_with('.') {
+            &field.name
+        } else {
+            &field.json_name
+        };
+        push_string(&mut self.out, name);
+        self.out.push(':');
+    }
+
+    fn field(&mut self, reader: &mut ReaderImpl, field: &FieldDescriptor, spans: &[Span], first: &mut bool) -> Result<()> {
+        if field.is_map() {
+            if !spans.is_empty() || self.emit_defaults {
+                self.key(field, first);
+                self.map(reader, field, spans)?;
+            }
+        } else if field.is_repeated() {
+            if !spans.is_empty() || self.emit_defaults {
+                self.key(field, first);
+                self.repeated(reader, field, spans)?;
+            }
+        } else if let FieldType::Message(index) = field.field_type {
+            if !spans.is_empty() {
+                for span in spans {
+                    if !matches!(span.wire_type, 2 | 3) {
+                        return Err(wire_type_error(field, span.wire_type, span.offset));
+                    }
+                }
+                self.key(field, first);
+                self.message(reader, self.registry.message_at(index), spans)?;
+            }
+        } else {
+            match spans.last() {
+                Some(span) if field.has_presence || self.emit_defaults || !is_zero(reader, span)? => {
+                    self.key(field, first);
+                    self.single(reader, field, span)?;
+                }
+                Some(_) => {}
+                None if self.emit_defaults && !field.has_presence => {
+                    self.key(field, first);
+                    self.zero(field);
+                }
+                None => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Prints the zero default of a field without presence
+    fn zero(&mut self, field: &FieldDescriptor) {
+        match field.field_type {
+            FieldType::Scalar(scalar) => self.out.push_str(match scalar {
+                ScalarType::Int64
+                | ScalarType::Uint64
+                | ScalarType::Sint64
+                | ScalarType::Fixed64
+                | ScalarType::Sfixed64 => "\"0\"",
+                ScalarType::Bool => "false",
+                ScalarType::String | ScalarType::Bytes => "\"\"",
+                _ => "0",
+            }),
+            FieldType::Enum(index) => self.enum_value(index, 0),
+            FieldType::Message(_) => self.out.push_str("{}"),
+        }
+    }
+
+    fn repeated(&mut self, reader: &mut ReaderImpl, field: &FieldDescriptor, spans: &[Span]) -> Result<()> {
+        self.out.push('[');
+        let mut len = 0;
+        for span in spans {
+            if span.wire_type == 2 && field.is_packable() {
+                reader.seek(span.offset);
+                reader.push_limit(span.length)?;
+                let result = self.packed(reader, field, &mut len);
+                reader.pop_limit()?;
+                result?;
+            } else {
+                if len > 0 {
+                    self.out.push(',');
+                }
+                match field.field_type {
+                    FieldType::Message(index) => {
+                        if !matches!(span.wire_type, 2 | 3) {
+                            return Err(wire_type_error(field, span.wire_type, span.offset).in_index(len));
+                        }
+                        self.message(reader, self.registry.message_at(index), &[*span])
+                    }
+                    _ => self.single(reader, field, span),
+                }
+                .map_err(|e| e.in_index(len))?;
+                len += 1;
+            }
+        }
+        self.out.push(']');
+        Ok(())
+    }
+
+    fn packed(&mut self, reader: &mut ReaderImpl, field: &FieldDescriptor, len: &mut usize) -> Result<()> {
+        while !reader.at_end() {
+            if *len > 0 {
+                self.out.push(',');
+            }
+            match field.field_type {
+                FieldType::Scalar(scalar) => self.scalar(reader, scalar, 0),
+                FieldType::Enum(index) => {
+                    let value = reader.read_varint64()? as i32;
+                    self.enum_value(index, value);
+                    Ok(())
+                }
+                FieldType::Message(_) => unreachable!("messages aren't packable"),
+            }
+            .map_err(|e| e.in_index(*len))?;
+            *len += 1;
+        }
+        Ok(())
+    }
+
+    /// Prints a single scalar or enum value
+    fn single(&mut self, reader: &mut ReaderImpl, field: &FieldDescriptor, span: &Span) -> Result<()> {
+        if span.wire_type != field.value_wire_type() {
+            return Err(wire_type_error(field, span.wire_type, span.offset));
+        }
+        reader.seek(span.offset);
+        match field.field_type {
+            FieldType::Scalar(scalar) => self.scalar(reader, scalar, span.length),
+            FieldType::Enum(index) => {
+                let value = reader.read_varint64()? as i32;
+                self.enum_value(index, value);
+                Ok(())
+            }
+            FieldType::Message(index) => self.message(reader, self.registry.message_at(index), &[*span]),
+        }
+    }
+
+    /// Prints the scalar at the reader's position; `length` is the payload length of strings and bytes
+    fn scalar(&mut self, reader: &mut ReaderImpl, scalar: ScalarType, length: usize) -> Result<()> {
+        let out = &mut self.out;
+        match scalar {
+            ScalarType::Int32 => write!(out, "{}", reader.read_varint64()? as i32),
+            ScalarType::Uint32 => write!(out, "{}", reader.read_varint32()?),
+            ScalarType::Sint32 => write!(out, "{}", reader.read_sint32()?),
+            ScalarType::Fixed32 => write!(out, "{}", reader.read_fixed32()?),
+            ScalarType::Sfixed32 => write!(out, "{}", reader.read_sfixed32()?),
+            ScalarType::Int64 => write!(out, "\"{}\"", reader.read_varint64()? as i64),
+            ScalarType::Uint64 => write!(out, "\"{}\"", reader.read_varint64()?),
+            ScalarType::Sint64 => write!(out, "\"{}\"", reader.read_sint64()?),
+            ScalarType::Fixed64 => write!(out, "\"{}\"", reader.read_fixed64()?),
+            ScalarType::Sfixed64 => write!(out, "\"{}\"", reader.read_sfixed64()?),
+            ScalarType::Bool => write!(out, "{}", reader.read_varint64()? != 0),
+            ScalarType::Float => {
+                push_float(out, reader.read_float()?);
+                Ok(())
+            }
+            ScalarType::Double => {
+                push_float(out, reader.read_double()?);
+                Ok(())
+            }
+            ScalarType::String => {
+                let text = reader.read_text(length, self.utf8)?.into_lossy();
+                push_string(out, &text);
+                Ok(())
+            }
+            ScalarType::Bytes => {
+                let data = reader.read_slice(length)?;
+                out.push('"');
+                out.push_str(&base64::encode(data));
+                out.push('"');
+                Ok(())
+            }
+        }
+        .expect("writing to a String can't fail");
+        Ok(())
+    }
+
+    fn enum_value(&mut self, index: usize, value: i32) {
+        let descriptor = self.registry.enum_at(index);
+        if descriptor.full_name == NULL_VALUE {
+            self.out.push_str("null");
+            return;
+        }
+        match descriptor.name_of(value) {
+            Some(name) if !self.enums_as_ints => push_string(&mut self.out, name),
+            _ => self.out.push_str(&value.to_string()),
+        }
+    }
+
+    /// Reads a map key, which JSON always represents as a string
+    fn map_key(&self, reader: &mut ReaderImpl, key_type: ScalarType, span: &Span) -> Result<String> {
+        if span.wire_type != key_type.wire_type() {
+            return Err(ProtoError::at(
+                ErrorKind::InvalidWireType,
+                span.offset,
+                format!("invalid wire type {} for map key at offset {}", span.wire_type, span.offset),
+            ));
+        }
+        reader.seek(span.offset);
+        Ok(match key_type {
+            ScalarType::String => reader.read_text(span.length, self.utf8)?.into_lossy(),
+            ScalarType::Bool => (reader.read_varint64()? != 0).to_string(),
+            ScalarType::Int32 => (reader.read_varint64()? as i32).to_string(),
+            ScalarType::Uint32 => reader.read_varint32()?.to_string(),
+            ScalarType::Sint32 => reader.read_sint32()?.to_string(),
+            ScalarType::Fixed32 => reader.read_fixed32()?.to_string(),
+            ScalarType::Sfixed32 => reader.read_sfixed32()?.to_string(),
+            ScalarType::Int64 => (reader.read_varint64()? as i64).to_string(),
+            ScalarType::Uint64 => reader.read_varint64()?.to_string(),
+            ScalarType::Sint64 => reader.read_sint64()?.to_string(),
+            ScalarType::Fixed64 => reader.read_fixed64()?.to_string(),
+            ScalarType::Sfixed64 => reader.read_sfixed64()?.to_string(),
+            ScalarType::Double | ScalarType::Float | ScalarType::Bytes => {
+                return Err(ProtoError::new(
+                    ErrorKind::Malformed,
+                    format!("invalid key type: {}", key_type.name()),
+                ))
+            }
+        })
+    }
+
+    fn map(&mut self, reader: &mut ReaderImpl, field: &FieldDescriptor, spans: &[Span]) -> Result<()> {
+        let key_type = field.key_type.unwrap_or(ScalarType::String);
+        // Later entries replace earlier ones with the same key but keep their position
+        let mut entries: Vec<(String, Vec<Span>)> = Vec::new();
+        let mut positions: HashMap<String, usize> = HashMap::new();
+        for span in spans {
+            if span.wire_type != 2 {
+                return Err(wire_type_error(field, span.wire_type, span.offset));
+            }
+            let mut table = Vec::new();
+            reader.seek(span.offset);
+            reader.push_limit(span.length)?;
+            let result = scan::scan(reader, false, &mut table);
+            reader.pop_limit()?;
+            result?;
+
+            let mut key = None;
+            let mut values = Vec::new();
+            for row in table.chunks_exact(4) {
+                let found = Span {
+                    wire_type: row[1],
+                    offset: row[2] as usize,
+                    length: row[3] as usize,
+                };
+                match row[0] {
+                    1 => key = Some(found),
+                    2 => values.push(found),
+                    _ => {}
+                }
+            }
+            let key = match key {
+                Some(key) => self.map_key(reader, key_type, &key)?,
+                None if key_type == ScalarType::Bool => "false".to_string(),
+                None if key_type == ScalarType::String => String::new(),
+                None => "0".to_string(),
+            };
+            match positions.get(&key) {
+                Some(&position) => entries[position].1 = values,
+                None => {
+                    positions.insert(key.clone(), entries.len());
+                    entries.push((key, values));
+                }
+            }
+        }
+
+        self.out.push('{');
+        for (i, (key, values)) in entries.iter().enumerate() {
+            if i > 0 {
+                self.out.push(',');
+            }
+            push_string(&mut self.out, key);
+            self.out.push(':');
+            let result = match (field.field_type, values.last()) {
+                (FieldType::Message(index), _) => {
+                    self.message(reader, self.registry.message_at(index), values)
+                }
+                (_, Some(value)) => self.single(reader, field, value),
+                (_, None) => {
+                    self.zero(field);
+                    Ok(())
+                }
+            };
+            result.map_err(|e| {
+                if key_type == ScalarType::String {
+                    e.in_index(format!("\"{}\"", key))
+                } else {
+                    e.in_index(key)
+                }
+            })?;
+        }
+        self.out.push('}');
+        Ok(())
+    }
+
+    /// Reads the last varint of field `number`, 0 when it's missing
+    fn varint(&self, reader: &mut ReaderImpl, message: &MessageDescriptor, fields: &[Vec<Span>], number: u32) -> Result<u64> {
+        match message.field_index(number).and_then(|index| fields[index].last()) {
+            Some(span) if span.wire_type == 0 => {
+                reader.seek(span.offset);
+                reader.read_varint64()
+            }
+            Some(span) => Err(ProtoError::at(
+                ErrorKind::InvalidWireType,
+                span.offset,
+                format!("invalid wire type {} for field {} at offset {}", span.wire_type, number, span.offset),
+            )),
+            None => Ok(0),
+        }
+    }
+
+    fn well_known(
+        &mut self,
+        reader: &mut ReaderImpl,
+        message: &MessageDescriptor,
+        well_known: WellKnown,
+        spans: &[Span],
+    ) -> Result<()> {
+        let fields = collect(reader, message, spans, None)?;
+        let invalid = |value: String| {
+            ProtoError::new(
+                ErrorKind::Malformed,
+                format!("invalid {}: {}", message.full_name, value),
+            )
+        };
+        let field_of = |number: u32| message.field_index(number).map(|index| (&message.fields[index], &fields[index]));
+        match well_known {
+            WellKnown::Timestamp | WellKnown::Duration => {
+                let seconds = self.varint(reader, message, &fields, 1)? as i64;
+                let nanos = self.varint(reader, message, &fields, 2)? as i32;
+                let text = if well_known == WellKnown::Timestamp {
+                    wkt::format_timestamp(seconds, nanos)
+                } else {
+                    wkt::format_duration(seconds, nanos)
+                };
+                let text = text.ok_or_else(|| invalid(format!("seconds {} nanos {}", seconds, nanos)))?;
+                push_string(&mut self.out, &text);
+            }
+            WellKnown::FieldMask => {
+                let mut paths = Vec::new();
+                if let Some((_, spans)) = field_of(1) {
+                    for span in spans {
+                        reader.seek(span.offset);
+                        paths.push(json_name_of(&reader.read_text(span.length, self.utf8)?.into_lossy()));
+                    }
+                }
+                push_string(&mut self.out, &paths.join(","));
+            }
+            WellKnown::Struct | WellKnown::ListValue => match field_of(1) {
+                Some((field, spans)) if field.is_map() => self.map(reader, field, spans)?,
+                Some((field, spans)) if field.is_repeated() => self.repeated(reader, field, spans)?,
+                _ => return Err(invalid("unexpected schema".to_string())),
+            },
+            WellKnown::Value => {
+                let present = message.fields.iter().zip(&fields).find(|(_, spans)| !spans.is_empty());
+                match present {
+                    Some((field, spans)) => {
+                        let span = spans.last().expect("present fields have a span");
+                        if field.number == 2 && span.wire_type == 1 {
+                            reader.seek(span.offset);
+                            let number = reader.read_double()?;
+                            if !number.is_finite() {
+                                return Err(invalid(format!("number {}", number)));
+                            }
+                        }
+                        match field.field_type {
+                            FieldType::Message(index) => {
+                                self.message(reader, self.registry.message_at(index), spans)?
+                            }
+                            _ => self.single(reader, field, span)?,
+                        }
+                    }
+                    None => return Err(invalid("no kind set".to_string())),
+                }
+            }
+            WellKnown::Wrapper => match field_of(1) {
+                Some((field, spans)) => match spans.last() {
+                    Some(span) => self.single(reader, field, span)?,
+                    None => self.zero(field),
+                },
+                None => return Err(invalid("unexpected schema".to_string())),
+            },
+            WellKnown::Any => {
+                let type_url = match field_of(1).and_then(|(_, spans)| spans.last()) {
+                    Some(span) => {
+                        reader.seek(span.offset);
+                        reader.read_text(span.length, self.utf8)?.into_lossy()
+                    }
+                    None => String::new(),
+                };
+                let value = field_of(2).and_then(|(_, spans)| spans.last().copied());
+                if type_url.is_empty() && value.is_none() {
+                    self.out.push_str("{}");
+                    return Ok(());
+                }
+                let inner = self.any_type(&type_url)?;
+                let value = value.map_or(Vec::new(), |span| vec![Span::payload(span.offset, span.length)]);
+                self.out.push_str("{\"@type\":");
+                push_string(&mut self.out, &type_url);
+                if inner.well_known.is_some() {
+                    self.out.push_str(",\"value\":");
+                    self.message(reader, inner, &value)?;
+                } else {
+                    reader.descend()?;
+                    let result = self.fields(reader, inner, &value, &mut false);
+                    reader.ascend();
+                    result?;
+                }
+                self.out.push('}');
+            }
+        }
+        Ok(())
+    }
+
+    /// The registered message of an `Any` type URL
+    fn any_type(&self, type_url: &str) -> Result<&'a MessageDescriptor> {
+        any_type(self.registry, type_url)
+    }
+}
+
+/// The registered message of an `Any` type URL
+pub fn any_type<'a>(registry: &'a Registry, type_url: &str) -> Result<&'a MessageDescriptor> {
+    match registry.resolve_type_url(type_url) {
+        Some(index) => Ok(registry.message_at(index)),
+        None => Err(ProtoError::new(
+            ErrorKind::Malformed,
+            format!("unknown type in google.protobuf.Any: {}", type_url),
+        )),
+    }
+}
+
+/// A scalar converted from JSON or text, ready to be written
+pub enum Wire<'v> {
+    Varint(u64),
+    Fixed32(u32),
+    Fixed64(u64),
+    Bytes(Cow<'v, [u8]>),
+}
+
+impl Wire<'_> {
+    pub fn is_zero(&self) -> bool {
+        match self {
+            Wire::Varint(value) | Wire::Fixed64(value) => *value == 0,
+            Wire::Fixed32(value) => *value == 0,
+            Wire::Bytes(data) => data.is_empty(),
+        }
+    }
+
+    pub fn write(&self, writer: &mut WriterImpl) {
+        match self {
+            Wire::Varint(value) => writer.write_varint64(*value),
+            Wire::Fixed32(value) => writer.write_fixed32(*value),
+            Wire::Fixed64(value) => writer.write_fixed64(*value),
+            Wire::Bytes(data) => {
+                writer.write_varint32(data.len() as u32);
+                writer.write_bytes(data);
+            }
+        }
+    }
+}
+
+/// Reads an integer from a JSON number or a decimal string, which 64-bit values use
+fn integer(value: &Value) -> Result<i128> {
+    let exact = match value {
+        Value::Number(number) => number
+            .as_i64()
+            .map(i128::from)
+            .or_else(|| number.as_u64().map(i128::from))
+            .or_else(|| number.as_f64().and_then(integral)),
+        Value::String(text) => text
+            .parse::<i128>()
+            .ok()
+            .or_else(|| text.parse::<f64>().ok().and_then(integral)),
+        _ => None,
+    };
+    exact.ok_or_else(|| type_error("integer", value))
+}
+
+fn integral(value: f64) -> Option<i128> {
+    if value.is_finite() && value.fract() == 0.0 && value.abs() < 1.9e19 {
+        Some(value as i128)
+    } else {
+        None
+    }
+}
+
+fn in_range<T: TryFrom<i128>>(value: i128, scalar: ScalarType) -> Result<T> {
+    T::try_from(value).map_err(|_| {
+        ProtoError::new(
+            ErrorKind::TypeMismatch,
+            format!("{} value out of range: {}", scalar.name(), value),
+        )
+    })
+}
+
+fn float(value: &Value) -> Result<f64> {
+    match value {
+        Value::Number(number) => number.as_f64(),
+        Value::String(text) => match text.as_str() {
+            "NaN" => Some(f64::NAN),
+            "Infinity" => Some(f64::INFINITY),
+            "-Infinity" => Some(f64::NEG_INFINITY),
+            text => text.parse::<f64>().ok().filter(|number| number.is_finite()),
+        },
+        _ => None,
+    }
+    .ok_or_else(|| type_error("number", value))
+}
+
+/// Converts an integer to one of the integer types, checking its range
+pub fn integer_wire(scalar: ScalarType, value: i128) -> Result<Wire<'static>> {
+    Ok(match scalar {
+        ScalarType::Int32 => Wire::Varint(in_range::<i32>(value, scalar)? as i64 as u64),
+        ScalarType::Uint32 => Wire::Varint(in_range::<u32>(value, scalar)? as u64),
+        ScalarType::Sint32 => {
+            let value = in_range::<i32>(value, scalar)?;
+            Wire::Varint(((value << 1) ^ (value >> 31)) as u32 as u64)
+        }
+        ScalarType::Fixed32 => Wire::Fixed32(in_range::<u32>(value, scalar)?),
+        ScalarType::Sfixed32 => Wire::Fixed32(in_range::<i32>(value, scalar)? as u32),
+        ScalarType::Int64 => Wire::Varint(in_range::<i64>(value, scalar)? as u64),
+        ScalarType::Uint64 => Wire::Varint(in_range::<u64>(value, scalar)?),
+        ScalarType::Sint64 => {
+            let value = in_range::<i64>(value, scalar)?;
+            Wire::Varint(((value << 1) ^ (value >> 63)) as u64)
+        }
+        ScalarType::Fixed64 => Wire::Fixed64(in_range::<u64>(value, scalar)?),
+        ScalarType::Sfixed64 => Wire::Fixed64(in_range::<i64>(value, scalar)? as u64),
+        ScalarType::Float | ScalarType::Double => float_wire(scalar, value as f64)?,
+        ScalarType::Bool | ScalarType::String | ScalarType::Bytes => {
+            return Err(ProtoError::new(
+                ErrorKind::TypeMismatch,
+                format!("{} expected, got {}", scalar.name(), value),
+            ))
+        }
+    })
+}
+
+/// Converts a number to `float` or `double`, rejecting finite values a float can't hold
+pub fn float_wire(scalar: ScalarType, number: f64) -> Result<Wire<'static>> {
+    if scalar == ScalarType::Double {
+        return Ok(Wire::Fixed64(number.to_bits()));
+    }
+    if number.is_finite() && number.abs() > f32::MAX as f64 {
+        return Err(ProtoError::new(
+            ErrorKind::TypeMismatch,
+            format!("float value out of range: {}", number),
+        ));
+    }
+    Ok(Wire::Fixed32((number as f32).to_bits()))
+}
+
+/// Converts a JSON value to `scalar`
+fn scalar(scalar: ScalarType, value: &Value) -> Result<Wire<'_>> {
+    Ok(match scalar {
+        ScalarType::Float | ScalarType::Double => float_wire(scalar, float(value)?)?,
+        ScalarType::Bool => match value {
+            Value::Bool(flag) => Wire::Varint(*flag as u64),
+            _ => return Err(type_error("boolean", value)),
+        },
+        ScalarType::String => match value {
+            Value::String(text) => Wire::Bytes(Cow::Borrowed(text.as_bytes())),
+            _ => return Err(type_error("string", value)),
+        },
+        ScalarType::Bytes => match value {
+            Value::String(text) => Wire::Bytes(Cow::Owned(
+                base64::decode(text).ok_or_else(|| type_error("base64 string", value))?,
+            )),
+            _ => return Err(type_error("base64 string", value)),
+        },
+        _ => integer_wire(scalar, integer(value)?)?,
+    })
+}
+
+/// Converts a JSON object key to a map key of `key_type`
+fn map_key(key_type: ScalarType, key: &str) -> Result<Wire<'_>> {
+    let value = match (key_type, key) {
+        (ScalarType::String, _) => return Ok(Wire::Bytes(Cow::Borrowed(key.as_bytes()))),
+        (ScalarType::Bool, "true") => Value::Bool(true),
+        (ScalarType::Bool, "false") => Value::Bool(false),
+        _ => Value::String(key.to_string()),
+    };
+    Ok(match scalar(key_type, &value)? {
+        Wire::Varint(value) => Wire::Varint(value),
+        Wire::Fixed32(value) => Wire::Fixed32(value),
+        Wire::Fixed64(value) => Wire::Fixed64(value),
+        Wire::Bytes(_) => unreachable!("only string keys are length-delimited"),
+    })
+}
+
+/// Parses proto3 JSON into binary messages, writing fields in number order
+pub struct Parser<'a> {
+    registry: &'a Registry,
+    ignore_unknown: bool,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(registry: &'a Registry, options: &JsonToBinaryOptions) -> Self {
+        Parser {
+            registry,
+            ignore_unknown: options.ignore_unknown_fields.unwrap_or(false),
+        }
+    }
+
+    pub fn parse(&self, writer: &mut WriterImpl, message: &MessageDescriptor, text: &[u8]) -> Result<()> {
+        let value: Value = serde_json::from_slice(text).map_err(|e| {
+            ProtoError::new(ErrorKind::Malformed, format!("invalid JSON: {}", e))
+        })?;
+        self.message(writer, message, &value)
+    }
+
+    /// Writes the fields of `message` given as JSON, without a tag or length
+    fn message(&self, writer: &mut WriterImpl, message: &MessageDescriptor, value: &Value) -> Result<()> {
+        if let Some(well_known) = message.well_known {
+            return self.well_known(writer, message, well_known, value);
+        }
+        match value {
+            Value::Object(object) => self.object(writer, message, object, false),
+            _ => Err(type_error("object", value)),
+        }
+    }
+
+    fn find_field(message: &MessageDescriptor, key: &str) -> Option<usize> {
+        let proto_name = if key.contains('_') { Some(json_name_of(key)) } else { None };
+        message.fields.iter().position(|field| {
+            field.json_name == key
+                || field.name == key
+                || proto_name.as_deref() == Some(field.name.as_str())
+        })
+    }
+
+    /// Writes the members of `object`; `any` skips the `@type` member of an expanded `Any`
+    fn object(&self, writer: &mut WriterImpl, message: &MessageDescriptor, object: &Map<String, Value>, any: bool) -> Result<()> {
+        let mut found: Vec<(usize, &str, &Value)> = Vec::with_capacity(object.len());
+        for (key, value) in object {
+            if any && key == "@type" {
+                continue;
+            }
+            match Self::find_field(message, key) {
+                Some(index) => found.push((index, key, value)),
+                None if self.ignore_unknown => {}
+                None => {
+                    return Err(ProtoError::new(
+                        ErrorKind::Malformed,
+                        format!("unknown field '{}' in {}", key, message.full_name),
+                    ))
+                }
+            }
+        }
+        found.sort_by_key(|(index, _, _)| *index);
+
+        let mut oneofs = vec![None; message.oneofs.len()];
+        for pair in found.windows(2) {
+            if pair[0].0 == pair[1].0 {
+                return Err(ProtoError::new(
+                    ErrorKind::Malformed,
+                    format!("duplicate field '{}'", pair[1].1),
+                ));
+            }
+        }
+        for &(index, key, value) in &found {
+            let field = &message.fields[index];
+            if value.is_null() && !self.accepts_null(field) {
+                continue;
+            }
+            if let Some(oneof) = field.oneof {
+                if let Some(previous) = oneofs[oneof].replace(key) {
+                    return Err(ProtoError::new(
+                        ErrorKind::Malformed,
+                        format!(
+                            "multiple values for oneof {}: '{}' and '{}'",
+                            message.oneofs[oneof].name, previous, key
+                        ),
+                    ));
+                }
+            }
+            self.field(writer, field, value).map_err(|e| e.in_field(&field.name))?;
+        }
+        Ok(())
+    }
+
+    /// `null` clears fields, except for singular `Value` and `NullValue` fields where it's a value
+    fn accepts_null(&self, field: &FieldDescriptor) -> bool {
+        !field.is_repeated() && self.null_is_value(field)
+    }
+
+    fn null_is_value(&self, field: &FieldDescriptor) -> bool {
+        match field.field_type {
+            FieldType::Message(index) => self.registry.message_at(index).well_known == Some(WellKnown::Value),
+            FieldType::Enum(index) => self.registry.enum_at(index).full_name == NULL_VALUE,
+            FieldType::Scalar(_) => false,
+        }
+    }
+
+    fn field(&self, writer: &mut WriterImpl, field: &FieldDescriptor, value: &Value) -> Result<()> {
+        if field.is_map() {
+            let object = match value {
+                Value::Object(object) => object,
+                _ => return Err(type_error("object", value)),
+            };
+            let key_type = field.key_type.unwrap_or(ScalarType::String);
+            for (key, entry) in object {
+                self.map_entry(writer, field, key_type, key, entry).map_err(|e| {
+                    if key_type == ScalarType::String {
+                        e.in_index(format!("\"{}\"", key))
+                    } else {
+                        e.in_index(key)
+                    }
+                })?;
+            }
+        } else if field.is_repeated() {
+            let array = match value {
+                Value::Array(array) => array,
+                _ => return Err(type_error("array", value)),
+            };
+            if field.packed && !array.is_empty() {
+                writer.write_varint32(field.number << 3 | 2);
+                writer.fork();
+                for (i, element) in array.iter().enumerate() {
+                    if let Some(wire) = self.value(field, element).map_err(|e| e.in_index(i))? {
+                        wire.write(writer);
+                    }
+                }
+                writer.ldelim();
+            } else {
+                for (i, element) in array.iter().enumerate() {
+                    if element.is_null() && !self.null_is_value(field) {
+                        return Err(type_error("value", element).in_index(i));
+                    }
+                    self.single(writer, field, element).map_err(|e| e.in_index(i))?;
+                }
+            }
+        } else if let FieldType::Message(_) = field.field_type {
+            self.single(writer, field, value)?;
+        } else if let Some(wire) = self.value(field, value)? {
+            // Fields without presence aren't written when they hold their default
+            if field.has_presence || !wire.is_zero() {
+                writer.write_varint32(field.tag);
+                wire.write(writer);
+            }
+        }
+        Ok(())
+    }
+
+    fn map_entry(&self, writer: &mut WriterImpl, field: &FieldDescriptor, key_type: ScalarType, key: &str, value: &Value) -> Result<()> {
+        if value.is_null() && !self.null_is_value(field) {
+            return Err(type_error("value", value));
+        }
+        writer.write_varint32(field.tag);
+        writer.fork();
+        writer.write_varint32(8 | key_type.wire_type());
+        map_key(key_type, key)?.write(writer);
+        match field.field_type {
+            FieldType::Message(index) => {
+                writer.write_varint32(18);
+                writer.fork();
+                self.message(writer, self.registry.message_at(index), value)?;
+                writer.ldelim();
+            }
+            _ => {
+                if let Some(wire) = self.value(field, value)? {
+                    writer.write_varint32(16 | field.value_wire_type());
+                    wire.write(writer);
+                }
+            }
+        }
+        writer.ldelim();
+        Ok(())
+    }
+
+    /// Writes one value of `field` with its tag
+    fn single(&self, writer: &mut WriterImpl, field: &FieldDescriptor, value: &Value) -> Result<()> {
+        match field.field_type {
+            FieldType::Message(index) => {
+                let message = self.registry.message_at(index);
+                if field.delimited {
+                    writer.write_varint32(field.number << 3 | 3);
+                    self.message(writer, message, value)?;
+                    writer.write_varint32(field.number << 3 | 4);
+                } else {
+                    writer.write_varint32(field.number << 3 | 2);
+                    writer.fork();
+                    self.message(writer, message, value)?;
+                    writer.ldelim();
+                }
+            }
+            _ => {
+                if let Some(wire) = self.value(field, value)? {
+                    writer.write_varint32(field.tag);
+                    wire.write(writer);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Converts a scalar or enum value, `None` for unknown enum names that are ignored
+    fn value<'v>(&self, field: &FieldDescriptor, value: &'v Value) -> Result<Option<Wire<'v>>> {
+        match field.field_type {
+            FieldType::Scalar(scalar_type) => Ok(Some(scalar(scalar_type, value)?)),
+            FieldType::Enum(index) => {
+                let descriptor = self.registry.enum_at(index);
+                let number = match value {
+                    Value::Null if descriptor.full_name == NULL_VALUE => 0,
+                    Value::String(name) => match descriptor.value_of(name) {
+                        Some(number) => number,
+                        None if self.ignore_unknown => return Ok(None),
+                        None => {
+                            return Err(ProtoError::new(
+                                ErrorKind::TypeMismatch,
+                                format!("unknown value '{}' of enum {}", name, descriptor.full_name),
+                            ))
+                        }
+                    },
+                    Value::Number(_) => in_range::<i32>(integer(value)?, ScalarType::Int32)?,
+                    _ => return Err(type_error("enum value", value)),
+                };
+                Ok(Some(Wire::Varint(number as i64 as u64)))
+            }
+            FieldType::Message(_) => Err(type_error("scalar", value)),
+        }
+    }
+
+    fn well_known(&self, writer: &mut WriterImpl, message: &MessageDescriptor, well_known: WellKnown, value: &Value) -> Result<()> {
+        let invalid = || {
+            ProtoError::new(
+                ErrorKind::TypeMismatch,
+                format!("invalid {}: {}", message.full_name, value),
+            )
+        };
+        let field_of = |number: u32| {
+            message
+                .field_index(number)
+                .map(|index| &message.fields[index])
+                .ok_or_else(invalid)
+        };
+        match well_known {
+            WellKnown::Timestamp | WellKnown::Duration => {
+                let text = value.as_str().ok_or_else(invalid)?;
+                let (seconds, nanos) = if well_known == WellKnown::Timestamp {
+                    wkt::parse_timestamp(text)
+                } else {
+                    wkt::parse_duration(text)
+                }
+                .ok_or_else(invalid)?;
+                if seconds != 0 {
+                    writer.write_varint32(8);
+                    writer.write_varint64(seconds as u64);
+                }
+                if nanos != 0 {
+                    writer.write_varint32(16);
+                    writer.write_varint64(nanos as i64 as u64);
+                }
+            }
+            WellKnown::FieldMask => {
+                let text = value.as_str().ok_or_else(invalid)?;
+                for path in text.split(',').filter(|path| !path.is_empty()) {
+                    let mut snake = String::with_capacity(path.len() + 4);
+                    for c in path.chars() {
+                        if c.is_ascii_uppercase() {
+                            snake.push('_');
+                            snake.push(c.to_ascii_lowercase());
+                        } else {
+                            snake.push(c);
+                        }
+                    }
+                    writer.write_varint32(10);
+                    writer.write_varint32(snake.len() as u32);
+                    writer.write_bytes(snake.as_bytes());
+                }
+            }
+            WellKnown::Struct => {
+                if !value.is_object() {
+                    return Err(invalid());
+                }
+                self.field(writer, field_of(1)?, value)?;
+            }
+            WellKnown::ListValue => {
+                if !value.is_array() {
+                    return Err(invalid());
+                }
+                self.field(writer, field_of(1)?, value)?;
+            }
+            WellKnown::Value => {
+                let number = match value {
+                    Value::Null => 1,
+                    Value::Number(_) => 2,
+                    Value::String(_) => 3,
+                    Value::Bool(_) => 4,
+                    Value::Object(_) => 5,
+                    Value::Array(_) => 6,
+                };
+                let field = field_of(number)?;
+                if number == 2 {
+                    writer.write_varint32(17);
+                    writer.write_double(value.as_f64().ok_or_else(invalid)?);
+                } else {
+                    self.single(writer, field, value)?;
+                }
+            }
+            WellKnown::Wrapper => {
+                if value.is_null() {
+                    return Err(invalid());
+                }
+                self.field(writer, field_of(1)?, value)?;
+            }
+            WellKnown::Any => {
+                let object = value.as_object().ok_or_else(invalid)?;
+                if object.is_empty() {
+                    return Ok(());
+                }
+                let type_url = object.get("@type").and_then(Value::as_str).ok_or_else(|| {
+                    ProtoError::new(
+                        ErrorKind::Malformed,
+                        "missing '@type' in google.protobuf.Any".to_string(),
+                    )
+                })?;
+                let inner = any_type(self.registry, type_url)?;
+                writer.write_varint32(10);
+                writer.write_varint32(type_url.len() as u32);
+                writer.write_bytes(type_url.as_bytes());
+                writer.write_varint32(18);
+                writer.fork();
+                if inner.well_known.is_some() {
+                    let packed = object.get("value").ok_or_else(|| {
+                        ProtoError::new(
+                            ErrorKind::Malformed,
+                            format!("missing 'value' for {} in google.protobuf.Any", inner.full_name),
+                        )
+                    })?;
+                    self.message(writer, inner, packed)?;
+                } else {
+                    self.object(writer, inner, object, true)?;
+                }
+                writer.ldelim();
+            }
+        }
+        Ok(())
+    }
+}
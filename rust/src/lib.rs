@@ -2,14 +2,221 @@
 
 use napi::bindgen_prelude::*;
 use napi_derive::napi;
-use napi::Either;
+use napi::JsString;
 use napi::JsUnknown;
 
 mod writer;
 mod reader;
+mod base64;
+mod descriptor;
+mod encoder;
+mod decoder;
+mod opcodes;
+mod long;
+mod tag;
+mod plan;
+mod scan;
+mod packed;
+mod error;
+mod utf8;
+mod wkt;
+mod json;
+mod text;
+mod raw;
+mod protoscope;
+mod descriptor_set;
+mod parse;
+mod pool;
 
 use writer::WriterImpl;
-use reader::ReaderImpl;
+use reader::{DecodeLimits, ReaderImpl, Source};
+use decoder::DecodeOptions;
+use encoder::EncodeOptions;
+use long::LongRepr;
+use tag::Tag;
+use scan::ScanOptions;
+use error::{ErrorKind, IntoNapi, ProtoError};
+use utf8::Utf8Policy;
+use json::{BinaryToJsonOptions, JsonToBinaryOptions};
+use text::{FromTextFormatOptions, ToTextFormatOptions};
+use raw::DecodeRawOptions;
+use parse::ParseProtoOptions;
+
+/// Reads element `index` of an `encodeAll` operation, or of the operation list
+fn operand<T: FromNapiValue>(op: &Array, index: u32) -> error::Result<T> {
+    match op.get::<T>(index) {
+        Ok(Some(value)) => Ok(value),
+        Ok(None) => Err(ProtoError::new(
+            ErrorKind::TypeMismatch,
+            format!("missing operand {}", index),
+        )),
+        Err(e) => Err(ProtoError::new(ErrorKind::TypeMismatch, e.reason)),
+    }
+}
+
+/// Encodes operation `index` of `encodeAll`
+fn encode_op(env: &Env, writer: &mut WriterImpl, operations: &Array, index: u32) -> error::Result<()> {
+    let op: Array = operand(operations, index)?;
+    if op.len() == 0 {
+        return Ok(());
+    }
+    let op_type: String = operand(&op, 0)?;
+
+    // Nesting ops take no value, length prefixes are back-patched natively
+    match op_type.as_str() {
+        "fork" => {
+            writer.fork();
+            return Ok(());
+        }
+        "ldelim" => {
+            writer.ldelim();
+            return Ok(());
+        }
+        "reset" => {
+            writer.reset();
+            return Ok(());
+        }
+        _ => {}
+    }
+    if op.len() < 2 {
+        return Ok(());
+    }
+
+    match op_type.as_str() {
+        "u32" => {
+            let value: u32 = operand(&op, 1)?;
+            writer.write_varint32(value);
+        }
+        "i32" => {
+            let value: i32 = operand(&op, 1)?;
+            // Negative numbers encode as 10 byte varint
+            if value < 0 {
+                writer.write_varint64(value as i64 as u64);
+            } else {
+                writer.write_varint32(value as u32);
+            }
+        }
+        "u64" | "i64" => {
+            // Handle Long.js objects, BigInt, decimal strings and numbers
+            let value: JsUnknown = operand(&op, 1)?;
+            writer.write_varint64(long::expect_bits(&value)?);
+        }
+        "s64" => {
+            let value: JsUnknown = operand(&op, 1)?;
+            writer.write_sint64(long::expect_bits(&value)? as i64);
+        }
+        "bool" => {
+            let value: bool = operand(&op, 1)?;
+            writer.write_varint32(if value { 1 } else { 0 });
+        }
+        "f32" => {
+            let value: u32 = operand(&op, 1)?;
+            writer.write_fixed32(value);
+        }
+        "f64" => {
+            let value: JsUnknown = operand(&op, 1)?;
+            writer.write_fixed64(long::expect_bits(&value)?);
+        }
+        "float" => {
+            let value: f64 = operand(&op, 1)?;
+            writer.write_float(value as f32);
+        }
+        "double" => {
+            let value: f64 = operand(&op, 1)?;
+            writer.write_double(value);
+        }
+        "bytes" => {
+            let buffer: Buffer = operand(&op, 1)?;
+            let bytes = buffer.as_ref();
+            writer.write_varint32(bytes.len() as u32);
+            writer.write_bytes(bytes);
+        }
+        "string" => {
+            let value: JsString = operand(&op, 1)?;
+            let bytes = utf8::encode(value, writer.utf8())?;
+            writer.write_varint32(bytes.len() as u32);
+            writer.write_bytes(&bytes);
+        }
+        "packed_int32" => {
+            let values: Int32Array = operand(&op, 1)?;
+            packed::write_int32(writer, &values);
+        }
+        "packed_uint32" => {
+            let values: Uint32Array = operand(&op, 1)?;
+            packed::write_uint32(writer, &values);
+        }
+        "packed_sint32" => {
+            let values: Int32Array = operand(&op, 1)?;
+            packed::write_sint32(writer, &values);
+        }
+        "packed_int64" => {
+            let values: BigInt64Array = operand(&op, 1)?;
+            packed::write_int64(writer, &values);
+        }
+        "packed_uint64" => {
+            let values: BigUint64Array = operand(&op, 1)?;
+            packed::write_uint64(writer, &values);
+        }
+        "packed_sint64" => {
+            let values: BigInt64Array = operand(&op, 1)?;
+            packed::write_sint64(writer, &values);
+        }
+        "packed_fixed32" => {
+            let values: Uint32Array = operand(&op, 1)?;
+            packed::write_fixed32(writer, &values);
+        }
+        "packed_sfixed32" => {
+            let values: Int32Array = operand(&op, 1)?;
+            packed::write_sfixed32(writer, &values);
+        }
+        "packed_fixed64" => {
+            let values: BigUint64Array = operand(&op, 1)?;
+            packed::write_fixed64(writer, &values);
+        }
+        "packed_sfixed64" => {
+            let values: BigInt64Array = operand(&op, 1)?;
+            packed::write_sfixed64(writer, &values);
+        }
+        "packed_float" => {
+            let values: Float32Array = operand(&op, 1)?;
+            packed::write_float(writer, &values);
+        }
+        "packed_double" => {
+            let values: Float64Array = operand(&op, 1)?;
+            packed::write_double(writer, &values);
+        }
+        "packed_bool" => {
+            let values: Uint8Array = operand(&op, 1)?;
+            packed::write_bool(writer, &values);
+        }
+        "group_start" | "group_end" => {
+            // ["group_start", field] ... ["group_end", field]
+            let field: u32 = operand(&op, 1)?;
+            let wire_type = if op_type == "group_start" { 3 } else { 4 };
+            tag::check_write(env, field, wire_type)?;
+            writer.write_tag(field, wire_type);
+        }
+        "tag" => {
+            // ["tag", field, wireType]
+            let field: u32 = operand(&op, 1)?;
+            let wire_type: u32 = operand(&op, 2)?;
+            tag::check_write(env, field, wire_type)?;
+            writer.write_tag(field, wire_type);
+        }
+        _ => {
+            // Ignore unknown operations
+        }
+    }
+    Ok(())
+}
+
+/// Options of a native `Writer`
+#[napi(object)]
+#[derive(Default)]
+pub struct WriterOptions {
+    /// `"strict"` (default), `"replace"` or `"bytes"` for strings with lone surrogates
+    pub utf8: Option<String>,
+}
 
 #[napi]
 pub struct Writer {
@@ -19,172 +226,39 @@ pub struct Writer {
 #[napi]
 impl Writer {
     #[napi(constructor)]
-    pub fn new() -> Self {
-        Writer {
-            inner: WriterImpl::new(),
-        }
+    pub fn new(options: Option<WriterOptions>) -> Result<Self> {
+        let options = options.unwrap_or_default();
+        let mut inner = WriterImpl::new();
+        inner.set_utf8(Utf8Policy::from_option(options.utf8.as_deref())?);
+        Ok(Writer { inner })
     }
 
     /// Batch encode all operations (new: core optimization)
     /// This is the key performance optimization - processes all operations in one FFI call
     #[napi]
-    pub fn encode_all(operations: Array) -> Result<Buffer> {
+    pub fn encode_all(env: Env, operations: Array) -> Result<Buffer> {
         let mut writer = WriterImpl::new();
         let len = operations.len();
         
         for i in 0..len {
-            let op: Array = operations.get(i)?.unwrap();
-            if op.len() < 2 {
-                continue;
-            }
-            
-            let op_type: String = op.get(0)?.unwrap();
-            
-            match op_type.as_str() {
-                "u32" => {
-                    let value: u32 = op.get(1)?.unwrap();
-                    writer.write_varint32(value);
-                }
-                "i32" => {
-                    let value: i32 = op.get(1)?.unwrap();
-                    // Negative numbers encode as 10 byte varint
-                    if value < 0 {
-                        writer.write_varint64(value as i64 as u64);
-                    } else {
-                        writer.write_varint32(value as u32);
-                    }
-                }
-                "u64" => {
-                    // Handle Long.js object or number  
-                    let value: JsUnknown = op.get(1)?.unwrap();
-                    // Try as object first (Long.js)
-                    let val = if let Ok(obj) = value.coerce_to_object() {
-                        if let (Ok(Some(low)), Ok(Some(high))) = (obj.get::<_, u32>("low"), obj.get::<_, u32>("high")) {
-                            ((high as u64) << 32) | (low as u64)
-                        } else {
-                            // Not a Long object, try parsing as number
-                            if let Ok(num) = obj.coerce_to_number() {
-                                num.get_double()? as i64 as u64
-                            } else {
-                                0
-                            }
-                        }
-                    } else {
-                        // Try as number directly
-                        if let Ok(num_val) = op.get::<f64>(1) {
-                            num_val.unwrap_or(0.0) as i64 as u64
-                        } else {
-                            0
-                        }
-                    };
-                    writer.write_varint64(val);
-                }
-                "i64" => {
-                    let value: JsUnknown = op.get(1)?.unwrap();
-                    let val = if let Ok(obj) = value.coerce_to_object() {
-                        if let (Ok(Some(low)), Ok(Some(high))) = (obj.get::<_, u32>("low"), obj.get::<_, i32>("high")) {
-                            (((high as i64) << 32) | (low as i64)) as u64
-                        } else {
-                            // Not a Long object, try parsing as number
-                            if let Ok(num) = obj.coerce_to_number() {
-                                num.get_double()? as i64 as u64
-                            } else {
-                                0
-                            }
-                        }
-                    } else {
-                        // Try as number directly
-                        if let Ok(num_val) = op.get::<f64>(1) {
-                            num_val.unwrap_or(0.0) as i64 as u64
-                        } else {
-                            0
-                        }
-                    };
-                    writer.write_varint64(val);
-                }
-                "s64" => {
-                    let value: JsUnknown = op.get(1)?.unwrap();
-                    let val = if let Ok(obj) = value.coerce_to_object() {
-                        if let (Ok(Some(low)), Ok(Some(high))) = (obj.get::<_, u32>("low"), obj.get::<_, i32>("high")) {
-                            ((high as i64) << 32) | (low as i64)
-                        } else {
-                            // Not a Long object, try parsing as number
-                            if let Ok(num) = obj.coerce_to_number() {
-                                num.get_double()? as i64
-                            } else {
-                                0
-                            }
-                        }
-                    } else {
-                        // Try as number directly
-                        if let Ok(num_val) = op.get::<f64>(1) {
-                            num_val.unwrap_or(0.0) as i64
-                        } else {
-                            0
-                        }
-                    };
-                    writer.write_sint64(val);
-                }
-                "bool" => {
-                    let value: bool = op.get(1)?.unwrap();
-                    writer.write_varint32(if value { 1 } else { 0 });
-                }
-                "f32" => {
-                    let value: u32 = op.get(1)?.unwrap();
-                    writer.write_fixed32(value);
-                }
-                "f64" => {
-                    let value: JsUnknown = op.get(1)?.unwrap();
-                    let val = if let Ok(obj) = value.coerce_to_object() {
-                        if let (Ok(Some(low)), Ok(Some(high))) = (obj.get::<_, u32>("low"), obj.get::<_, u32>("high")) {
-                            ((high as u64) << 32) | (low as u64)
-                        } else {
-                            // Not a Long object, try parsing as number
-                            if let Ok(num) = obj.coerce_to_number() {
-                                num.get_double()? as i64 as u64
-                            } else {
-                                0
-                            }
-                        }
-                    } else {
-                        // Try as number directly
-                        if let Ok(num_val) = op.get::<f64>(1) {
-                            num_val.unwrap_or(0.0) as i64 as u64
-                        } else {
-                            0
-                        }
-                    };
-                    writer.write_fixed64(val);
-                }
-                "float" => {
-                    let value: f64 = op.get(1)?.unwrap();
-                    writer.write_float(value as f32);
-                }
-                "double" => {
-                    let value: f64 = op.get(1)?.unwrap();
-                    writer.write_double(value);
-                }
-                "bytes" => {
-                    let buffer: Buffer = op.get(1)?.unwrap();
-                    let bytes = buffer.as_ref();
-                    writer.write_varint32(bytes.len() as u32);
-                    writer.write_bytes(bytes);
-                }
-                "string" => {
-                    let value: String = op.get(1)?.unwrap();
-                    let bytes = value.as_bytes();
-                    writer.write_varint32(bytes.len() as u32);
-                    writer.write_bytes(bytes);
-                }
-                _ => {
-                    // Ignore unknown operations
-                }
-            }
+            encode_op(&env, &mut writer, &operations, i)
+                .map_err(|e| e.prefixed(&format!("encodeAll op {}", i)))
+                .into_napi(&env)?;
         }
         
         Ok(writer.finish().into())
     }
 
+    /// Batch encode a compact opcode stream (see `Opcode`) in one FFI call.
+    /// Operands come from side tables: numbers, the UTF-8 bytes of all string
+    /// operands concatenated, and byte buffers, each consumed in order.
+    #[napi]
+    pub fn encode_ops(env: Env, ops: Uint8Array, numbers: Float64Array, strings: Buffer, buffers: Vec<Buffer>) -> Result<Buffer> {
+        let mut writer = WriterImpl::new();
+        opcodes::encode_ops(&mut writer, &ops, &numbers, &strings, &buffers).into_napi(&env)?;
+        Ok(writer.finish().into())
+    }
+
     #[napi]
     pub fn uint32(&mut self, value: u32) -> &Self {
         self.inner.write_varint32(value);
@@ -208,49 +282,34 @@ impl Writer {
         self
     }
 
+    /// Accepts a number, BigInt, decimal string or Long
     #[napi]
-    pub fn uint64(&mut self, value: Either<i64, Object>) -> &Self {
-        let val = match value {
-            Either::A(num) => num as u64,
-            Either::B(obj) => {
-                // Handle Long object with low and high parts
-                let low: u32 = obj.get::<_, u32>("low").ok().flatten().unwrap_or(0);
-                let high: u32 = obj.get::<_, u32>("high").ok().flatten().unwrap_or(0);
-                ((high as u64) << 32) | (low as u64)
-            }
-        };
-        self.inner.write_varint64(val);
-        self
+    pub fn uint64(&mut self, env: Env, value: JsUnknown) -> Result<&Self> {
+        self.inner.write_varint64(long::expect_bits(&value).into_napi(&env)?);
+        Ok(self)
     }
 
+    /// Accepts a number, BigInt, decimal string or Long
     #[napi]
-    pub fn int64(&mut self, value: Either<i64, Object>) -> &Self {
-        let val = match value {
-            Either::A(num) => num as u64,
-            Either::B(obj) => {
-                // Handle Long object with low and high parts
-                let low: u32 = obj.get::<_, u32>("low").ok().flatten().unwrap_or(0);
-                let high: i32 = obj.get::<_, i32>("high").ok().flatten().unwrap_or(0);
-                (((high as i64) << 32) | (low as i64)) as u64
-            }
-        };
-        self.inner.write_varint64(val);
-        self
+    pub fn int64(&mut self, env: Env, value: JsUnknown) -> Result<&Self> {
+        self.inner.write_varint64(long::expect_bits(&value).into_napi(&env)?);
+        Ok(self)
     }
 
+    /// Accepts a number, BigInt, decimal string or Long
     #[napi]
-    pub fn sint64(&mut self, value: Either<i64, Object>) -> &Self {
-        let val = match value {
-            Either::A(num) => num,
-            Either::B(obj) => {
-                // Handle Long object with low and high parts
-                let low: u32 = obj.get::<_, u32>("low").ok().flatten().unwrap_or(0);
-                let high: i32 = obj.get::<_, i32>("high").ok().flatten().unwrap_or(0);
-                ((high as i64) << 32) | (low as i64)
-            }
-        };
-        self.inner.write_sint64(val);
-        self
+    pub fn sint64(&mut self, env: Env, value: JsUnknown) -> Result<&Self> {
+        self.inner.write_sint64(long::expect_bits(&value).into_napi(&env)? as i64);
+        Ok(self)
+    }
+
+    /// Writes the tag of `field` with the given wire type. Throws on field numbers
+    /// outside 1..2^29-1 or invalid wire types and warns on reserved field numbers.
+    #[napi]
+    pub fn tag(&mut self, env: Env, field: u32, wire_type: u32) -> Result<&Self> {
+        tag::check_write(&env, field, wire_type).into_napi(&env)?;
+        self.inner.write_tag(field, wire_type);
+        Ok(self)
     }
 
     #[napi]
@@ -271,34 +330,18 @@ impl Writer {
         self
     }
 
+    /// Accepts a number, BigInt, decimal string or Long
     #[napi]
-    pub fn fixed64(&mut self, value: Either<i64, Object>) -> &Self {
-        let val = match value {
-            Either::A(num) => num as u64,
-            Either::B(obj) => {
-                // Handle Long object with low and high parts
-                let low: u32 = obj.get::<_, u32>("low").ok().flatten().unwrap_or(0);
-                let high: u32 = obj.get::<_, u32>("high").ok().flatten().unwrap_or(0);
-                ((high as u64) << 32) | (low as u64)
-            }
-        };
-        self.inner.write_fixed64(val);
-        self
+    pub fn fixed64(&mut self, env: Env, value: JsUnknown) -> Result<&Self> {
+        self.inner.write_fixed64(long::expect_bits(&value).into_napi(&env)?);
+        Ok(self)
     }
 
+    /// Accepts a number, BigInt, decimal string or Long
     #[napi]
-    pub fn sfixed64(&mut self, value: Either<i64, Object>) -> &Self {
-        let val = match value {
-            Either::A(num) => num as u64,
-            Either::B(obj) => {
-                // Handle Long object with low and high parts
-                let low: u32 = obj.get::<_, u32>("low").ok().flatten().unwrap_or(0);
-                let high: i32 = obj.get::<_, i32>("high").ok().flatten().unwrap_or(0);
-                (((high as i64) << 32) | (low as i64)) as u64
-            }
-        };
-        self.inner.write_fixed64(val);
-        self
+    pub fn sfixed64(&mut self, env: Env, value: JsUnknown) -> Result<&Self> {
+        self.inner.write_fixed64(long::expect_bits(&value).into_napi(&env)?);
+        Ok(self)
     }
 
     #[napi]
@@ -319,11 +362,96 @@ impl Writer {
         self
     }
 
+    /// Writes a string, with `utf8` overriding the writer's policy for this call
     #[napi]
-    pub fn string(&mut self, value: String) -> &Self {
-        let bytes = value.as_bytes();
+    pub fn string(&mut self, env: Env, value: JsString, utf8: Option<String>) -> Result<&Self> {
+        let policy = match utf8 {
+            Some(utf8) => Utf8Policy::from_option(Some(&utf8))?,
+            None => self.inner.utf8(),
+        };
+        let bytes = utf8::encode(value, policy).into_napi(&env)?;
         self.inner.write_varint32(bytes.len() as u32);
-        self.inner.write_bytes(bytes);
+        self.inner.write_bytes(&bytes);
+        Ok(self)
+    }
+
+    // Packed repeated fields: a length-delimited block of values, without a tag
+
+    #[napi]
+    pub fn packed_int32(&mut self, values: Int32Array) -> &Self {
+        packed::write_int32(&mut self.inner, &values);
+        self
+    }
+
+    #[napi]
+    pub fn packed_uint32(&mut self, values: Uint32Array) -> &Self {
+        packed::write_uint32(&mut self.inner, &values);
+        self
+    }
+
+    #[napi]
+    pub fn packed_sint32(&mut self, values: Int32Array) -> &Self {
+        packed::write_sint32(&mut self.inner, &values);
+        self
+    }
+
+    #[napi]
+    pub fn packed_int64(&mut self, values: BigInt64Array) -> &Self {
+        packed::write_int64(&mut self.inner, &values);
+        self
+    }
+
+    #[napi]
+    pub fn packed_uint64(&mut self, values: BigUint64Array) -> &Self {
+        packed::write_uint64(&mut self.inner, &values);
+        self
+    }
+
+    #[napi]
+    pub fn packed_sint64(&mut self, values: BigInt64Array) -> &Self {
+        packed::write_sint64(&mut self.inner, &values);
+        self
+    }
+
+    #[napi]
+    pub fn packed_fixed32(&mut self, values: Uint32Array) -> &Self {
+        packed::write_fixed32(&mut self.inner, &values);
+        self
+    }
+
+    #[napi]
+    pub fn packed_sfixed32(&mut self, values: Int32Array) -> &Self {
+        packed::write_sfixed32(&mut self.inner, &values);
+        self
+    }
+
+    #[napi]
+    pub fn packed_fixed64(&mut self, values: BigUint64Array) -> &Self {
+        packed::write_fixed64(&mut self.inner, &values);
+        self
+    }
+
+    #[napi]
+    pub fn packed_sfixed64(&mut self, values: BigInt64Array) -> &Self {
+        packed::write_sfixed64(&mut self.inner, &values);
+        self
+    }
+
+    #[napi]
+    pub fn packed_float(&mut self, values: Float32Array) -> &Self {
+        packed::write_float(&mut self.inner, &values);
+        self
+    }
+
+    #[napi]
+    pub fn packed_double(&mut self, values: Float64Array) -> &Self {
+        packed::write_double(&mut self.inner, &values);
+        self
+    }
+
+    #[napi]
+    pub fn packed_bool(&mut self, values: Uint8Array) -> &Self {
+        packed::write_bool(&mut self.inner, &values);
         self
     }
 
@@ -352,123 +480,280 @@ impl Writer {
     }
 
     #[napi(getter)]
+    #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> u32 {
         self.inner.len() as u32
     }
 }
 
+/// Options of a native `Reader`
+#[napi(object)]
+#[derive(Default)]
+pub struct ReaderOptions {
+    /// `"number"` (default), `"string"`, `"bigint"` or `"long"` (`{ low, high, unsigned }`) for 64-bit values
+    pub longs: Option<String>,
+    /// Deepest nesting of sub-messages and groups, 100 by default
+    pub max_depth: Option<u32>,
+    /// Largest input accepted, unlimited by default
+    pub max_message_size: Option<u32>,
+    /// Longest bytes or string value, unlimited by default
+    pub max_bytes_length: Option<u32>,
+    /// Most fields read, unlimited by default
+    pub max_fields: Option<u32>,
+    /// Reject non-minimal and overlong varints, with a `NON_CANONICAL` error naming the rule
+    pub strict: Option<bool>,
+    /// `"strict"` (default), `"replace"` or `"bytes"` for strings that aren't valid UTF-8
+    pub utf8: Option<String>,
+}
+
 #[napi]
 pub struct Reader {
     inner: ReaderImpl,
+    longs: LongRepr,
 }
 
 #[napi]
 impl Reader {
+    /// Reads a Buffer, Uint8Array, ArrayBuffer or DataView in place, without copying it
     #[napi(constructor)]
-    pub fn new(buffer: Buffer) -> Self {
-        Reader {
-            inner: ReaderImpl::new(buffer.to_vec()),
-        }
+    pub fn new(env: Env, buffer: JsUnknown, options: Option<ReaderOptions>) -> Result<Self> {
+        let options = options.unwrap_or_default();
+        let mut inner = ReaderImpl::from_source(Source::from_js(&env, buffer)?);
+        inner
+            .set_decode_limits(DecodeLimits::from_options(
+                options.max_depth,
+                options.max_message_size,
+                options.max_bytes_length,
+                options.max_fields,
+            ))
+            .into_napi(&env)?;
+        inner.set_strict(options.strict.unwrap_or(false));
+        inner.set_utf8(Utf8Policy::from_option(options.utf8.as_deref())?);
+        Ok(Reader {
+            inner,
+            longs: LongRepr::from_option(options.longs.as_deref())?,
+        })
     }
 
     #[napi]
-    pub fn uint32(&mut self) -> Result<u32> {
-        self.inner.read_varint32()
+    pub fn uint32(&mut self, env: Env) -> Result<u32> {
+        self.inner.read_varint32().into_napi(&env)
     }
 
     #[napi]
-    pub fn int32(&mut self) -> Result<i32> {
-        let value = self.inner.read_varint32()?;
-        Ok(value as i32)
+    pub fn int32(&mut self, env: Env) -> Result<i32> {
+        self.inner.read_int32().into_napi(&env)
     }
 
     #[napi]
-    pub fn sint32(&mut self) -> Result<i32> {
-        self.inner.read_sint32()
+    pub fn sint32(&mut self, env: Env) -> Result<i32> {
+        self.inner.read_sint32().into_napi(&env)
     }
 
     #[napi]
-    pub fn uint64(&mut self) -> Result<i64> {
-        let value = self.inner.read_varint64()?;
-        Ok(value as i64)
+    pub fn uint64(&mut self, env: Env) -> Result<JsUnknown> {
+        let bits = self.inner.read_varint64().into_napi(&env)?;
+        self.longs.to_js(&env, bits, true)
     }
 
     #[napi]
-    pub fn int64(&mut self) -> Result<i64> {
-        let value = self.inner.read_varint64()?;
-        Ok(value as i64)
+    pub fn int64(&mut self, env: Env) -> Result<JsUnknown> {
+        let bits = self.inner.read_varint64().into_napi(&env)?;
+        self.longs.to_js(&env, bits, false)
     }
 
     #[napi]
-    pub fn sint64(&mut self) -> Result<i64> {
-        self.inner.read_sint64()
+    pub fn sint64(&mut self, env: Env) -> Result<JsUnknown> {
+        let bits = self.inner.read_sint64().into_napi(&env)? as u64;
+        self.longs.to_js(&env, bits, false)
     }
 
     #[napi]
-    pub fn bool(&mut self) -> Result<bool> {
-        let value = self.inner.read_varint32()?;
+    pub fn bool(&mut self, env: Env) -> Result<bool> {
+        let value = self.inner.read_varint32().into_napi(&env)?;
         Ok(value != 0)
     }
 
     #[napi]
-    pub fn fixed32(&mut self) -> Result<u32> {
-        self.inner.read_fixed32()
+    pub fn fixed32(&mut self, env: Env) -> Result<u32> {
+        self.inner.read_fixed32().into_napi(&env)
     }
 
     #[napi]
-    pub fn sfixed32(&mut self) -> Result<i32> {
-        self.inner.read_sfixed32()
+    pub fn sfixed32(&mut self, env: Env) -> Result<i32> {
+        self.inner.read_sfixed32().into_napi(&env)
     }
 
     #[napi]
-    pub fn fixed64(&mut self) -> Result<i64> {
-        let value = self.inner.read_fixed64()?;
-        Ok(value as i64)
+    pub fn fixed64(&mut self, env: Env) -> Result<JsUnknown> {
+        let bits = self.inner.read_fixed64().into_napi(&env)?;
+        self.longs.to_js(&env, bits, true)
     }
 
     #[napi]
-    pub fn sfixed64(&mut self) -> Result<i64> {
-        self.inner.read_sfixed64()
+    pub fn sfixed64(&mut self, env: Env) -> Result<JsUnknown> {
+        let bits = self.inner.read_sfixed64().into_napi(&env)? as u64;
+        self.longs.to_js(&env, bits, false)
     }
 
     #[napi]
-    pub fn float(&mut self) -> Result<f64> {
-        let value = self.inner.read_float()?;
+    pub fn float(&mut self, env: Env) -> Result<f64> {
+        let value = self.inner.read_float().into_napi(&env)?;
         Ok(value as f64)
     }
 
     #[napi]
-    pub fn double(&mut self) -> Result<f64> {
-        self.inner.read_double()
+    pub fn double(&mut self, env: Env) -> Result<f64> {
+        self.inner.read_double().into_napi(&env)
     }
 
     #[napi]
-    pub fn bytes(&mut self) -> Result<Buffer> {
-        let len = self.inner.read_varint32()? as usize;
-        let data = self.inner.read_bytes(len)?;
+    pub fn bytes(&mut self, env: Env) -> Result<Buffer> {
+        let len = self.inner.read_varint32().into_napi(&env)? as usize;
+        let data = self.inner.read_bytes(len).into_napi(&env)?;
         Ok(Buffer::from(data))
     }
 
+    /// Reads a string, with `utf8` overriding the reader's policy for this call.
+    /// Under the `bytes` policy invalid UTF-8 is returned as a Buffer.
+    #[napi(ts_return_type = "string | Buffer")]
+    pub fn string(&mut self, env: Env, utf8: Option<String>) -> Result<JsUnknown> {
+        let policy = match utf8 {
+            Some(utf8) => Utf8Policy::from_option(Some(&utf8))?,
+            None => self.inner.utf8(),
+        };
+        let len = self.inner.read_varint32().into_napi(&env)? as usize;
+        let text = self.inner.read_text(len, policy).into_napi(&env)?;
+        text.to_js(&env)
+    }
+
+    // Packed repeated fields: reads a length-delimited block of values, without a tag
+
+    #[napi]
+    pub fn packed_int32(&mut self, env: Env) -> Result<Int32Array> {
+        Ok(Int32Array::new(packed::read_int32(&mut self.inner).into_napi(&env)?))
+    }
+
     #[napi]
-    pub fn string(&mut self) -> Result<String> {
-        let len = self.inner.read_varint32()? as usize;
-        let bytes = self.inner.read_bytes(len)?;
-        String::from_utf8(bytes).map_err(|e| {
-            napi::Error::new(
-                napi::Status::GenericFailure,
-                format!("invalid utf8: {}", e),
-            )
-        })
+    pub fn packed_uint32(&mut self, env: Env) -> Result<Uint32Array> {
+        Ok(Uint32Array::new(packed::read_uint32(&mut self.inner).into_napi(&env)?))
+    }
+
+    #[napi]
+    pub fn packed_sint32(&mut self, env: Env) -> Result<Int32Array> {
+        Ok(Int32Array::new(packed::read_sint32(&mut self.inner).into_napi(&env)?))
+    }
+
+    #[napi]
+    pub fn packed_int64(&mut self, env: Env) -> Result<BigInt64Array> {
+        Ok(BigInt64Array::new(packed::read_int64(&mut self.inner).into_napi(&env)?))
+    }
+
+    #[napi]
+    pub fn packed_uint64(&mut self, env: Env) -> Result<BigUint64Array> {
+        Ok(BigUint64Array::new(packed::read_uint64(&mut self.inner).into_napi(&env)?))
+    }
+
+    #[napi]
+    pub fn packed_sint64(&mut self, env: Env) -> Result<BigInt64Array> {
+        Ok(BigInt64Array::new(packed::read_sint64(&mut self.inner).into_napi(&env)?))
+    }
+
+    #[napi]
+    pub fn packed_fixed32(&mut self, env: Env) -> Result<Uint32Array> {
+        Ok(Uint32Array::new(packed::read_fixed32(&mut self.inner).into_napi(&env)?))
+    }
+
+    #[napi]
+    pub fn packed_sfixed32(&mut self, env: Env) -> Result<Int32Array> {
+        Ok(Int32Array::new(packed::read_sfixed32(&mut self.inner).into_napi(&env)?))
+    }
+
+    #[napi]
+    pub fn packed_fixed64(&mut self, env: Env) -> Result<BigUint64Array> {
+        Ok(BigUint64Array::new(packed::read_fixed64(&mut self.inner).into_napi(&env)?))
+    }
+
+    #[napi]
+    pub fn packed_sfixed64(&mut self, env: Env) -> Result<BigInt64Array> {
+        Ok(BigInt64Array::new(packed::read_sfixed64(&mut self.inner).into_napi(&env)?))
+    }
+
+    #[napi]
+    pub fn packed_float(&mut self, env: Env) -> Result<Float32Array> {
+        Ok(Float32Array::new(packed::read_float(&mut self.inner).into_napi(&env)?))
+    }
+
+    #[napi]
+    pub fn packed_double(&mut self, env: Env) -> Result<Float64Array> {
+        Ok(Float64Array::new(packed::read_double(&mut self.inner).into_napi(&env)?))
+    }
+
+    #[napi]
+    pub fn packed_bool(&mut self, env: Env) -> Result<Uint8Array> {
+        Ok(Uint8Array::new(packed::read_bool(&mut self.inner).into_napi(&env)?))
+    }
+
+    #[napi]
+    pub fn skip(&mut self, env: Env, length: u32) -> Result<()> {
+        self.inner.skip(length as usize).into_napi(&env)
+    }
+
+    /// Runs a read plan (see `plan::read_all`) in one FFI call and returns the values as a flat array
+    #[napi(ts_args_type = "plan: Array<[string] | [string, number]>")]
+    pub fn read_all(&mut self, env: Env, plan: Object) -> Result<Object> {
+        plan::read_all(&env, &mut self.inner, self.longs, &plan).into_napi(&env)
     }
 
+    /// Indexes the fields from the current position to the limit without decoding them,
+    /// as a `Uint32Array` of `(field, wireType, valueOffset, valueLength)` rows.
+    /// The reader's position and its count of fields towards `maxFields` are left unchanged.
     #[napi]
-    pub fn skip(&mut self, length: u32) -> Result<()> {
-        self.inner.skip(length as usize)
+    pub fn scan(&mut self, env: Env, options: Option<ScanOptions>) -> Result<Uint32Array> {
+        let recursive = options.unwrap_or_default().recursive.unwrap_or(false);
+        let start = self.inner.pos();
+        let fields = self.inner.fields_read();
+        let mut table = Vec::new();
+        let result = scan::scan(&mut self.inner, recursive, &mut table);
+        self.inner.seek(start);
+        self.inner.set_fields_read(fields);
+        result.into_napi(&env)?;
+        Ok(Uint32Array::new(table))
     }
 
+    /// Reads a field tag, throwing on invalid field numbers and wire types
     #[napi]
-    pub fn skip_type(&mut self, wire_type: u32) -> Result<()> {
-        self.inner.skip_type(wire_type)
+    pub fn read_tag(&mut self, env: Env) -> Result<Tag> {
+        let (field, wire_type) = self.inner.read_tag().into_napi(&env)?;
+        Ok(Tag { field, wire_type })
+    }
+
+    #[napi]
+    pub fn skip_type(&mut self, env: Env, wire_type: u32) -> Result<()> {
+        self.inner.skip_type(wire_type).into_napi(&env)
+    }
+
+    /// Limits all reads to the next `length` bytes until `popLimit`
+    #[napi]
+    pub fn push_limit(&mut self, env: Env, length: u32) -> Result<()> {
+        self.inner.push_limit(length as usize).into_napi(&env)
+    }
+
+    /// Restores the limit in effect before the last `pushLimit`
+    #[napi]
+    pub fn pop_limit(&mut self, env: Env) -> Result<()> {
+        self.inner.pop_limit().into_napi(&env)
+    }
+
+    /// Whether the current limit has been reached
+    #[napi]
+    pub fn at_end(&self) -> bool {
+        self.inner.at_end()
+    }
+
+    #[napi]
+    pub fn bytes_until_limit(&self) -> u32 {
+        self.inner.bytes_until_limit() as u32
     }
 
     #[napi(getter)]
@@ -477,7 +762,334 @@ impl Reader {
     }
 
     #[napi(getter)]
+    #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> u32 {
         self.inner.len() as u32
     }
 }
+
+/// Register a protobuf.js `Type.toJSON()` or `Root.toJSON()` descriptor with the native codec.
+/// Returns the handle of `type_name`, which is required for namespace descriptors.
+#[napi]
+pub fn register_type(descriptor: serde_json::Value, type_name: Option<String>) -> Result<u32> {
+    descriptor::write_registry()?.register_json(&descriptor, type_name.as_deref())
+}
+
+/// Convert a serialized `google.protobuf.FileDescriptorSet`, like the output of
+/// `protoc --descriptor_set_out` or `buf build`, to protobuf.js `Root.toJSON()` JSON
+#[napi(ts_return_type = "object")]
+pub fn descriptor_set_to_json(env: Env, buffer: JsUnknown) -> Result<serde_json::Value> {
+    let mut reader = ReaderImpl::from_source(Source::from_js(&env, buffer)?);
+    descriptor_set::to_json(&mut reader).into_napi(&env)
+}
+
+/// Register every type of a serialized `google.protobuf.FileDescriptorSet` with the native
+/// codec, for `lookupType`, and return the set as protobuf.js `Root.toJSON()` JSON
+#[napi(ts_return_type = "object")]
+pub fn register_descriptor_set(env: Env, buffer: JsUnknown) -> Result<serde_json::Value> {
+    let mut reader = ReaderImpl::from_source(Source::from_js(&env, buffer)?);
+    let json = descriptor_set::to_json(&mut reader).into_napi(&env)?;
+    descriptor::write_registry()?.register_root(&json)?;
+    Ok(json)
+}
+
+/// Parse `.proto` files and their imports to protobuf.js `Root.toJSON()` JSON. Files and
+/// imports are resolved through `options.files`, then `options.includePaths`.
+#[napi(ts_return_type = "object")]
+pub fn parse_proto(
+    env: Env,
+    files: Either<String, Vec<String>>,
+    options: Option<ParseProtoOptions>,
+) -> Result<serde_json::Value> {
+    let files = match files {
+        Either::A(file) => vec![file],
+        Either::B(files) => files,
+    };
+    parse::parse(&files, &options.unwrap_or_default()).into_napi(&env)
+}
+
+/// Register every type of `.proto` files and their imports with the native codec,
+/// for `lookupType`, and return them as protobuf.js `Root.toJSON()` JSON
+#[napi(ts_return_type = "object")]
+pub fn register_proto(
+    env: Env,
+    files: Either<String, Vec<String>>,
+    options: Option<ParseProtoOptions>,
+) -> Result<serde_json::Value> {
+    let json = parse_proto(env, files, options)?;
+    descriptor::write_registry()?.register_root(&json)?;
+    Ok(json)
+}
+
+/// Look up the handle of a registered message type by its fully qualified name
+#[napi]
+pub fn lookup_type(type_name: String) -> Result<Option<u32>> {
+    Ok(match descriptor::read_registry()?.lookup(&type_name) {
+        Some(descriptor::TypeRef::Message(index)) => Some(index as u32),
+        _ => None,
+    })
+}
+
+/// Reflection over the types registered with the native codec, so tools can inspect
+/// them without keeping a protobuf.js `Root`. Names are fully qualified, with or
+/// without a leading dot.
+#[napi]
+#[derive(Default)]
+pub struct DescriptorPool {}
+
+#[napi]
+impl DescriptorPool {
+    #[napi(constructor)]
+    pub fn new() -> Self {
+        DescriptorPool {}
+    }
+
+    /// The message type `name` with its fields and oneofs, or `null`
+    #[napi(ts_return_type = "object | null")]
+    pub fn find_message(&self, name: String) -> Result<Option<serde_json::Value>> {
+        let registry = descriptor::read_registry()?;
+        Ok(match registry.lookup(&name) {
+            Some(descriptor::TypeRef::Message(index)) => Some(pool::message(&registry, index)),
+            _ => None,
+        })
+    }
+
+    /// The message type of a handle, which also reaches types registered without a name
+    #[napi(ts_return_type = "object")]
+    pub fn message(&self, handle: u32) -> Result<serde_json::Value> {
+        let registry = descriptor::read_registry()?;
+        registry.message(handle)?;
+        Ok(pool::message(&registry, handle as usize))
+    }
+
+    /// The enum type `name` with its values, or `null`
+    #[napi(ts_return_type = "object | null")]
+    pub fn find_enum(&self, name: String) -> Result<Option<serde_json::Value>> {
+        let registry = descriptor::read_registry()?;
+        Ok(match registry.lookup(&name) {
+            Some(descriptor::TypeRef::Enum(index)) => Some(pool::enumeration(&registry, index)),
+            _ => None,
+        })
+    }
+
+    /// The service `name` with its methods, or `null`
+    #[napi(ts_return_type = "object | null")]
+    pub fn find_service(&self, name: String) -> Result<Option<serde_json::Value>> {
+        Ok(descriptor::read_registry()?.lookup_service(&name).map(pool::service))
+    }
+
+    /// The extension fields registered for the message type `extendee`
+    #[napi(ts_return_type = "object[]")]
+    pub fn find_extensions(&self, extendee: String) -> Result<Vec<serde_json::Value>> {
+        let registry = descriptor::read_registry()?;
+        match registry.lookup(&extendee) {
+            Some(descriptor::TypeRef::Message(index)) => Ok(pool::extensions(&registry, index)),
+            _ => Err(Error::new(Status::InvalidArg, format!("no such message type: {}", extendee))),
+        }
+    }
+
+    /// The types, services and namespaces directly within a namespace or message,
+    /// the root by default, as `{ name, fullName, kind }`
+    #[napi(ts_return_type = "object[]")]
+    pub fn nested(&self, scope: Option<String>) -> Result<Vec<serde_json::Value>> {
+        let registry = descriptor::read_registry()?;
+        Ok(pool::nested(&registry, scope.as_deref().unwrap_or_default()))
+    }
+}
+
+/// Encode a whole JS object as the registered message type in one FFI call
+#[napi]
+pub fn encode(env: Env, type_handle: u32, message: Object, options: Option<EncodeOptions>) -> Result<Buffer> {
+    let registry = descriptor::read_registry()?;
+    let descriptor = registry.message(type_handle)?;
+    let options = options.unwrap_or_default();
+    let mut writer = WriterImpl::new();
+    writer.set_utf8(Utf8Policy::from_option(options.utf8.as_deref())?);
+    writer.set_well_known_types(options.well_known_types.unwrap_or(false));
+    encoder::encode_message(&mut writer, &registry, descriptor, &message).into_napi(&env)?;
+    Ok(writer.finish().into())
+}
+
+/// Decode a buffer as the registered message type into a plain JS object in one FFI call.
+/// Accepts the same inputs as `Reader`, which are read in place.
+#[napi]
+pub fn decode(env: Env, type_handle: u32, buffer: JsUnknown, options: Option<DecodeOptions>) -> Result<Object> {
+    let registry = descriptor::read_registry()?;
+    let descriptor = registry.message(type_handle)?;
+    let options = decoder::Options::from_js(options)?;
+    let mut reader = ReaderImpl::from_source(Source::from_js(&env, buffer)?);
+    reader.set_decode_limits(options.limits).into_napi(&env)?;
+    reader.set_strict(options.strict);
+    reader.set_utf8(options.utf8);
+    decoder::Decoder::new(&env, &registry, options)
+        .decode_message(&mut reader, descriptor, None)
+        .into_napi(&env)
+}
+
+/// Map an `Any` type URL to a registered message type. Any URL ending in a registered
+/// type's full name already resolves to it; this adds others, which `packAny` then writes.
+#[napi]
+pub fn register_type_url(type_url: String, type_handle: u32) -> Result<()> {
+    descriptor::write_registry()?.register_type_url(&type_url, type_handle)
+}
+
+/// Encode a JS object as the registered message type wrapped in a `google.protobuf.Any`
+#[napi]
+pub fn pack_any(env: Env, type_handle: u32, message: Object, options: Option<EncodeOptions>) -> Result<Buffer> {
+    let registry = descriptor::read_registry()?;
+    let descriptor = registry.message(type_handle)?;
+    let type_url = registry.type_url(type_handle as usize).ok_or_else(|| {
+        Error::new(
+            Status::InvalidArg,
+            format!("type handle {} has no name, register a type URL for it", type_handle),
+        )
+    })?;
+    let options = options.unwrap_or_default();
+    let mut writer = WriterImpl::new();
+    writer.set_utf8(Utf8Policy::from_option(options.utf8.as_deref())?);
+    writer.set_well_known_types(options.well_known_types.unwrap_or(false));
+    encoder::encode_any(&mut writer, &registry, &type_url, descriptor, &message).into_napi(&env)?;
+    Ok(writer.finish().into())
+}
+
+/// Decode a `google.protobuf.Any` as its payload with an `@type` property, unpacking
+/// nested `Any` values too. Payloads of unknown types are kept as the `Any` with its
+/// raw bytes unless `unknownAnys` is `"error"`.
+#[napi(ts_return_type = "object")]
+pub fn unpack_any(env: Env, buffer: JsUnknown, options: Option<DecodeOptions>) -> Result<JsUnknown> {
+    let registry = descriptor::read_registry()?;
+    let any = match registry.lookup("google.protobuf.Any") {
+        Some(descriptor::TypeRef::Message(index)) => registry.message_at(index),
+        _ => {
+            return Err(Error::new(
+                Status::InvalidArg,
+                "google.protobuf.Any is not registered".to_string(),
+            ))
+        }
+    };
+    let mut options = decoder::Options::from_js(options)?;
+    options.unpack_anys = true;
+    let mut reader = ReaderImpl::from_source(Source::from_js(&env, buffer)?);
+    reader.set_decode_limits(options.limits).into_napi(&env)?;
+    reader.set_strict(options.strict);
+    reader.set_utf8(options.utf8);
+    decoder::Decoder::new(&env, &registry, options)
+        .decode_value(&mut reader, any, None)
+        .into_napi(&env)
+}
+
+/// Convert a binary message of the registered type to proto3 JSON text in one FFI call
+#[napi]
+pub fn binary_to_json(env: Env, type_handle: u32, buffer: JsUnknown, options: Option<BinaryToJsonOptions>) -> Result<String> {
+    let registry = descriptor::read_registry()?;
+    let descriptor = registry.message(type_handle)?;
+    let options = options.unwrap_or_default();
+    let limits = DecodeLimits::from_options(
+        options.max_depth,
+        options.max_message_size,
+        options.max_bytes_length,
+        options.max_fields,
+    );
+    let printer = json::Printer::new(&registry, options)?;
+    let mut reader = ReaderImpl::from_source(Source::from_js(&env, buffer)?);
+    reader.set_decode_limits(limits).into_napi(&env)?;
+    printer.print(&mut reader, descriptor).into_napi(&env)
+}
+
+/// Convert proto3 JSON text to a binary message of the registered type in one FFI call
+#[napi]
+pub fn json_to_binary(env: Env, type_handle: u32, json: JsString, options: Option<JsonToBinaryOptions>) -> Result<Buffer> {
+    let registry = descriptor::read_registry()?;
+    let descriptor = registry.message(type_handle)?;
+    let options = options.unwrap_or_default();
+    // Lone surrogates can't be parsed as JSON when written as is, so `bytes` replaces them too
+    let policy = match Utf8Policy::from_option(options.utf8.as_deref())? {
+        Utf8Policy::Strict => Utf8Policy::Strict,
+        _ => Utf8Policy::Replace,
+    };
+    let text = utf8::encode(json, policy).into_napi(&env)?;
+    let mut writer = WriterImpl::new();
+    json::Parser::new(&registry, &options)
+        .parse(&mut writer, descriptor, &text)
+        .into_napi(&env)?;
+    Ok(writer.finish().into())
+}
+
+/// Print a binary message of the registered type in the protobuf text format
+#[napi]
+pub fn to_text_format(env: Env, type_handle: u32, buffer: JsUnknown, options: Option<ToTextFormatOptions>) -> Result<String> {
+    let registry = descriptor::read_registry()?;
+    let descriptor = registry.message(type_handle)?;
+    let options = options.unwrap_or_default();
+    let limits = DecodeLimits::from_options(
+        options.max_depth,
+        options.max_message_size,
+        options.max_bytes_length,
+        options.max_fields,
+    );
+    let printer = text::Printer::new(&registry, options)?;
+    let mut reader = ReaderImpl::from_source(Source::from_js(&env, buffer)?);
+    reader.set_decode_limits(limits).into_napi(&env)?;
+    printer.print(&mut reader, descriptor).into_napi(&env)
+}
+
+/// Parse the protobuf text format into a binary message of the registered type
+#[napi]
+pub fn from_text_format(env: Env, type_handle: u32, text: JsString, options: Option<FromTextFormatOptions>) -> Result<Buffer> {
+    let registry = descriptor::read_registry()?;
+    let descriptor = registry.message(type_handle)?;
+    let options = options.unwrap_or_default();
+    let policy = Utf8Policy::from_option(options.utf8.as_deref())?;
+    let source = match policy {
+        Utf8Policy::Strict => utf8::encode(text, Utf8Policy::Strict),
+        _ => utf8::encode(text, Utf8Policy::Replace),
+    }
+    .into_napi(&env)?;
+    let source = String::from_utf8(source).map_err(|e| Error::new(Status::InvalidArg, e.to_string()))?;
+    let mut writer = WriterImpl::new();
+    text::Parser::new(&registry, policy)
+        .parse(&mut writer, descriptor, &source)
+        .into_napi(&env)?;
+    Ok(writer.finish().into())
+}
+
+/// Dump a buffer without a schema as a tree of fields with guessed meanings, or as
+/// text like `protoc --decode_raw`. Reading stops at the first field that can't be read.
+#[napi(ts_return_type = "{ fields: object[], end: number, error?: Error } | string")]
+pub fn decode_raw(env: Env, buffer: JsUnknown, options: Option<DecodeRawOptions>) -> Result<JsUnknown> {
+    let options = options.unwrap_or_default();
+    let longs = LongRepr::from_option(options.longs.as_deref())?;
+    let text = match options.format.as_deref() {
+        None | Some("tree") => false,
+        Some("text") => true,
+        Some(other) => {
+            return Err(Error::new(
+                Status::InvalidArg,
+                format!("invalid format option: {}", other),
+            ))
+        }
+    };
+    let mut reader = ReaderImpl::from_source(Source::from_js(&env, buffer)?);
+    let dump = raw::decode(&mut reader);
+    if text {
+        let text = raw::to_text(&mut reader, &dump).into_napi(&env)?;
+        return Ok(env.create_string(&text)?.into_unknown());
+    }
+    Ok(raw::to_js(&env, &mut reader, dump, longs)?.into_unknown())
+}
+
+/// Print a buffer in a protoscope-like language, like `1: 42` and `2: {"hi"}`, that
+/// `fromProtoscope` assembles back into the same bytes
+#[napi]
+pub fn to_protoscope(env: Env, buffer: JsUnknown) -> Result<String> {
+    let mut reader = ReaderImpl::from_source(Source::from_js(&env, buffer)?);
+    protoscope::disassemble(&mut reader).into_napi(&env)
+}
+
+/// Assemble bytes from the protoscope-like language, which can also spell out
+/// malformed input such as overlong varints and wrong lengths
+#[napi]
+pub fn from_protoscope(env: Env, text: String) -> Result<Buffer> {
+    let data = protoscope::assemble(&text).into_napi(&env)?;
+    Ok(data.into())
+}
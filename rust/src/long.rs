@@ -0,0 +1,111 @@
+use napi::Env;
+use napi::Error;
+use napi::JsBigInt;
+use napi::JsNumber;
+use napi::JsObject;
+use napi::JsString;
+use napi::JsUnknown;
+use napi::Result;
+use napi::Status;
+use napi::ValueType;
+
+use crate::error::{ErrorKind, ProtoError};
+
+/// How 64-bit integers are handed back to JavaScript
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum LongRepr {
+    /// Plain number, losing precision above 2^53
+    Number,
+    /// Decimal string
+    String,
+    /// BigInt
+    BigInt,
+    /// Long.js-shaped `{ low, high, unsigned }` object
+    Long,
+}
+
+impl LongRepr {
+    /// Parses a `longs` option, defaulting to numbers
+    pub fn from_option(value: Option<&str>) -> Result<LongRepr> {
+        Ok(match value {
+            None | Some("number") => LongRepr::Number,
+            Some("string") => LongRepr::String,
+            Some("bigint") => LongRepr::BigInt,
+            Some("long") => LongRepr::Long,
+            Some(other) => {
+                return Err(Error::new(
+                    Status::InvalidArg,
+                    format!("invalid longs option: {}", other),
+                ))
+            }
+        })
+    }
+
+    /// Converts the two's complement `bits` to this representation
+    pub fn to_js(self, env: &Env, bits: u64, unsigned: bool) -> Result<JsUnknown> {
+        match self {
+            LongRepr::Number if unsigned => Ok(env.create_double(bits as f64)?.into_unknown()),
+            LongRepr::Number => Ok(env.create_double(bits as i64 as f64)?.into_unknown()),
+            LongRepr::String if unsigned => Ok(env.create_string(&bits.to_string())?.into_unknown()),
+            LongRepr::String => Ok(env
+                .create_string(&(bits as i64).to_string())?
+                .into_unknown()),
+            LongRepr::BigInt if unsigned => env.create_bigint_from_u64(bits)?.into_unknown(),
+            LongRepr::BigInt => env.create_bigint_from_i64(bits as i64)?.into_unknown(),
+            LongRepr::Long => {
+                // Long.js keeps both halves as signed 32-bit integers
+                let mut long = env.create_object()?;
+                long.set_named_property("low", env.create_int32(bits as u32 as i32)?)?;
+                long.set_named_property("high", env.create_int32((bits >> 32) as u32 as i32)?)?;
+                long.set_named_property("unsigned", env.get_boolean(unsigned)?)?;
+                Ok(long.into_unknown())
+            }
+        }
+    }
+}
+
+/// Reads the two's complement bits of a 64-bit value given as a number, BigInt,
+/// decimal string or Long-like `{ low, high }` object. Returns `None` for any other value.
+pub fn bits_of(value: &JsUnknown) -> Result<Option<u64>> {
+    Ok(match value.get_type()? {
+        ValueType::Number => {
+            let num = unsafe { value.cast::<JsNumber>() }.get_double()?;
+            if !num.is_finite() {
+                return Ok(None);
+            }
+            Some(if num < 0.0 { num as i64 as u64 } else { num as u64 })
+        }
+        ValueType::BigInt => {
+            // Truncates to the low 64 bits, which is the two's complement for either sign
+            let (bits, _) = unsafe { value.cast::<JsBigInt>() }.get_i64()?;
+            Some(bits as u64)
+        }
+        ValueType::String => {
+            let text = unsafe { value.cast::<JsString>() }.into_utf8()?;
+            let text = text.as_str()?.trim();
+            text.parse::<u64>()
+                .or_else(|_| text.parse::<i64>().map(|v| v as u64))
+                .ok()
+        }
+        ValueType::Object => {
+            let obj = unsafe { value.cast::<JsObject>() };
+            match (obj.get::<_, f64>("low")?, obj.get::<_, f64>("high")?) {
+                (Some(low), Some(high)) => {
+                    Some(((high as i64 as u32 as u64) << 32) | (low as i64 as u32 as u64))
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    })
+}
+
+/// Like `bits_of`, but rejects values that aren't 64-bit integers
+pub fn expect_bits(value: &JsUnknown) -> crate::error::Result<u64> {
+    bits_of(value)?.ok_or_else(|| {
+        ProtoError::new(
+            ErrorKind::TypeMismatch,
+            "integer, BigInt, decimal string or Long expected".to_owned(),
+        )
+    })
+}
@@ -0,0 +1,210 @@
+use napi::bindgen_prelude::Buffer;
+use napi_derive::napi;
+
+use crate::error::{ErrorKind, ProtoError, Result};
+use crate::tag;
+use crate::writer::WriterImpl;
+
+/// Operations of the compact batch format consumed by `Writer.encodeOps`.
+/// Operands are taken in order from the numeric side table unless noted otherwise.
+#[napi]
+#[derive(Debug, PartialEq, Eq)]
+pub enum Opcode {
+    /// varint from one uint32 operand
+    Uint32 = 0,
+    /// varint from one int32 operand, sign-extended to 10 bytes when negative
+    Int32 = 1,
+    /// zig-zag varint from one int32 operand
+    Sint32 = 2,
+    /// varint from two operands: low and high 32 bits
+    Uint64 = 3,
+    /// zig-zag varint from two operands: low and high 32 bits of a two's complement value
+    Sint64 = 4,
+    /// varint 0 or 1 from one operand
+    Bool = 5,
+    /// little-endian 32 bits from one uint32 operand
+    Fixed32 = 6,
+    /// little-endian 64 bits from two operands: low and high 32 bits
+    Fixed64 = 7,
+    /// 32-bit float from one operand
+    Float = 8,
+    /// 64-bit float from one operand
+    Double = 9,
+    /// length-prefixed bytes from the next buffer of the buffer table
+    Bytes = 10,
+    /// length-prefixed string; one operand holds its UTF-8 length within the string table
+    String = 11,
+    /// starts a length-delimited nested message, no operands
+    Fork = 12,
+    /// ends the innermost nested message, writing its length prefix
+    Ldelim = 13,
+    /// discards the innermost nested message
+    Reset = 14,
+    /// start group tag from one field number operand
+    GroupStart = 15,
+    /// end group tag from one field number operand
+    GroupEnd = 16,
+}
+
+impl Opcode {
+    #[inline]
+    fn from_u8(op: u8) -> Option<Opcode> {
+        Some(match op {
+            0 => Opcode::Uint32,
+            1 => Opcode::Int32,
+            2 => Opcode::Sint32,
+            3 => Opcode::Uint64,
+            4 => Opcode::Sint64,
+            5 => Opcode::Bool,
+            6 => Opcode::Fixed32,
+            7 => Opcode::Fixed64,
+            8 => Opcode::Float,
+            9 => Opcode::Double,
+            10 => Opcode::Bytes,
+            11 => Opcode::String,
+            12 => Opcode::Fork,
+            13 => Opcode::Ldelim,
+            14 => Opcode::Reset,
+            15 => Opcode::GroupStart,
+            16 => Opcode::GroupEnd,
+            _ => return None,
+        })
+    }
+}
+
+fn exhausted(table: &str, index: usize) -> ProtoError {
+    ProtoError::new(
+        ErrorKind::Malformed,
+        format!("{} table exhausted at opcode {}", table, index),
+    )
+}
+
+/// Sequential reader over the side tables of an opcode stream
+struct Operands<'a> {
+    numbers: &'a [f64],
+    number: usize,
+    strings: &'a [u8],
+    string: usize,
+    buffers: &'a [Buffer],
+    buffer: usize,
+}
+
+impl<'a> Operands<'a> {
+    #[inline]
+    fn number(&mut self, index: usize) -> Result<f64> {
+        let value = *self
+            .numbers
+            .get(self.number)
+            .ok_or_else(|| exhausted("number", index))?;
+        self.number += 1;
+        Ok(value)
+    }
+
+    #[inline]
+    fn bits64(&mut self, index: usize) -> Result<u64> {
+        let low = self.number(index)? as u32;
+        let high = self.number(index)? as u32;
+        Ok(((high as u64) << 32) | (low as u64))
+    }
+
+    #[inline]
+    fn string(&mut self, index: usize) -> Result<&'a [u8]> {
+        let len = self.number(index)? as usize;
+        let end = self.string + len;
+        if end > self.strings.len() {
+            return Err(exhausted("string", index));
+        }
+        let bytes = &self.strings[self.string..end];
+        self.string = end;
+        Ok(bytes)
+    }
+
+    #[inline]
+    fn buffer(&mut self, index: usize) -> Result<&'a [u8]> {
+        let buffer = self
+            .buffers
+            .get(self.buffer)
+            .ok_or_else(|| exhausted("buffer", index))?;
+        self.buffer += 1;
+        Ok(buffer.as_ref())
+    }
+}
+
+/// Replays an opcode stream onto `writer`
+pub fn encode_ops(
+    writer: &mut WriterImpl,
+    ops: &[u8],
+    numbers: &[f64],
+    strings: &[u8],
+    buffers: &[Buffer],
+) -> Result<()> {
+    let mut operands = Operands {
+        numbers,
+        number: 0,
+        strings,
+        string: 0,
+        buffers,
+        buffer: 0,
+    };
+
+    for (index, &op) in ops.iter().enumerate() {
+        let op = Opcode::from_u8(op).ok_or_else(|| {
+            ProtoError::new(
+                ErrorKind::Malformed,
+                format!("invalid opcode {} at {}", op, index),
+            )
+        })?;
+        match op {
+            Opcode::Uint32 => writer.write_varint32(operands.number(index)? as u32),
+            Opcode::Int32 => {
+                let value = operands.number(index)? as i32;
+                if value < 0 {
+                    writer.write_varint64(value as i64 as u64);
+                } else {
+                    writer.write_varint32(value as u32);
+                }
+            }
+            Opcode::Sint32 => writer.write_sint32(operands.number(index)? as i32),
+            Opcode::Uint64 => writer.write_varint64(operands.bits64(index)?),
+            Opcode::Sint64 => writer.write_sint64(operands.bits64(index)? as i64),
+            Opcode::Bool => {
+                writer.write_varint32(if operands.number(index)? != 0.0 { 1 } else { 0 })
+            }
+            Opcode::Fixed32 => writer.write_fixed32(operands.number(index)? as u32),
+            Opcode::Fixed64 => writer.write_fixed64(operands.bits64(index)?),
+            Opcode::Float => writer.write_float(operands.number(index)? as f32),
+            Opcode::Double => writer.write_double(operands.number(index)?),
+            Opcode::Bytes => {
+                let bytes = operands.buffer(index)?;
+                writer.write_varint32(bytes.len() as u32);
+                writer.write_bytes(bytes);
+            }
+            Opcode::String => {
+                let bytes = operands.string(index)?;
+                writer.write_varint32(bytes.len() as u32);
+                writer.write_bytes(bytes);
+            }
+            Opcode::Fork => writer.fork(),
+            Opcode::Ldelim => writer.ldelim(),
+            Opcode::Reset => writer.reset(),
+            Opcode::GroupStart | Opcode::GroupEnd => {
+                let field = operands.number(index)? as u32;
+                let wire_type = if op == Opcode::GroupStart { 3 } else { 4 };
+                tag::validate(field, wire_type)?;
+                writer.write_tag(field, wire_type);
+            }
+        }
+    }
+
+    if operands.string != strings.len() {
+        return Err(ProtoError::new(
+            ErrorKind::Malformed,
+            format!(
+                "string table length mismatch: {} of {} bytes used",
+                operands.string,
+                strings.len()
+            ),
+        ));
+    }
+    Ok(())
+}
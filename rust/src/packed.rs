@@ -0,0 +1,157 @@
+use crate::error::{ErrorKind, ProtoError, Result};
+use crate::reader::ReaderImpl;
+use crate::writer::WriterImpl;
+
+/// Decodes varints up to the end of a packed payload, which bounds every read.
+/// The payload's limit is popped again when a read fails.
+fn read_varints<T>(reader: &mut ReaderImpl, mut read: impl FnMut(&mut ReaderImpl) -> Result<T>) -> Result<Vec<T>> {
+    let len = reader.read_varint32()? as usize;
+    reader.push_limit(len)?;
+    let mut values = Vec::new();
+    let mut result = Ok(());
+    while !reader.at_end() {
+        match read(reader) {
+            Ok(value) => values.push(value),
+            Err(error) => {
+                result = Err(error);
+                break;
+            }
+        }
+    }
+    reader.pop_limit()?;
+    result.map(|()| values)
+}
+
+/// Copies a packed payload of little-endian fixed-width values in one go
+macro_rules! read_fixed {
+    ($name:ident, $type:ty) => {
+        pub fn $name(reader: &mut ReaderImpl) -> Result<Vec<$type>> {
+            const SIZE: usize = std::mem::size_of::<$type>();
+            let len = reader.read_varint32()? as usize;
+            if len % SIZE != 0 {
+                return Err(ProtoError::at(
+                    ErrorKind::Malformed,
+                    reader.pos(),
+                    format!("packed field length {} is not a multiple of {}", len, SIZE),
+                ));
+            }
+            let data = reader.read_slice(len)?;
+            #[cfg(target_endian = "little")]
+            {
+                let mut values = Vec::<$type>::with_capacity(len / SIZE);
+                // Safe: every bit pattern is a valid value and the wire order matches memory order
+                unsafe {
+                    std::ptr::copy_nonoverlapping(data.as_ptr(), values.as_mut_ptr() as *mut u8, len);
+                    values.set_len(len / SIZE);
+                }
+                Ok(values)
+            }
+            #[cfg(target_endian = "big")]
+            Ok(data
+                .chunks_exact(SIZE)
+                .map(|chunk| {
+                    let mut bytes = [0u8; SIZE];
+                    bytes.copy_from_slice(chunk);
+                    <$type>::from_le_bytes(bytes)
+                })
+                .collect())
+        }
+    };
+}
+
+read_fixed!(read_fixed32, u32);
+read_fixed!(read_sfixed32, i32);
+read_fixed!(read_fixed64, u64);
+read_fixed!(read_sfixed64, i64);
+read_fixed!(read_float, f32);
+read_fixed!(read_double, f64);
+
+pub fn read_int32(reader: &mut ReaderImpl) -> Result<Vec<i32>> {
+    read_varints(reader, |r| r.read_int32())
+}
+
+pub fn read_uint32(reader: &mut ReaderImpl) -> Result<Vec<u32>> {
+    read_varints(reader, |r| r.read_varint32())
+}
+
+pub fn read_sint32(reader: &mut ReaderImpl) -> Result<Vec<i32>> {
+    read_varints(reader, |r| r.read_sint32())
+}
+
+pub fn read_int64(reader: &mut ReaderImpl) -> Result<Vec<i64>> {
+    read_varints(reader, |r| Ok(r.read_varint64()? as i64))
+}
+
+pub fn read_uint64(reader: &mut ReaderImpl) -> Result<Vec<u64>> {
+    read_varints(reader, |r| r.read_varint64())
+}
+
+pub fn read_sint64(reader: &mut ReaderImpl) -> Result<Vec<i64>> {
+    read_varints(reader, |r| r.read_sint64())
+}
+
+pub fn read_bool(reader: &mut ReaderImpl) -> Result<Vec<u8>> {
+    read_varints(reader, |r| Ok((r.read_varint64()? != 0) as u8))
+}
+
+/// Writes fixed-width values as one length-prefixed little-endian block
+macro_rules! write_fixed {
+    ($name:ident, $type:ty) => {
+        pub fn $name(writer: &mut WriterImpl, values: &[$type]) {
+            let len = std::mem::size_of_val(values);
+            writer.write_varint32(len as u32);
+            #[cfg(target_endian = "little")]
+            // Safe: the slice is plain data whose memory order matches the wire order
+            writer.write_bytes(unsafe { std::slice::from_raw_parts(values.as_ptr() as *const u8, len) });
+            #[cfg(target_endian = "big")]
+            for value in values {
+                writer.write_bytes(&value.to_le_bytes());
+            }
+        }
+    };
+}
+
+write_fixed!(write_fixed32, u32);
+write_fixed!(write_sfixed32, i32);
+write_fixed!(write_fixed64, u64);
+write_fixed!(write_sfixed64, i64);
+write_fixed!(write_float, f32);
+write_fixed!(write_double, f64);
+
+/// Writes varints into a forked length-delimited block
+fn write_varints<T: Copy>(writer: &mut WriterImpl, values: &[T], mut write: impl FnMut(&mut WriterImpl, T)) {
+    writer.fork();
+    for &value in values {
+        write(writer, value);
+    }
+    writer.ldelim();
+}
+
+pub fn write_int32(writer: &mut WriterImpl, values: &[i32]) {
+    // Negative int32 is encoded as 10-byte varint
+    write_varints(writer, values, |w, v| w.write_varint64(v as i64 as u64))
+}
+
+pub fn write_uint32(writer: &mut WriterImpl, values: &[u32]) {
+    write_varints(writer, values, |w, v| w.write_varint32(v))
+}
+
+pub fn write_sint32(writer: &mut WriterImpl, values: &[i32]) {
+    write_varints(writer, values, |w, v| w.write_sint32(v))
+}
+
+pub fn write_int64(writer: &mut WriterImpl, values: &[i64]) {
+    write_varints(writer, values, |w, v| w.write_varint64(v as u64))
+}
+
+pub fn write_uint64(writer: &mut WriterImpl, values: &[u64]) {
+    write_varints(writer, values, |w, v| w.write_varint64(v))
+}
+
+pub fn write_sint64(writer: &mut WriterImpl, values: &[i64]) {
+    write_varints(writer, values, |w, v| w.write_sint64(v))
+}
+
+pub fn write_bool(writer: &mut WriterImpl, values: &[u8]) {
+    write_varints(writer, values, |w, v| w.write_varint32((v != 0) as u32))
+}
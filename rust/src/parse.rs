@@ -0,0 +1,1490 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use napi_derive::napi;
+use serde_json::{Map, Number, Value};
+
+use crate::descriptor_set;
+use crate::error::{ErrorKind, ProtoError, Result};
+use crate::text::{self, Token, Tokenizer};
+
+/// Options of `parseProto` and `registerProto`
+#[napi(object)]
+#[derive(Default)]
+pub struct ParseProtoOptions {
+    /// Sources by import path, searched before `includePaths`
+    pub files: Option<HashMap<String, String>>,
+    /// Directories searched for files and imports, in order. Defaults to the
+    /// working directory unless `files` is given.
+    pub include_paths: Option<Vec<String>>,
+    /// Keeps field names as declared instead of converting them to camelCase
+    pub keep_case: Option<bool>,
+    /// Adds the comments of elements like `toJSON({ keepComments: true })`
+    pub keep_comments: Option<bool>,
+}
+
+/// The google/protobuf files protobuf.js bundles as `protobuf.common`, which
+/// imports resolve to before any source, with the same field names
+const COMMON: &[(&str, &str)] = &[
+    (
+        "google/protobuf/any.proto",
+        "message Any { string type_url = 1; bytes value = 2; }",
+    ),
+    (
+        "google/protobuf/duration.proto",
+        "message Duration { int64 seconds = 1; int32 nanos = 2; }",
+    ),
+    (
+        "google/protobuf/timestamp.proto",
+        "message Timestamp { int64 seconds = 1; int32 nanos = 2; }",
+    ),
+    ("google/protobuf/empty.proto", "message Empty {}"),
+    (
+        "google/protobuf/struct.proto",
+        "message Struct { map<string, Value> fields = 1; } \
+         message Value { oneof kind { NullValue nullValue = 1; double numberValue = 2; string stringValue = 3; \
+         bool boolValue = 4; Struct structValue = 5; ListValue listValue = 6; } } \
+         enum NullValue { NULL_VALUE = 0; } \
+         message ListValue { repeated Value values = 1; }",
+    ),
+    (
+        "google/protobuf/wrappers.proto",
+        "message DoubleValue { double value = 1; } message FloatValue { float value = 1; } \
+         message Int64Value { int64 value = 1; } message UInt64Value { uint64 value = 1; } \
+         message Int32Value { int32 value = 1; } message UInt32Value { uint32 value = 1; } \
+         message BoolValue { bool value = 1; } message StringValue { string value = 1; } \
+         message BytesValue { bytes value = 1; }",
+    ),
+    (
+        "google/protobuf/field_mask.proto",
+        "message FieldMask { repeated string paths = 1; }",
+    ),
+];
+
+const MAP_KEY_TYPES: &[&str] = &[
+    "int32", "int64", "uint32", "uint64", "sint32", "sint64", "fixed32", "fixed64", "sfixed32", "sfixed64", "bool",
+    "string",
+];
+
+const MAX_FIELD_NUMBER: i64 = 536870911;
+
+/// Parses `files` and their imports to protobuf.js `Root.toJSON()` JSON
+pub fn parse(files: &[String], options: &ParseProtoOptions) -> Result<Value> {
+    let mut loader = Loader {
+        options,
+        loaded: HashSet::new(),
+        root: Map::new(),
+    };
+    for file in files {
+        if loader.loaded.contains(file.as_str()) {
+            continue;
+        }
+        match loader.source(file)? {
+            Some(source) => loader.load(file, &source)?,
+            None => {
+                return Err(ProtoError::new(
+                    ErrorKind::Malformed,
+                    format!("file not found: {}", file),
+                ))
+            }
+        }
+    }
+    Ok(Value::Object(loader.root))
+}
+
+struct Loader<'o> {
+    options: &'o ParseProtoOptions,
+    /// Import paths of the files parsed so far, which are skipped when imported again
+    loaded: HashSet<String>,
+    root: Map<String, Value>,
+}
+
+enum Source {
+    Text(String),
+    /// A file of `COMMON`, which is parsed as proto3 in `google.protobuf` keeping the field names
+    Common(&'static str),
+}
+
+impl Loader<'_> {
+    fn source(&self, name: &str) -> Result<Option<Source>> {
+        if let Some((_, text)) = COMMON.iter().find(|(path, _)| *path == name) {
+            return Ok(Some(Source::Common(text)));
+        }
+        if let Some(text) = self.options.files.as_ref().and_then(|files| files.get(name)) {
+            return Ok(Some(Source::Text(text.clone())));
+        }
+        let default_paths = [".".to_string()];
+        let include_paths = match (&self.options.include_paths, &self.options.files) {
+            (Some(paths), _) => paths.as_slice(),
+            (None, Some(_)) => &[],
+            (None, None) => &default_paths,
+        };
+        for dir in include_paths {
+            let path = Path::new(dir).join(name);
+            match std::fs::read_to_string(&path) {
+                Ok(text) => return Ok(Some(Source::Text(text))),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => {
+                    return Err(ProtoError::new(
+                        ErrorKind::Malformed,
+                        format!("can't read {}: {}", path.display(), e),
+                    ))
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Parses a file into the root, then its imports
+    fn load(&mut self, name: &str, source: &Source) -> Result<()> {
+        self.loaded.insert(name.to_string());
+        let (text, common) = match source {
+            Source::Text(text) => (text.as_str(), false),
+            Source::Common(text) => (*text, true),
+        };
+        let mut parser = Parser {
+            tokens: Tokenizer::proto(name, text),
+            keep_case: common || self.options.keep_case.unwrap_or(false),
+            keep_comments: self.options.keep_comments.unwrap_or(false),
+            edition: if common { "proto3" } else { "proto2" },
+        };
+        let mut file = parser.file()?;
+        if common {
+            file.package = Some("google.protobuf".to_string());
+        }
+        let imports = std::mem::take(&mut file.imports);
+        file.merge(&mut self.root).map_err(|e| e.prefixed(name))?;
+
+        for import in &imports {
+            if self.loaded.contains(&import.path) {
+                continue;
+            }
+            match self.source(&import.path)? {
+                Some(source) => self.load(&import.path, &source)?,
+                None if import.weak => {}
+                None => {
+                    return Err(parser
+                        .tokens
+                        .error_at(import.offset, format!("import \"{}\" not found", import.path)))
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Import {
+    path: String,
+    weak: bool,
+    /// Offset of the path in the importing file
+    offset: usize,
+}
+
+/// A named element of a `nested` object
+struct Element {
+    name: String,
+    json: Value,
+    /// Offset of the name, for duplicate errors
+    offset: usize,
+}
+
+#[derive(Default)]
+struct File {
+    package: Option<String>,
+    imports: Vec<Import>,
+    /// Top-level messages, enums, services and extension fields
+    nested: Map<String, Value>,
+    /// `option features.*` values, which protobuf.js keeps on the top-level elements
+    features: Vec<(String, Value)>,
+    /// Other file options with the package they were declared in, whose namespace keeps them
+    options: Vec<(Option<String>, String, Value)>,
+    edition: &'static str,
+}
+
+impl File {
+    fn merge(mut self, root: &mut Map<String, Value>) -> Result<()> {
+        for (package, name, value) in self.options.drain(..) {
+            let namespace = descriptor_set::namespace(root, package.as_deref().unwrap_or_default());
+            if let Value::Object(options) = namespace
+                .entry("options")
+                .or_insert_with(|| Value::Object(Map::new()))
+            {
+                set_option(options, &name, value);
+            }
+        }
+        let namespace = descriptor_set::namespace(root, self.package.as_deref().unwrap_or_default());
+        if self.nested.is_empty() {
+            return Ok(());
+        }
+        let nested = namespace
+            .entry("nested")
+            .or_insert_with(|| Value::Object(Map::new()));
+        let Value::Object(nested) = nested else {
+            unreachable!("nested is always an object")
+        };
+        for (name, json) in self.nested {
+            let Value::Object(element) = json else {
+                unreachable!("elements are always objects")
+            };
+            let mut json = Map::new();
+            if self.edition != "proto3" {
+                json.insert("edition".to_string(), Value::String(self.edition.to_string()));
+            }
+            json.extend(element);
+            if !self.features.is_empty() {
+                let options = json
+                    .entry("options")
+                    .or_insert_with(|| Value::Object(Map::new()));
+                if let Value::Object(options) = options {
+                    for (feature, value) in &self.features {
+                        set_property(options, feature, value.clone(), true);
+                    }
+                }
+            }
+            descriptor_set::insert(nested, &name, Value::Object(json))?;
+        }
+        Ok(())
+    }
+}
+
+/// A message while its body is read
+#[derive(Default)]
+struct Message {
+    options: Map<String, Value>,
+    oneofs: Map<String, Value>,
+    fields: Map<String, Value>,
+    extensions: Vec<Value>,
+    reserved: Vec<Value>,
+    group: bool,
+    nested: Map<String, Value>,
+    /// Name and name offset of the fields by number
+    numbers: HashMap<i64, (String, usize)>,
+}
+
+impl Message {
+    fn is_taken(&self, name: &str) -> bool {
+        self.fields.contains_key(name) || self.oneofs.contains_key(name) || self.nested.contains_key(name)
+    }
+
+    fn into_json(self) -> Map<String, Value> {
+        let mut json = Map::new();
+        if !self.options.is_empty() {
+            json.insert("options".to_string(), Value::Object(self.options));
+        }
+        if !self.oneofs.is_empty() {
+            json.insert("oneofs".to_string(), Value::Object(self.oneofs));
+        }
+        json.insert("fields".to_string(), Value::Object(self.fields));
+        if !self.extensions.is_empty() {
+            json.insert("extensions".to_string(), Value::Array(self.extensions));
+        }
+        if !self.reserved.is_empty() {
+            json.insert("reserved".to_string(), Value::Array(self.reserved));
+        }
+        if self.group {
+            json.insert("group".to_string(), Value::Bool(true));
+        }
+        if !self.nested.is_empty() {
+            json.insert("nested".to_string(), Value::Object(self.nested));
+        }
+        json
+    }
+}
+
+/// A field with the type of its group, if it is one
+struct Field {
+    name: String,
+    offset: usize,
+    number: i64,
+    json: Map<String, Value>,
+    /// The synthetic oneof of a proto3 `optional` field
+    synthetic: bool,
+    group: Option<Element>,
+}
+
+struct Parser<'t> {
+    tokens: Tokenizer<'t>,
+    keep_case: bool,
+    keep_comments: bool,
+    /// `"proto2"`, `"proto3"`, `"2023"` or `"2024"`
+    edition: &'static str,
+}
+
+impl<'t> Parser<'t> {
+    fn is_editions(&self) -> bool {
+        !matches!(self.edition, "proto2" | "proto3")
+    }
+
+    fn file(&mut self) -> Result<File> {
+        let mut file = File::default();
+        let mut head = true;
+        loop {
+            let keyword = match self.tokens.peek()? {
+                Token::End => break,
+                Token::Ident(keyword) => *keyword,
+                Token::Symbol(b';') => {
+                    self.tokens.next()?;
+                    continue;
+                }
+                _ => return Err(self.unexpected_next("declaration")),
+            };
+            match keyword {
+                "package" | "import" | "syntax" | "edition" if !head => {
+                    return Err(self
+                        .tokens
+                        .error(format!("'{}' must come before any definitions", keyword)))
+                }
+                "package" => {
+                    self.tokens.next()?;
+                    if file.package.is_some() {
+                        return Err(self.tokens.error("duplicate package".to_string()));
+                    }
+                    file.package = Some(self.type_name()?);
+                    self.tokens.expect_symbol(b';')?;
+                }
+                "import" => {
+                    self.tokens.next()?;
+                    self.import(&mut file)?;
+                }
+                "syntax" => {
+                    self.tokens.next()?;
+                    self.tokens.expect_symbol(b'=')?;
+                    self.edition = match self.string()?.as_str() {
+                        "proto2" => "proto2",
+                        "proto3" => "proto3",
+                        syntax => return Err(self.tokens.error(format!("unsupported syntax \"{}\"", syntax))),
+                    };
+                    self.tokens.expect_symbol(b';')?;
+                }
+                "edition" => {
+                    self.tokens.next()?;
+                    self.tokens.expect_symbol(b'=')?;
+                    self.edition = match self.string()?.as_str() {
+                        "2023" => "2023",
+                        "2024" => "2024",
+                        edition => return Err(self.tokens.error(format!("unsupported edition \"{}\"", edition))),
+                    };
+                    self.tokens.expect_symbol(b';')?;
+                }
+                "option" => {
+                    self.tokens.next()?;
+                    let option = self.option()?;
+                    for (name, value) in option.settings {
+                        if name.starts_with("features.") {
+                            file.features.retain(|(feature, _)| *feature != name);
+                            file.features.push((name, value));
+                        } else {
+                            file.options.push((file.package.clone(), name, value));
+                        }
+                    }
+                    self.tokens.try_symbol(b';')?;
+                }
+                _ => {
+                    for element in self.declaration()? {
+                        insert(&self.tokens, &mut file.nested, element)?;
+                    }
+                    head = false;
+                }
+            }
+        }
+        file.edition = self.edition;
+        Ok(file)
+    }
+
+    fn import(&mut self, file: &mut File) -> Result<()> {
+        let mut weak = false;
+        match self.tokens.peek()? {
+            Token::Ident("weak") => {
+                self.tokens.next()?;
+                weak = true;
+            }
+            Token::Ident("public") => {
+                self.tokens.next()?;
+            }
+            Token::Ident("option") => {
+                self.tokens.next()?;
+                if self.edition != "2024" {
+                    return Err(self.tokens.error("'import option' requires edition 2024".to_string()));
+                }
+                self.string()?;
+                return self.tokens.expect_symbol(b';');
+            }
+            _ => {}
+        }
+        self.tokens.peek()?;
+        let offset = self.tokens.start;
+        let path = self.string()?;
+        self.tokens.expect_symbol(b';')?;
+        file.imports.push(Import { path, weak, offset });
+        Ok(())
+    }
+
+    /// Reads a message, enum, service or extend block, which are allowed in files,
+    /// messages and services, and returns the elements it declares
+    fn declaration(&mut self) -> Result<Vec<Element>> {
+        let start = self.peek_offset()?;
+        let mut keyword = self.ident("declaration")?;
+        if matches!(keyword, "export" | "local") {
+            if self.edition != "2024" {
+                return Err(self.tokens.error_at(start, format!("'{}' requires edition 2024", keyword)));
+            }
+            keyword = self.ident("'message' or 'enum'")?;
+            if !matches!(keyword, "message" | "enum") {
+                return Err(self.tokens.error(format!("expected 'message' or 'enum', got '{}'", keyword)));
+            }
+        }
+        match keyword {
+            "message" => Ok(vec![self.message(start)?]),
+            "enum" => Ok(vec![self.enumeration(start)?]),
+            "service" => Ok(vec![self.service(start)?]),
+            "extend" => self.extend(),
+            _ => Err(self.tokens.error(format!("unexpected '{}'", keyword))),
+        }
+    }
+
+    fn message(&mut self, start: usize) -> Result<Element> {
+        let offset = self.peek_offset()?;
+        let name = self.ident("message name")?.to_string();
+        let comment = self.leading_comment(start);
+        self.tokens.expect_symbol(b'{')?;
+        let message = self.message_body(Message::default())?;
+        self.tokens.try_symbol(b';')?;
+        Ok(Element {
+            name,
+            json: self.commented(message.into_json(), comment),
+            offset,
+        })
+    }
+
+    /// Reads the declarations of a message or group up to its closing brace
+    fn message_body(&mut self, mut message: Message) -> Result<Message> {
+        while !self.tokens.try_symbol(b'}')? {
+            let start = self.peek_offset()?;
+            let keyword = match self.tokens.peek()? {
+                Token::Ident(keyword) => *keyword,
+                Token::Symbol(b';') => {
+                    self.tokens.next()?;
+                    continue;
+                }
+                _ => return Err(self.unexpected_next("field or declaration")),
+            };
+            match keyword {
+                "option" => {
+                    self.tokens.next()?;
+                    self.option_statement(&mut message.options)?;
+                }
+                "message" | "enum" | "service" | "extend" | "export" | "local" => {
+                    for element in self.declaration()? {
+                        if message.is_taken(&element.name) {
+                            return Err(self.duplicate(&element));
+                        }
+                        insert(&self.tokens, &mut message.nested, element)?;
+                    }
+                }
+                "oneof" => {
+                    self.tokens.next()?;
+                    self.oneof(&mut message, start)?;
+                }
+                "extensions" => {
+                    self.tokens.next()?;
+                    let ranges = self.ranges(false, false)?;
+                    message.extensions.extend(ranges);
+                }
+                "reserved" => {
+                    self.tokens.next()?;
+                    let ranges = self.ranges(true, false)?;
+                    message.reserved.extend(ranges);
+                }
+                _ => {
+                    let field = self.labeled_field(start, None)?;
+                    self.add_field(&mut message, field, None)?;
+                }
+            }
+        }
+        self.check_reserved(&message)?;
+        Ok(message)
+    }
+
+    /// Reads a field with its optional label, or a map field
+    fn labeled_field(&mut self, start: usize, extend: Option<&str>) -> Result<Field> {
+        let keyword = match self.tokens.peek()? {
+            Token::Ident(keyword) => *keyword,
+            _ => return Err(self.unexpected_next("field")),
+        };
+        let rule = match keyword {
+            "required" if self.edition != "proto2" => {
+                return Err(self.tokens.error("'required' is only allowed in proto2".to_string()))
+            }
+            "optional" if self.is_editions() => {
+                return Err(self
+                    .tokens
+                    .error("'optional' is not allowed in editions, use features.field_presence".to_string()))
+            }
+            "optional" if self.edition == "proto3" => Some("proto3_optional"),
+            "required" => Some("required"),
+            "optional" => Some("optional"),
+            "repeated" => Some("repeated"),
+            "map" if extend.is_none() => {
+                self.tokens.next()?;
+                if self.tokens.try_symbol(b'<')? {
+                    return self.map_field(start);
+                }
+                return self.field(start, None, extend, Some("map"));
+            }
+            _ if self.edition == "proto2" => {
+                return Err(self.tokens.error(format!("missing label before '{}'", keyword)))
+            }
+            _ => None,
+        };
+        if rule.is_some() {
+            self.tokens.next()?;
+        }
+        self.field(start, rule, extend, None)
+    }
+
+    /// Reads a field after its label. `type_name` is its first name part when
+    /// it has been read already.
+    fn field(
+        &mut self,
+        start: usize,
+        rule: Option<&'static str>,
+        extend: Option<&str>,
+        type_name: Option<&str>,
+    ) -> Result<Field> {
+        if type_name.is_none() && matches!(self.tokens.peek()?, Token::Ident("group")) {
+            self.tokens.next()?;
+            return self.group(start, rule, extend);
+        }
+        let field_type = match type_name {
+            Some(first) => self.type_name_after(first.to_string())?,
+            None => self.type_name()?,
+        };
+        let offset = self.peek_offset()?;
+        let name = self.ident("field name")?;
+        let name = if self.keep_case { name.to_string() } else { camel_case(name) };
+        self.tokens.expect_symbol(b'=')?;
+        let number = self.field_number()?;
+
+        let mut json = Map::new();
+        if let Some(rule @ ("required" | "repeated")) = rule {
+            json.insert("rule".to_string(), Value::String(rule.to_string()));
+        }
+        json.insert("type".to_string(), Value::String(field_type));
+        json.insert("id".to_string(), Value::from(number));
+        if let Some(extend) = extend {
+            json.insert("extend".to_string(), Value::String(extend.to_string()));
+        }
+        let mut options = Map::new();
+        self.inline_options(&mut options)?;
+        let synthetic = rule == Some("proto3_optional");
+        if synthetic {
+            options.insert("proto3_optional".to_string(), Value::Bool(true));
+        }
+        if !options.is_empty() {
+            json.insert("options".to_string(), Value::Object(options));
+        }
+        let comment = self.statement_comment(start)?;
+        Ok(Field {
+            name,
+            offset,
+            number,
+            json: self.comment_into(json, comment),
+            synthetic,
+            group: None,
+        })
+    }
+
+    fn map_field(&mut self, start: usize) -> Result<Field> {
+        self.tokens.peek()?;
+        let key_offset = self.tokens.start;
+        let key_type = self.ident("map key type")?;
+        if !MAP_KEY_TYPES.contains(&key_type) {
+            return Err(self.tokens.error_at(key_offset, format!("invalid map key type '{}'", key_type)));
+        }
+        self.tokens.expect_symbol(b',')?;
+        let value_type = self.type_name()?;
+        self.tokens.expect_symbol(b'>')?;
+        let offset = self.peek_offset()?;
+        let name = self.ident("field name")?;
+        let name = if self.keep_case { name.to_string() } else { camel_case(name) };
+        self.tokens.expect_symbol(b'=')?;
+        let number = self.field_number()?;
+
+        let mut json = Map::new();
+        json.insert("keyType".to_string(), Value::String(key_type.to_string()));
+        json.insert("type".to_string(), Value::String(value_type));
+        json.insert("id".to_string(), Value::from(number));
+        let mut options = Map::new();
+        self.inline_options(&mut options)?;
+        if !options.is_empty() {
+            json.insert("options".to_string(), Value::Object(options));
+        }
+        let comment = self.statement_comment(start)?;
+        Ok(Field {
+            name,
+            offset,
+            number,
+            json: self.comment_into(json, comment),
+            synthetic: false,
+            group: None,
+        })
+    }
+
+    /// Reads a proto2 group, a field whose message type is declared in place
+    fn group(&mut self, start: usize, rule: Option<&'static str>, extend: Option<&str>) -> Result<Field> {
+        if self.is_editions() {
+            return Err(self.tokens.error_at(
+                start,
+                "groups are not allowed in editions, use features.message_encoding = DELIMITED".to_string(),
+            ));
+        }
+        let offset = self.peek_offset()?;
+        let mut type_name = self.ident("group name")?.to_string();
+        let name = lc_first(&type_name);
+        if name == type_name {
+            type_name = uc_first(&type_name);
+        }
+        self.tokens.expect_symbol(b'=')?;
+        let number = self.field_number()?;
+
+        let mut json = Map::new();
+        if let Some(rule @ ("required" | "repeated")) = rule {
+            json.insert("rule".to_string(), Value::String(rule.to_string()));
+        }
+        json.insert("type".to_string(), Value::String(type_name.clone()));
+        json.insert("id".to_string(), Value::from(number));
+        if let Some(extend) = extend {
+            json.insert("extend".to_string(), Value::String(extend.to_string()));
+        }
+        let mut options = Map::new();
+        self.inline_options(&mut options)?;
+        if !options.is_empty() {
+            json.insert("options".to_string(), Value::Object(options));
+        }
+        let comment = self.leading_comment(start);
+        self.tokens.expect_symbol(b'{')?;
+        let body = self.message_body(Message {
+            group: true,
+            ..Message::default()
+        })?;
+        Ok(Field {
+            name,
+            offset,
+            number,
+            json: self.comment_into(json, Value::Null),
+            synthetic: false,
+            group: Some(Element {
+                name: type_name,
+                json: self.commented(body.into_json(), comment),
+                offset,
+            }),
+        })
+    }
+
+    /// Adds a field to a message and, with `oneof`, to the field list of that oneof
+    fn add_field(&self, message: &mut Message, field: Field, oneof: Option<&mut Vec<Value>>) -> Result<()> {
+        if let Some(group) = field.group {
+            if message.is_taken(&group.name) {
+                return Err(self.duplicate(&group));
+            }
+            message.nested.insert(group.name, group.json);
+        }
+        if message.is_taken(&field.name) {
+            return Err(self.tokens.error_at(field.offset, format!("duplicate name '{}'", field.name)));
+        }
+        if let Some((other, _)) = message.numbers.get(&field.number) {
+            return Err(self.tokens.error_at(
+                field.offset,
+                format!("field number {} is already used by '{}'", field.number, other),
+            ));
+        }
+        message.numbers.insert(field.number, (field.name.clone(), field.offset));
+        if field.synthetic {
+            let oneof_name = format!("_{}", field.name);
+            if message.is_taken(&oneof_name) {
+                return Err(self.tokens.error_at(field.offset, format!("duplicate name '{}'", oneof_name)));
+            }
+            let mut json = Map::new();
+            json.insert("oneof".to_string(), Value::Array(vec![Value::String(field.name.clone())]));
+            message.oneofs.insert(oneof_name, self.comment_into(json, Value::Null).into());
+        }
+        if let Some(oneof) = oneof {
+            oneof.push(Value::String(field.name.clone()));
+        }
+        message.fields.insert(field.name, Value::Object(field.json));
+        Ok(())
+    }
+
+    /// Errors for fields whose number or name is reserved
+    fn check_reserved(&self, message: &Message) -> Result<()> {
+        let mut fields: Vec<_> = message.numbers.iter().collect();
+        fields.sort_by_key(|(_, (_, offset))| *offset);
+        for (number, (name, offset)) in fields {
+            for reserved in &message.reserved {
+                let taken = match reserved {
+                    Value::String(reserved) => reserved == name,
+                    Value::Array(range) => {
+                        range[0].as_i64().is_some_and(|start| start <= *number)
+                            && range[1].as_i64().is_some_and(|end| *number <= end)
+                    }
+                    _ => false,
+                };
+                if taken {
+                    return Err(self.tokens.error_at(
+                        *offset,
+                        format!("field '{}' uses reserved {}", name, match reserved {
+                            Value::String(_) => "name".to_string(),
+                            _ => format!("number {}", number),
+                        }),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn oneof(&mut self, message: &mut Message, start: usize) -> Result<()> {
+        let offset = self.peek_offset()?;
+        let name = self.ident("oneof name")?;
+        let name = if self.keep_case { name.to_string() } else { camel_case(name) };
+        if message.is_taken(&name) {
+            return Err(self.tokens.error_at(offset, format!("duplicate name '{}'", name)));
+        }
+        let comment = self.leading_comment(start);
+        self.tokens.expect_symbol(b'{')?;
+        let mut options = Map::new();
+        let mut fields = Vec::new();
+        while !self.tokens.try_symbol(b'}')? {
+            let start = self.peek_offset()?;
+            match self.tokens.peek()? {
+                Token::Ident("option") => {
+                    self.tokens.next()?;
+                    self.option_statement(&mut options)?;
+                }
+                Token::Symbol(b';') => {
+                    self.tokens.next()?;
+                }
+                Token::Ident("required" | "optional" | "repeated") => {
+                    return Err(self.tokens.error("oneof fields can't have labels".to_string()))
+                }
+                _ => {
+                    let field = self.field(start, None, None, None)?;
+                    self.add_field(message, field, Some(&mut fields))?;
+                }
+            }
+        }
+        let mut json = Map::new();
+        if !options.is_empty() {
+            json.insert("options".to_string(), Value::Object(options));
+        }
+        json.insert("oneof".to_string(), Value::Array(fields));
+        message.oneofs.insert(name, self.comment_into(json, comment).into());
+        Ok(())
+    }
+
+    fn enumeration(&mut self, start: usize) -> Result<Element> {
+        let offset = self.peek_offset()?;
+        let name = self.ident("enum name")?.to_string();
+        let comment = self.leading_comment(start);
+        self.tokens.expect_symbol(b'{')?;
+        let mut options = Map::new();
+        let mut values_options = Map::new();
+        let mut values = Map::new();
+        let mut reserved = Vec::new();
+        let mut comments = Map::new();
+        // Offsets of the values, for the alias check once `allow_alias` is known
+        let mut offsets = Vec::new();
+        while !self.tokens.try_symbol(b'}')? {
+            let start = self.peek_offset()?;
+            match self.ident_or_semicolon("enum value")? {
+                None => {}
+                Some("option") => self.option_statement(&mut options)?,
+                Some("reserved") => reserved.extend(self.ranges(true, true)?),
+                Some(value_name) => {
+                    if values.contains_key(value_name) {
+                        return Err(self.tokens.error_at(start, format!("duplicate name '{}'", value_name)));
+                    }
+                    self.tokens.expect_symbol(b'=')?;
+                    let number = self.number(true)?;
+                    if i32::try_from(number).is_err() {
+                        return Err(self.tokens.error(format!("enum value {} out of range", number)));
+                    }
+                    let mut value_options = Map::new();
+                    self.inline_options(&mut value_options)?;
+                    let value_comment = self.statement_comment(start)?;
+                    if !value_options.is_empty() {
+                        values_options.insert(value_name.to_string(), Value::Object(value_options));
+                    }
+                    values.insert(value_name.to_string(), Value::from(number));
+                    comments.insert(value_name.to_string(), value_comment);
+                    offsets.push((value_name, number, start));
+                }
+            }
+        }
+        self.tokens.try_symbol(b';')?;
+
+        if options.get("allow_alias") != Some(&Value::Bool(true)) {
+            let mut seen = HashMap::new();
+            for (value_name, number, start) in offsets {
+                if let Some(other) = seen.insert(number, value_name) {
+                    return Err(self.tokens.error_at(
+                        start,
+                        format!("'{}' reuses value {} of '{}' without option allow_alias", value_name, number, other),
+                    ));
+                }
+            }
+        }
+        for value in &reserved {
+            let taken = values.iter().find(|(value_name, number)| match value {
+                Value::String(reserved) => reserved == *value_name,
+                Value::Array(range) => range[0].as_i64() <= number.as_i64() && number.as_i64() <= range[1].as_i64(),
+                _ => false,
+            });
+            if let Some((value_name, _)) = taken {
+                return Err(self.tokens.error_at(offset, format!("enum value '{}' is reserved", value_name)));
+            }
+        }
+
+        let mut json = Map::new();
+        if !options.is_empty() {
+            json.insert("options".to_string(), Value::Object(options));
+        }
+        if !values_options.is_empty() {
+            json.insert("valuesOptions".to_string(), Value::Object(values_options));
+        }
+        json.insert("values".to_string(), Value::Object(values));
+        if !reserved.is_empty() {
+            json.insert("reserved".to_string(), Value::Array(reserved));
+        }
+        let mut json = self.comment_into(json, comment);
+        if self.keep_comments {
+            json.insert("comments".to_string(), Value::Object(comments));
+        }
+        Ok(Element {
+            name,
+            json: Value::Object(json),
+            offset,
+        })
+    }
+
+    fn service(&mut self, start: usize) -> Result<Element> {
+        let offset = self.peek_offset()?;
+        let name = self.ident("service name")?.to_string();
+        let comment = self.leading_comment(start);
+        self.tokens.expect_symbol(b'{')?;
+        let mut options = Map::new();
+        let mut methods = Map::new();
+        let mut nested = Map::new();
+        while !self.tokens.try_symbol(b'}')? {
+            let start = self.peek_offset()?;
+            let keyword = match self.tokens.peek()? {
+                Token::Ident(keyword) => *keyword,
+                Token::Symbol(b';') => {
+                    self.tokens.next()?;
+                    continue;
+                }
+                _ => return Err(self.unexpected_next("'rpc'")),
+            };
+            match keyword {
+                "option" => {
+                    self.tokens.next()?;
+                    self.option_statement(&mut options)?;
+                }
+                "rpc" => {
+                    self.tokens.next()?;
+                    let method = self.method(start)?;
+                    if methods.contains_key(&method.name) || nested.contains_key(&method.name) {
+                        return Err(self.duplicate(&method));
+                    }
+                    methods.insert(method.name, method.json);
+                }
+                _ => {
+                    for element in self.declaration()? {
+                        if methods.contains_key(&element.name) {
+                            return Err(self.duplicate(&element));
+                        }
+                        insert(&self.tokens, &mut nested, element)?;
+                    }
+                }
+            }
+        }
+        self.tokens.try_symbol(b';')?;
+        let mut json = Map::new();
+        if !options.is_empty() {
+            json.insert("options".to_string(), Value::Object(options));
+        }
+        json.insert("methods".to_string(), Value::Object(methods));
+        if !nested.is_empty() {
+            json.insert("nested".to_string(), Value::Object(nested));
+        }
+        Ok(Element {
+            name,
+            json: self.commented(json, comment),
+            offset,
+        })
+    }
+
+    fn method(&mut self, start: usize) -> Result<Element> {
+        let offset = self.peek_offset()?;
+        let name = self.ident("method name")?.to_string();
+        let mut json = Map::new();
+        for (i, key) in ["request", "response"].into_iter().enumerate() {
+            if i == 1 && self.ident("'returns'")? != "returns" {
+                return Err(self.tokens.error("expected 'returns'".to_string()));
+            }
+            self.tokens.expect_symbol(b'(')?;
+            let stream = matches!(self.tokens.peek()?, Token::Ident("stream"));
+            if stream {
+                self.tokens.next()?;
+            }
+            json.insert(format!("{}Type", key), Value::String(self.type_name()?));
+            if stream {
+                json.insert(format!("{}Stream", key), Value::Bool(true));
+            }
+            self.tokens.expect_symbol(b')')?;
+        }
+        let mut options = Map::new();
+        let mut parsed = Vec::new();
+        let comment = if self.tokens.try_symbol(b'{')? {
+            let comment = self.leading_comment(start);
+            while !self.tokens.try_symbol(b'}')? {
+                match self.ident_or_semicolon("'option'")? {
+                    None => {}
+                    Some("option") => {
+                        let option = self.option()?;
+                        for (name, value) in option.settings {
+                            set_option(&mut options, &name, value);
+                        }
+                        set_parsed_option(&mut parsed, &option.name, option.path.as_deref(), option.value);
+                        self.tokens.expect_symbol(b';')?;
+                    }
+                    Some(keyword) => return Err(self.tokens.error(format!("expected 'option', got '{}'", keyword))),
+                }
+            }
+            self.tokens.try_symbol(b';')?;
+            comment
+        } else {
+            self.statement_comment(start)?
+        };
+        if !options.is_empty() {
+            json.insert("options".to_string(), Value::Object(options));
+        }
+        let mut json = self.comment_into(json, comment);
+        if !parsed.is_empty() {
+            json.insert("parsedOptions".to_string(), Value::Array(parsed));
+        }
+        Ok(Element {
+            name,
+            json: Value::Object(json),
+            offset,
+        })
+    }
+
+    /// Reads an `extend` block, whose fields are elements of the enclosing scope
+    fn extend(&mut self) -> Result<Vec<Element>> {
+        let extendee = self.type_name()?;
+        self.tokens.expect_symbol(b'{')?;
+        let mut elements = Vec::new();
+        while !self.tokens.try_symbol(b'}')? {
+            let start = self.peek_offset()?;
+            if self.tokens.try_symbol(b';')? {
+                continue;
+            }
+            let field = self.labeled_field(start, Some(&extendee))?;
+            elements.extend(field.group);
+            elements.push(Element {
+                name: field.name,
+                json: Value::Object(field.json),
+                offset: field.offset,
+            });
+        }
+        self.tokens.try_symbol(b';')?;
+        Ok(elements)
+    }
+
+    /// Reads the ranges of `extensions` or `reserved` up to the `;`, with `names`
+    /// also reserved names, and with `negative` negative numbers like enums allow
+    fn ranges(&mut self, names: bool, negative: bool) -> Result<Vec<Value>> {
+        let editions = self.is_editions();
+        let mut ranges = Vec::new();
+        loop {
+            match self.tokens.peek()? {
+                Token::Str(_) if names => {
+                    if editions {
+                        return Err(self.tokens.error("reserved names must be identifiers in editions".to_string()));
+                    }
+                    ranges.push(Value::String(self.string()?));
+                }
+                Token::Ident(name) if names && editions && !matches!(*name, "max" | "MAX" | "Max") => {
+                    ranges.push(Value::String(self.ident("name")?.to_string()));
+                }
+                _ => {
+                    let start = self.number(negative)?;
+                    let end = if matches!(self.tokens.peek()?, Token::Ident("to")) {
+                        self.tokens.next()?;
+                        self.number(negative)?
+                    } else {
+                        start
+                    };
+                    ranges.push(Value::Array(vec![Value::from(start), Value::from(end)]));
+                }
+            }
+            if !self.tokens.try_symbol(b',')? {
+                break;
+            }
+        }
+        // Options of ranges, like extension declarations, are read and dropped like protobuf.js does
+        self.inline_options(&mut Map::new())?;
+        self.tokens.expect_symbol(b';')?;
+        Ok(ranges)
+    }
+
+    /// Reads an option after the `option` keyword, up to the `;`
+    fn option_statement(&mut self, options: &mut Map<String, Value>) -> Result<()> {
+        for (name, value) in self.option()?.settings {
+            set_option(options, &name, value);
+        }
+        self.tokens.expect_symbol(b';')
+    }
+
+    /// Reads options in brackets after a field or enum value, if there are any
+    fn inline_options(&mut self, options: &mut Map<String, Value>) -> Result<()> {
+        if !self.tokens.try_symbol(b'[')? {
+            return Ok(());
+        }
+        loop {
+            for (name, value) in self.option()?.settings {
+                set_option(options, &name, value);
+            }
+            if !self.tokens.try_symbol(b',')? {
+                break;
+            }
+        }
+        self.tokens.expect_symbol(b']')
+    }
+
+    /// Reads `name = value`, where the name can be an extension in parentheses
+    /// followed by a path within it and the value can be an aggregate
+    fn option(&mut self) -> Result<ParsedOption> {
+        let mut parts = Vec::new();
+        loop {
+            if self.tokens.try_symbol(b'(')? {
+                parts.push(format!("({})", self.type_name()?));
+                self.tokens.expect_symbol(b')')?;
+            } else {
+                parts.push(self.ident("option name")?.to_string());
+            }
+            if !self.tokens.try_symbol(b'.')? {
+                break;
+            }
+        }
+        let name = parts.remove(0);
+        let path = (!parts.is_empty()).then(|| parts.join("."));
+        self.tokens.expect_symbol(b'=')?;
+        let full_name = match &path {
+            Some(path) => format!("{}.{}", name, path),
+            None => name.clone(),
+        };
+        let mut settings = Vec::new();
+        let value = self.option_value(&full_name, &mut settings)?;
+        Ok(ParsedOption {
+            name,
+            path,
+            value,
+            settings,
+        })
+    }
+
+    /// Reads a value or an aggregate in braces, adding the options it sets to
+    /// `settings` by their full path like protobuf.js does
+    fn option_value(&mut self, name: &str, settings: &mut Vec<(String, Value)>) -> Result<Value> {
+        if !self.tokens.try_symbol(b'{')? {
+            let value = self.value()?;
+            settings.push((name.to_string(), value.clone()));
+            return Ok(value);
+        }
+        let mut object = Map::new();
+        while !self.tokens.try_symbol(b'}')? {
+            let key = self.ident("name")?;
+            let path = format!("{}.{}", name, key);
+            self.tokens.try_symbol(b':')?;
+            let value = if matches!(self.tokens.peek()?, Token::Symbol(b'{')) {
+                self.option_value(&path, settings)?
+            } else if self.tokens.try_symbol(b'[')? {
+                let mut list = Vec::new();
+                if !self.tokens.try_symbol(b']')? {
+                    loop {
+                        list.push(self.value()?);
+                        if !self.tokens.try_symbol(b',')? {
+                            break;
+                        }
+                    }
+                    self.tokens.expect_symbol(b']')?;
+                }
+                if let Some(last) = list.last() {
+                    settings.push((path, last.clone()));
+                }
+                Value::Array(list)
+            } else {
+                let value = self.value()?;
+                settings.push((path, value.clone()));
+                value
+            };
+            match object.get_mut(key) {
+                Some(previous) if is_truthy(previous) => *previous = concat(previous.take(), value),
+                _ => {
+                    object.insert(key.to_string(), value);
+                }
+            }
+            self.tokens.try_symbol(b',')?;
+            self.tokens.try_symbol(b';')?;
+        }
+        Ok(Value::Object(object))
+    }
+
+    /// Reads a string, boolean, number or identifier like an enum value name
+    fn value(&mut self) -> Result<Value> {
+        let negative = self.tokens.try_symbol(b'-')?;
+        match self.tokens.peek()? {
+            Token::Str(_) if !negative => Ok(Value::String(self.string()?)),
+            Token::Number(text) => {
+                let text = *text;
+                self.tokens.next()?;
+                number_value(text, negative).ok_or_else(|| self.tokens.error(format!("invalid number '{}'", text)))
+            }
+            Token::Ident(name @ ("inf" | "INF" | "Inf" | "nan" | "NAN" | "Nan" | "NaN")) => {
+                let value = format!("{}{}", if negative { "-" } else { "" }, name.to_ascii_lowercase());
+                self.tokens.next()?;
+                Ok(Value::String(value))
+            }
+            Token::Ident("true" | "TRUE") if !negative => {
+                self.tokens.next()?;
+                Ok(Value::Bool(true))
+            }
+            Token::Ident("false" | "FALSE") if !negative => {
+                self.tokens.next()?;
+                Ok(Value::Bool(false))
+            }
+            Token::Ident(_) if !negative => Ok(Value::String(self.type_name()?)),
+            _ => Err(self.unexpected_next("value")),
+        }
+    }
+
+    /// Reads a field number, which may be `max`
+    fn field_number(&mut self) -> Result<i64> {
+        let number = self.number(false)?;
+        if !(1..=MAX_FIELD_NUMBER).contains(&number) {
+            return Err(self.tokens.error(format!("field number {} out of range", number)));
+        }
+        Ok(number)
+    }
+
+    /// Reads an integer, `max` for the largest field number, or with `negative` a negative integer
+    fn number(&mut self, negative: bool) -> Result<i64> {
+        let minus = negative && self.tokens.try_symbol(b'-')?;
+        let number = match self.tokens.next()? {
+            Token::Ident("max" | "MAX" | "Max") if !minus => return Ok(MAX_FIELD_NUMBER),
+            Token::Number(text) => text::integer_literal(text)
+                .and_then(|value| i64::try_from(value).ok())
+                .ok_or_else(|| self.tokens.error(format!("invalid integer '{}'", text)))?,
+            token => return Err(self.tokens.unexpected("number", &token)),
+        };
+        Ok(if minus { -number } else { number })
+    }
+
+    /// Reads one or more adjacent string literals, which are concatenated
+    fn string(&mut self) -> Result<String> {
+        let mut data = match self.tokens.next()? {
+            Token::Str(data) => data,
+            token => return Err(self.tokens.unexpected("string", &token)),
+        };
+        while matches!(self.tokens.peek()?, Token::Str(_)) {
+            if let Token::Str(more) = self.tokens.next()? {
+                data.extend(more);
+            }
+        }
+        // Invalid UTF-8 from byte escapes keeps one character per byte, like protobuf.js
+        Ok(String::from_utf8(data).unwrap_or_else(|e| e.into_bytes().into_iter().map(char::from).collect()))
+    }
+
+    fn ident(&mut self, expected: &str) -> Result<&'t str> {
+        match self.tokens.next()? {
+            Token::Ident(name) => Ok(name),
+            token => Err(self.tokens.unexpected(expected, &token)),
+        }
+    }
+
+    fn ident_or_semicolon(&mut self, expected: &str) -> Result<Option<&'t str>> {
+        match self.tokens.next()? {
+            Token::Ident(name) => Ok(Some(name)),
+            Token::Symbol(b';') => Ok(None),
+            token => Err(self.tokens.unexpected(expected, &token)),
+        }
+    }
+
+    /// Reads a possibly qualified name like `Foo`, `foo.Bar` or `.foo.Bar`
+    fn type_name(&mut self) -> Result<String> {
+        let absolute = self.tokens.try_symbol(b'.')?;
+        let first = self.ident("type name")?;
+        self.type_name_after(format!("{}{}", if absolute { "." } else { "" }, first))
+    }
+
+    fn type_name_after(&mut self, mut name: String) -> Result<String> {
+        while self.tokens.try_symbol(b'.')? {
+            name.push('.');
+            name.push_str(self.ident("name")?);
+        }
+        Ok(name)
+    }
+
+    /// An error for the next token, which didn't match what the caller peeked for
+    fn unexpected_next(&mut self, expected: &str) -> ProtoError {
+        match self.tokens.next() {
+            Ok(token) => self.tokens.unexpected(expected, &token),
+            Err(error) => error,
+        }
+    }
+
+    fn peek_offset(&mut self) -> Result<usize> {
+        self.tokens.peek()?;
+        Ok(self.tokens.start)
+    }
+
+    fn duplicate(&self, element: &Element) -> ProtoError {
+        self.tokens.error_at(element.offset, format!("duplicate name '{}'", element.name))
+    }
+
+    /// Reads the `;` ending a statement that started at `start`, returning
+    /// its leading comment or else the comment after it on the same line
+    fn statement_comment(&mut self, start: usize) -> Result<Value> {
+        let leading = self.leading_comment(start);
+        self.tokens.expect_symbol(b';')?;
+        if !leading.is_null() || !self.keep_comments {
+            return Ok(leading);
+        }
+        let text = self.tokens.text();
+        let end = self.tokens.start + 1;
+        let rest = text[end..].trim_start_matches([' ', '\t']);
+        let from = text.len() - rest.len();
+        let to = if rest.starts_with("//") {
+            from + rest.find('\n').unwrap_or(rest.len())
+        } else if rest.starts_with("/*") {
+            match rest.find("*/") {
+                Some(close) => from + close + 2,
+                None => return Ok(Value::Null),
+            }
+        } else {
+            return Ok(Value::Null);
+        };
+        Ok(comment_text(&text[from..to]))
+    }
+
+    /// The comments on the lines right above `start`, with no blank line in between
+    fn leading_comment(&self, start: usize) -> Value {
+        if !self.keep_comments {
+            return Value::Null;
+        }
+        let text = self.tokens.text();
+        let mut end = start;
+        let mut block = Vec::new();
+        for &(from, to) in self.tokens.comments.iter().rev().filter(|(_, to)| *to <= start) {
+            let gap = &text[to..end];
+            let line = &text[text[..from].rfind('\n').map_or(0, |i| i + 1)..from];
+            if !gap.trim().is_empty() || gap.matches('\n').count() > 1 || !line.trim().is_empty() {
+                break;
+            }
+            block.push(comment_text(&text[from..to]));
+            end = from;
+        }
+        if block.is_empty() {
+            return Value::Null;
+        }
+        let lines: Vec<_> = block.iter().rev().filter_map(Value::as_str).collect();
+        Value::String(lines.join("\n"))
+    }
+
+    /// Adds `comment` to an element, when comments are kept
+    fn comment_into(&self, mut json: Map<String, Value>, comment: Value) -> Map<String, Value> {
+        if self.keep_comments {
+            json.insert("comment".to_string(), comment);
+        }
+        json
+    }
+
+    fn commented(&self, json: Map<String, Value>, comment: Value) -> Value {
+        Value::Object(self.comment_into(json, comment))
+    }
+}
+
+/// An option with the settings it makes, by their full dotted name
+struct ParsedOption {
+    /// The option, like `deprecated` or `(my.ext)`
+    name: String,
+    /// The path within the option, like `a.b` of `(my.ext).a.b`
+    path: Option<String>,
+    value: Value,
+    settings: Vec<(String, Value)>,
+}
+
+/// Adds an element to a `nested` object, erroring on a name that is taken
+fn insert(tokens: &Tokenizer, nested: &mut Map<String, Value>, element: Element) -> Result<()> {
+    if nested.contains_key(&element.name) {
+        return Err(tokens.error_at(element.offset, format!("duplicate name '{}'", element.name)));
+    }
+    nested.insert(element.name, element.json);
+    Ok(())
+}
+
+/// Sets an option like protobuf.js `ReflectionObject#setOption`, which nests `features.*`
+fn set_option(options: &mut Map<String, Value>, name: &str, value: Value) {
+    if name.starts_with("features.") {
+        set_property(options, name, value, false);
+    } else {
+        options.insert(name.to_string(), value);
+    }
+}
+
+/// Adds an option to `parsedOptions` like protobuf.js `ReflectionObject#setParsedOption`,
+/// merging paths within an option into its first entry
+fn set_parsed_option(parsed: &mut Vec<Value>, name: &str, path: Option<&str>, value: Value) {
+    let mut option = Map::new();
+    match path {
+        Some(path) => {
+            if let Some(Value::Object(existing)) = parsed.iter_mut().find_map(|option| option.get_mut(name)) {
+                set_property(existing, path, value, false);
+                return;
+            }
+            let mut object = Map::new();
+            set_property(&mut object, path, value, false);
+            option.insert(name.to_string(), Value::Object(object));
+        }
+        None => {
+            option.insert(name.to_string(), value);
+        }
+    }
+    parsed.push(Value::Object(option));
+}
+
+/// Sets a dotted path like protobuf.js `util.setProperty`, which collects values
+/// set twice into an array or, with `if_not_set`, keeps the first one
+fn set_property(object: &mut Map<String, Value>, path: &str, value: Value, if_not_set: bool) {
+    match path.split_once('.') {
+        Some((part, rest)) => {
+            let child = object
+                .entry(part)
+                .or_insert_with(|| Value::Object(Map::new()));
+            if !child.is_object() {
+                *child = Value::Object(Map::new());
+            }
+            if let Value::Object(child) = child {
+                set_property(child, rest, value, if_not_set);
+            }
+        }
+        None => match object.get_mut(path) {
+            Some(previous) if is_truthy(previous) => {
+                if !if_not_set {
+                    *previous = concat(previous.take(), value);
+                }
+            }
+            _ => {
+                object.insert(path.to_string(), value);
+            }
+        },
+    }
+}
+
+fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(value) => *value,
+        Value::Number(number) => number.as_f64() != Some(0.0),
+        Value::String(value) => !value.is_empty(),
+        _ => true,
+    }
+}
+
+/// `[].concat(a).concat(b)`
+fn concat(a: Value, b: Value) -> Value {
+    let mut values = Vec::new();
+    for value in [a, b] {
+        match value {
+            Value::Array(items) => values.extend(items),
+            value => values.push(value),
+        }
+    }
+    Value::Array(values)
+}
+
+/// Converts a number literal, keeping integers exact where JSON allows and
+/// values out of the range of a double as `"inf"`
+fn number_value(text: &str, negative: bool) -> Option<Value> {
+    if let Some(value) = text::integer_literal(text) {
+        let value = if negative { -value } else { value };
+        if let Ok(value) = i64::try_from(value) {
+            return Some(Value::from(value));
+        }
+        if let Ok(value) = u64::try_from(value) {
+            return Some(Value::from(value));
+        }
+        return Number::from_f64(value as f64).map(Value::Number);
+    }
+    if !text.bytes().all(|b| b.is_ascii_digit() || matches!(b, b'.' | b'e' | b'E' | b'+' | b'-')) {
+        return None;
+    }
+    let value: f64 = text.parse().ok()?;
+    let value = if negative { -value } else { value };
+    Some(match Number::from_f64(value) {
+        Some(number) => Value::Number(number),
+        None => Value::String(if negative { "-inf" } else { "inf" }.to_string()),
+    })
+}
+
+/// The text of a comment without its markers and the leading `*` of block comment lines
+fn comment_text(comment: &str) -> Value {
+    let lines: Vec<&str> = match comment.strip_prefix("/*") {
+        Some(block) => block
+            .strip_suffix("*/")
+            .unwrap_or(block)
+            .lines()
+            .map(|line| line.trim().trim_start_matches('*').trim())
+            .collect(),
+        None => vec![comment.trim_start_matches('/').trim()],
+    };
+    let text = lines.join("\n");
+    Value::String(text.trim().to_string())
+}
+
+/// protobuf.js `util.camelCase`, which uppercases the lowercase letter after each underscore
+fn camel_case(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    let mut chars = name.chars().peekable();
+    if let Some(first) = chars.next() {
+        out.push(first);
+    }
+    while let Some(c) = chars.next() {
+        match chars.peek() {
+            Some(next) if c == '_' && next.is_ascii_lowercase() => {
+                out.push(next.to_ascii_uppercase());
+                chars.next();
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+fn lc_first(name: &str) -> String {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .map(|first| first.to_lowercase().chain(chars).collect())
+        .unwrap_or_default()
+}
+
+fn uc_first(name: &str) -> String {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .map(|first| first.to_uppercase().chain(chars).collect())
+        .unwrap_or_default()
+}
@@ -0,0 +1,129 @@
+use napi::Env;
+use napi::JsObject;
+use napi::JsString;
+use napi::JsUnknown;
+use napi::ValueType;
+
+use crate::error::{ErrorKind, ProtoError, Result};
+use crate::long::LongRepr;
+use crate::reader::ReaderImpl;
+
+fn plan_error(index: u32, message: String) -> ProtoError {
+    ProtoError::new(ErrorKind::Malformed, format!("plan op {}: {}", index, message))
+}
+
+/// Executes a read plan, the decoding counterpart of `Writer.encodeAll`, and
+/// returns every value read as one flat array.
+///
+/// Each op is `[name]` or `[name, argument]`, using the `encodeAll` names
+/// (`u32`, `i32`, `s32`, `u64`, `i64`, `s64`, `bool`, `f32`, `sf32`, `f64`,
+/// `sf64`, `float`, `double`, `bytes`, `string`) plus:
+/// - `tag`: reads a tag and appends its field number and wire type
+/// - `skip_type`: skips a value of the given wire type, defaulting to the last tag's
+/// - `skip`: skips the given number of bytes
+/// - `sub`: reads a length and limits the following ops to that many bytes
+/// - `end`: skips whatever is left of the innermost `sub`
+///
+/// Limits pushed by `sub` are popped again when the plan fails or leaves them open.
+pub fn read_all(env: &Env, reader: &mut ReaderImpl, longs: LongRepr, plan: &JsObject) -> Result<JsObject> {
+    let mut depth = 0;
+    let result = run(env, reader, longs, plan, &mut depth);
+    for _ in 0..depth {
+        reader.pop_limit()?;
+        reader.ascend();
+    }
+    result
+}
+
+fn run(env: &Env, reader: &mut ReaderImpl, longs: LongRepr, plan: &JsObject, depth: &mut usize) -> Result<JsObject> {
+    let len = plan.get_array_length()?;
+    let mut values = env.create_array_with_length(0)?;
+    let mut count = 0;
+    let mut push = |value: JsUnknown| -> Result<()> {
+        values.set_element(count, value)?;
+        count += 1;
+        Ok(())
+    };
+    let mut last_tag: Option<(u32, u32)> = None;
+
+    for index in 0..len {
+        let op: JsObject = plan.get_element(index)?;
+        let name: JsString = op.get_element(0)?;
+        let name = name.into_utf8()?;
+        let argument = || -> Result<Option<u32>> {
+            let value: JsUnknown = op.get_element(1)?;
+            Ok(match value.get_type()? {
+                ValueType::Number => Some(value.coerce_to_number()?.get_uint32()?),
+                _ => None,
+            })
+        };
+
+        match name.as_str()? {
+            "u32" => push(env.create_uint32(reader.read_varint32()?)?.into_unknown())?,
+            "i32" => push(env.create_int32(reader.read_int32()?)?.into_unknown())?,
+            "s32" => push(env.create_int32(reader.read_sint32()?)?.into_unknown())?,
+            "u64" => push(longs.to_js(env, reader.read_varint64()?, true)?)?,
+            "i64" => push(longs.to_js(env, reader.read_varint64()?, false)?)?,
+            "s64" => push(longs.to_js(env, reader.read_sint64()? as u64, false)?)?,
+            "bool" => push(env.get_boolean(reader.read_varint32()? != 0)?.into_unknown())?,
+            "f32" => push(env.create_uint32(reader.read_fixed32()?)?.into_unknown())?,
+            "sf32" => push(env.create_int32(reader.read_sfixed32()?)?.into_unknown())?,
+            "f64" => push(longs.to_js(env, reader.read_fixed64()?, true)?)?,
+            "sf64" => push(longs.to_js(env, reader.read_sfixed64()? as u64, false)?)?,
+            "float" => push(env.create_double(reader.read_float()? as f64)?.into_unknown())?,
+            "double" => push(env.create_double(reader.read_double()?)?.into_unknown())?,
+            "bytes" => {
+                let len = reader.read_varint32()? as usize;
+                let data = reader.read_slice(len)?;
+                push(env.create_buffer_with_data(data.to_vec())?.into_raw().into_unknown())?;
+            }
+            "string" => {
+                let len = reader.read_varint32()? as usize;
+                let policy = reader.utf8();
+                let text = reader.read_text(len, policy)?;
+                push(text.to_js(env)?)?;
+            }
+            "tag" => {
+                let (field, wire_type) = reader.read_tag()?;
+                last_tag = Some((field, wire_type));
+                push(env.create_uint32(field)?.into_unknown())?;
+                push(env.create_uint32(wire_type)?.into_unknown())?;
+            }
+            "skip_type" => {
+                match (argument()?, last_tag) {
+                    (Some(wire_type), _) => reader.skip_type(wire_type)?,
+                    (None, Some((field, wire_type))) => reader.skip_field(field, wire_type)?,
+                    (None, None) => {
+                        return Err(plan_error(index, "skip_type needs a wire type or a preceding tag".to_owned()))
+                    }
+                }
+            }
+            "skip" => {
+                let len = argument()?
+                    .ok_or_else(|| plan_error(index, "skip needs a length".to_owned()))?;
+                reader.skip(len as usize)?;
+            }
+            "sub" => {
+                let len = reader.read_varint32()? as usize;
+                reader.descend()?;
+                if let Err(e) = reader.push_limit(len) {
+                    reader.ascend();
+                    return Err(e);
+                }
+                *depth += 1;
+            }
+            "end" => {
+                if *depth == 0 {
+                    return Err(plan_error(index, "end without sub".to_owned()));
+                }
+                reader.skip(reader.bytes_until_limit())?;
+                reader.pop_limit()?;
+                reader.ascend();
+                *depth -= 1;
+            }
+            other => return Err(plan_error(index, format!("unknown op '{}'", other))),
+        }
+    }
+
+    Ok(values)
+}
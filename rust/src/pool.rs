@@ -0,0 +1,180 @@
+use serde_json::{Map, Value};
+
+use crate::descriptor::{
+    EnumDescriptor, FieldDescriptor, FieldType, Label, MessageDescriptor, NestedKind, Registry, ServiceDescriptor,
+};
+
+/// The last part of a fully qualified name
+fn simple_name(full_name: &str) -> &str {
+    full_name.rsplit('.').next().unwrap_or(full_name)
+}
+
+fn string(value: &str) -> Value {
+    Value::String(value.to_string())
+}
+
+/// Adds `options` when there are some
+fn with_options(mut object: Map<String, Value>, options: &Map<String, Value>) -> Value {
+    if !options.is_empty() {
+        object.insert("options".to_string(), Value::Object(options.clone()));
+    }
+    Value::Object(object)
+}
+
+/// `{ name, fullName, handle, fields, oneofs, group, options }` of the message at `index`.
+/// Extension fields are left to `extensions`.
+pub fn message(registry: &Registry, index: usize) -> Value {
+    let message = registry.message_at(index);
+    let mut object = Map::new();
+    object.insert("name".to_string(), string(simple_name(&message.full_name)));
+    object.insert("fullName".to_string(), string(&message.full_name));
+    object.insert("handle".to_string(), Value::from(index));
+    let fields = message
+        .fields
+        .iter()
+        .filter(|field| !field.name.starts_with('.'))
+        .map(|field| self::field(registry, message, field))
+        .collect();
+    object.insert("fields".to_string(), Value::Array(fields));
+    let oneofs = message
+        .oneofs
+        .iter()
+        .map(|oneof| {
+            let mut object = Map::new();
+            object.insert("name".to_string(), string(&oneof.name));
+            let fields = oneof.fields.iter().map(|&i| string(&message.fields[i].name)).collect();
+            object.insert("fields".to_string(), Value::Array(fields));
+            Value::Object(object)
+        })
+        .collect();
+    object.insert("oneofs".to_string(), Value::Array(oneofs));
+    if message.group {
+        object.insert("group".to_string(), Value::Bool(true));
+    }
+    with_options(object, &message.options)
+}
+
+/// `{ name, number, label, type, typeName, keyType, jsonName, defaultValue, packed, oneof, options }`
+/// of a field, where `type` is a scalar type, `"message"` or `"enum"` and `typeName`
+/// the fully qualified name of the latter two
+pub fn field(registry: &Registry, message: &MessageDescriptor, field: &FieldDescriptor) -> Value {
+    let mut object = Map::new();
+    let name = field.name.strip_prefix('.').unwrap_or(&field.name);
+    object.insert("name".to_string(), string(name));
+    object.insert("number".to_string(), Value::from(field.number));
+    let label = match field.label {
+        Label::Optional => "optional",
+        Label::Required => "required",
+        Label::Repeated => "repeated",
+    };
+    object.insert("label".to_string(), string(label));
+    let (field_type, type_name) = match field.field_type {
+        FieldType::Scalar(scalar) => (scalar.name(), None),
+        FieldType::Message(index) => ("message", Some(&registry.message_at(index).full_name)),
+        FieldType::Enum(index) => ("enum", Some(&registry.enum_at(index).full_name)),
+    };
+    object.insert("type".to_string(), string(field_type));
+    if let Some(type_name) = type_name {
+        object.insert("typeName".to_string(), string(type_name));
+    }
+    if let Some(key_type) = field.key_type {
+        object.insert("keyType".to_string(), string(key_type.name()));
+    }
+    object.insert("jsonName".to_string(), string(&field.json_name));
+    if let Some(default_value) = &field.default_value {
+        object.insert("defaultValue".to_string(), default_value.clone());
+    }
+    if field.packed {
+        object.insert("packed".to_string(), Value::Bool(true));
+    }
+    if let Some(oneof) = field.oneof {
+        object.insert("oneof".to_string(), string(&message.oneofs[oneof].name));
+    }
+    if field.name.starts_with('.') {
+        object.insert("extendee".to_string(), string(&message.full_name));
+    }
+    with_options(object, &field.options)
+}
+
+/// The extension fields registered for the message at `index`, ordered by number
+pub fn extensions(registry: &Registry, index: usize) -> Vec<Value> {
+    let message = registry.message_at(index);
+    message
+        .fields
+        .iter()
+        .filter(|field| field.name.starts_with('.'))
+        .map(|field| self::field(registry, message, field))
+        .collect()
+}
+
+/// `{ name, fullName, values: [{ name, number, options }], options }` of the enum at `index`
+pub fn enumeration(registry: &Registry, index: usize) -> Value {
+    let enumeration: &EnumDescriptor = registry.enum_at(index);
+    let mut object = Map::new();
+    object.insert("name".to_string(), string(simple_name(&enumeration.full_name)));
+    object.insert("fullName".to_string(), string(&enumeration.full_name));
+    let values = enumeration
+        .values
+        .iter()
+        .map(|(name, number)| {
+            let mut value = Map::new();
+            value.insert("name".to_string(), string(name));
+            value.insert("number".to_string(), Value::from(*number));
+            match enumeration.values_options.get(name) {
+                Some(Value::Object(options)) => with_options(value, options),
+                _ => Value::Object(value),
+            }
+        })
+        .collect();
+    object.insert("values".to_string(), Value::Array(values));
+    with_options(object, &enumeration.options)
+}
+
+/// `{ name, fullName, methods: [{ name, inputType, outputType, clientStreaming, serverStreaming, options }], options }`
+pub fn service(service: &ServiceDescriptor) -> Value {
+    let mut object = Map::new();
+    object.insert("name".to_string(), string(simple_name(&service.full_name)));
+    object.insert("fullName".to_string(), string(&service.full_name));
+    let methods = service
+        .methods
+        .iter()
+        .map(|method| {
+            let mut object = Map::new();
+            object.insert("name".to_string(), string(&method.name));
+            object.insert("inputType".to_string(), string(&method.input_type));
+            object.insert("outputType".to_string(), string(&method.output_type));
+            object.insert("clientStreaming".to_string(), Value::Bool(method.client_streaming));
+            object.insert("serverStreaming".to_string(), Value::Bool(method.server_streaming));
+            with_options(object, &method.options)
+        })
+        .collect();
+    object.insert("methods".to_string(), Value::Array(methods));
+    with_options(object, &service.options)
+}
+
+/// `[{ name, fullName, kind }]` of the elements directly within `scope`, where `kind`
+/// is `"message"`, `"enum"`, `"service"` or `"namespace"`
+pub fn nested(registry: &Registry, scope: &str) -> Vec<Value> {
+    let scope = scope.strip_prefix('.').unwrap_or(scope);
+    registry
+        .nested(scope)
+        .into_iter()
+        .map(|(name, kind)| {
+            let mut object = Map::new();
+            let full_name = match scope {
+                "" => name.clone(),
+                _ => format!("{}.{}", scope, name),
+            };
+            let kind = match kind {
+                Some(NestedKind::Message) => "message",
+                Some(NestedKind::Enum) => "enum",
+                Some(NestedKind::Service) => "service",
+                None => "namespace",
+            };
+            object.insert("name".to_string(), Value::String(name));
+            object.insert("fullName".to_string(), Value::String(full_name));
+            object.insert("kind".to_string(), string(kind));
+            Value::Object(object)
+        })
+        .collect()
+}
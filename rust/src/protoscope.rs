@@ -0,0 +1,425 @@
+use std::fmt::Write as _;
+
+use crate::error::{ErrorKind, ProtoError, Result};
+use crate::json::Wire;
+use crate::raw::{self, Field, Guess, Value};
+use crate::reader::ReaderImpl;
+use crate::text::{self, Token, Tokenizer};
+use crate::writer::WriterImpl;
+
+/// Wire type names that may follow a field number, like `1:I64`, indexed by wire type
+const WIRE_TYPES: [&str; 6] = ["VARINT", "I64", "LEN", "SGROUP", "EGROUP", "I32"];
+
+const MAX_DEPTH: usize = 100;
+
+/// Most padding bytes a `long-form:N` prefix may add
+const MAX_LONG_FORM: usize = 64;
+
+/// Bytes of `value` as a minimal varint
+fn varint_len(value: u64) -> usize {
+    (64 - value.leading_zeros() as usize).max(1).div_ceil(7)
+}
+
+fn push_indent(out: &mut String, indent: usize) {
+    for _ in 0..indent {
+        out.push_str("  ");
+    }
+}
+
+/// Appends `long-form:N ` when a varint of `value` took `len` bytes instead of the fewest
+fn push_long_form(out: &mut String, len: usize, value: u64) {
+    let extra = len.saturating_sub(varint_len(value));
+    if extra > 0 {
+        let _ = write!(out, "long-form:{} ", extra);
+    }
+}
+
+fn push_hex(out: &mut String, data: &[u8]) {
+    out.push('`');
+    for byte in data {
+        let _ = write!(out, "{:02x}", byte);
+    }
+    out.push('`');
+}
+
+/// Appends a fixed value as a short float literal when there is one, in hex otherwise
+fn push_fixed<T: Copy + Into<f64> + std::fmt::Display>(out: &mut String, bits: u64, float: T, digits: usize) {
+    let literal = float.to_string();
+    if bits != 0 && float.into().is_finite() && literal.len() <= 12 {
+        out.push_str(&literal);
+        if !literal.contains('.') {
+            out.push_str(".0");
+        }
+    } else {
+        let _ = write!(out, "0x{:0width$x}", bits, width = digits);
+    }
+}
+
+/// Prints a buffer in a protoscope-like language that `assemble` turns back into
+/// the same bytes, ending with a comment and a hex literal for whatever can't be read
+pub fn disassemble(reader: &mut ReaderImpl) -> Result<String> {
+    let dump = raw::decode(reader);
+    let mut out = String::new();
+    print_fields(&mut out, reader, &dump.fields, 0)?;
+    if let Some(error) = &dump.error {
+        let _ = writeln!(out, "# {}", error.message());
+        reader.seek(dump.end);
+        let rest = reader.read_slice(reader.len() - dump.end)?;
+        push_hex(&mut out, rest);
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+fn print_fields(out: &mut String, reader: &mut ReaderImpl, fields: &[Field], indent: usize) -> Result<()> {
+    for field in fields {
+        push_indent(out, indent);
+        let tag = (field.number as u64) << 3 | field.wire_type as u64;
+        push_long_form(out, field.value_offset - field.offset, tag);
+        match &field.value {
+            Value::Varint(value) => {
+                let _ = write!(out, "{}: ", field.number);
+                push_long_form(out, field.end - field.value_offset, *value);
+                let _ = write!(out, "{}", *value as i64);
+            }
+            Value::Fixed64(value) => {
+                let _ = write!(out, "{}: ", field.number);
+                push_fixed(out, *value, f64::from_bits(*value), 16);
+                out.push_str("i64");
+            }
+            Value::Fixed32(value) => {
+                let _ = write!(out, "{}: ", field.number);
+                push_fixed(out, *value as u64, f32::from_bits(*value), 8);
+                out.push_str("i32");
+            }
+            Value::Delimited { offset, length, guess } => {
+                let _ = write!(out, "{}: ", field.number);
+                push_long_form(out, offset - field.value_offset, *length as u64);
+                out.push('{');
+                reader.seek(*offset);
+                match guess {
+                    _ if *length == 0 => {}
+                    Guess::Message(fields) => {
+                        out.push('\n');
+                        print_fields(out, reader, fields, indent + 1)?;
+                        push_indent(out, indent);
+                    }
+                    Guess::Packed(values) if values.iter().map(|value| varint_len(*value)).sum::<usize>() == *length => {
+                        let values: Vec<String> = values.iter().map(|value| (*value as i64).to_string()).collect();
+                        out.push_str(&values.join(" "));
+                    }
+                    Guess::String => {
+                        let data = reader.read_slice(*length)?;
+                        text::push_string(out, std::str::from_utf8(data).unwrap_or_default());
+                    }
+                    _ => push_hex(out, reader.read_slice(*length)?),
+                }
+                out.push('}');
+            }
+            Value::Group(fields) => {
+                // The end tag can only be implied when it has the fewest bytes
+                let body_end = fields.last().map_or(field.value_offset, |last| last.end);
+                let end_tag = (field.number as u64) << 3 | 4;
+                if field.end - body_end == varint_len(end_tag) {
+                    let _ = writeln!(out, "{}: !{{", field.number);
+                    print_fields(out, reader, fields, indent + 1)?;
+                    push_indent(out, indent);
+                    out.push('}');
+                } else {
+                    let _ = writeln!(out, "{}:SGROUP", field.number);
+                    print_fields(out, reader, fields, indent)?;
+                    push_indent(out, indent);
+                    push_long_form(out, field.end - body_end, end_tag);
+                    let _ = write!(out, "{}:EGROUP", field.number);
+                }
+            }
+        }
+        out.push('\n');
+    }
+    Ok(())
+}
+
+/// Writes `value` as a varint padded with `extra` bytes of continuation
+fn write_varint(writer: &mut WriterImpl, value: u64, extra: usize) {
+    if extra == 0 {
+        writer.write_varint64(value);
+        return;
+    }
+    let mut bytes = Vec::with_capacity(varint_len(value) + extra);
+    let mut value = value;
+    while value >= 0x80 {
+        bytes.push((value & 0x7F | 0x80) as u8);
+        value >>= 7;
+    }
+    bytes.push(value as u8 | 0x80);
+    bytes.resize(bytes.len() + extra - 1, 0x80);
+    bytes.push(0);
+    writer.write_bytes(&bytes);
+}
+
+/// Parses a float literal, `inf` or `nan`
+fn float<T: std::str::FromStr>(text: &str) -> Option<T> {
+    let valid = matches!(text, "inf" | "nan")
+        || (text.bytes().any(|b| b.is_ascii_digit())
+            && text.bytes().all(|b| b.is_ascii_digit() || matches!(b, b'.' | b'e' | b'E' | b'+' | b'-')));
+    if !valid {
+        return None;
+    }
+    text.parse().ok()
+}
+
+/// Encodes a value literal: `true` and `false`, integers as varints, or with a
+/// `z` suffix as zigzag varints, and integers or floats with an `i32` or `i64`
+/// suffix as fixed values. Floats without a suffix are doubles.
+fn literal(word: &str, negative: bool) -> Option<Wire<'static>> {
+    match word {
+        "true" if !negative => return Some(Wire::Varint(1)),
+        "false" if !negative => return Some(Wire::Varint(0)),
+        _ => {}
+    }
+    let (body, suffix) = ["i32", "i64", "z"]
+        .iter()
+        .find_map(|suffix| Some((word.strip_suffix(suffix)?, *suffix)))
+        .unwrap_or((word, ""));
+    if let Some(value) = text::integer_literal(body) {
+        let value = if negative { -value } else { value };
+        let int64 = i64::MIN as i128..=u64::MAX as i128;
+        return match suffix {
+            "" if int64.contains(&value) => Some(Wire::Varint(value as u64)),
+            "i64" if int64.contains(&value) => Some(Wire::Fixed64(value as u64)),
+            "i32" if (i32::MIN as i128..=u32::MAX as i128).contains(&value) => Some(Wire::Fixed32(value as u32)),
+            "z" => {
+                let value = i64::try_from(value).ok()?;
+                Some(Wire::Varint(((value << 1) ^ (value >> 63)) as u64))
+            }
+            _ => None,
+        };
+    }
+    match suffix {
+        "" | "i64" => {
+            let value: f64 = float(body)?;
+            Some(Wire::Fixed64(if negative { -value } else { value }.to_bits()))
+        }
+        "i32" => {
+            let value: f32 = float(body)?;
+            Some(Wire::Fixed32(if negative { -value } else { value }.to_bits()))
+        }
+        _ => None,
+    }
+}
+
+/// Assembles the language `disassemble` prints into bytes. Besides fields like
+/// `1: 42`, `2: {"hi"}` and `3: !{ ... }`, it takes raw values anywhere, explicit
+/// wire types like `1:LEN`, hex literals like `` `ff00` `` and `long-form:N`
+/// before a tag, varint or `{` to pad it, which makes malformed input easy to write.
+pub fn assemble(text: &str) -> Result<Vec<u8>> {
+    let mut tokenizer = Tokenizer::new(text);
+    let mut tokens = Vec::new();
+    loop {
+        let token = tokenizer.next()?;
+        let end = matches!(token, Token::End);
+        tokens.push((token, tokenizer.start));
+        if end {
+            break;
+        }
+    }
+    let mut assembler = Assembler { text, tokens, pos: 0 };
+    let mut writer = WriterImpl::new();
+    assembler.block(&mut writer, false, 0)?;
+    Ok(writer.finish())
+}
+
+struct Assembler<'t> {
+    text: &'t str,
+    /// Every token with its offset, ending with `Token::End`
+    tokens: Vec<(Token<'t>, usize)>,
+    pos: usize,
+}
+
+impl<'t> Assembler<'t> {
+    fn peek(&self, ahead: usize) -> &Token<'t> {
+        &self.tokens[(self.pos + ahead).min(self.tokens.len() - 1)].0
+    }
+
+    /// Consumes the next token, leaving `Token::End` in its place
+    fn next(&mut self) -> (Token<'t>, usize) {
+        let at = self.pos.min(self.tokens.len() - 1);
+        self.pos = at + 1;
+        if matches!(self.tokens[at].0, Token::End) {
+            self.pos = at;
+            return (Token::End, at);
+        }
+        (std::mem::replace(&mut self.tokens[at].0, Token::End), at)
+    }
+
+    /// A `MALFORMED` error at the token with index `at`
+    fn error(&self, at: usize, message: String) -> ProtoError {
+        text::locate(self.text, self.tokens[at].1, ProtoError::new(ErrorKind::Malformed, message))
+    }
+
+    fn unexpected(&self, at: usize, expected: &str, token: &Token) -> ProtoError {
+        self.error(at, format!("expected {}, got {}", expected, text::describe(token)))
+    }
+
+    /// Assembles values up to the end of the input or, when `nested`, the closing `}`
+    fn block(&mut self, writer: &mut WriterImpl, nested: bool, depth: usize) -> Result<()> {
+        if depth > MAX_DEPTH {
+            return Err(self.error(self.pos.saturating_sub(1), "too deeply nested".to_string()));
+        }
+        // Field number of the last tag, which `!{` closes with an end tag
+        let mut group = None;
+        // Padding for the next varint and the index of its `long-form`
+        let mut long_form: Option<(usize, usize)> = None;
+        loop {
+            let (token, at) = self.next();
+            let extra = long_form.map_or(0, |(extra, _)| extra);
+            let varint = match token {
+                Token::End if nested => return Err(self.unexpected(at, "'}'", &token)),
+                Token::End => break,
+                Token::Symbol(b'}') if nested => break,
+                Token::Ident("long") if long_form.is_none() => {
+                    long_form = Some((self.long_form(at)?, at));
+                    continue;
+                }
+                Token::Number(number) if matches!(self.peek(0), Token::Symbol(b':')) => {
+                    self.pos += 1;
+                    let field = text::integer_literal(number)
+                        .and_then(|field| u64::try_from(field).ok())
+                        .filter(|field| *field < 1 << 61)
+                        .ok_or_else(|| self.error(at, format!("invalid field number '{}'", number)))?;
+                    let wire_type = self.wire_type(at, field)?;
+                    write_varint(writer, field << 3 | wire_type, extra);
+                    group = Some(field);
+                    long_form = None;
+                    continue;
+                }
+                Token::Symbol(b'{') => {
+                    let mut nested = WriterImpl::new();
+                    self.block(&mut nested, true, depth + 1)?;
+                    let data = nested.finish();
+                    write_varint(writer, data.len() as u64, extra);
+                    writer.write_bytes(&data);
+                    true
+                }
+                Token::Symbol(b'!') => {
+                    match self.next() {
+                        (Token::Symbol(b'{'), _) => {}
+                        (token, at) => return Err(self.unexpected(at, "'{'", &token)),
+                    }
+                    let field = group.ok_or_else(|| self.error(at, "'!{' must follow a tag".to_string()))?;
+                    self.block(writer, true, depth + 1)?;
+                    writer.write_varint64(field << 3 | 4);
+                    false
+                }
+                Token::Symbol(b'`') => {
+                    writer.write_bytes(&self.hex(at)?);
+                    false
+                }
+                Token::Str(data) => {
+                    writer.write_bytes(&data);
+                    false
+                }
+                Token::Symbol(b'-') | Token::Number(_) | Token::Ident(_) => {
+                    let negative = matches!(token, Token::Symbol(b'-'));
+                    let (word, word_at) = if negative { self.next() } else { (token, at) };
+                    let value = match word {
+                        Token::Number(word) | Token::Ident(word) => literal(word, negative).ok_or_else(|| {
+                            let sign = if negative { "-" } else { "" };
+                            self.error(at, format!("invalid value '{}{}'", sign, word))
+                        })?,
+                        token => return Err(self.unexpected(word_at, "number", &token)),
+                    };
+                    match value {
+                        Wire::Varint(value) => {
+                            write_varint(writer, value, extra);
+                            true
+                        }
+                        wire => {
+                            wire.write(writer);
+                            false
+                        }
+                    }
+                }
+                token => return Err(self.unexpected(at, "value", &token)),
+            };
+            if let Some((_, long_at)) = long_form.take() {
+                if !varint {
+                    return Err(self.error(long_at, "long-form must come before a tag, varint or '{'".to_string()));
+                }
+            }
+            group = None;
+        }
+        if let Some((_, long_at)) = long_form {
+            return Err(self.error(long_at, "long-form must come before a tag, varint or '{'".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Reads the rest of `long-form:N` after `long` and returns N
+    fn long_form(&mut self, at: usize) -> Result<usize> {
+        let extra = match (self.peek(0), self.peek(1), self.peek(2), self.peek(3)) {
+            (Token::Symbol(b'-'), Token::Ident("form"), Token::Symbol(b':'), Token::Number(extra)) => {
+                extra.parse().ok().filter(|extra| (1..=MAX_LONG_FORM).contains(extra))
+            }
+            _ => None,
+        };
+        self.pos += 4;
+        extra.ok_or_else(|| self.error(at, format!("expected long-form:N with N from 1 to {}", MAX_LONG_FORM)))
+    }
+
+    /// Reads an explicit wire type after the `:` of a tag, or infers it from the value that follows
+    fn wire_type(&mut self, at: usize, field: u64) -> Result<u64> {
+        if let Token::Ident(name) = self.peek(0) {
+            if let Some(wire_type) = WIRE_TYPES.iter().position(|known| known == name) {
+                self.pos += 1;
+                return Ok(wire_type as u64);
+            }
+        }
+        let mut ahead = 0;
+        if matches!(self.peek(0), Token::Ident("long")) {
+            ahead = 5;
+        }
+        let negative = matches!(self.peek(ahead), Token::Symbol(b'-'));
+        if negative {
+            ahead += 1;
+        }
+        let inferred = match self.peek(ahead) {
+            Token::Symbol(b'{' | b'`') | Token::Str(_) if !negative => Some(2),
+            Token::Symbol(b'!') if !negative => Some(3),
+            // Another tag
+            _ if matches!(self.peek(ahead + 1), Token::Symbol(b':')) => None,
+            Token::Number(word) | Token::Ident(word) => literal(word, negative).map(|wire| match wire {
+                Wire::Varint(_) => 0,
+                Wire::Fixed64(_) => 1,
+                Wire::Bytes(_) => 2,
+                Wire::Fixed32(_) => 5,
+            }),
+            _ => None,
+        };
+        inferred.ok_or_else(|| {
+            self.error(
+                at,
+                format!("can't infer the wire type of field {}, write it like {}:LEN", field, field),
+            )
+        })
+    }
+
+    /// Reads hex digits up to the closing backtick
+    fn hex(&mut self, at: usize) -> Result<Vec<u8>> {
+        let mut digits = String::new();
+        loop {
+            match self.next() {
+                (Token::Symbol(b'`'), _) => break,
+                (Token::Number(part) | Token::Ident(part), _) => digits.push_str(part),
+                (token, at) => return Err(self.unexpected(at, "hex digits or '`'", &token)),
+            }
+        }
+        if !digits.len().is_multiple_of(2) || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(self.error(at, format!("invalid hex literal `{}`", digits)));
+        }
+        Ok((0..digits.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).expect("hex digits"))
+            .collect())
+    }
+}
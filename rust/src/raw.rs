@@ -0,0 +1,343 @@
+use std::fmt::Write as _;
+
+use napi::Env;
+use napi::JsObject;
+use napi_derive::napi;
+
+use crate::error::{ErrorKind, ProtoError, Result};
+use crate::long::LongRepr;
+use crate::reader::ReaderImpl;
+use crate::text;
+
+/// Options of `decodeRaw`
+#[napi(object)]
+#[derive(Default)]
+pub struct DecodeRawOptions {
+    /// `"tree"` (default) for an object tree or `"text"` for annotated text like `protoc --decode_raw`
+    pub format: Option<String>,
+    /// `"number"` (default), `"string"`, `"bigint"` or `"long"` for 64-bit values in the tree
+    pub longs: Option<String>,
+}
+
+/// The most likely meaning of a length-delimited value
+pub enum Guess {
+    /// Valid UTF-8 without control characters other than whitespace
+    String,
+    /// Fields that exactly fill the value
+    Message(Vec<Field>),
+    /// Varints that exactly fill the value
+    Packed(Vec<u64>),
+    Bytes,
+}
+
+impl Guess {
+    fn kind(&self) -> &'static str {
+        match self {
+            Guess::String => "string",
+            Guess::Message(_) => "message",
+            Guess::Packed(_) => "packed",
+            Guess::Bytes => "bytes",
+        }
+    }
+}
+
+pub enum Value {
+    Varint(u64),
+    Fixed64(u64),
+    Fixed32(u32),
+    Delimited {
+        offset: usize,
+        length: usize,
+        guess: Guess,
+    },
+    Group(Vec<Field>),
+}
+
+pub struct Field {
+    pub number: u32,
+    pub wire_type: u32,
+    /// Offset of the tag
+    pub offset: usize,
+    /// Offset after the tag, where the value or its length starts
+    pub value_offset: usize,
+    /// Offset after the value, including the end tag of groups
+    pub end: usize,
+    pub value: Value,
+}
+
+/// The fields read from a buffer without a schema
+pub struct Dump {
+    pub fields: Vec<Field>,
+    /// Offset where reading stopped, the end of the input unless there is an `error`
+    pub end: usize,
+    /// Why reading stopped early
+    pub error: Option<ProtoError>,
+}
+
+/// Reads fields between the reader's position and its end, keeping the fields
+/// before the first one that can't be read
+pub fn decode(reader: &mut ReaderImpl) -> Dump {
+    let mut fields = Vec::new();
+    while !reader.at_end() {
+        let start = reader.pos();
+        match read_field(reader, None) {
+            Ok(field) => fields.extend(field),
+            Err(error) => {
+                return Dump {
+                    fields,
+                    end: start,
+                    error: Some(error),
+                }
+            }
+        }
+    }
+    Dump {
+        fields,
+        end: reader.pos(),
+        error: None,
+    }
+}
+
+/// Reads the fields up to the reader's limit or, within a group, its end tag
+fn read_fields(reader: &mut ReaderImpl, group: Option<u32>) -> Result<Vec<Field>> {
+    let mut fields = Vec::new();
+    loop {
+        if reader.at_end() {
+            return match group {
+                None => Ok(fields),
+                Some(number) => Err(ProtoError::at(
+                    ErrorKind::Truncated,
+                    reader.pos(),
+                    format!("missing end group for field {}", number),
+                )),
+            };
+        }
+        match read_field(reader, group)? {
+            Some(field) => fields.push(field),
+            None => return Ok(fields),
+        }
+    }
+}
+
+/// Reads the next field, or `None` at the end tag of `group`
+fn read_field(reader: &mut ReaderImpl, group: Option<u32>) -> Result<Option<Field>> {
+    let offset = reader.pos();
+    let (number, wire_type) = reader.read_tag()?;
+    let value_offset = reader.pos();
+    let value = match wire_type {
+        0 => Value::Varint(reader.read_varint64()?),
+        1 => Value::Fixed64(reader.read_fixed64()?),
+        5 => Value::Fixed32(reader.read_fixed32()?),
+        2 => {
+            let length = reader.read_varint32()? as usize;
+            let start = reader.pos();
+            reader.skip(length)?;
+            Value::Delimited {
+                offset: start,
+                length,
+                guess: guess(reader, start, length),
+            }
+        }
+        3 => {
+            reader.descend()?;
+            let fields = read_fields(reader, Some(number));
+            reader.ascend();
+            Value::Group(fields?)
+        }
+        _ if group == Some(number) => return Ok(None),
+        _ => {
+            return Err(ProtoError::at(
+                ErrorKind::Malformed,
+                offset,
+                format!("unexpected end group for field {} at offset {}", number, offset),
+            ))
+        }
+    };
+    Ok(Some(Field {
+        number,
+        wire_type,
+        offset,
+        value_offset,
+        end: reader.pos(),
+        value,
+    }))
+}
+
+/// Guesses what the value at `offset` is, leaving the reader after it
+fn guess(reader: &mut ReaderImpl, offset: usize, length: usize) -> Guess {
+    let resume = reader.pos();
+    reader.seek(offset);
+    let printable = match reader.read_slice(length).map(std::str::from_utf8) {
+        Ok(Ok(text)) => text.chars().all(|c| !c.is_control() || matches!(c, '\n' | '\r' | '\t')),
+        _ => false,
+    };
+    let guess = if printable {
+        Guess::String
+    } else if let Some(fields) = within(reader, offset, length, |reader| {
+        reader.descend()?;
+        let fields = read_fields(reader, None);
+        reader.ascend();
+        fields
+    }) {
+        Guess::Message(fields)
+    } else if let Some(values) = within(reader, offset, length, |reader| {
+        let mut values = Vec::new();
+        while !reader.at_end() {
+            values.push(reader.read_varint64()?);
+        }
+        Ok(values)
+    }) {
+        Guess::Packed(values)
+    } else {
+        Guess::Bytes
+    };
+    reader.seek(resume);
+    guess
+}
+
+/// Runs `read` limited to the value at `offset`, `None` when it fails
+fn within<T>(
+    reader: &mut ReaderImpl,
+    offset: usize,
+    length: usize,
+    read: impl FnOnce(&mut ReaderImpl) -> Result<T>,
+) -> Option<T> {
+    reader.seek(offset);
+    reader.push_limit(length).ok()?;
+    let result = read(reader);
+    reader.pop_limit().ok()?;
+    result.ok()
+}
+
+/// Converts a dump to `{ fields, end, error }`, where each field is
+/// `{ field, wireType, offset, ... }` with the properties of its wire type
+pub fn to_js(env: &Env, reader: &mut ReaderImpl, dump: Dump, longs: LongRepr) -> napi::Result<JsObject> {
+    let mut object = env.create_object()?;
+    object.set_named_property("fields", fields_to_js(env, reader, &dump.fields, longs)?)?;
+    object.set_named_property("end", env.create_uint32(dump.end as u32)?)?;
+    if let Some(error) = dump.error {
+        object.set_named_property("error", error.into_js(env)?)?;
+    }
+    Ok(object)
+}
+
+fn fields_to_js(env: &Env, reader: &mut ReaderImpl, fields: &[Field], longs: LongRepr) -> napi::Result<JsObject> {
+    let mut array = env.create_array_with_length(fields.len())?;
+    for (i, field) in fields.iter().enumerate() {
+        let mut object = env.create_object()?;
+        object.set_named_property("field", env.create_uint32(field.number)?)?;
+        object.set_named_property("wireType", env.create_uint32(field.wire_type)?)?;
+        object.set_named_property("offset", env.create_uint32(field.offset as u32)?)?;
+        match &field.value {
+            Value::Varint(value) => {
+                object.set_named_property("value", longs.to_js(env, *value, true)?)?;
+                if (*value as i64) < 0 {
+                    object.set_named_property("int", longs.to_js(env, *value, false)?)?;
+                }
+                let zigzag = (value >> 1) ^ (value & 1).wrapping_neg();
+                object.set_named_property("sint", longs.to_js(env, zigzag, false)?)?;
+            }
+            Value::Fixed64(value) => {
+                object.set_named_property("value", longs.to_js(env, *value, true)?)?;
+                object.set_named_property("double", env.create_double(f64::from_bits(*value))?)?;
+            }
+            Value::Fixed32(value) => {
+                object.set_named_property("value", env.create_uint32(*value)?)?;
+                object.set_named_property("float", env.create_double(f32::from_bits(*value) as f64)?)?;
+            }
+            Value::Delimited { offset, length, guess } => {
+                reader.seek(*offset);
+                let data = reader.read_slice(*length).map_err(|e| e.into_napi(env))?;
+                let text = std::str::from_utf8(data).ok().map(str::to_string);
+                let bytes = env.create_buffer_with_data(data.to_vec())?.into_raw();
+                object.set_named_property("length", env.create_uint32(*length as u32)?)?;
+                object.set_named_property("kind", env.create_string(guess.kind())?)?;
+                object.set_named_property("bytes", bytes)?;
+                if let Some(text) = text {
+                    object.set_named_property("string", env.create_string(&text)?)?;
+                }
+                match guess {
+                    Guess::Message(fields) => {
+                        object.set_named_property("fields", fields_to_js(env, reader, fields, longs)?)?;
+                    }
+                    Guess::Packed(values) => {
+                        let mut packed = env.create_array_with_length(values.len())?;
+                        for (j, value) in values.iter().enumerate() {
+                            packed.set_element(j as u32, longs.to_js(env, *value, true)?)?;
+                        }
+                        object.set_named_property("packed", packed)?;
+                    }
+                    Guess::String | Guess::Bytes => {}
+                }
+            }
+            Value::Group(fields) => {
+                object.set_named_property("fields", fields_to_js(env, reader, fields, longs)?)?;
+            }
+        }
+        array.set_element(i as u32, object)?;
+    }
+    Ok(array)
+}
+
+/// Prints a dump like `protoc --decode_raw`, with the other readings of fixed
+/// and packed values in comments and a last comment when reading stopped early
+pub fn to_text(reader: &mut ReaderImpl, dump: &Dump) -> Result<String> {
+    let mut out = String::new();
+    print_fields(&mut out, reader, &dump.fields, 0)?;
+    if let Some(error) = &dump.error {
+        let _ = writeln!(out, "# stopped at offset {}: {}", dump.end, error.message());
+    }
+    Ok(out)
+}
+
+fn print_fields(out: &mut String, reader: &mut ReaderImpl, fields: &[Field], indent: usize) -> Result<()> {
+    for field in fields {
+        for _ in 0..indent {
+            out.push_str("  ");
+        }
+        let _ = write!(out, "{}", field.number);
+        let nested = match &field.value {
+            Value::Varint(value) => {
+                let _ = write!(out, ": {}", value);
+                None
+            }
+            Value::Fixed64(value) => {
+                let _ = write!(out, ": 0x{:016x}  # ", value);
+                text::push_float(out, f64::from_bits(*value));
+                None
+            }
+            Value::Fixed32(value) => {
+                let _ = write!(out, ": 0x{:08x}  # ", value);
+                text::push_float(out, f32::from_bits(*value));
+                None
+            }
+            Value::Delimited { guess: Guess::Message(fields), .. } | Value::Group(fields) => Some(fields),
+            Value::Delimited { offset, length, guess } => {
+                reader.seek(*offset);
+                let data = reader.read_slice(*length)?;
+                out.push_str(": ");
+                match (guess, std::str::from_utf8(data)) {
+                    (Guess::String, Ok(text)) => text::push_string(out, text),
+                    _ => text::push_bytes(out, data),
+                }
+                if let Guess::Packed(values) = guess {
+                    let values: Vec<String> = values.iter().map(u64::to_string).collect();
+                    let _ = write!(out, "  # packed: {}", values.join(", "));
+                }
+                None
+            }
+        };
+        match nested {
+            Some(fields) => {
+                out.push_str(" {\n");
+                print_fields(out, reader, fields, indent + 1)?;
+                for _ in 0..indent {
+                    out.push_str("  ");
+                }
+                out.push_str("}\n");
+            }
+            None => out.push('\n'),
+        }
+    }
+    Ok(())
+}
@@ -1,23 +1,266 @@
-use napi::Result;
+use std::ops::Deref;
+
+use napi::bindgen_prelude::{FromNapiValue, Uint8Array};
+use napi::Env;
 use napi::Error;
+use napi::JsArrayBuffer;
+use napi::JsDataView;
+use napi::JsObject;
+use napi::JsTypedArray;
+use napi::JsUnknown;
+use napi::NapiRaw;
 use napi::Status;
+use napi::TypedArrayType;
+
+use crate::error::{ErrorKind, ProtoError, Result};
+use crate::tag;
+use crate::utf8::{self, Text, Utf8Policy};
+
+/// Bytes a reader decodes from
+pub enum Source {
+    /// A view of JS memory, kept alive by the napi reference the `Uint8Array` holds
+    Shared(Uint8Array),
+}
+
+impl Source {
+    /// Views a Buffer, Uint8Array (or any other TypedArray), ArrayBuffer or DataView in place
+    pub fn from_js(env: &Env, input: JsUnknown) -> napi::Result<Source> {
+        let raw = unsafe { input.raw() };
+        let (arraybuffer, byte_offset, byte_length) = if input.is_typedarray()? {
+            let object: JsObject = unsafe { input.cast() };
+            let byte_length = object.get_named_property::<u32>("byteLength")? as usize;
+            let array: JsTypedArray = unsafe { input.cast() };
+            let value = array.into_value()?;
+            if matches!(value.typedarray_type, TypedArrayType::Uint8) {
+                // Buffers are Uint8Arrays, so these need no extra view
+                let array = unsafe { Uint8Array::from_napi_value(env.raw(), raw) }?;
+                return Ok(Source::Shared(array));
+            }
+            (value.arraybuffer, value.byte_offset, byte_length)
+        } else if input.is_dataview()? {
+            let view: JsDataView = unsafe { input.cast() };
+            let value = view.into_value()?;
+            (value.arraybuffer, value.byte_offset as usize, value.length as usize)
+        } else if is_arraybuffer(env, &input)? {
+            let arraybuffer: JsArrayBuffer = unsafe { input.cast() };
+            let value = arraybuffer.into_value()?;
+            let len = value.len();
+            (value.into_raw(), 0, len)
+        } else {
+            return Err(Error::new(
+                Status::InvalidArg,
+                "Buffer, Uint8Array, ArrayBuffer or DataView expected".to_owned(),
+            ));
+        };
+        let view = arraybuffer.into_typedarray(TypedArrayType::Uint8, byte_length, byte_offset)?;
+        let array = unsafe { Uint8Array::from_napi_value(env.raw(), view.raw()) }?;
+        Ok(Source::Shared(array))
+    }
+}
+
+impl Deref for Source {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &[u8] {
+        match self {
+            Source::Shared(array) => array,
+        }
+    }
+}
+
+fn is_arraybuffer(env: &Env, value: &JsUnknown) -> napi::Result<bool> {
+    let mut result = false;
+    let status = unsafe { napi::sys::napi_is_arraybuffer(env.raw(), value.raw(), &mut result) };
+    if status != napi::sys::Status::napi_ok {
+        return Err(Error::new(Status::from(status), "napi_is_arraybuffer failed".to_owned()));
+    }
+    Ok(result)
+}
+
+/// Nesting depth allowed by default, matching the other protobuf runtimes
+pub const DEFAULT_MAX_DEPTH: usize = 100;
+
+/// Safety limits for decoding untrusted input
+#[derive(Clone, Copy)]
+pub struct DecodeLimits {
+    /// Deepest nesting of sub-messages and groups
+    pub max_depth: usize,
+    /// Largest input accepted
+    pub max_message_size: usize,
+    /// Longest bytes or string value (or packed payload) read in one piece
+    pub max_bytes_length: usize,
+    /// Most fields read from one input
+    pub max_fields: usize,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        DecodeLimits {
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_message_size: usize::MAX,
+            max_bytes_length: usize::MAX,
+            max_fields: usize::MAX,
+        }
+    }
+}
+
+impl DecodeLimits {
+    /// Builds limits from the optional JS options, using the defaults for missing ones
+    pub fn from_options(
+        max_depth: Option<u32>,
+        max_message_size: Option<u32>,
+        max_bytes_length: Option<u32>,
+        max_fields: Option<u32>,
+    ) -> Self {
+        let defaults = DecodeLimits::default();
+        DecodeLimits {
+            max_depth: max_depth.map_or(defaults.max_depth, |n| n as usize),
+            max_message_size: max_message_size.map_or(defaults.max_message_size, |n| n as usize),
+            max_bytes_length: max_bytes_length.map_or(defaults.max_bytes_length, |n| n as usize),
+            max_fields: max_fields.map_or(defaults.max_fields, |n| n as usize),
+        }
+    }
+}
 
 pub struct ReaderImpl {
-    buf: Vec<u8>,
+    buf: Source,
     pos: usize,
+    /// End of the innermost pushed limit, or of the buffer. Every read is bounded by it.
+    limit: usize,
+    /// Limits to restore on `pop_limit`, outermost first
+    limits: Vec<usize>,
+    decode_limits: DecodeLimits,
+    /// Current nesting depth of sub-messages and groups
+    depth: usize,
+    /// Number of fields read so far
+    fields: usize,
+    /// Reject encodings that aren't canonical, see `set_strict`
+    strict: bool,
+    /// Policy for strings that aren't valid UTF-8
+    utf8: Utf8Policy,
 }
 
 impl ReaderImpl {
-    pub fn new(buf: Vec<u8>) -> Self {
-        ReaderImpl { buf, pos: 0 }
+    /// Reads from `source` without copying it
+    pub fn from_source(source: Source) -> Self {
+        let limit = source.len();
+        ReaderImpl {
+            buf: source,
+            pos: 0,
+            limit,
+            limits: Vec::new(),
+            decode_limits: DecodeLimits::default(),
+            depth: 0,
+            fields: 0,
+            strict: false,
+            utf8: Utf8Policy::Strict,
+        }
+    }
+
+    /// In strict mode varints must be minimal and fit their type without
+    /// discarding bits. The schema decoder adds the rules that need a schema.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    pub fn set_utf8(&mut self, policy: Utf8Policy) {
+        self.utf8 = policy;
+    }
+
+    /// The reader's UTF-8 policy, which `read_text` callers may override
+    pub fn utf8(&self) -> Utf8Policy {
+        self.utf8
+    }
+
+    /// Applies `limits`, failing if the input is already larger than allowed
+    pub fn set_decode_limits(&mut self, limits: DecodeLimits) -> Result<()> {
+        if self.buf.len() > limits.max_message_size {
+            return Err(ProtoError::new(
+                ErrorKind::LimitExceeded,
+                format!(
+                    "message size {} exceeds maxMessageSize {}",
+                    self.buf.len(),
+                    limits.max_message_size
+                ),
+            ));
+        }
+        self.decode_limits = limits;
+        Ok(())
+    }
+
+    /// Enters a sub-message or group, failing past the maximum depth. Pair with `ascend`.
+    pub fn descend(&mut self) -> Result<()> {
+        if self.depth >= self.decode_limits.max_depth {
+            return Err(ProtoError::at(
+                ErrorKind::LimitExceeded,
+                self.pos,
+                format!(
+                    "nesting depth exceeds maxDepth {} at offset {}",
+                    self.decode_limits.max_depth, self.pos
+                ),
+            ));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    pub fn ascend(&mut self) {
+        self.depth -= 1;
+    }
+
+    /// Counts a field about to be decoded, failing past the maximum number of fields
+    pub fn count_field(&mut self) -> Result<()> {
+        if self.fields >= self.decode_limits.max_fields {
+            return Err(ProtoError::at(
+                ErrorKind::LimitExceeded,
+                self.pos,
+                format!(
+                    "field count exceeds maxFields {} at offset {}",
+                    self.decode_limits.max_fields, self.pos
+                ),
+            ));
+        }
+        self.fields += 1;
+        Ok(())
+    }
+
+    /// Number of fields counted towards `maxFields` so far
+    pub fn fields_read(&self) -> usize {
+        self.fields
+    }
+
+    /// Resets the field count, to read fields again without counting them twice
+    pub fn set_fields_read(&mut self, fields: usize) {
+        self.fields = fields;
+    }
+
+    #[inline]
+    fn check_length(&self, len: usize) -> Result<()> {
+        if len > self.decode_limits.max_bytes_length {
+            return Err(ProtoError::at(
+                ErrorKind::LimitExceeded,
+                self.pos,
+                format!(
+                    "length {} exceeds maxBytesLength {} at offset {}",
+                    len, self.decode_limits.max_bytes_length, self.pos
+                ),
+            ));
+        }
+        self.check_bounds(len)
     }
 
     #[inline]
     fn check_bounds(&self, len: usize) -> Result<()> {
-        if self.pos + len > self.buf.len() {
-            Err(Error::new(
-                Status::GenericFailure,
-                format!("index out of range: {} + {} > {}", self.pos, len, self.buf.len()),
+        if self.pos + len > self.limit {
+            Err(ProtoError::at(
+                ErrorKind::Truncated,
+                self.pos,
+                format!("index out of range: {} + {} > {}", self.pos, len, self.limit),
             ))
         } else {
             Ok(())
@@ -26,6 +269,9 @@ impl ReaderImpl {
 
     #[inline]
     pub fn read_varint32(&mut self) -> Result<u32> {
+        if self.strict {
+            return self.read_varint32_strict();
+        }
         let mut value: u32 = 0;
         let mut shift = 0;
 
@@ -42,10 +288,11 @@ impl ReaderImpl {
                 }
                 // Skip remaining bytes if continuation bit is set
                 for _ in 0..5 {
-                    if self.pos >= self.buf.len() {
-                        return Err(Error::new(
-                            Status::GenericFailure,
-                            format!("index out of range: {} + 10 > {}", self.pos - 1, self.buf.len()),
+                    if self.pos >= self.limit {
+                        return Err(ProtoError::at(
+                            ErrorKind::Truncated,
+                            self.pos - 1,
+                            format!("index out of range: {} + 10 > {}", self.pos - 1, self.limit),
                         ));
                     }
                     if self.buf[self.pos] < 0x80 {
@@ -54,7 +301,7 @@ impl ReaderImpl {
                     }
                     self.pos += 1;
                 }
-                return Ok(value);
+                return Err(self.varint_overflow());
             }
 
             value |= ((byte & 0x7F) as u32) << shift;
@@ -67,6 +314,9 @@ impl ReaderImpl {
 
     #[inline]
     pub fn read_varint64(&mut self) -> Result<u64> {
+        if self.strict {
+            return self.read_varint64_strict();
+        }
         let mut value: u64 = 0;
         let mut shift = 0;
 
@@ -77,6 +327,9 @@ impl ReaderImpl {
 
             if shift == 63 {
                 // Last byte
+                if byte >= 0x80 {
+                    return Err(self.varint_overflow());
+                }
                 value |= ((byte & 0x01) as u64) << shift;
                 return Ok(value);
             }
@@ -93,6 +346,90 @@ impl ReaderImpl {
         }
     }
 
+    /// Reads a varint that must be minimal and keep all of its bits
+    fn read_varint64_strict(&mut self) -> Result<u64> {
+        let start = self.pos;
+        let mut value: u64 = 0;
+        for shift in (0..64).step_by(7) {
+            self.check_bounds(1)?;
+            let byte = self.buf[self.pos];
+            self.pos += 1;
+            if shift == 63 && byte > 1 {
+                if byte >= 0x80 {
+                    return Err(self.varint_overflow());
+                }
+                return Err(ProtoError::non_canonical(
+                    "overlong_varint",
+                    start,
+                    format!("varint at offset {} has bits past the 64th", start),
+                ));
+            }
+            value |= ((byte & 0x7F) as u64) << shift;
+            if byte < 0x80 {
+                if byte == 0 && shift > 0 {
+                    return Err(ProtoError::non_canonical(
+                        "non_minimal_varint",
+                        start,
+                        format!("varint at offset {} is not minimally encoded", start),
+                    ));
+                }
+                return Ok(value);
+            }
+        }
+        unreachable!("the 10th byte always ends the varint")
+    }
+
+    /// Like `read_varint64_strict`, also rejecting values beyond 32 bits
+    fn read_varint32_strict(&mut self) -> Result<u32> {
+        let start = self.pos;
+        let value = self.read_varint64_strict()?;
+        if value > u32::MAX as u64 {
+            return Err(ProtoError::non_canonical(
+                "overlong_varint",
+                start,
+                format!("varint at offset {} overflows 32 bits", start),
+            ));
+        }
+        Ok(value as u32)
+    }
+
+    /// Reads an int32 or enum value, which negative values sign-extend to 10 bytes.
+    /// Strict mode rejects any other value beyond 32 bits.
+    #[inline]
+    pub fn read_int32(&mut self) -> Result<i32> {
+        let start = self.pos;
+        let value = self.read_varint64()?;
+        if self.strict && value > u32::MAX as u64 && value < i32::MIN as i64 as u64 {
+            return Err(ProtoError::non_canonical(
+                "overlong_varint",
+                start,
+                format!("varint at offset {} overflows 32 bits", start),
+            ));
+        }
+        Ok(value as i32)
+    }
+
+    /// Error for a varint that still continues after 10 bytes, which end at `pos`
+    #[cold]
+    fn varint_overflow(&self) -> ProtoError {
+        ProtoError::at(
+            ErrorKind::VarintOverflow,
+            self.pos - 10,
+            format!("invalid varint encoding at offset {}", self.pos - 10),
+        )
+    }
+
+    /// Reads a tag as `(field, wire_type)`, validating both
+    #[inline]
+    pub fn read_tag(&mut self) -> Result<(u32, u32)> {
+        self.count_field()?;
+        let start = self.pos;
+        let value = self.read_varint32()?;
+        let (field, wire_type) = (value >> 3, value & 7);
+        tag::validate(field, wire_type).map_err(|e| e.with_offset(start))?;
+        Ok((field, wire_type))
+    }
+
     #[inline]
     pub fn read_sint32(&mut self) -> Result<i32> {
         let value = self.read_varint32()?;
@@ -161,12 +498,28 @@ impl ReaderImpl {
 
     #[inline]
     pub fn read_bytes(&mut self, len: usize) -> Result<Vec<u8>> {
-        self.check_bounds(len)?;
+        self.check_length(len)?;
         let bytes = self.buf[self.pos..self.pos + len].to_vec();
         self.pos += len;
         Ok(bytes)
     }
 
+    /// Borrows `len` bytes from the buffer without copying
+    #[inline]
+    pub fn read_slice(&mut self, len: usize) -> Result<&[u8]> {
+        self.check_length(len)?;
+        let start = self.pos;
+        self.pos += len;
+        Ok(&self.buf[start..self.pos])
+    }
+
+    /// Borrows `len` bytes as UTF-8 text
+    #[inline]
+    pub fn read_text(&mut self, len: usize, policy: Utf8Policy) -> Result<Text<'_>> {
+        let start = self.pos;
+        utf8::decode(self.read_slice(len)?, start, policy)
+    }
+
     #[inline]
     pub fn skip(&mut self, len: usize) -> Result<()> {
         self.check_bounds(len)?;
@@ -178,15 +531,18 @@ impl ReaderImpl {
         match wire_type {
             0 => {
                 // Varint
-                loop {
+                if self.strict {
+                    return self.read_varint64_strict().map(|_| ());
+                }
+                for _ in 0..10 {
                     self.check_bounds(1)?;
                     let byte = self.buf[self.pos];
                     self.pos += 1;
                     if byte < 0x80 {
-                        break;
+                        return Ok(());
                     }
                 }
-                Ok(())
+                Err(self.varint_overflow())
             }
             1 => {
                 // 64-bit
@@ -199,32 +555,111 @@ impl ReaderImpl {
             }
             3 => {
                 // Start group (deprecated) - read until end group (wire type 4)
-                loop {
-                    let field_and_type = self.read_varint32()?;
-                    let wire_type = field_and_type & 7;
-                    if wire_type == 4 {
-                        // End group
-                        break;
-                    }
-                    self.skip_type(wire_type)?;
-                }
-                Ok(())
+                self.group(None).map(|_| ())
             }
             5 => {
                 // 32-bit
                 self.skip(4)
             }
-            _ => Err(Error::new(
-                Status::GenericFailure,
+            _ => Err(ProtoError::at(
+                ErrorKind::InvalidWireType,
+                self.pos,
                 format!("invalid wire type {} at offset {}", wire_type, self.pos),
             )),
         }
     }
 
+    /// Skips the value of a field whose tag was just read. Unlike `skip_type`, this
+    /// checks that a group is closed by the end tag of the same field.
+    pub fn skip_field(&mut self, field: u32, wire_type: u32) -> Result<()> {
+        match wire_type {
+            3 => self.skip_group(field).map(|_| ()),
+            _ => self.skip_type(wire_type),
+        }
+    }
+
+    /// Skips the rest of a group of `field` and returns the offset of its end tag
+    pub fn skip_group(&mut self, field: u32) -> Result<usize> {
+        self.group(Some(field))
+    }
+
+    fn group(&mut self, field: Option<u32>) -> Result<usize> {
+        self.descend()?;
+        let result = self.group_contents(field);
+        self.ascend();
+        result
+    }
+
+    fn group_contents(&mut self, field: Option<u32>) -> Result<usize> {
+        loop {
+            let start = self.pos;
+            let field_and_type = self.read_varint32()?;
+            let (number, wire_type) = (field_and_type >> 3, field_and_type & 7);
+            match wire_type {
+                4 => match field {
+                    Some(field) if field != number => {
+                        return Err(ProtoError::at(
+                            ErrorKind::Malformed,
+                            start,
+                            format!(
+                                "end group for field {} doesn't close field {} at offset {}",
+                                number, field, start
+                            ),
+                        ))
+                    }
+                    _ => return Ok(start),
+                },
+                3 => {
+                    self.count_field()?;
+                    self.group(Some(number))?;
+                }
+                _ => {
+                    self.count_field()?;
+                    self.skip_type(wire_type)?;
+                }
+            }
+        }
+    }
+
     pub fn pos(&self) -> usize {
         self.pos
     }
 
+    /// Moves to `pos`, which must not be past the current limit
+    pub fn seek(&mut self, pos: usize) {
+        debug_assert!(pos <= self.limit);
+        self.pos = pos;
+    }
+
+    /// Limits reads to the next `len` bytes until `pop_limit`, which fails if
+    /// they extend past the current limit
+    pub fn push_limit(&mut self, len: usize) -> Result<()> {
+        self.check_bounds(len)?;
+        self.limits.push(self.limit);
+        self.limit = self.pos + len;
+        Ok(())
+    }
+
+    /// Restores the limit in effect before the last `push_limit`
+    pub fn pop_limit(&mut self) -> Result<()> {
+        self.limit = self.limits.pop().ok_or_else(|| {
+            ProtoError::new(ErrorKind::Malformed, "pop_limit without push_limit".to_owned())
+        })?;
+        Ok(())
+    }
+
+    /// Whether the current limit has been reached
+    #[inline]
+    pub fn at_end(&self) -> bool {
+        self.pos >= self.limit
+    }
+
+    /// Bytes left before the current limit
+    #[inline]
+    pub fn bytes_until_limit(&self) -> usize {
+        self.limit - self.pos
+    }
+
     pub fn len(&self) -> usize {
         self.buf.len()
     }
@@ -0,0 +1,66 @@
+use napi_derive::napi;
+
+use crate::error::{ErrorKind, ProtoError, Result};
+use crate::reader::ReaderImpl;
+
+/// Options of `Reader.scan`
+#[napi(object)]
+#[derive(Default)]
+pub struct ScanOptions {
+    /// Also index the fields of length-delimited values that parse as messages
+    pub recursive: Option<bool>,
+}
+
+/// Indexes every field between the reader's position and its limit as rows of
+/// `(field, wire type, value offset, value length)`. Offsets are absolute
+/// positions in the buffer; varints and fixed values span their encoded bytes,
+/// length-delimited values their payload and groups their contents without the
+/// end tag. When `recursive`, the rows of a nested message directly follow the
+/// row of the field containing it, which can be told apart by their offsets.
+pub fn scan(reader: &mut ReaderImpl, recursive: bool, table: &mut Vec<u32>) -> Result<()> {
+    while !reader.at_end() {
+        let (field, wire_type) = reader.read_tag()?;
+        let (offset, length) = match wire_type {
+            2 => {
+                let len = reader.read_varint32()? as usize;
+                let offset = reader.pos();
+                reader.skip(len)?;
+                (offset, len)
+            }
+            3 => {
+                let offset = reader.pos();
+                let end = reader.skip_group(field)?;
+                (offset, end - offset)
+            }
+            4 => {
+                return Err(ProtoError::at(
+                    ErrorKind::Malformed,
+                    reader.pos(),
+                    format!("unexpected end group for field {} at offset {}", field, reader.pos()),
+                ))
+            }
+            _ => {
+                let offset = reader.pos();
+                reader.skip_field(field, wire_type)?;
+                (offset, reader.pos() - offset)
+            }
+        };
+        table.extend_from_slice(&[field, wire_type, offset as u32, length as u32]);
+
+        // Values nested deeper than the reader's maximum depth are kept opaque
+        if recursive && wire_type == 2 && length > 0 && reader.descend().is_ok() {
+            let resume = reader.pos();
+            let rows = table.len();
+            reader.seek(offset);
+            reader.push_limit(length)?;
+            // Anything that doesn't parse as a message exactly filling the value is kept opaque
+            if scan(reader, true, table).is_err() {
+                table.truncate(rows);
+            }
+            reader.pop_limit()?;
+            reader.ascend();
+            reader.seek(resume);
+        }
+    }
+    Ok(())
+}
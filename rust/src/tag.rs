@@ -0,0 +1,52 @@
+use napi::Env;
+use napi::JsFunction;
+use napi::JsObject;
+use napi_derive::napi;
+
+use crate::error::{ErrorKind, ProtoError, Result};
+
+/// Largest valid field number, 2^29 - 1
+pub const MAX_FIELD_NUMBER: u32 = (1 << 29) - 1;
+
+/// Field numbers reserved for the protobuf implementation
+pub const RESERVED_FIELD_NUMBERS: std::ops::RangeInclusive<u32> = 19000..=19999;
+
+/// A decoded field tag
+#[napi(object)]
+pub struct Tag {
+    pub field: u32,
+    pub wire_type: u32,
+}
+
+/// Checks that `field` and `wire_type` form a valid tag
+pub fn validate(field: u32, wire_type: u32) -> Result<()> {
+    if field == 0 || field > MAX_FIELD_NUMBER {
+        return Err(ProtoError::new(
+            ErrorKind::InvalidFieldNumber,
+            format!("invalid field number {}: must be 1..{}", field, MAX_FIELD_NUMBER),
+        ));
+    }
+    if wire_type > 5 {
+        return Err(ProtoError::new(
+            ErrorKind::InvalidWireType,
+            format!("invalid wire type {} for field {}", wire_type, field),
+        ));
+    }
+    Ok(())
+}
+
+/// Validates a tag about to be written, warning through `process.emitWarning`
+/// when it uses a reserved field number
+pub fn check_write(env: &Env, field: u32, wire_type: u32) -> Result<()> {
+    validate(field, wire_type)?;
+    if RESERVED_FIELD_NUMBERS.contains(&field) {
+        let process: JsObject = env.get_global()?.get_named_property("process")?;
+        let emit_warning: JsFunction = process.get_named_property("emitWarning")?;
+        let message = env.create_string(&format!(
+            "field number {} is reserved for the protobuf implementation (19000-19999)",
+            field
+        ))?;
+        emit_warning.call(Some(&process), &[message])?;
+    }
+    Ok(())
+}
@@ -0,0 +1,1109 @@
+use std::borrow::Cow;
+use std::fmt::Write as _;
+
+use napi_derive::napi;
+
+use crate::descriptor::{
+    json_name_of, FieldDescriptor, FieldType, MessageDescriptor, Registry, ScalarType, WellKnown,
+};
+use crate::error::{ErrorKind, ProtoError, Result};
+use crate::json::{self, Span, Wire};
+use crate::reader::ReaderImpl;
+use crate::scan;
+use crate::utf8::{self, Text, Utf8Policy};
+use crate::writer::WriterImpl;
+
+/// Options of `toTextFormat`
+#[napi(object)]
+#[derive(Default)]
+pub struct ToTextFormatOptions {
+    /// Print everything on one line, like protoc's short debug string
+    pub single_line: Option<bool>,
+    /// Deepest nesting of sub-messages and groups, 100 by default
+    pub max_depth: Option<u32>,
+    /// Largest input accepted, unlimited by default
+    pub max_message_size: Option<u32>,
+    /// Longest bytes or string value, unlimited by default
+    pub max_bytes_length: Option<u32>,
+    /// Most fields read, unlimited by default
+    pub max_fields: Option<u32>,
+    /// `"strict"` (default), `"replace"`, or `"bytes"` to print strings that aren't
+    /// valid UTF-8 with octal escapes
+    pub utf8: Option<String>,
+}
+
+/// Options of `fromTextFormat`
+#[napi(object)]
+#[derive(Default)]
+pub struct FromTextFormatOptions {
+    /// `"strict"` (default) rejects string literals that aren't valid UTF-8 and lone
+    /// surrogates in the text, `"replace"` replaces both and `"bytes"` keeps the literals as is
+    pub utf8: Option<String>,
+}
+
+/// Nesting limit of the parser, like the reader's default `maxDepth`
+const MAX_DEPTH: usize = 100;
+
+fn wire_type_error(number: u32, wire_type: u32, offset: usize) -> ProtoError {
+    ProtoError::at(
+        ErrorKind::InvalidWireType,
+        offset,
+        format!("invalid wire type {} for field {} at offset {}", wire_type, number, offset),
+    )
+}
+
+/// Appends `c` with the C-style escapes of the text format
+fn push_escaped(out: &mut String, c: char) {
+    match c {
+        '"' => out.push_str("\\\""),
+        '\'' => out.push_str("\\'"),
+        '\\' => out.push_str("\\\\"),
+        '\n' => out.push_str("\\n"),
+        '\r' => out.push_str("\\r"),
+        '\t' => out.push_str("\\t"),
+        c if c < ' ' || c == '\x7f' => {
+            let _ = write!(out, "\\{:03o}", c as u32);
+        }
+        c => out.push(c),
+    }
+}
+
+/// Appends `data` as a quoted literal with every byte outside printable ASCII escaped
+pub fn push_bytes(out: &mut String, data: &[u8]) {
+    out.push('"');
+    for &byte in data {
+        if byte < 0x80 {
+            push_escaped(out, byte as char);
+        } else {
+            let _ = write!(out, "\\{:03o}", byte);
+        }
+    }
+    out.push('"');
+}
+
+pub fn push_string(out: &mut String, text: &str) {
+    out.push('"');
+    for c in text.chars() {
+        push_escaped(out, c);
+    }
+    out.push('"');
+}
+
+pub fn push_float<T: Copy + Into<f64> + std::fmt::Display + std::fmt::LowerExp>(out: &mut String, value: T) {
+    let double: f64 = value.into();
+    let _ = if double.is_nan() {
+        write!(out, "nan")
+    } else if double.is_infinite() {
+        write!(out, "{}", if double > 0.0 { "inf" } else { "-inf" })
+    } else if double != 0.0 && (double.abs() < 1e-7 || double.abs() >= 1e21) {
+        write!(out, "{:e}", value)
+    } else {
+        write!(out, "{}", value)
+    };
+}
+
+/// Prints binary messages in the protobuf text format
+pub struct Printer<'a> {
+    registry: &'a Registry,
+    single_line: bool,
+    utf8: Utf8Policy,
+    indent: usize,
+    out: String,
+}
+
+impl<'a> Printer<'a> {
+    pub fn new(registry: &'a Registry, options: ToTextFormatOptions) -> napi::Result<Self> {
+        Ok(Printer {
+            registry,
+            single_line: options.single_line.unwrap_or(false),
+            utf8: Utf8Policy::from_option(options.utf8.as_deref())?,
+            indent: 0,
+            out: String::new(),
+        })
+    }
+
+    /// Prints the fields of the message between the reader's position and its end
+    pub fn print(mut self, reader: &mut ReaderImpl, message: &MessageDescriptor) -> Result<String> {
+        let span = Span::payload(reader.pos(), reader.len() - reader.pos());
+        self.contents(reader, message, &[span])?;
+        Ok(self.out)
+    }
+
+    /// Starts a line, or separates the field from the previous one on a single line
+    fn begin(&mut self, name: &str) {
+        if self.single_line {
+            if !self.out.is_empty() {
+                self.out.push(' ');
+            }
+        } else {
+            for _ in 0..self.indent {
+                self.out.push_str("  ");
+            }
+        }
+        self.out.push_str(name);
+    }
+
+    fn end(&mut self) {
+        if !self.single_line {
+            self.out.push('\n');
+        }
+    }
+
+    /// Prints `name {`, the fields printed by `contents` and the closing brace
+    fn nested(
+        &mut self,
+        reader: &mut ReaderImpl,
+        name: &str,
+        contents: impl FnOnce(&mut Self, &mut ReaderImpl) -> Result<()>,
+    ) -> Result<()> {
+        reader.descend()?;
+        self.begin(name);
+        self.out.push_str(" {");
+        self.end();
+        self.indent += 1;
+        let result = contents(self, reader);
+        self.indent -= 1;
+        reader.ascend();
+        result?;
+        self.begin("}");
+        self.end();
+        Ok(())
+    }
+
+    fn contents(&mut self, reader: &mut ReaderImpl, message: &MessageDescriptor, spans: &[Span]) -> Result<()> {
+        let mut unknown = Vec::new();
+        let fields = json::collect(reader, message, spans, Some(&mut unknown))?;
+        if message.well_known == Some(WellKnown::Any) && self.any(reader, message, &fields)? {
+            return Ok(());
+        }
+        for (field, spans) in message.fields.iter().zip(&fields) {
+            self.field(reader, field, spans)
+                .map_err(|e| e.in_field(&field.name))?;
+        }
+        for (number, span) in unknown {
+            self.unknown(reader, number, &span)?;
+        }
+        Ok(())
+    }
+
+    /// Prints an `Any` of a registered type as `[type_url] { ... }`, or returns false
+    /// when it's printed like any other message
+    fn any(&mut self, reader: &mut ReaderImpl, message: &MessageDescriptor, fields: &[Vec<Span>]) -> Result<bool> {
+        let last = |number: u32| message.field_index(number).and_then(|index| fields[index].last().copied());
+        let (type_url, value) = match (last(1), last(2)) {
+            (Some(type_url), Some(value)) if type_url.wire_type == 2 && value.wire_type == 2 => (type_url, value),
+            _ => return Ok(false),
+        };
+        reader.seek(type_url.offset);
+        let type_url = reader.read_text(type_url.length, self.utf8)?.into_lossy();
+        let inner = match json::any_type(self.registry, &type_url) {
+            Ok(inner) => inner,
+            Err(_) => return Ok(false),
+        };
+        let value = [Span::payload(value.offset, value.length)];
+        self.nested(reader, &format!("[{}]", type_url), |printer, reader| {
+            printer.contents(reader, inner, &value)
+        })?;
+        Ok(true)
+    }
+
+    /// The name a field is printed with: `[pkg.ext]` for extensions and the type name for groups
+    fn name(&self, field: &FieldDescriptor) -> String {
+        if field.name.starts_with('.') {
+            return field.json_name.clone();
+        }
+        if let FieldType::Message(index) = field.field_type {
+            let message = self.registry.message_at(index);
+            if field.delimited && message.group {
+                return message.full_name.rsplit('.').next().unwrap_or_default().to_string();
+            }
+        }
+        field.name.clone()
+    }
+
+    fn field(&mut self, reader: &mut ReaderImpl, field: &FieldDescriptor, spans: &[Span]) -> Result<()> {
+        let name = self.name(field);
+        if field.is_map() {
+            for (i, span) in spans.iter().enumerate() {
+                if span.wire_type != 2 {
+                    return Err(json::wire_type_error(field, span.wire_type, span.offset));
+                }
+                self.nested(reader, &name, |printer, reader| printer.map_entry(reader, field, span))
+                    .map_err(|e| e.in_index(i))?;
+            }
+        } else if field.is_repeated() {
+            let mut len = 0;
+            for span in spans {
+                if span.wire_type == 2 && field.is_packable() {
+                    reader.seek(span.offset);
+                    reader.push_limit(span.length)?;
+                    let mut result = Ok(());
+                    while result.is_ok() && !reader.at_end() {
+                        self.begin(&name);
+                        self.out.push_str(": ");
+                        result = self.scalar(reader, field.field_type, 0).map_err(|e| e.in_index(len));
+                        self.end();
+                        len += 1;
+                    }
+                    reader.pop_limit()?;
+                    result?;
+                } else {
+                    self.value(reader, field, &name, &[*span])
+                        .map_err(|e| e.in_index(len))?;
+                    len += 1;
+                }
+            }
+        } else if let FieldType::Message(_) = field.field_type {
+            if !spans.is_empty() {
+                self.value(reader, field, &name, spans)?;
+            }
+        } else if let Some(span) = spans.last() {
+            if field.has_presence || !json::is_zero(reader, span)? {
+                self.value(reader, field, &name, &[*span])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Prints one value of `field`, merging the occurrences of a message
+    fn value(&mut self, reader: &mut ReaderImpl, field: &FieldDescriptor, name: &str, spans: &[Span]) -> Result<()> {
+        if let FieldType::Message(index) = field.field_type {
+            if let Some(span) = spans.iter().find(|span| !matches!(span.wire_type, 2 | 3)) {
+                return Err(json::wire_type_error(field, span.wire_type, span.offset));
+            }
+            let message = self.registry.message_at(index);
+            return self.nested(reader, name, |printer, reader| printer.contents(reader, message, spans));
+        }
+        let span = spans.last().expect("values have a span");
+        if span.wire_type != field.value_wire_type() {
+            return Err(json::wire_type_error(field, span.wire_type, span.offset));
+        }
+        reader.seek(span.offset);
+        self.begin(name);
+        self.out.push_str(": ");
+        self.scalar(reader, field.field_type, span.length)?;
+        self.end();
+        Ok(())
+    }
+
+    /// Prints the scalar or enum value at the reader's position; `length` is the payload
+    /// length of strings and bytes
+    fn scalar(&mut self, reader: &mut ReaderImpl, field_type: FieldType, length: usize) -> Result<()> {
+        let scalar = match field_type {
+            FieldType::Scalar(scalar) => scalar,
+            FieldType::Enum(index) => {
+                let value = reader.read_varint64()? as i32;
+                match self.registry.enum_at(index).name_of(value) {
+                    Some(name) => self.out.push_str(name),
+                    None => self.out.push_str(&value.to_string()),
+                }
+                return Ok(());
+            }
+            FieldType::Message(_) => unreachable!("messages are printed by value"),
+        };
+        let out = &mut self.out;
+        match scalar {
+            ScalarType::Int32 => write!(out, "{}", reader.read_varint64()? as i32),
+            ScalarType::Uint32 => write!(out, "{}", reader.read_varint32()?),
+            ScalarType::Sint32 => write!(out, "{}", reader.read_sint32()?),
+            ScalarType::Fixed32 => write!(out, "{}", reader.read_fixed32()?),
+            ScalarType::Sfixed32 => write!(out, "{}", reader.read_sfixed32()?),
+            ScalarType::Int64 => write!(out, "{}", reader.read_varint64()? as i64),
+            ScalarType::Uint64 => write!(out, "{}", reader.read_varint64()?),
+            ScalarType::Sint64 => write!(out, "{}", reader.read_sint64()?),
+            ScalarType::Fixed64 => write!(out, "{}", reader.read_fixed64()?),
+            ScalarType::Sfixed64 => write!(out, "{}", reader.read_sfixed64()?),
+            ScalarType::Bool => write!(out, "{}", reader.read_varint64()? != 0),
+            ScalarType::Float => {
+                push_float(out, reader.read_float()?);
+                Ok(())
+            }
+            ScalarType::Double => {
+                push_float(out, reader.read_double()?);
+                Ok(())
+            }
+            ScalarType::String => {
+                match reader.read_text(length, self.utf8)? {
+                    Text::Str(text) => push_string(out, &text),
+                    Text::Bytes(data) => push_bytes(out, data),
+                }
+                Ok(())
+            }
+            ScalarType::Bytes => {
+                push_bytes(out, reader.read_slice(length)?);
+                Ok(())
+            }
+        }
+        .expect("writing to a String can't fail");
+        Ok(())
+    }
+
+    /// Prints the `key` and `value` of a map entry
+    fn map_entry(&mut self, reader: &mut ReaderImpl, field: &FieldDescriptor, span: &Span) -> Result<()> {
+        let mut table = Vec::new();
+        reader.seek(span.offset);
+        reader.push_limit(span.length)?;
+        let result = scan::scan(reader, false, &mut table);
+        reader.pop_limit()?;
+        result?;
+
+        let key_type = field.key_type.unwrap_or(ScalarType::String);
+        let mut key = None;
+        let mut values = Vec::new();
+        for row in table.chunks_exact(4) {
+            let found = Span {
+                wire_type: row[1],
+                offset: row[2] as usize,
+                length: row[3] as usize,
+            };
+            match row[0] {
+                1 => key = Some(found),
+                2 => values.push(found),
+                _ => {}
+            }
+        }
+        if let Some(key) = key {
+            if key.wire_type != key_type.wire_type() {
+                return Err(wire_type_error(1, key.wire_type, key.offset));
+            }
+            reader.seek(key.offset);
+            self.begin("key: ");
+            self.scalar(reader, FieldType::Scalar(key_type), key.length)?;
+            self.end();
+        }
+        if !values.is_empty() {
+            self.value(reader, field, "value", &values)?;
+        }
+        Ok(())
+    }
+
+    /// Prints a field the schema doesn't know by its number
+    fn unknown(&mut self, reader: &mut ReaderImpl, number: u32, span: &Span) -> Result<()> {
+        let name = number.to_string();
+        if span.wire_type == 3 {
+            return self.nested(reader, &name, |printer, reader| {
+                let mut table = Vec::new();
+                reader.seek(span.offset);
+                reader.push_limit(span.length)?;
+                let result = scan::scan(reader, false, &mut table);
+                reader.pop_limit()?;
+                result?;
+                for row in table.chunks_exact(4) {
+                    let found = Span {
+                        wire_type: row[1],
+                        offset: row[2] as usize,
+                        length: row[3] as usize,
+                    };
+                    printer.unknown(reader, row[0], &found)?;
+                }
+                Ok(())
+            });
+        }
+        reader.seek(span.offset);
+        self.begin(&name);
+        self.out.push_str(": ");
+        let out = &mut self.out;
+        match span.wire_type {
+            0 => write!(out, "{}", reader.read_varint64()?),
+            1 => write!(out, "0x{:016x}", reader.read_fixed64()?),
+            5 => write!(out, "0x{:08x}", reader.read_fixed32()?),
+            _ => {
+                push_bytes(out, reader.read_slice(span.length)?);
+                Ok(())
+            }
+        }
+        .expect("writing to a String can't fail");
+        self.end();
+        Ok(())
+    }
+}
+
+pub enum Token<'t> {
+    Ident(&'t str),
+    /// A number literal without its sign, like `12`, `0x1F`, `.5` or `1.5e3f`
+    Number(&'t str),
+    /// A string literal with its escapes resolved
+    Str(Vec<u8>),
+    Symbol(u8),
+    End,
+}
+
+pub fn describe(token: &Token) -> String {
+    match token {
+        Token::Ident(text) | Token::Number(text) => format!("'{}'", text),
+        Token::Str(_) => "string".to_string(),
+        Token::Symbol(symbol) => format!("'{}'", *symbol as char),
+        Token::End => "end of input".to_string(),
+    }
+}
+
+/// The line and column of `offset` in `text`, both starting at 1
+fn position(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().unwrap_or_default().chars().count() + 1;
+    (line, column)
+}
+
+/// Adds the line and column of `offset` in `text` to an error
+pub fn locate(text: &str, offset: usize, error: ProtoError) -> ProtoError {
+    let (line, column) = position(text, offset);
+    error
+        .prefixed(&format!("{}:{}", line, column))
+        .with_offset(offset)
+}
+
+/// Splits the text format into tokens, skipping whitespace and `#` comments
+pub struct Tokenizer<'t> {
+    text: &'t str,
+    pos: usize,
+    /// Offset of the token returned or peeked last
+    pub start: usize,
+    peeked: Option<Token<'t>>,
+    /// Name of the `.proto` file being read, which switches to `//` and `/* */` comments
+    file: Option<&'t str>,
+    /// Spans of the comments skipped so far in a `.proto` file
+    pub comments: Vec<(usize, usize)>,
+}
+
+impl<'t> Tokenizer<'t> {
+    pub fn new(text: &'t str) -> Self {
+        Tokenizer {
+            text,
+            pos: 0,
+            start: 0,
+            peeked: None,
+            file: None,
+            comments: Vec::new(),
+        }
+    }
+
+    /// Splits the `.proto` file `file`, whose errors are located as `file:line:column`
+    pub fn proto(file: &'t str, text: &'t str) -> Self {
+        Tokenizer {
+            file: Some(file),
+            ..Tokenizer::new(text)
+        }
+    }
+
+    pub fn text(&self) -> &'t str {
+        self.text
+    }
+
+    fn locate(&self, offset: usize, error: ProtoError) -> ProtoError {
+        match self.file {
+            Some(file) => {
+                let (line, column) = position(self.text, offset);
+                error
+                    .prefixed(&format!("{}:{}:{}", file, line, column))
+                    .with_offset(offset)
+            }
+            None => locate(self.text, offset, error),
+        }
+    }
+
+    /// A `MALFORMED` error at the current token
+    pub fn error(&self, message: String) -> ProtoError {
+        self.error_at(self.start, message)
+    }
+
+    /// A `MALFORMED` error at `offset`
+    pub fn error_at(&self, offset: usize, message: String) -> ProtoError {
+        self.locate(offset, ProtoError::new(ErrorKind::Malformed, message))
+    }
+
+    pub fn unexpected(&self, expected: &str, token: &Token) -> ProtoError {
+        self.error(format!("expected {}, got {}", expected, describe(token)))
+    }
+
+    pub fn peek(&mut self) -> Result<&Token<'t>> {
+        if self.peeked.is_none() {
+            let token = self.read()?;
+            self.peeked = Some(token);
+        }
+        Ok(self.peeked.as_ref().expect("just peeked"))
+    }
+
+    pub fn next(&mut self) -> Result<Token<'t>> {
+        match self.peeked.take() {
+            Some(token) => Ok(token),
+            None => self.read(),
+        }
+    }
+
+    /// Consumes `symbol` if it comes next
+    pub fn try_symbol(&mut self, symbol: u8) -> Result<bool> {
+        if matches!(self.peek()?, Token::Symbol(next) if *next == symbol) {
+            self.peeked = None;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    pub fn expect_symbol(&mut self, symbol: u8) -> Result<()> {
+        match self.next()? {
+            Token::Symbol(next) if next == symbol => Ok(()),
+            token => Err(self.unexpected(&format!("'{}'", symbol as char), &token)),
+        }
+    }
+
+    /// Reads the opening brace of a message and returns the matching closing one
+    fn open(&mut self) -> Result<u8> {
+        match self.next()? {
+            Token::Symbol(b'{') => Ok(b'}'),
+            Token::Symbol(b'<') => Ok(b'>'),
+            token => Err(self.unexpected("'{'", &token)),
+        }
+    }
+
+    /// Reads the extension name or type URL between brackets, after the `[`
+    fn bracketed(&mut self) -> Result<String> {
+        let mut name = String::new();
+        loop {
+            match self.next()? {
+                Token::Ident(part) => name.push_str(part),
+                token => return Err(self.unexpected("name", &token)),
+            }
+            match self.next()? {
+                Token::Symbol(b']') => return Ok(name),
+                Token::Symbol(separator @ (b'.' | b'/')) => name.push(separator as char),
+                token => return Err(self.unexpected("']'", &token)),
+            }
+        }
+    }
+
+    fn read(&mut self) -> Result<Token<'t>> {
+        let bytes = self.text.as_bytes();
+        loop {
+            match bytes.get(self.pos) {
+                Some(b'#') if self.file.is_none() => self.skip_line(),
+                Some(b'/') if self.file.is_some() => self.skip_comment()?,
+                Some(b) if b.is_ascii_whitespace() => self.pos += 1,
+                _ => break,
+            }
+        }
+        self.start = self.pos;
+        let first = match bytes.get(self.pos) {
+            Some(&first) => first,
+            None => return Ok(Token::End),
+        };
+        if first.is_ascii_alphabetic() || first == b'_' {
+            while bytes.get(self.pos).is_some_and(|&b| b.is_ascii_alphanumeric() || b == b'_') {
+                self.pos += 1;
+            }
+            return Ok(Token::Ident(&self.text[self.start..self.pos]));
+        }
+        if first.is_ascii_digit() || (first == b'.' && bytes.get(self.pos + 1).is_some_and(u8::is_ascii_digit)) {
+            let hex = bytes[self.pos..].starts_with(b"0x") || bytes[self.pos..].starts_with(b"0X");
+            self.pos += 1;
+            while let Some(&b) = bytes.get(self.pos) {
+                let exponent_sign = matches!(b, b'+' | b'-') && !hex && matches!(bytes[self.pos - 1], b'e' | b'E');
+                if !(b.is_ascii_alphanumeric() || b == b'.' || exponent_sign) {
+                    break;
+                }
+                self.pos += 1;
+            }
+            return Ok(Token::Number(&self.text[self.start..self.pos]));
+        }
+        if first == b'"' || first == b'\'' {
+            return self.string(first).map(Token::Str);
+        }
+        if !first.is_ascii() {
+            return Err(self.error(format!(
+                "unexpected character '{}'",
+                self.text[self.pos..].chars().next().unwrap_or_default()
+            )));
+        }
+        self.pos += 1;
+        Ok(Token::Symbol(first))
+    }
+
+    fn skip_line(&mut self) {
+        let bytes = self.text.as_bytes();
+        while bytes.get(self.pos).is_some_and(|&b| b != b'\n') {
+            self.pos += 1;
+        }
+    }
+
+    /// Skips a `//` or `/* */` comment of a `.proto` file, recording its span
+    fn skip_comment(&mut self) -> Result<()> {
+        let start = self.pos;
+        match self.text.as_bytes().get(start + 1) {
+            Some(b'/') => self.skip_line(),
+            Some(b'*') => match self.text[start + 2..].find("*/") {
+                Some(end) => self.pos = start + 2 + end + 2,
+                None => {
+                    self.start = start;
+                    return Err(self.error("unterminated comment".to_string()));
+                }
+            },
+            _ => {
+                self.start = start;
+                return Err(self.error("unexpected character '/'".to_string()));
+            }
+        }
+        self.comments.push((start, self.pos));
+        Ok(())
+    }
+
+    /// Reads a quoted literal and resolves its escapes
+    fn string(&mut self, quote: u8) -> Result<Vec<u8>> {
+        let bytes = self.text.as_bytes();
+        let mut data = Vec::new();
+        self.pos += 1;
+        loop {
+            let b = match bytes.get(self.pos) {
+                Some(b'\n') | None => return Err(self.error("unterminated string".to_string())),
+                Some(&b) => b,
+            };
+            self.pos += 1;
+            if b == quote {
+                return Ok(data);
+            }
+            if b != b'\\' {
+                data.push(b);
+                continue;
+            }
+            let escape = bytes.get(self.pos).copied().unwrap_or_default();
+            self.pos += 1;
+            match escape {
+                b'a' => data.push(0x07),
+                b'b' => data.push(0x08),
+                b'f' => data.push(0x0C),
+                b'n' => data.push(b'\n'),
+                b'r' => data.push(b'\r'),
+                b't' => data.push(b'\t'),
+                b'v' => data.push(0x0B),
+                b'\\' | b'\'' | b'"' | b'?' => data.push(escape),
+                b'0'..=b'7' => {
+                    let mut value = (escape - b'0') as u32;
+                    for _ in 0..2 {
+                        match bytes.get(self.pos) {
+                            Some(&digit @ b'0'..=b'7') => {
+                                value = value * 8 + (digit - b'0') as u32;
+                                self.pos += 1;
+                            }
+                            _ => break,
+                        }
+                    }
+                    if value > 0xFF {
+                        return Err(self.error(format!("octal escape out of range: \\{:o}", value)));
+                    }
+                    data.push(value as u8);
+                }
+                b'x' | b'X' => {
+                    let value = self.hex_digits(1, 2)?;
+                    data.push(value as u8);
+                }
+                b'u' | b'U' => {
+                    let len = if escape == b'u' { 4 } else { 8 };
+                    let value = self.hex_digits(len, len)?;
+                    let c = char::from_u32(value).ok_or_else(|| {
+                        self.error(format!("invalid unicode escape: U+{:04X}", value))
+                    })?;
+                    data.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                _ => return Err(self.error(format!("invalid escape '\\{}'", escape as char))),
+            }
+        }
+    }
+
+    fn hex_digits(&mut self, min: usize, max: usize) -> Result<u32> {
+        let bytes = self.text.as_bytes();
+        let start = self.pos;
+        while self.pos - start < max && bytes.get(self.pos).is_some_and(u8::is_ascii_hexdigit) {
+            self.pos += 1;
+        }
+        if self.pos - start < min {
+            return Err(self.error("invalid hex escape".to_string()));
+        }
+        Ok(u32::from_str_radix(&self.text[start..self.pos], 16).expect("hex digits"))
+    }
+}
+
+/// Parses a decimal, `0x` hexadecimal or `0` octal integer literal
+pub fn integer_literal(text: &str) -> Option<i128> {
+    let (digits, radix) = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(digits) => (digits, 16),
+        None if text.len() > 1 && text.starts_with('0') => (&text[1..], 8),
+        None => (text, 10),
+    };
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    i128::from_str_radix(digits, radix).ok()
+}
+
+/// Parses an integer or floating point literal, which may end with an `f`
+fn float_literal(text: &str) -> Option<f64> {
+    if let Some(value) = integer_literal(text) {
+        return Some(value as f64);
+    }
+    let text = text.strip_suffix(['f', 'F']).unwrap_or(text);
+    if !text.bytes().all(|b| b.is_ascii_digit() || matches!(b, b'.' | b'e' | b'E' | b'+' | b'-')) {
+        return None;
+    }
+    text.parse().ok()
+}
+
+/// A value parsed for a field, kept until the message is written in field number order
+enum Item {
+    Scalar(Wire<'static>),
+    /// The encoded contents of a message or map entry
+    Message(Vec<u8>),
+}
+
+/// Parses the protobuf text format into binary messages, writing fields in number order
+pub struct Parser<'a> {
+    registry: &'a Registry,
+    utf8: Utf8Policy,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(registry: &'a Registry, utf8: Utf8Policy) -> Self {
+        Parser { registry, utf8 }
+    }
+
+    pub fn parse(&self, writer: &mut WriterImpl, message: &MessageDescriptor, text: &str) -> Result<()> {
+        let mut tokens = Tokenizer::new(text);
+        self.message(&mut tokens, writer, message, None, 0)
+    }
+
+    /// Parses fields up to `close`, or to the end of the input at the top level
+    fn message(
+        &self,
+        tokens: &mut Tokenizer,
+        writer: &mut WriterImpl,
+        message: &MessageDescriptor,
+        close: Option<u8>,
+        depth: usize,
+    ) -> Result<()> {
+        if depth > MAX_DEPTH {
+            return Err(tokens.locate(
+                tokens.start,
+                ProtoError::new(
+                    ErrorKind::LimitExceeded,
+                    format!("maximum nesting depth of {} exceeded", MAX_DEPTH),
+                ),
+            ));
+        }
+        let mut items: Vec<Vec<Item>> = message.fields.iter().map(|_| Vec::new()).collect();
+        let mut oneofs = vec![None; message.oneofs.len()];
+        loop {
+            match tokens.peek()? {
+                Token::End if close.is_none() => break,
+                Token::Symbol(symbol) if Some(*symbol) == close => {
+                    tokens.next()?;
+                    break;
+                }
+                _ => {}
+            }
+            let (name, index) = match tokens.next()? {
+                Token::Ident(name) => (name.to_string(), self.find_field(message, name)),
+                Token::Symbol(b'[') => {
+                    let start = tokens.start;
+                    let name = tokens.bracketed()?;
+                    if name.contains('/') {
+                        self.any(tokens, start, message, &name, &mut items, depth)?;
+                        if !tokens.try_symbol(b',')? {
+                            tokens.try_symbol(b';')?;
+                        }
+                        continue;
+                    }
+                    let extension = format!(".{}", name);
+                    let index = message.fields.iter().position(|field| field.name == extension);
+                    (format!("[{}]", name), index)
+                }
+                token => {
+                    let expected = if close.is_some() { "field name or '}'" } else { "field name" };
+                    return Err(tokens.unexpected(expected, &token));
+                }
+            };
+            let index = index.ok_or_else(|| {
+                tokens.error(format!("unknown field '{}' in {}", name, message.full_name))
+            })?;
+            let field = &message.fields[index];
+            if !field.is_repeated() && !items[index].is_empty() {
+                return Err(tokens.error(format!("non-repeated field '{}' is specified multiple times", name)));
+            }
+            if let Some(oneof) = field.oneof {
+                if let Some(previous) = oneofs[oneof].replace(index) {
+                    if previous != index {
+                        return Err(tokens.error(format!(
+                            "multiple values for oneof {}: '{}' and '{}'",
+                            message.oneofs[oneof].name, message.fields[previous].name, field.name
+                        )));
+                    }
+                }
+            }
+            self.field(tokens, field, &mut items[index], depth)?;
+            if !tokens.try_symbol(b',')? {
+                tokens.try_symbol(b';')?;
+            }
+        }
+
+        for (field, items) in message.fields.iter().zip(items) {
+            if field.packed && !items.is_empty() {
+                writer.write_varint32(field.number << 3 | 2);
+                writer.fork();
+                for item in &items {
+                    if let Item::Scalar(wire) = item {
+                        wire.write(writer);
+                    }
+                }
+                writer.ldelim();
+                continue;
+            }
+            for item in items {
+                match item {
+                    Item::Scalar(wire) => {
+                        // Fields without presence aren't written when they hold their default
+                        if field.has_presence || field.is_repeated() || !wire.is_zero() {
+                            writer.write_varint32(field.tag);
+                            wire.write(writer);
+                        }
+                    }
+                    Item::Message(data) if field.delimited => {
+                        writer.write_varint32(field.number << 3 | 3);
+                        writer.write_bytes(&data);
+                        writer.write_varint32(field.number << 3 | 4);
+                    }
+                    Item::Message(data) => {
+                        writer.write_varint32(field.number << 3 | 2);
+                        writer.write_varint32(data.len() as u32);
+                        writer.write_bytes(&data);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Finds a field by its name, its name as protobuf.js camel cases it, or the type name of a group
+    fn find_field(&self, message: &MessageDescriptor, name: &str) -> Option<usize> {
+        let camel_case = if name.contains('_') { Some(json_name_of(name)) } else { None };
+        message.fields.iter().position(|field| {
+            field.name == name
+                || camel_case.as_deref() == Some(field.name.as_str())
+                || match field.field_type {
+                    FieldType::Message(index) => {
+                        let group = self.registry.message_at(index);
+                        field.delimited && group.group && group.full_name.rsplit('.').next() == Some(name)
+                    }
+                    _ => false,
+                }
+        })
+    }
+
+    /// Parses the expanded `[type_url] { ... }` form of an `Any` that starts at `start`
+    fn any(
+        &self,
+        tokens: &mut Tokenizer,
+        start: usize,
+        message: &MessageDescriptor,
+        type_url: &str,
+        items: &mut [Vec<Item>],
+        depth: usize,
+    ) -> Result<()> {
+        let fields = match message.well_known {
+            Some(WellKnown::Any) => message.field_index(1).zip(message.field_index(2)),
+            _ => None,
+        };
+        let malformed = |message: String| tokens.locate(start, ProtoError::new(ErrorKind::Malformed, message));
+        let (url_index, value_index) = fields.ok_or_else(|| {
+            malformed(format!("type URL '{}' outside of google.protobuf.Any", type_url))
+        })?;
+        if !items[url_index].is_empty() || !items[value_index].is_empty() {
+            return Err(malformed("google.protobuf.Any is specified multiple times".to_string()));
+        }
+        let inner = json::any_type(self.registry, type_url).map_err(|e| tokens.locate(start, e))?;
+        tokens.try_symbol(b':')?;
+        let value = self.nested(tokens, inner, depth)?;
+        items[url_index].push(Item::Scalar(Wire::Bytes(Cow::Owned(type_url.as_bytes().to_vec()))));
+        items[value_index].push(Item::Message(value));
+        Ok(())
+    }
+
+    /// Parses the value of a field after its name, or the list of values of a repeated field
+    fn field(&self, tokens: &mut Tokenizer, field: &FieldDescriptor, items: &mut Vec<Item>, depth: usize) -> Result<()> {
+        if field.is_map() || matches!(field.field_type, FieldType::Message(_)) {
+            tokens.try_symbol(b':')?;
+        } else {
+            tokens.expect_symbol(b':')?;
+        }
+        if field.is_repeated() && tokens.try_symbol(b'[')? {
+            if tokens.try_symbol(b']')? {
+                return Ok(());
+            }
+            loop {
+                items.push(self.single(tokens, field, depth)?);
+                if tokens.try_symbol(b']')? {
+                    return Ok(());
+                }
+                tokens.expect_symbol(b',')?;
+            }
+        }
+        items.push(self.single(tokens, field, depth)?);
+        Ok(())
+    }
+
+    fn single(&self, tokens: &mut Tokenizer, field: &FieldDescriptor, depth: usize) -> Result<Item> {
+        if field.is_map() {
+            return Ok(Item::Message(self.map_entry(tokens, field, depth)?));
+        }
+        match field.field_type {
+            FieldType::Message(index) => Ok(Item::Message(self.nested(
+                tokens,
+                self.registry.message_at(index),
+                depth,
+            )?)),
+            field_type => Ok(Item::Scalar(self.scalar(tokens, field_type)?)),
+        }
+    }
+
+    /// Parses `{ ... }` or `< ... >` into the encoded fields of `message`
+    fn nested(&self, tokens: &mut Tokenizer, message: &MessageDescriptor, depth: usize) -> Result<Vec<u8>> {
+        let close = tokens.open()?;
+        let mut writer = WriterImpl::new();
+        self.message(tokens, &mut writer, message, Some(close), depth + 1)?;
+        Ok(writer.finish())
+    }
+
+    /// Parses a map entry given as a message of `key` and `value`
+    fn map_entry(&self, tokens: &mut Tokenizer, field: &FieldDescriptor, depth: usize) -> Result<Vec<u8>> {
+        let close = tokens.open()?;
+        let key_type = field.key_type.unwrap_or(ScalarType::String);
+        let mut key = None;
+        let mut value = None;
+        while !tokens.try_symbol(close)? {
+            let name = match tokens.next()? {
+                Token::Ident(name @ ("key" | "value")) => name,
+                token => return Err(tokens.unexpected("'key' or 'value'", &token)),
+            };
+            if (name == "key" && key.is_some()) || (name == "value" && value.is_some()) {
+                return Err(tokens.error(format!("non-repeated field '{}' is specified multiple times", name)));
+            }
+            if name == "key" {
+                tokens.expect_symbol(b':')?;
+                key = Some(self.scalar(tokens, FieldType::Scalar(key_type))?);
+            } else if let FieldType::Message(index) = field.field_type {
+                tokens.try_symbol(b':')?;
+                value = Some(Item::Message(self.nested(tokens, self.registry.message_at(index), depth)?));
+            } else {
+                tokens.expect_symbol(b':')?;
+                value = Some(Item::Scalar(self.scalar(tokens, field.field_type)?));
+            }
+            if !tokens.try_symbol(b',')? {
+                tokens.try_symbol(b';')?;
+            }
+        }
+
+        let mut writer = WriterImpl::new();
+        if let Some(key) = key {
+            writer.write_varint32(8 | key_type.wire_type());
+            key.write(&mut writer);
+        }
+        match value {
+            Some(Item::Scalar(wire)) => {
+                writer.write_varint32(16 | field.value_wire_type());
+                wire.write(&mut writer);
+            }
+            Some(Item::Message(data)) => {
+                writer.write_varint32(18);
+                writer.write_varint32(data.len() as u32);
+                writer.write_bytes(&data);
+            }
+            None => {}
+        }
+        Ok(writer.finish())
+    }
+
+    /// Parses a scalar or enum value
+    fn scalar(&self, tokens: &mut Tokenizer, field_type: FieldType) -> Result<Wire<'static>> {
+        let scalar = match field_type {
+            FieldType::Scalar(scalar) => scalar,
+            FieldType::Enum(index) => {
+                let descriptor = self.registry.enum_at(index);
+                let negative = tokens.try_symbol(b'-')?;
+                let number = match tokens.next()? {
+                    Token::Ident(name) if !negative => descriptor.value_of(name).ok_or_else(|| {
+                        tokens.error(format!("unknown value '{}' of enum {}", name, descriptor.full_name))
+                    })?,
+                    Token::Number(text) => {
+                        let value = integer_literal(text)
+                            .ok_or_else(|| tokens.error(format!("integer expected, got {}", text)))?;
+                        let value = if negative { -value } else { value };
+                        i32::try_from(value).map_err(|_| {
+                            tokens.error(format!("enum value out of range: {}", value))
+                        })?
+                    }
+                    token => return Err(tokens.unexpected("enum value", &token)),
+                };
+                return Ok(Wire::Varint(number as i64 as u64));
+            }
+            FieldType::Message(_) => unreachable!("messages are parsed by nested"),
+        };
+        match scalar {
+            ScalarType::String | ScalarType::Bytes => {
+                let mut data = match tokens.next()? {
+                    Token::Str(data) => data,
+                    token => return Err(tokens.unexpected("string", &token)),
+                };
+                // Adjacent literals are concatenated
+                while let Token::Str(_) = tokens.peek()? {
+                    if let Token::Str(more) = tokens.next()? {
+                        data.extend_from_slice(&more);
+                    }
+                }
+                if scalar == ScalarType::String {
+                    data = match utf8::decode(&data, 0, self.utf8) {
+                        Ok(Text::Str(Cow::Owned(text))) => text.into_bytes(),
+                        Ok(_) => data,
+                        Err(_) => {
+                            return Err(tokens.locate(
+                                tokens.start,
+                                ProtoError::new(
+                                    ErrorKind::InvalidUtf8,
+                                    "invalid utf8 in string literal".to_string(),
+                                ),
+                            ))
+                        }
+                    };
+                }
+                Ok(Wire::Bytes(Cow::Owned(data)))
+            }
+            ScalarType::Bool => match tokens.next()? {
+                Token::Ident("true" | "True" | "t") => Ok(Wire::Varint(1)),
+                Token::Ident("false" | "False" | "f") => Ok(Wire::Varint(0)),
+                Token::Number("1") => Ok(Wire::Varint(1)),
+                Token::Number("0") => Ok(Wire::Varint(0)),
+                token => Err(tokens.unexpected("boolean", &token)),
+            },
+            ScalarType::Float | ScalarType::Double => {
+                let negative = tokens.try_symbol(b'-')?;
+                let value = match tokens.next()? {
+                    Token::Number(text) => float_literal(text)
+                        .ok_or_else(|| tokens.error(format!("number expected, got {}", text)))?,
+                    Token::Ident(text) => match text.to_ascii_lowercase().as_str() {
+                        "inf" | "infinity" => f64::INFINITY,
+                        "nan" => f64::NAN,
+                        _ => return Err(tokens.error(format!("number expected, got {}", text))),
+                    },
+                    token => return Err(tokens.unexpected("number", &token)),
+                };
+                let value = if negative { -value } else { value };
+                json::float_wire(scalar, value).map_err(|e| tokens.locate(tokens.start, e))
+            }
+            _ => {
+                let negative = tokens.try_symbol(b'-')?;
+                let value = match tokens.next()? {
+                    Token::Number(text) => integer_literal(text)
+                        .ok_or_else(|| tokens.error(format!("integer expected, got {}", text)))?,
+                    token => return Err(tokens.unexpected("integer", &token)),
+                };
+                let value = if negative { -value } else { value };
+                json::integer_wire(scalar, value).map_err(|e| tokens.locate(tokens.start, e))
+            }
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use std::borrow::Cow;
+
+use napi::Env;
+use napi::Error;
+use napi::JsString;
+use napi::JsUnknown;
+use napi::Status;
+
+use crate::error::{ErrorKind, ProtoError, Result};
+
+/// How strings that aren't valid UTF-8 are read, and JS strings with lone surrogates written
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum Utf8Policy {
+    /// Fail with `INVALID_UTF8`
+    #[default]
+    Strict,
+    /// Substitute U+FFFD for every invalid sequence
+    Replace,
+    /// Read the raw bytes as a Buffer, and write lone surrogates as 3-byte
+    /// sequences like protobuf.js does
+    Bytes,
+}
+
+impl Utf8Policy {
+    /// Parses a `utf8` option, defaulting to `strict`
+    pub fn from_option(value: Option<&str>) -> napi::Result<Utf8Policy> {
+        Ok(match value {
+            None | Some("strict") => Utf8Policy::Strict,
+            Some("replace") => Utf8Policy::Replace,
+            Some("bytes") => Utf8Policy::Bytes,
+            Some(other) => {
+                return Err(Error::new(
+                    Status::InvalidArg,
+                    format!("invalid utf8 option: {}", other),
+                ))
+            }
+        })
+    }
+}
+
+/// A string value as read under a `Utf8Policy`
+pub enum Text<'a> {
+    Str(Cow<'a, str>),
+    /// Invalid UTF-8 kept as is by `Utf8Policy::Bytes`
+    Bytes(&'a [u8]),
+}
+
+impl Text<'_> {
+    pub fn to_js(&self, env: &Env) -> napi::Result<JsUnknown> {
+        match self {
+            Text::Str(text) => Ok(env.create_string(text)?.into_unknown()),
+            Text::Bytes(data) => Ok(env
+                .create_buffer_with_data(data.to_vec())?
+                .into_raw()
+                .into_unknown()),
+        }
+    }
+
+    /// The text with invalid sequences replaced, for places that need a string like map keys
+    pub fn into_lossy(self) -> String {
+        match self {
+            Text::Str(text) => text.into_owned(),
+            Text::Bytes(data) => String::from_utf8_lossy(data).into_owned(),
+        }
+    }
+}
+
+/// Decodes `data`, which starts at `offset` in the input
+pub fn decode(data: &[u8], offset: usize, policy: Utf8Policy) -> Result<Text<'_>> {
+    match std::str::from_utf8(data) {
+        Ok(text) => Ok(Text::Str(Cow::Borrowed(text))),
+        Err(e) => match policy {
+            Utf8Policy::Strict => Err(ProtoError::at(
+                ErrorKind::InvalidUtf8,
+                offset + e.valid_up_to(),
+                format!("invalid utf8: {}", e),
+            )),
+            Utf8Policy::Replace => Ok(Text::Str(String::from_utf8_lossy(data))),
+            Utf8Policy::Bytes => Ok(Text::Bytes(data)),
+        },
+    }
+}
+
+/// Encodes a JS string as UTF-8. Napi already replaces lone surrogates, so
+/// only strings containing U+FFFD are read again as UTF-16 to look for them.
+pub fn encode(value: JsString, policy: Utf8Policy) -> Result<Vec<u8>> {
+    let text = value.into_utf8()?.into_owned()?;
+    if policy == Utf8Policy::Replace || !text.contains('\u{FFFD}') {
+        return Ok(text.into_bytes());
+    }
+    let units = value.into_utf16()?;
+    // The buffer napi fills ends with a NUL
+    let units = units.as_slice().split_last().map_or(&[][..], |(_, units)| units);
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut index = 0;
+    for unit in char::decode_utf16(units.iter().copied()) {
+        match unit {
+            Ok(c) => {
+                bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                index += c.len_utf16();
+            }
+            Err(e) if policy == Utf8Policy::Strict => {
+                return Err(ProtoError::new(
+                    ErrorKind::InvalidUtf8,
+                    format!(
+                        "lone surrogate U+{:04X} at index {}",
+                        e.unpaired_surrogate(),
+                        index
+                    ),
+                ))
+            }
+            Err(e) => {
+                let unit = e.unpaired_surrogate();
+                bytes.extend_from_slice(&[
+                    0xE0 | (unit >> 12) as u8,
+                    0x80 | (unit >> 6 & 0x3F) as u8,
+                    0x80 | (unit & 0x3F) as u8,
+                ]);
+                index += 1;
+            }
+        }
+    }
+    Ok(bytes)
+}
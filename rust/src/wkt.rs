@@ -0,0 +1,200 @@
+/// Seconds of 0001-01-01T00:00:00Z, the earliest valid `Timestamp`
+pub const TIMESTAMP_MIN_SECONDS: i64 = -62_135_596_800;
+/// Seconds of 9999-12-31T23:59:59Z, the latest valid `Timestamp`
+pub const TIMESTAMP_MAX_SECONDS: i64 = 253_402_300_799;
+/// About 10,000 years, the range of a valid `Duration` either way
+pub const DURATION_MAX_SECONDS: i64 = 315_576_000_000;
+
+/// Days since 1970-01-01 of a proleptic Gregorian date
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Inverse of `days_from_civil`
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Appends a fraction of 3, 6 or 9 digits, or nothing for whole seconds
+fn push_nanos(out: &mut String, nanos: u32) {
+    if nanos == 0 {
+        return;
+    }
+    let digits = if nanos.is_multiple_of(1_000_000) {
+        format!(".{:03}", nanos / 1_000_000)
+    } else if nanos.is_multiple_of(1_000) {
+        format!(".{:06}", nanos / 1_000)
+    } else {
+        format!(".{:09}", nanos)
+    };
+    out.push_str(&digits);
+}
+
+/// Parses up to 9 fractional digits following the `.` as nanoseconds
+fn parse_nanos(fraction: &str) -> Option<u32> {
+    if fraction.is_empty() || fraction.len() > 9 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let digits: u32 = fraction.parse().ok()?;
+    Some(digits * 10u32.pow(9 - fraction.len() as u32))
+}
+
+/// Formats a `Timestamp` as RFC 3339 in UTC, like `1972-01-01T10:00:20.021Z`
+pub fn format_timestamp(seconds: i64, nanos: i32) -> Option<String> {
+    if !(TIMESTAMP_MIN_SECONDS..=TIMESTAMP_MAX_SECONDS).contains(&seconds)
+        || !(0..1_000_000_000).contains(&nanos)
+    {
+        return None;
+    }
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86_400));
+    let time = seconds.rem_euclid(86_400);
+    let mut out = format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    );
+    push_nanos(&mut out, nanos as u32);
+    out.push('Z');
+    Some(out)
+}
+
+fn digits(text: &str, range: std::ops::Range<usize>) -> Option<u32> {
+    let part = text.get(range)?;
+    if !part.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    part.parse().ok()
+}
+
+/// Parses an RFC 3339 timestamp with a `Z` or `±hh:mm` offset into seconds and nanos
+pub fn parse_timestamp(text: &str) -> Option<(i64, i32)> {
+    let bytes = text.as_bytes();
+    if bytes.len() < 20
+        || bytes[4] != b'-'
+        || bytes[7] != b'-'
+        || !matches!(bytes[10], b'T' | b't')
+        || bytes[13] != b':'
+        || bytes[16] != b':'
+    {
+        return None;
+    }
+    let year = digits(text, 0..4)?;
+    let month = digits(text, 5..7)?;
+    let day = digits(text, 8..10)?;
+    let hour = digits(text, 11..13)?;
+    let minute = digits(text, 14..16)?;
+    let second = digits(text, 17..19)?;
+    let days_in_month = match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        _ => return None,
+    };
+    if year == 0 || day == 0 || day > days_in_month || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+
+    let mut rest = &text[19..];
+    let mut nanos = 0;
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let end = fraction.find(|c: char| !c.is_ascii_digit()).unwrap_or(fraction.len());
+        nanos = parse_nanos(&fraction[..end])?;
+        rest = &fraction[end..];
+    }
+    let offset = match rest {
+        "Z" | "z" => 0,
+        _ => {
+            let sign = match rest.as_bytes().first()? {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return None,
+            };
+            if rest.len() != 6 || rest.as_bytes()[3] != b':' {
+                return None;
+            }
+            let (hours, minutes) = (digits(rest, 1..3)?, digits(rest, 4..6)?);
+            if hours > 23 || minutes > 59 {
+                return None;
+            }
+            sign * (hours as i64 * 3600 + minutes as i64 * 60)
+        }
+    };
+
+    let seconds = days_from_civil(year as i64, month, day) * 86_400
+        + hour as i64 * 3600
+        + minute as i64 * 60
+        + second as i64
+        - offset;
+    if !(TIMESTAMP_MIN_SECONDS..=TIMESTAMP_MAX_SECONDS).contains(&seconds) {
+        return None;
+    }
+    Some((seconds, nanos as i32))
+}
+
+/// Formats a `Duration` as decimal seconds with an `s` suffix, like `-1.500s`
+pub fn format_duration(seconds: i64, nanos: i32) -> Option<String> {
+    if !(-DURATION_MAX_SECONDS..=DURATION_MAX_SECONDS).contains(&seconds)
+        || !(-999_999_999..=999_999_999).contains(&nanos)
+        || (seconds > 0 && nanos < 0)
+        || (seconds < 0 && nanos > 0)
+    {
+        return None;
+    }
+    let mut out = String::new();
+    if seconds < 0 || nanos < 0 {
+        out.push('-');
+    }
+    out.push_str(&seconds.unsigned_abs().to_string());
+    push_nanos(&mut out, nanos.unsigned_abs());
+    out.push('s');
+    Some(out)
+}
+
+/// Parses decimal seconds with an `s` suffix into seconds and nanos of the same sign
+pub fn parse_duration(text: &str) -> Option<(i64, i32)> {
+    let number = text.strip_suffix('s')?;
+    let (negative, number) = match number.strip_prefix('-') {
+        Some(number) => (true, number),
+        None => (false, number),
+    };
+    let (whole, fraction) = match number.split_once('.') {
+        Some((whole, fraction)) => (whole, Some(fraction)),
+        None => (number, None),
+    };
+    if whole.is_empty() || !whole.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let seconds: i64 = whole.parse().ok()?;
+    let nanos = match fraction {
+        Some(fraction) => parse_nanos(fraction)? as i32,
+        None => 0,
+    };
+    if seconds > DURATION_MAX_SECONDS {
+        return None;
+    }
+    if negative {
+        Some((-seconds, -nanos))
+    } else {
+        Some((seconds, nanos))
+    }
+}
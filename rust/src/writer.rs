@@ -1,23 +1,75 @@
-use std::collections::VecDeque;
-
-#[derive(Debug)]
-#[allow(dead_code)]
-pub struct State {
-    pub len: usize,
-    pub buf: Vec<u8>,
+use crate::error::{ErrorKind, ProtoError, Result};
+use crate::reader::DEFAULT_MAX_DEPTH;
+use crate::utf8::Utf8Policy;
+
+/// Number of bytes `value` takes as a varint
+#[inline]
+fn varint32_size(value: u32) -> usize {
+    match value {
+        0..=0x7F => 1,
+        0x80..=0x3FFF => 2,
+        0x4000..=0x1F_FFFF => 3,
+        0x20_0000..=0xFFF_FFFF => 4,
+        _ => 5,
+    }
 }
 
 pub struct WriterImpl {
     buf: Vec<u8>,
-    states: VecDeque<State>,
+    /// Offsets of the length slots reserved by open forks, innermost last.
+    /// Nested messages are written in place and their length is back-patched
+    /// by `ldelim`, so forking never copies the buffer.
+    forks: Vec<usize>,
+    /// Policy for JS strings with lone surrogates
+    utf8: Utf8Policy,
+    /// Whether well-known types are given as native JS values
+    well_known_types: bool,
+    /// Nesting of the messages being encoded from JS objects
+    depth: usize,
 }
 
 impl WriterImpl {
     pub fn new() -> Self {
         WriterImpl {
             buf: Vec::new(),
-            states: VecDeque::new(),
+            forks: Vec::new(),
+            utf8: Utf8Policy::Strict,
+            well_known_types: false,
+            depth: 0,
+        }
+    }
+
+    pub fn set_utf8(&mut self, policy: Utf8Policy) {
+        self.utf8 = policy;
+    }
+
+    pub fn utf8(&self) -> Utf8Policy {
+        self.utf8
+    }
+
+    pub fn set_well_known_types(&mut self, enabled: bool) {
+        self.well_known_types = enabled;
+    }
+
+    pub fn well_known_types(&self) -> bool {
+        self.well_known_types
+    }
+
+    /// Enters a nested message, failing past the reader's default maximum depth so
+    /// that objects referring to themselves can't overflow the stack. Pair with `ascend`.
+    pub fn descend(&mut self) -> Result<()> {
+        if self.depth >= DEFAULT_MAX_DEPTH {
+            return Err(ProtoError::new(
+                ErrorKind::LimitExceeded,
+                format!("nesting depth exceeds maxDepth {}", DEFAULT_MAX_DEPTH),
+            ));
         }
+        self.depth += 1;
+        Ok(())
+    }
+
+    pub fn ascend(&mut self) {
+        self.depth -= 1;
     }
 
     #[inline]
@@ -38,6 +90,11 @@ impl WriterImpl {
         self.buf.push(value as u8);
     }
 
+    #[inline]
+    pub fn write_tag(&mut self, field: u32, wire_type: u32) {
+        self.write_varint32(field << 3 | wire_type);
+    }
+
     #[inline]
     pub fn write_sint32(&mut self, value: i32) {
         let encoded = ((value << 1) ^ (value >> 31)) as u32;
@@ -75,44 +132,63 @@ impl WriterImpl {
         self.buf.extend_from_slice(data);
     }
 
+    /// Starts a nested message, reserving one byte for its length
     pub fn fork(&mut self) {
-        let state = State {
-            len: self.buf.len(),
-            buf: self.buf.clone(),
-        };
-        self.states.push_back(state);
-        self.buf.clear();
+        self.forks.push(self.buf.len());
+        self.buf.push(0);
     }
 
+    /// Discards everything written since the last `fork`
     pub fn reset(&mut self) {
-        if let Some(state) = self.states.pop_back() {
-            self.buf = state.buf;
+        if let Some(slot) = self.forks.pop() {
+            self.buf.truncate(slot);
         } else {
             self.buf.clear();
         }
     }
 
+    /// Ends the nested message started by the last `fork` and writes its length
+    /// into the reserved slot, shifting the contents only if it needs more than one byte
     pub fn ldelim(&mut self) {
-        let fork_len = self.buf.len();
-        let fork_data = self.buf.clone();
-        
-        // Reset to parent state
-        self.reset();
-        
-        // Write length as varint
-        self.write_varint32(fork_len as u32);
-        
-        // Append fork data
-        if fork_len > 0 {
-            self.buf.extend_from_slice(&fork_data);
+        let (slot, start) = match self.forks.pop() {
+            Some(slot) => (slot, slot + 1),
+            // Without a fork the whole buffer is length-delimited
+            None => (0, 0),
+        };
+        let end = self.buf.len();
+        let len = (end - start) as u32;
+        let size = varint32_size(len);
+        let reserved = start - slot;
+        if size != reserved {
+            self.buf.resize(slot + size + (end - start), 0);
+            self.buf.copy_within(start..end, slot + size);
         }
+
+        let mut value = len;
+        let mut pos = slot;
+        while value >= 0x80 {
+            self.buf[pos] = (value & 0x7F | 0x80) as u8;
+            value >>= 7;
+            pos += 1;
+        }
+        self.buf[pos] = value as u8;
     }
 
+    /// Returns the contents of the innermost open fork, or the whole buffer, and resets the writer
     pub fn finish(&mut self) -> Vec<u8> {
-        std::mem::take(&mut self.buf)
+        let start = self.forks.last().map_or(0, |slot| slot + 1);
+        self.forks.clear();
+        if start == 0 {
+            std::mem::take(&mut self.buf)
+        } else {
+            let data = self.buf.split_off(start);
+            self.buf.clear();
+            data
+        }
     }
 
+    /// Length of the innermost open fork, or of the whole buffer
     pub fn len(&self) -> usize {
-        self.buf.len()
+        self.buf.len() - self.forks.last().map_or(0, |slot| slot + 1)
     }
 }
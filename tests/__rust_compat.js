@@ -122,5 +122,666 @@ tape.test("Rust implementation", function(test) {
         test.end();
     });
     
+    test.test(test.name + " - compact opcode stream", function(test) {
+        var native = protobuf.native,
+            Opcode = native.Opcode;
+        
+        var buffer = native.Writer.encodeOps(
+            Uint8Array.from([ Opcode.Uint32, Opcode.Int32, Opcode.Sint64, Opcode.String, Opcode.Bytes, Opcode.Fixed32 ]),
+            Float64Array.from([ 300, -1, 1, 0, 3, 7 ]),
+            Buffer.from("hé"),
+            [ Buffer.from([ 9 ]) ]
+        );
+        test.deepEqual(Array.from(buffer), [
+            172, 2,
+            255, 255, 255, 255, 255, 255, 255, 255, 255, 1,
+            2,
+            3, 104, 195, 169,
+            1, 9,
+            7, 0, 0, 0
+        ], "should encode every opcode from its side tables");
+        
+        test.throws(function() {
+            native.Writer.encodeOps(Uint8Array.from([ 42 ]), new Float64Array(0), Buffer.alloc(0), []);
+        }, /invalid opcode 42/, "should reject unknown opcodes");
+        
+        test.throws(function() {
+            native.Writer.encodeOps(Uint8Array.from([ Opcode.Uint32 ]), new Float64Array(0), Buffer.alloc(0), []);
+        }, /number table exhausted/, "should reject missing operands");
+        
+        var writer = protobuf.Writer.create();
+        writer.uint32(10).fork().string("ünïcode").int32(-3).fixed64(5).ldelim().sint64(-2).double(1.5);
+        var expected = require("../src/writer").create();
+        expected.uint32(10).fork().string("ünïcode").int32(-3).fixed64(5).ldelim().sint64(-2).double(1.5);
+        test.deepEqual(Array.from(writer.finish()), Array.from(expected.finish()), "should match the JavaScript writer");
+        
+        test.end();
+    });
+    
+    test.test(test.name + " - zero-copy inputs", function(test) {
+        var bytes = [ 0, 8, 150, 1, 18, 2, 104, 105, 0 ];
+        var arrayBuffer = new Uint8Array(bytes).buffer;
+        var inputs = {
+            "Buffer": Buffer.from(bytes).subarray(1, 8),
+            "Uint8Array": new Uint8Array(arrayBuffer, 1, 7),
+            "ArrayBuffer": new Uint8Array(bytes.slice(1, 8)).buffer,
+            "DataView": new DataView(arrayBuffer, 1, 7)
+        };
+        Object.keys(inputs).forEach(function(kind) {
+            var reader = new protobuf.native.Reader(inputs[kind]);
+            test.equal(reader.len, 7, kind + " should respect the view's offset and length");
+            test.equal(reader.uint32(), 8, kind + " should read the tag");
+            test.equal(reader.uint32(), 150, kind + " should read the varint");
+            test.equal(reader.uint32(), 18, kind + " should read the next tag");
+            test.equal(reader.string(), "hi", kind + " should read the string");
+        });
+        
+        var shared = Buffer.from([ 8, 1 ]);
+        var reader = new protobuf.native.Reader(shared);
+        shared[1] = 2;
+        reader.uint32();
+        test.equal(reader.uint32(), 2, "should read the caller's memory in place");
+        
+        test.throws(function() {
+            new protobuf.native.Reader("not bytes");
+        }, /Buffer, Uint8Array, ArrayBuffer or DataView expected/, "should reject other inputs");
+        
+        test.end();
+    });
+    
+    test.test(test.name + " - 64-bit representations", function(test) {
+        var native = protobuf.native;
+        var max = "18446744073709551615";
+        var writer = new native.Writer();
+        writer.uint64(BigInt(max));
+        writer.int64("-2");
+        writer.fixed64({ low: -1, high: -1 });
+        writer.sint64(BigInt(-3));
+        writer.sfixed64(-4);
+        var buffer = writer.finish();
+        
+        var reader = new native.Reader(buffer, { longs: "bigint" });
+        test.equal(reader.uint64(), BigInt(max), "should read unsigned values above 2^63 as BigInt");
+        test.equal(reader.int64(), BigInt(-2), "should read signed values as BigInt");
+        test.equal(reader.fixed64(), BigInt(max), "should read fixed64 as BigInt");
+        test.equal(reader.sint64(), BigInt(-3), "should read sint64 as BigInt");
+        test.equal(reader.sfixed64(), BigInt(-4), "should read sfixed64 as BigInt");
+        
+        reader = new native.Reader(buffer, { longs: "string" });
+        test.equal(reader.uint64(), max, "should read decimal strings");
+        test.equal(reader.int64(), "-2", "should read negative decimal strings");
+        
+        reader = new native.Reader(buffer, { longs: "long" });
+        test.deepEqual(reader.uint64(), { low: -1, high: -1, unsigned: true }, "should read Long-shaped objects");
+        test.deepEqual(reader.int64(), { low: -2, high: -1, unsigned: false }, "should keep the sign of Long-shaped objects");
+        
+        reader = new native.Reader(buffer);
+        reader.uint64();
+        test.equal(reader.int64(), -2, "should read numbers by default");
+        
+        test.throws(function() {
+            new native.Reader(buffer, { longs: "float" });
+        }, /invalid longs option: float/, "should reject unknown representations");
+        
+        test.throws(function() {
+            new native.Writer().uint64("abc");
+        }, /integer, BigInt, decimal string or Long expected/, "should reject non-integers");
+        
+        test.deepEqual(Array.from(native.Writer.encodeAll([ [ "u64", BigInt(max) ], [ "s64", "-1" ], [ "f64", { low: 1, high: 0 } ] ])),
+            [ 255, 255, 255, 255, 255, 255, 255, 255, 255, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0 ], "should accept the same inputs in encodeAll");
+        
+        var jsWriter = protobuf.Writer.create();
+        jsWriter.uint64(BigInt(max)).int64(BigInt(-2));
+        reader = protobuf.Reader.create(jsWriter.finish());
+        var value = reader.uint64();
+        test.equal(value.toString ? value.toString() : String(value), protobuf.util.Long ? max : String(Number(max)), "should accept BigInt in the wrapped writer");
+        
+        test.end();
+    });
+    
+    test.test(test.name + " - nested fork/ldelim", function(test) {
+        var native = new protobuf.native.Writer();
+        var js = require("../src/writer").create();
+        [ native, js ].forEach(function(writer) {
+            writer.uint32(10).fork();
+            for (var depth = 0; depth < 7; ++depth) {
+                writer.uint32(10).fork().string(new Array(20 * (depth + 1)).join("x"));
+            }
+            for (depth = 0; depth < 7; ++depth) {
+                writer.ldelim();
+            }
+            writer.uint32(18).fork().uint32(1).ldelim();
+            writer.fork().string("discarded").reset();
+            writer.ldelim();
+        });
+        test.deepEqual(Array.from(native.finish()), Array.from(js.finish()), "should back-patch lengths of every size");
+        
+        var writer = new protobuf.native.Writer();
+        writer.uint32(1).fork().uint32(2);
+        test.equal(writer.len, 1, "should report the length of the open fork");
+        writer.ldelim();
+        test.equal(writer.len, 3, "should report the whole length after ldelim");
+        writer.fork().uint32(3).reset();
+        test.deepEqual(Array.from(writer.finish()), [ 1, 1, 2 ], "should discard reset forks");
+        
+        test.end();
+    });
+    
+    test.test(test.name + " - tags", function(test) {
+        var native = protobuf.native;
+        var writer = new native.Writer();
+        writer.tag(1, 0).uint32(150).tag(536870911, 2).string("");
+        var buffer = writer.finish();
+        test.deepEqual(Array.from(buffer), [ 8, 150, 1, 250, 255, 255, 255, 15, 0 ], "should write tags");
+        
+        var reader = new native.Reader(buffer);
+        test.deepEqual(reader.readTag(), { field: 1, wireType: 0 }, "should read tags");
+        reader.skipType(0);
+        test.deepEqual(reader.readTag(), { field: 536870911, wireType: 2 }, "should read the largest field number");
+        
+        test.throws(function() {
+            writer.tag(0, 0);
+        }, /invalid field number 0/, "should reject field number 0");
+        test.throws(function() {
+            writer.tag(536870912, 0);
+        }, /invalid field number 536870912/, "should reject field numbers above 2^29-1");
+        test.throws(function() {
+            writer.tag(1, 6);
+        }, /invalid wire type 6/, "should reject invalid wire types");
+        test.throws(function() {
+            new native.Reader(Buffer.from([ 7 ])).readTag();
+        }, /invalid field number 0/, "should reject invalid tags when reading");
+        
+        test.deepEqual(Array.from(native.Writer.encodeAll([ [ "tag", 2, 5 ], [ "f32", 1 ] ])), [ 21, 1, 0, 0, 0 ], "should write tags in encodeAll");
+        test.throws(function() {
+            native.Writer.encodeAll([ [ "tag", 5 ] ]);
+        }, /encodeAll op 0: missing operand 2/, "should require the wire type of tags in encodeAll");
+        
+        process.once("warning", function(warning) {
+            test.ok(/field number 19000 is reserved/.test(warning.message), "should warn on reserved field numbers");
+            test.end();
+        });
+        new native.Writer().tag(19000, 0);
+    });
+    
+    test.test(test.name + " - nesting in batches", function(test) {
+        var native = protobuf.native,
+            Opcode = native.Opcode;
+        var expected = [ 10, 5, 8, 1, 18, 1, 120, 19, 8, 2, 20, 24, 3 ];
+        
+        test.deepEqual(Array.from(native.Writer.encodeAll([
+            [ "u32", 10 ], [ "fork" ], [ "u32", 8 ], [ "u32", 1 ], [ "u32", 18 ], [ "string", "x" ], [ "ldelim" ],
+            [ "group_start", 2 ], [ "u32", 8 ], [ "u32", 2 ], [ "group_end", 2 ],
+            [ "fork" ], [ "u32", 99 ], [ "reset" ],
+            [ "u32", 24 ], [ "u32", 3 ]
+        ])), expected, "should encode nested messages and groups with encodeAll");
+        
+        test.deepEqual(Array.from(native.Writer.encodeOps(
+            Uint8Array.from([
+                Opcode.Uint32, Opcode.Fork, Opcode.Uint32, Opcode.Uint32, Opcode.Uint32, Opcode.String, Opcode.Ldelim,
+                Opcode.GroupStart, Opcode.Uint32, Opcode.Uint32, Opcode.GroupEnd,
+                Opcode.Fork, Opcode.Uint32, Opcode.Reset,
+                Opcode.Uint32, Opcode.Uint32
+            ]),
+            Float64Array.from([ 10, 8, 1, 18, 1, 2, 8, 2, 2, 99, 24, 3 ]),
+            Buffer.from("x"),
+            []
+        )), expected, "should encode nested messages and groups with encodeOps");
+        
+        test.throws(function() {
+            native.Writer.encodeOps(Uint8Array.from([ Opcode.GroupStart ]), Float64Array.from([ 0 ]), Buffer.alloc(0), []);
+        }, /invalid field number 0/, "should validate group field numbers");
+        
+        var writer = protobuf.Writer.create();
+        writer.uint32(10).fork().uint32(8).uint32(300);
+        test.equal(writer.len, 3, "should track the length of the open fork");
+        writer.ldelim();
+        test.equal(writer.len, 5, "should track the length including the prefix");
+        test.deepEqual(Array.from(writer.finish()), [ 10, 3, 8, 172, 2 ], "should encode forks natively");
+        
+        writer = protobuf.Writer.create();
+        writer.string("a\ud83d").string("\ude00b");
+        test.deepEqual(Array.from(writer.finish()), [ 4, 97, 239, 191, 189, 4, 239, 191, 189, 98 ], "should encode lone surrogates of neighbouring strings separately");
+        
+        [ 0, 1, -1, 63, -64, 64, 2147483647, -2147483648, BigInt("9223372036854775807"), BigInt("-9223372036854775808") ].forEach(function(value) {
+            writer = protobuf.Writer.create();
+            writer.sint64(value).fixed64(value).sfixed64(value);
+            var len = writer.len;
+            test.equal(len, writer.finish().length, "should track the length of sint64 and fixed64 " + value);
+        });
+
+        test.end();
+    });
+    
+    test.test(test.name + " - read plans", function(test) {
+        var native = protobuf.native;
+        var buffer = native.Writer.encodeAll([
+            [ "tag", 1, 0 ], [ "s64", -2 ],
+            [ "tag", 2, 2 ], [ "fork" ], [ "tag", 1, 2 ], [ "string", "hi" ], [ "tag", 2, 0 ], [ "u32", 7 ], [ "ldelim" ],
+            [ "tag", 3, 1 ], [ "double", 1.5 ],
+            [ "tag", 4, 2 ], [ "bytes", Buffer.from([ 1, 2 ]) ],
+            [ "tag", 5, 5 ], [ "float", 0.5 ]
+        ]);
+        
+        var reader = new native.Reader(buffer, { longs: "bigint" });
+        var values = reader.readAll([
+            [ "tag" ], [ "s64" ],
+            [ "tag" ], [ "sub" ], [ "tag" ], [ "string" ], [ "end" ],
+            [ "tag" ], [ "skip_type" ],
+            [ "tag" ], [ "bytes" ],
+            [ "tag" ], [ "skip", 4 ]
+        ]);
+        test.deepEqual(values, [ 1, 0, BigInt(-2), 2, 2, 1, 2, "hi", 3, 1, 4, 2, Buffer.from([ 1, 2 ]), 5, 5 ], "should read every value into one flat array");
+        test.equal(reader.pos, reader.len, "should consume the whole buffer");
+        
+        test.throws(function() {
+            new native.Reader(buffer).readAll([ [ "tag" ], [ "skip_type" ], [ "tag" ], [ "sub" ], [ "skip", 9 ] ]);
+        }, /index out of range: 4 \+ 9 > 10/, "should keep reads inside sub-messages");
+        
+        test.throws(function() {
+            new native.Reader(buffer).readAll([ [ "nope" ] ]);
+        }, /plan op 0: unknown op 'nope'/, "should reject unknown ops");
+        
+        test.end();
+    });
+    
+    test.test(test.name + " - scan", function(test) {
+        var native = protobuf.native;
+        var buffer = native.Writer.encodeAll([
+            [ "tag", 1, 0 ], [ "u32", 300 ],
+            [ "tag", 2, 2 ], [ "fork" ], [ "tag", 1, 2 ], [ "string", "\u0000" ], [ "ldelim" ],
+            [ "tag", 3, 2 ], [ "string", "\u0001" ],
+            [ "group_start", 4 ], [ "tag", 1, 5 ], [ "f32", 1 ], [ "group_end", 4 ],
+            [ "tag", 5, 1 ], [ "double", 2 ]
+        ]);
+        var reader = new native.Reader(buffer);
+        var table = reader.scan();
+        test.ok(table instanceof Uint32Array, "should return a Uint32Array");
+        test.deepEqual(Array.from(table), [
+            1, 0, 1, 2,
+            2, 2, 5, 3,
+            3, 2, 10, 1,
+            4, 3, 12, 5,
+            5, 1, 19, 8
+        ], "should index every top-level field");
+        test.equal(reader.pos, 0, "should leave the position unchanged");
+        
+        test.deepEqual(Array.from(reader.scan({ recursive: true })).slice(4, 12), [
+            2, 2, 5, 3,
+            1, 2, 7, 1
+        ], "should index nested messages after their parent");
+        test.equal(reader.scan({ recursive: true }).length, 24, "should keep values that don't parse as messages opaque");
+        
+        reader.uint32();
+        reader.uint32();
+        test.equal(reader.scan().length, 16, "should start at the current position");
+        
+        test.throws(function() {
+            new native.Reader(Buffer.from([ 10, 5, 1 ])).scan();
+        }, /index out of range/, "should reject truncated fields");
+        test.throws(function() {
+            new native.Reader(Buffer.from([ 131, 1, 12 ])).scan();
+        }, /end group for field 1 doesn't close field 16 at offset 2/, "should reject groups closed by another field");
+        test.throws(function() {
+            new native.Reader(Buffer.from([ 11, 19, 12, 12 ])).scan();
+        }, /end group for field 1 doesn't close field 2 at offset 2/, "should reject nested groups closed by another field");
+        test.deepEqual(Array.from(new native.Reader(Buffer.from([ 131, 1, 8, 1, 132, 1 ])).scan()), [ 16, 3, 2, 2 ], "should measure groups up to their end tag");
+        reader = new native.Reader(Buffer.from([ 8, 1, 16, 2 ]), { maxFields: 2 });
+        test.equal(reader.scan().length, 8, "should scan within maxFields");
+        test.deepEqual(reader.readAll([ [ "tag" ], [ "u32" ], [ "tag" ], [ "u32" ] ]), [ 1, 0, 1, 2, 0, 2 ], "should not count scanned fields towards maxFields");
+        
+        test.end();
+    });
+    
+    test.test(test.name + " - packed TypedArrays", function(test) {
+        var native = protobuf.native;
+        var cases = [
+            [ "Double", Float64Array, [ 1.5, -2, 1e300 ], "double" ],
+            [ "Float", Float32Array, [ 1.5, -2 ], "float" ],
+            [ "Int32", Int32Array, [ 1, -1, 300 ], "int32" ],
+            [ "Uint32", Uint32Array, [ 0, 4294967295 ], "uint32" ],
+            [ "Sint32", Int32Array, [ -1, 1, -300 ], "sint32" ],
+            [ "Fixed32", Uint32Array, [ 1, 4294967295 ], "fixed32" ],
+            [ "Sfixed32", Int32Array, [ -1, 2 ], "sfixed32" ],
+            [ "Int64", BigInt64Array, [ BigInt(-1), BigInt("9007199254740993") ], "int64" ],
+            [ "Uint64", BigUint64Array, [ BigInt("18446744073709551615"), BigInt(1) ], "uint64" ],
+            [ "Sint64", BigInt64Array, [ BigInt(-3), BigInt(3) ], "sint64" ],
+            [ "Fixed64", BigUint64Array, [ BigInt("18446744073709551615") ], "fixed64" ],
+            [ "Sfixed64", BigInt64Array, [ BigInt(-5) ], "sfixed64" ],
+            [ "Bool", Uint8Array, [ 1, 0, 1 ], "bool" ]
+        ];
+        cases.forEach(function(c) {
+            var values = c[1].from(c[2]);
+            var buffer = new native.Writer()["packed" + c[0]](values).finish();
+            
+            var expected = new native.Writer().fork();
+            c[2].forEach(function(value) {
+                expected[c[3]](c[3] === "bool" ? Boolean(value) : value);
+            });
+            test.deepEqual(Array.from(buffer), Array.from(expected.ldelim().finish()), "packed" + c[0] + " should match writing each value");
+            
+            var read = new native.Reader(buffer)["packed" + c[0]]();
+            test.ok(read instanceof c[1], "packed" + c[0] + " should return a " + c[1].name);
+            test.deepEqual(Array.from(read), Array.from(values), "packed" + c[0] + " should round-trip");
+        });
+        
+        test.deepEqual(Array.from(native.Writer.encodeAll([ [ "tag", 1, 2 ], [ "packed_sint32", Int32Array.from([ -1, 1 ]) ] ])), [ 10, 2, 1, 2 ], "should write packed fields in encodeAll");
+        
+        test.throws(function() {
+            new native.Reader(Buffer.from([ 3, 0, 0, 0 ])).packedFixed32();
+        }, /not a multiple of 4/, "should reject partial fixed-width values");
+        test.throws(function() {
+            new native.Reader(Buffer.from([ 1, 128, 1 ])).packedUint32();
+        }, /index out of range: 2 \+ 1 > 2/, "should reject varints crossing the field end");
+        var reader = new native.Reader(Buffer.from([ 1, 128, 1, 8 ]));
+        test.throws(function() {
+            reader.packedInt32();
+        }, /index out of range/, "should reject truncated packed varints");
+        test.notOk(reader.atEnd(), "should leave the packed limit after a failed read");
+        test.equal(reader.bytesUntilLimit(), 2, "should report the bytes left after a failed packed read");
+        test.equal(reader.uint32(), 1, "should continue reading after a failed packed read");
+        
+        test.end();
+    });
+    
+    test.test(test.name + " - limits", function(test) {
+        var native = protobuf.native;
+        // field 1 { field 1: 150, field 2: "hi" }, field 2: 7
+        var buffer = Buffer.from([ 10, 7, 8, 150, 1, 18, 2, 104, 105, 16, 7 ]);
+        var reader = new native.Reader(buffer);
+        reader.readTag();
+        reader.pushLimit(reader.uint32());
+        test.equal(reader.bytesUntilLimit(), 7, "should report the bytes left before the limit");
+        test.deepEqual(Array.from(reader.scan()), [ 1, 0, 3, 2, 2, 2, 7, 2 ], "should scan up to the limit");
+        test.equal(reader.readTag().field, 1, "should read inside the limit");
+        test.equal(reader.uint32(), 150, "should read values inside the limit");
+        reader.readTag();
+        reader.skipType(2);
+        test.ok(reader.atEnd(), "should be at the end of the limit");
+        test.throws(function() {
+            reader.uint32();
+        }, /index out of range/, "should not read past the limit");
+        reader.popLimit();
+        test.notOk(reader.atEnd(), "should restore the outer limit");
+        reader.readTag();
+        test.equal(reader.uint32(), 7, "should continue after the sub-message");
+        test.ok(reader.atEnd(), "should end at the end of the buffer");
+        test.throws(function() {
+            reader.popLimit();
+        }, /pop_limit without push_limit/, "should reject unbalanced popLimit");
+        
+        reader = new native.Reader(buffer);
+        test.throws(function() {
+            reader.pushLimit(12);
+        }, /index out of range: 0 \+ 12 > 11/, "should reject limits past the buffer");
+        reader.pushLimit(2);
+        test.throws(function() {
+            reader.pushLimit(3);
+        }, /index out of range: 0 \+ 3 > 2/, "should reject limits past the current limit");
+        test.throws(function() {
+            reader.skipType(2);
+        }, /index out of range: 1 \+ 10 > 2/, "should honour the limit in skipType");
+        
+        // the sub-message claims 3 bytes but its string field claims 2 more than that
+        reader = new native.Reader(Buffer.from([ 10, 3, 18, 3, 104, 105, 106 ]));
+        reader.readTag();
+        reader.pushLimit(reader.uint32());
+        reader.readTag();
+        test.throws(function() {
+            reader.string();
+        }, /index out of range: 4 \+ 3 > 5/, "should report overlapping fields at the sub-message boundary");
+        
+        reader = new native.Reader(buffer);
+        test.throws(function() {
+            reader.readAll([ [ "tag" ], [ "sub" ], [ "skip", 8 ] ]);
+        }, /index out of range/, "should fail plans reading past a sub");
+        test.equal(reader.bytesUntilLimit(), 9, "should pop the limits of a failed plan");
+        
+        test.throws(function() {
+            new native.Reader(buffer, { maxMessageSize: 10 });
+        }, /message size 11 exceeds maxMessageSize 10/, "should reject inputs too large");
+        test.throws(function() {
+            new native.Reader(Buffer.from([ 3, 104, 105, 106 ]), { maxBytesLength: 2 }).string();
+        }, /length 3 exceeds maxBytesLength 2 at offset 1/, "should reject strings too long");
+        reader = new native.Reader(Buffer.from([ 11, 11, 8, 1, 12, 12 ]), { maxDepth: 1 });
+        reader.readTag();
+        test.throws(function() {
+            reader.skipType(3);
+        }, /nesting depth exceeds maxDepth 1 at offset 2/, "should limit the depth of skipped groups");
+        reader = new native.Reader(Buffer.from([ 11, 11, 8, 1, 12, 12 ]), { maxDepth: 2 });
+        reader.readTag();
+        reader.skipType(3);
+        test.ok(reader.atEnd(), "should skip groups within the maximum depth");
+        reader = new native.Reader(Buffer.from([ 8, 1, 8, 2 ]), { maxFields: 1 });
+        reader.readTag();
+        reader.uint32();
+        test.throws(function() {
+            reader.readTag();
+        }, /field count exceeds maxFields 1 at offset 2/, "should limit the number of fields");
+        
+        test.end();
+    });
+    
+    test.test(test.name + " - structured errors", function(test) {
+        var native = protobuf.native;
+        function caught(fn) {
+            try {
+                fn();
+            } catch (e) {
+                return e;
+            }
+            return null;
+        }
+        
+        var err = caught(function() { new native.Reader(Buffer.from([ 10, 5, 1 ])).bytes(); });
+        test.ok(err instanceof RangeError, "should throw RangeError for out-of-range reads");
+        test.equal(err.message, "index out of range: 1 + 10 > 3", "should keep the protobuf.js message");
+        test.equal(err.code, "TRUNCATED", "should set the error code");
+        test.equal(err.offset, 1, "should set the offset");
+        
+        err = caught(function() { new native.Reader(Buffer.from([ 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 1 ])).uint64(); });
+        test.equal(err.code, "VARINT_OVERFLOW", "should reject varints longer than 10 bytes");
+        test.notOk(err instanceof RangeError, "should throw plain errors otherwise");
+        test.equal(caught(function() { new native.Reader(Buffer.from([ 1 ])).skipType(7); }).code, "INVALID_WIRE_TYPE", "should report invalid wire types");
+        test.equal(caught(function() { new native.Reader(Buffer.from([ 2 ])).readTag(); }).code, "INVALID_FIELD_NUMBER", "should report invalid field numbers");
+        err = caught(function() { new native.Reader(Buffer.from([ 3, 97, 255, 98 ])).string(); });
+        test.equal(err.code, "INVALID_UTF8", "should report invalid utf8");
+        test.equal(err.offset, 2, "should point at the invalid byte");
+        test.equal(caught(function() { new native.Reader(Buffer.from([ 8 ]), { maxFields: 0 }).readTag(); }).code, "LIMIT_EXCEEDED", "should report exceeded limits");
+        
+        err = caught(function() { native.Writer.encodeAll([ [ "u32", 1 ], [ "u32", "x" ] ]); });
+        test.equal(err.code, "TYPE_MISMATCH", "should report mistyped encodeAll operands");
+        test.ok(/^encodeAll op 1: /.test(err.message), "should name the failing encodeAll op");
+        err = caught(function() { native.Writer.encodeAll([ [ "u64", {} ] ]); });
+        test.equal(err.message, "encodeAll op 0: integer, BigInt, decimal string or Long expected", "should report invalid 64-bit operands");
+        err = caught(function() { native.Writer.encodeAll([ 1 ]); });
+        test.equal(err.code, "TYPE_MISMATCH", "should reject operations that aren't arrays");
+        
+        test.end();
+    });
+    
+    test.test(test.name + " - strict", function(test) {
+        var native = protobuf.native;
+        function caught(fn) {
+            try {
+                fn();
+            } catch (e) {
+                return e;
+            }
+            return null;
+        }
+        
+        test.equal(new native.Reader(Buffer.from([ 128, 0 ])).uint32(), 0, "should accept non-minimal varints by default");
+        var err = caught(function() { new native.Reader(Buffer.from([ 128, 0 ]), { strict: true }).uint32(); });
+        test.equal(err.code, "NON_CANONICAL", "should reject non-minimal varints in strict mode");
+        test.equal(err.rule, "non_minimal_varint", "should name the failed rule");
+        test.equal(err.offset, 0, "should point at the varint");
+        test.equal(caught(function() { new native.Reader(Buffer.from([ 128, 128, 128, 128, 16 ]), { strict: true }).uint32(); }).rule, "overlong_varint", "should reject 32-bit varints with more bits");
+        test.equal(caught(function() { new native.Reader(Buffer.from([ 255, 255, 255, 255, 255, 255, 255, 255, 255, 3 ]), { strict: true }).uint64(); }).rule, "overlong_varint", "should reject bits past the 64th");
+        test.equal(new native.Reader(Buffer.from([ 255, 255, 255, 255, 255, 255, 255, 255, 255, 1 ]), { strict: true }).int32(), -1, "should accept sign-extended int32");
+        var extended = [ 128, 128, 128, 128, 248, 255, 255, 255, 255, 1 ];
+        test.equal(caught(function() { new native.Reader(Buffer.from([ 138 ].concat(extended.slice(1))), { strict: true }).readTag(); }).rule, "overlong_varint", "should reject sign-extended tags");
+        test.equal(caught(function() { new native.Reader(Buffer.from([ 130 ].concat(extended.slice(1), [ 0, 0 ])), { strict: true }).bytes(); }).rule, "overlong_varint", "should reject sign-extended lengths");
+        test.equal(caught(function() { new native.Reader(Buffer.from(extended), { strict: true }).uint32(); }).rule, "overlong_varint", "should reject sign-extended uint32");
+        test.equal(new native.Reader(Buffer.from(extended), { strict: true }).int32(), -2147483648, "should accept the sign extension of int32");
+        test.equal(caught(function() { new native.Reader(Buffer.from([ 128, 0 ]), { strict: true }).skipType(0); }).rule, "non_minimal_varint", "should check skipped varints");
+        
+        test.end();
+    });
+    
+    test.test(test.name + " - utf8 policy", function(test) {
+        var native = protobuf.native;
+        var invalid = Buffer.from([ 3, 97, 255, 98 ]);
+        
+        test.throws(function() { new native.Reader(invalid).string(); }, /invalid utf8/, "should reject invalid utf8 by default");
+        test.equal(new native.Reader(invalid, { utf8: "replace" }).string(), "a\ufffdb", "should replace invalid sequences");
+        test.same(new native.Reader(invalid, { utf8: "bytes" }).string(), Buffer.from([ 97, 255, 98 ]), "should fall back to bytes");
+        test.equal(new native.Reader(Buffer.from([ 2, 104, 105 ]), { utf8: "bytes" }).string(), "hi", "should keep valid strings as strings");
+        test.equal(new native.Reader(invalid).string("replace"), "a\ufffdb", "should accept a policy per call");
+        test.throws(function() { new native.Reader(invalid, { utf8: "replace" }).string("strict"); }, /invalid utf8/, "should let the call override the reader");
+        test.throws(function() { new native.Reader(invalid, { utf8: "lossy" }); }, /invalid utf8 option: lossy/, "should reject unknown policies");
+        
+        var lone = "a\ud800b";
+        var err = null;
+        try {
+            new native.Writer().string(lone);
+        } catch (e) {
+            err = e;
+        }
+        test.equal(err && err.code, "INVALID_UTF8", "should reject lone surrogates by default");
+        test.equal(err && err.message, "lone surrogate U+D800 at index 1", "should point at the lone surrogate");
+        test.same(new native.Writer({ utf8: "replace" }).string(lone).finish(), Buffer.from([ 5, 97, 239, 191, 189, 98 ]), "should replace lone surrogates");
+        test.same(new native.Writer().string(lone, "bytes").finish(), Buffer.from([ 5, 97, 237, 160, 128, 98 ]), "should write lone surrogates as is like protobuf.js");
+        test.same(new native.Writer().string("\ud83d\ude00\ufffd").finish(), Buffer.concat([ Buffer.from([ 7 ]), Buffer.from("\ud83d\ude00\ufffd") ]), "should accept pairs and U+FFFD itself");
+        test.throws(function() { native.Writer.encodeAll([ [ "string", lone ] ]); }, /encodeAll op 0: lone surrogate/, "should check encodeAll strings");
+        
+        test.end();
+    });
+    
+    test.test(test.name + " - decodeRaw", function(test) {
+        var native = protobuf.native;
+        var buffer = protobuf.Writer.create()
+            .uint32(8).int32(-2)
+            .uint32(18).string("testing")
+            .uint32(26).fork().uint32(8).uint32(150).ldelim()
+            .uint32(33).double(1.5)
+            .uint32(45).float(0.5)
+            .uint32(50).bytes(Buffer.from([ 1, 130, 1 ]))
+            .uint32(58).bytes(Buffer.from([ 255 ]))
+            .uint32(67).uint32(8).uint32(1).uint32(68)
+            .finish();
+        var dump = native.decodeRaw(buffer, { longs: "string" });
+        var fields = dump.fields;
+        
+        test.equal(dump.end, buffer.length, "should read the whole buffer");
+        test.equal(dump.error, undefined, "should not report an error");
+        test.same([ fields[0].field, fields[0].wireType, fields[0].offset, fields[0].value, fields[0].int ], [ 1, 0, 0, "18446744073709551614", "-2" ], "should read varints");
+        test.equal(native.decodeRaw(Buffer.from([ 8, 3 ])).fields[0].sint, -2, "should read varints as zigzag");
+        test.same([ fields[1].kind, fields[1].string, fields[1].length ], [ "string", "testing", 7 ], "should guess strings");
+        test.same([ fields[2].kind, fields[2].fields[0].field, fields[2].fields[0].value, fields[2].fields[0].offset ], [ "message", 1, "150", 22 ], "should guess sub-messages");
+        test.same([ fields[3].double, fields[4].float, fields[4].value ], [ 1.5, 0.5, 1056964608 ], "should read fixed values as floats too");
+        test.same([ fields[5].kind, fields[5].packed ], [ "packed", [ "1", "130" ] ], "should guess packed varints");
+        test.same([ fields[6].kind, fields[6].bytes ], [ "bytes", Buffer.from([ 255 ]) ], "should fall back to bytes");
+        test.same([ fields[7].wireType, fields[7].fields.length ], [ 3, 1 ], "should read groups");
+        
+        test.equal(native.decodeRaw(buffer, { format: "text" }), [
+            "1: 18446744073709551614",
+            "2: \"testing\"",
+            "3 {",
+            "  1: 150",
+            "}",
+            "4: 0x3ff8000000000000  # 1.5",
+            "5: 0x3f000000  # 0.5",
+            "6: \"\\001\\202\\001\"  # packed: 1, 130",
+            "7: \"\\377\"",
+            "8 {",
+            "  1: 1",
+            "}",
+            ""
+        ].join("\n"), "should print like protoc --decode_raw");
+        
+        var truncated = native.decodeRaw(buffer.slice(0, buffer.length - 1));
+        test.equal(truncated.fields.length, 7, "should keep the fields before truncated input");
+        test.equal(truncated.end, 47, "should report where reading stopped");
+        test.equal(truncated.error.code, "TRUNCATED", "should report why reading stopped");
+        test.ok(truncated.error instanceof RangeError, "should report truncation as a RangeError");
+        test.ok(/# stopped at offset 47: missing end group for field 8\n$/.test(native.decodeRaw(buffer.slice(0, buffer.length - 1), { format: "text" })), "should note where reading stopped in text");
+        test.equal(native.decodeRaw(Buffer.from([ 8, 1, 18, 5, 1 ])).error.message, "index out of range: 4 + 5 > 5", "should stop at lengths past the end");
+        test.equal(native.decodeRaw(Buffer.from([ 12 ])).error.code, "MALFORMED", "should stop at unexpected end groups");
+        test.throws(function() { native.decodeRaw(buffer, { format: "json" }); }, /invalid format option: json/, "should reject unknown formats");
+        
+        test.end();
+    });
+    
+    test.test(test.name + " - protoscope", function(test) {
+        var native = protobuf.native;
+        function caught(fn) {
+            try {
+                fn();
+            } catch (e) {
+                return e;
+            }
+            return null;
+        }
+        
+        var buffer = protobuf.Writer.create()
+            .uint32(8).uint32(42)
+            .uint32(18).string("hi")
+            .uint32(26).fork().uint32(8).uint32(150).ldelim()
+            .uint32(33).double(1.5)
+            .uint32(45).float(0.5)
+            .uint32(50).bytes(Buffer.from([ 1, 130, 1 ]))
+            .uint32(58).bytes(Buffer.from([ 255, 0 ]))
+            .uint32(67).uint32(8).uint32(1).uint32(68)
+            .uint32(72).int32(-1)
+            .finish();
+        var text = [
+            "1: 42",
+            "2: {\"hi\"}",
+            "3: {",
+            "  1: 150",
+            "}",
+            "4: 1.5i64",
+            "5: 0.5i32",
+            "6: {1 130}",
+            "7: {`ff00`}",
+            "8: !{",
+            "  1: 1",
+            "}",
+            "9: -1",
+            ""
+        ].join("\n");
+        test.equal(native.toProtoscope(buffer), text, "should print fields with their wire types");
+        test.same(native.fromProtoscope(text), Buffer.from(buffer), "should assemble printed text");
+        test.same(native.fromProtoscope("1:I64 3z 2: {} # comment\n`0a`"), Buffer.from([ 9, 6, 18, 0, 10 ]), "should take explicit wire types, raw values and hex");
+        test.same(native.fromProtoscope("1: 7i32 2: -1i64 3: true"), Buffer.from([ 13, 7, 0, 0, 0, 17, 255, 255, 255, 255, 255, 255, 255, 255, 24, 1 ]), "should infer wire types from values");
+        
+        var overlong = native.fromProtoscope("1: long-form:2 1");
+        test.same(overlong, Buffer.from([ 8, 129, 128, 0 ]), "should pad varints with long-form");
+        test.equal(native.toProtoscope(overlong), "1: long-form:2 1\n", "should print padded varints");
+        var reader = new native.Reader(overlong, { strict: true });
+        reader.uint32();
+        test.equal(caught(function() { reader.skipType(0); }).code, "NON_CANONICAL", "should write input that strict readers reject");
+        test.equal(caught(function() { new native.Reader(native.fromProtoscope("long-form:10 1")).uint64(); }).code, "VARINT_OVERFLOW", "should write varints longer than 10 bytes");
+        
+        var short = native.fromProtoscope("1:LEN 5 \"hi\"");
+        test.same(short, Buffer.from([ 10, 5, 104, 105 ]), "should write wrong lengths");
+        reader = new native.Reader(short);
+        reader.uint32();
+        test.equal(caught(function() { reader.bytes(); }).code, "TRUNCATED", "should write input that readers find truncated");
+        test.equal(native.toProtoscope(short), "# index out of range: 2 + 5 > 4\n`0a056869`\n", "should print what can't be read as hex");
+        var group = native.fromProtoscope("long-form:1 8:SGROUP 1: 1 long-form:1 8:EGROUP");
+        test.equal(native.toProtoscope(group), "long-form:1 8:SGROUP\n1: 1\nlong-form:1 8:EGROUP\n", "should keep padded end groups");
+        
+        var err = caught(function() { native.fromProtoscope("1: {\n  2: 3"); });
+        test.equal(err.code, "MALFORMED", "should report malformed text");
+        test.equal(err.message, "2:7: expected '}', got end of input", "should report the line and column");
+        test.ok(/can't infer the wire type of field 1/.test(caught(function() { native.fromProtoscope("1:"); }).message), "should need a value to infer wire types");
+        test.ok(/'!\{' must follow a tag/.test(caught(function() { native.fromProtoscope("!{}"); }).message), "should need a tag before groups");
+        test.ok(/invalid hex literal/.test(caught(function() { native.fromProtoscope("`abc`"); }).message), "should reject odd hex digits");
+        
+        test.end();
+    });
+    
     test.end();
 });
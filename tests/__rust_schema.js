@@ -0,0 +1,916 @@
+var tape = require("tape");
+var protobuf = require("..");
+var descriptor = require("../ext/descriptor");
+
+var proto = "syntax = \"proto3\";\n\
+package test;\n\
+message Inner {\n\
+    int32 id = 1;\n\
+    string label = 2;\n\
+}\n\
+enum Kind {\n\
+    UNKNOWN = 0;\n\
+    SMALL = 1;\n\
+    LARGE = 2;\n\
+}\n\
+message Outer {\n\
+    uint32 count = 1;\n\
+    int32 delta = 2;\n\
+    sint64 offset = 3;\n\
+    string name = 4;\n\
+    bytes payload = 5;\n\
+    double ratio = 6;\n\
+    bool flag = 7;\n\
+    Kind kind = 8;\n\
+    Inner inner = 9;\n\
+    repeated Inner items = 10;\n\
+    repeated int32 values = 11;\n\
+    map<string, int32> counts = 12;\n\
+    map<int32, Inner> byId = 13;\n\
+    oneof choice {\n\
+        string text = 14;\n\
+        fixed64 number = 15;\n\
+    }\n\
+}\n";
+
+tape.test("Rust schema-aware codec", function(test) {
+    test.ok(protobuf.native, "should expose native bindings");
+
+    // Compare against a root built from the same JSON the native registry sees
+    var root = protobuf.Root.fromJSON(protobuf.parse(proto).root.toJSON());
+    var Outer = root.lookupType("test.Outer");
+    var handle = protobuf.native.registerType(root.toJSON(), "test.Outer");
+
+    test.equal(protobuf.native.lookupType("test.Outer"), handle, "should look up registered types by name");
+    test.equal(protobuf.native.lookupType("test.Missing"), null, "should return null for unknown types");
+
+    var object = {
+        count: 300,
+        delta: -5,
+        offset: -1234567,
+        name: "hello",
+        payload: Buffer.from([1, 2, 3]),
+        ratio: 0.5,
+        flag: true,
+        kind: 2,
+        inner: { id: 7, label: "seven" },
+        items: [ { id: 1 }, { id: 2, label: "two" } ],
+        values: [ 1, -2, 300 ],
+        counts: { a: 1, b: 2 },
+        byId: { 5: { id: 5 } },
+        text: "choice"
+    };
+
+    test.test(test.name + " - encode", function(test) {
+        var expected = Outer.encode(Outer.fromObject(object)).finish();
+        var actual = protobuf.native.encode(handle, object);
+        test.deepEqual(Array.from(actual), Array.from(expected), "should match the JavaScript encoder");
+
+        test.deepEqual(Array.from(protobuf.native.encode(handle, { kind: "LARGE" })), [ 64, 2 ], "should accept enum names");
+        test.deepEqual(Array.from(protobuf.native.encode(handle, { payload: "AQID" })), [ 42, 3, 1, 2, 3 ], "should accept base64 bytes");
+        test.deepEqual(Array.from(protobuf.native.encode(handle, { name: null, inner: undefined })), [], "should skip null and undefined fields");
+
+        test.throws(function() {
+            protobuf.native.encode(handle, { name: 1 });
+        }, /name: string expected/, "should reject mistyped values");
+
+        test.throws(function() {
+            protobuf.native.encode(12345, {});
+        }, /unknown type handle/, "should reject unknown handles");
+
+        test.end();
+    });
+
+    test.test(test.name + " - decode", function(test) {
+        var buffer = Outer.encode(Outer.fromObject(object)).finish();
+        var decoded = protobuf.native.decode(handle, buffer);
+        var expected = Outer.toObject(Outer.decode(buffer), { longs: Number, bytes: Buffer });
+        test.deepEqual(decoded, JSON.parse(JSON.stringify(expected), function(key, value) {
+            return value && value.type === "Buffer" ? Buffer.from(value.data) : value;
+        }), "should match the JavaScript decoder");
+
+        decoded = protobuf.native.decode(handle, buffer, { enums: "string", longs: "string", bytes: "base64", oneofs: true });
+        test.equal(decoded.kind, "LARGE", "should convert enums to names");
+        test.equal(decoded.offset, "-1234567", "should convert longs to strings");
+        test.equal(decoded.payload, "AQID", "should convert bytes to base64");
+        test.equal(decoded.choice, "text", "should set virtual oneof properties");
+
+        decoded = protobuf.native.decode(handle, protobuf.native.encode(handle, { offset: BigInt("-9007199254740993"), number: BigInt("18446744073709551615") }), { longs: "bigint" });
+        test.equal(decoded.offset, BigInt("-9007199254740993"), "should encode and decode BigInt");
+        test.equal(decoded.number, BigInt("18446744073709551615"), "should keep unsigned values above 2^63");
+
+        decoded = protobuf.native.decode(handle, Buffer.alloc(0), { defaults: true });
+        test.deepEqual(decoded.items, [], "should set empty arrays for repeated fields");
+        test.deepEqual(decoded.counts, {}, "should set empty objects for map fields");
+        test.equal(decoded.inner, null, "should set null for message fields");
+        test.equal(decoded.name, "", "should set scalar defaults");
+        test.notOk("text" in decoded, "should not set defaults for oneof members");
+
+        decoded = protobuf.native.decode(handle, protobuf.native.encode(handle, { text: "a", number: 2 }));
+        test.notOk("text" in decoded, "should keep only the last oneof member");
+        test.equal(decoded.number, 2, "should read the last oneof member");
+
+        var view = new Uint8Array(buffer.length + 2);
+        view.set(buffer, 1);
+        test.deepEqual(protobuf.native.decode(handle, new DataView(view.buffer, 1, buffer.length)), protobuf.native.decode(handle, buffer), "should decode views in place");
+
+        test.throws(function() {
+            protobuf.native.decode(handle, Buffer.from([ 74, 5, 8 ]));
+        }, /index out of range/, "should reject truncated messages");
+
+        test.end();
+    });
+
+    test.test(test.name + " - proto2 groups and required fields", function(test) {
+        var root2 = protobuf.parse("syntax = \"proto2\"; message G { required int32 a = 1; optional group Grp = 2 { optional string s = 3; } optional int64 big = 5 [default = 7]; }").root;
+        var G = protobuf.Root.fromJSON(root2.toJSON()).lookupType("G");
+        var g = protobuf.native.registerType(root2.toJSON(), "G");
+        var buffer = protobuf.native.encode(g, { a: 1, grp: { s: "x" } });
+        test.deepEqual(Array.from(buffer), Array.from(G.encode({ a: 1, grp: { s: "x" } }).finish()), "should encode groups");
+        test.deepEqual(protobuf.native.decode(g, buffer), { a: 1, grp: { s: "x" } }, "should decode groups");
+        test.equal(protobuf.native.decode(g, Buffer.from([ 8, 1 ]), { defaults: true }).big, 7, "should use declared defaults");
+        test.throws(function() {
+            protobuf.native.decode(g, Buffer.alloc(0));
+        }, /missing required 'a'/, "should reject missing required fields");
+        var root4 = protobuf.parse("syntax = \"proto2\"; message R { required int32 a = 1; } message M { map<string, R> m = 1; }").root;
+        var m = protobuf.native.registerType(root4.toJSON(), "M");
+        // An entry with only the key "a"
+        test.same(protobuf.native.decode(m, Buffer.from([ 10, 3, 10, 1, 97 ])), { m: { a: {} } }, "should default missing map values without checking required fields");
+        test.same(protobuf.native.decode(m, Buffer.from([ 10, 3, 10, 1, 97 ]), { defaults: true }).m, { a: { a: 0 } }, "should set defaults on missing map values");
+        test.end();
+    });
+
+    test.test(test.name + " - registerType", function(test) {
+        var inner = protobuf.native.registerType(root.lookupType("test.Inner").toJSON());
+        test.deepEqual(Array.from(protobuf.native.encode(inner, { id: 1, label: "a" })), [ 8, 1, 18, 1, 97 ], "should register bare type descriptors");
+
+        test.throws(function() {
+            protobuf.native.registerType(root.toJSON());
+        }, /type name is required/, "should require a type name for namespaces");
+
+        test.throws(function() {
+            protobuf.native.registerType({ fields: { a: { type: "Missing", id: 1 } } });
+        }, /no such type: Missing/, "should report unresolvable types");
+
+        test.equal(protobuf.native.registerType(root.toJSON(), "test.Outer"), handle, "should reuse the handle of a registered type");
+        var registered = protobuf.native.lookupType("test.Inner");
+        test.equal(protobuf.native.registerType(root.toJSON(), "test.Inner"), registered, "should not register a root again");
+
+        var ext = { nested: { r: { nested: {
+            T: { fields: { a: { type: "int32", id: 1 } }, extensions: [ [ 100, 199 ] ] },
+            U: { fields: { b: { type: "int32", id: 1 } } },
+            x: { type: "string", id: 100, extend: "T" }
+        } } } };
+        var u = protobuf.native.registerType(ext, "r.U");
+        test.equal(protobuf.native.registerType(ext, "r.U"), u, "should register the same extensions again");
+        var t = protobuf.native.lookupType("r.T");
+        test.deepEqual(Array.from(protobuf.native.encode(t, { a: 1, ".r.x": "x" })), [ 8, 1, 162, 6, 1, 120 ], "should keep extensions once");
+
+        var failed = { nested: { r: { nested: {
+            V: { fields: { c: { type: "int32", id: 1 } } },
+            y: { type: "string", id: 101, extend: "T" },
+            z: { type: "Missing", id: 102, extend: "T" }
+        } } } };
+        test.throws(function() {
+            protobuf.native.registerType(failed, "r.V");
+        }, /no such type: Missing/, "should report failed extensions");
+        test.equal(protobuf.native.lookupType("r.V"), null, "should not register types of a failed descriptor");
+        test.deepEqual(Array.from(protobuf.native.encode(t, { ".r.y": "y" })), [], "should not add extensions of a failed descriptor");
+        test.throws(function() {
+            protobuf.native.registerType({ nested: { r: { nested: { w: { type: "bool", id: 100, extend: "T" } } } } }, "r.T");
+        }, /duplicate id 100 in r.T/, "should reject conflicting extensions");
+
+        test.end();
+    });
+
+    test.test(test.name + " - safety limits", function(test) {
+        var root3 = protobuf.parse("syntax = \"proto2\"; message Node { optional Node child = 1; optional bytes data = 2; repeated group Grp = 3 { optional int32 a = 4; } }").root;
+        var Node = root3.lookupType("Node");
+        var node = protobuf.native.registerType(root3.toJSON(), "Node");
+        var nested = { data: Buffer.from("abc") };
+        for (var i = 0; i < 5; ++i)
+            nested = { child: nested };
+        var buffer = Node.encode(nested).finish();
+
+        test.deepEqual(protobuf.native.decode(node, buffer, { maxDepth: 5 }), protobuf.native.decode(node, buffer), "should decode within the maximum depth");
+        test.throws(function() {
+            protobuf.native.decode(node, buffer, { maxDepth: 4 });
+        }, /nesting depth exceeds maxDepth 4 at offset 9/, "should reject messages nested too deeply");
+        test.throws(function() {
+            protobuf.native.decode(node, Buffer.from([ 43, 43, 44, 44 ]), { maxDepth: 1 });
+        }, /nesting depth exceeds maxDepth 1/, "should count unknown groups towards the depth");
+
+        var deep = [];
+        for (i = 0; i < 200; ++i)
+            deep.push(43);
+        test.throws(function() {
+            protobuf.native.decode(node, Buffer.from(deep));
+        }, /nesting depth exceeds maxDepth 100/, "should limit the depth by default");
+
+        test.throws(function() {
+            protobuf.native.decode(node, buffer, { maxMessageSize: buffer.length - 1 });
+        }, /message size 15 exceeds maxMessageSize 14/, "should reject messages too large");
+        test.throws(function() {
+            protobuf.native.decode(node, buffer, { maxBytesLength: 2 });
+        }, /length 3 exceeds maxBytesLength 2 at offset 12/, "should reject bytes too long");
+        test.throws(function() {
+            protobuf.native.decode(node, buffer, { maxFields: 5 });
+        }, /field count exceeds maxFields 5 at offset 11/, "should reject too many fields");
+        test.ok(protobuf.native.decode(node, buffer, { maxFields: 6, maxBytesLength: 3, maxMessageSize: buffer.length }), "should accept inputs at the limits");
+
+        var cyclic = { data: Buffer.from("abc") };
+        cyclic.child = cyclic;
+        var error = null;
+        try {
+            protobuf.native.encode(node, cyclic);
+        } catch (e) {
+            error = e;
+        }
+        test.equal(error && error.code, "LIMIT_EXCEEDED", "should reject objects referring to themselves");
+        test.ok(/nesting depth exceeds maxDepth 100$/.test(error && error.message), "should report the depth of self-referencing objects");
+        nested = {};
+        for (i = 0; i < 100; ++i)
+            nested = { child: nested };
+        test.ok(protobuf.native.decode(node, protobuf.native.encode(node, nested)), "should encode as deep as the decoder reads");
+
+        test.throws(function() {
+            protobuf.native.binaryToJson(node, buffer, { maxDepth: 4 });
+        }, /nesting depth exceeds maxDepth 4/, "should limit the depth of JSON conversion");
+        test.throws(function() {
+            protobuf.native.binaryToJson(node, buffer, { maxMessageSize: buffer.length - 1 });
+        }, /message size 15 exceeds maxMessageSize 14/, "should limit the size of JSON conversion");
+        test.throws(function() {
+            protobuf.native.binaryToJson(node, buffer, { maxBytesLength: 2 });
+        }, /length 3 exceeds maxBytesLength 2/, "should limit bytes in JSON conversion");
+        test.throws(function() {
+            protobuf.native.binaryToJson(node, buffer, { maxFields: 5 });
+        }, /field count exceeds maxFields 5/, "should limit the fields of JSON conversion");
+        test.equal(protobuf.native.binaryToJson(node, buffer, { maxDepth: 5, maxFields: 6, maxBytesLength: 3, maxMessageSize: buffer.length }), protobuf.native.binaryToJson(node, buffer), "should convert to JSON at the limits");
+        test.throws(function() {
+            protobuf.native.toTextFormat(node, buffer, { maxDepth: 4 });
+        }, /nesting depth exceeds maxDepth 4/, "should limit the depth of text printing");
+        test.throws(function() {
+            protobuf.native.toTextFormat(node, buffer, { maxMessageSize: buffer.length - 1 });
+        }, /message size 15 exceeds maxMessageSize 14/, "should limit the size of text printing");
+        test.throws(function() {
+            protobuf.native.toTextFormat(node, buffer, { maxBytesLength: 2 });
+        }, /length 3 exceeds maxBytesLength 2/, "should limit bytes in text printing");
+        test.throws(function() {
+            protobuf.native.toTextFormat(node, buffer, { maxFields: 5 });
+        }, /field count exceeds maxFields 5/, "should limit the fields of text printing");
+        test.equal(protobuf.native.toTextFormat(node, buffer, { maxDepth: 5, maxFields: 6, maxBytesLength: 3, maxMessageSize: buffer.length }), protobuf.native.toTextFormat(node, buffer), "should print text at the limits");
+
+        test.end();
+    });
+
+    test.test(test.name + " - error paths", function(test) {
+        function caught(fn) {
+            try {
+                fn();
+            } catch (e) {
+                return e;
+            }
+            return null;
+        }
+
+        var err = caught(function() { protobuf.native.encode(handle, { items: [ { id: 1 }, { id: {} } ] }); });
+        test.equal(err.code, "TYPE_MISMATCH", "should report mistyped values");
+        test.equal(err.path, "items[1].id", "should report the path of mistyped values");
+        test.equal(err.message, "items[1].id: number expected", "should prefix the message with the path");
+        err = caught(function() { protobuf.native.encode(handle, { byId: { 5: { label: 1 } } }); });
+        test.equal(err.path, "byId[5].label", "should index map values by key");
+        err = caught(function() { protobuf.native.encode(handle, { counts: { a: {} } }); });
+        test.equal(err.path, "counts[\"a\"]", "should quote string map keys");
+
+        // items[1] has a label with an invalid utf8 byte
+        var buffer = Buffer.from([ 82, 2, 8, 1, 82, 5, 8, 2, 18, 1, 255 ]);
+        err = caught(function() { protobuf.native.decode(handle, buffer); });
+        test.equal(err.code, "INVALID_UTF8", "should report invalid strings");
+        test.equal(err.path, "items[1].label", "should report the path of invalid values");
+        test.equal(err.offset, 10, "should report the offset of invalid values");
+        err = caught(function() { protobuf.native.decode(handle, Buffer.from([ 74, 5, 8 ])); });
+        test.ok(err instanceof RangeError, "should throw RangeError for truncated messages");
+        test.equal(err.path, "inner", "should report the path of truncated fields");
+        test.equal(caught(function() { protobuf.native.decode(handle, Buffer.from([ 74, 1, 9 ])); }).path, "inner.id", "should report wire type mismatches in their field");
+
+        test.end();
+    });
+
+    test.test(test.name + " - strict", function(test) {
+        function rule(bytes) {
+            try {
+                protobuf.native.decode(handle, Buffer.from(bytes), { strict: true });
+            } catch (e) {
+                test.equal(e.code, "NON_CANONICAL", "should throw NON_CANONICAL for " + e.rule);
+                return e;
+            }
+            return null;
+        }
+
+        test.same(protobuf.native.decode(handle, Outer.encode(object).finish(), { strict: true }).name, "hello", "should accept canonical input");
+        test.equal(rule([ 16, 255, 255, 255, 255, 255, 255, 255, 255, 255, 1 ]), null, "should accept sign-extended negative int32");
+
+        var err = rule([ 8, 128, 0 ]);
+        test.equal(err.rule, "non_minimal_varint", "should reject non-minimal varints");
+        test.equal(err.offset, 1, "should report the offset of the varint");
+        test.equal(err.path, "count", "should report the path of the varint");
+        test.equal(rule([ 8, 128, 128, 128, 128, 16 ]).rule, "overlong_varint", "should reject uint32 values beyond 32 bits");
+        test.equal(rule([ 88, 1 ]).rule, "wire_type_mismatch", "should reject unpacked packed fields");
+        test.equal(rule([ 74, 0, 75, 76 ]).rule, "wire_type_mismatch", "should reject messages encoded as groups");
+        err = rule([ 8, 1, 8, 2 ]);
+        test.equal(err.rule, "duplicate_field", "should reject repeated singular fields");
+        test.equal(err.offset, 2, "should report the offset of the duplicate tag");
+        test.equal(rule([ 114, 0, 121, 0, 0, 0, 0, 0, 0, 0, 0 ]).rule, "duplicate_field", "should reject two members of a oneof");
+        // counts = { "a": 1 } with a varint key, a fixed32 value and a repeated key
+        err = rule([ 98, 4, 8, 1, 16, 1 ]);
+        test.equal(err.rule, "wire_type_mismatch", "should reject map keys of the wrong wire type");
+        test.equal(err.offset, 2, "should report the offset of the map key");
+        test.equal(err.path, "counts", "should report the path of the map");
+        test.equal(rule([ 98, 8, 10, 1, 97, 21, 1, 0, 0, 0 ]).rule, "wire_type_mismatch", "should reject map values of the wrong wire type");
+        err = rule([ 98, 6, 10, 1, 97, 10, 1, 98 ]);
+        test.equal(err.rule, "duplicate_field", "should reject repeated map keys in an entry");
+        test.equal(err.offset, 5, "should report the offset of the repeated map key");
+        test.equal(rule([ 98, 4, 16, 1, 16, 2 ]).rule, "duplicate_field", "should reject repeated map values in an entry");
+        err = null;
+        try {
+            protobuf.native.decode(handle, Buffer.from([ 98, 4, 8, 1, 16, 1 ]));
+        } catch (e) {
+            err = e;
+        }
+        test.equal(err && err.code, "INVALID_WIRE_TYPE", "should reject map keys of the wrong wire type by default");
+        test.equal(rule([ 8, 1, 0 ]).rule, "trailing_garbage", "should reject zero tags");
+        test.equal(rule([ 8, 1, 12 ]).rule, "trailing_garbage", "should reject stray end group tags");
+
+        [ [ 8, 128, 0 ], [ 88, 1 ], [ 8, 1, 8, 2 ] ].forEach(function(bytes) {
+            test.ok(protobuf.native.decode(handle, Buffer.from(bytes)), "should accept non-canonical input by default");
+        });
+
+        test.end();
+    });
+
+    test.test(test.name + " - utf8 policy", function(test) {
+        // name = "a\xffb" and counts = { "\xff": 1 }
+        var buffer = Buffer.from([ 34, 3, 97, 255, 98, 98, 5, 10, 1, 255, 16, 1 ]);
+        test.throws(function() { protobuf.native.decode(handle, buffer); }, /name: invalid utf8/, "should reject invalid utf8 by default");
+        var decoded = protobuf.native.decode(handle, buffer, { utf8: "replace" });
+        test.equal(decoded.name, "a\ufffdb", "should replace invalid sequences");
+        test.same(Object.keys(decoded.counts), [ "\ufffd" ], "should replace in map keys");
+        decoded = protobuf.native.decode(handle, buffer, { utf8: "bytes" });
+        test.same(decoded.name, Buffer.from([ 97, 255, 98 ]), "should fall back to bytes");
+        test.same(Object.keys(decoded.counts), [ "\ufffd" ], "should replace in map keys, which must be strings");
+
+        test.throws(function() { protobuf.native.encode(handle, { name: "\udc00" }); }, /name: lone surrogate U\+DC00 at index 0/, "should reject lone surrogates by default");
+        test.same(protobuf.native.encode(handle, { name: "\udc00" }, { utf8: "bytes" }), Buffer.from([ 34, 3, 237, 176, 128 ]), "should honour the encode policy");
+
+        test.end();
+    });
+
+    test.test(test.name + " - proto3 json", function(test) {
+        var native = protobuf.native;
+        var wkt = new protobuf.Root();
+        [ "any", "duration", "timestamp", "struct", "wrappers", "field_mask" ].forEach(function(name) {
+            wkt.addJSON(protobuf.common.get("google/protobuf/" + name + ".proto").nested);
+        });
+        protobuf.parse("syntax = \"proto3\"; package j; enum Color { RED = 0; GREEN = 1; }\n\
+message Msg { int32 i = 1; int64 l = 2; string foo_bar = 3; bytes b = 4; double d = 5; Color c = 6; repeated int32 r = 7; map<int64, string> m = 8; string named = 9 [json_name = \"custom\"]; Msg child = 10;\n\
+    google.protobuf.Timestamp ts = 11; google.protobuf.Duration du = 12; google.protobuf.Struct st = 13; google.protobuf.Int32Value w = 14; google.protobuf.FieldMask fm = 15; google.protobuf.Any any = 16;\n\
+    oneof k { string s1 = 17; int32 s2 = 18; } }", wkt);
+        var Msg = wkt.lookupType("j.Msg");
+        var msg = native.registerType(wkt.toJSON(), "j.Msg");
+        test.deepEqual(Array.from(native.jsonToBinary(msg, "{\"r\":[],\"m\":{}}")), Array.from(Msg.encode(Msg.fromObject({ r: [], m: {} })).finish()), "should write nothing for empty repeated fields");
+
+        var buffer = Msg.encode(Msg.fromObject({
+            i: -5, l: "-3", fooBar: "x\"y", b: Buffer.from([ 1, 2, 255 ]), d: 0.1, c: 1, r: [ 1, -2 ], m: { 7: "z" }, named: "n", child: {},
+            ts: { seconds: 1000000000, nanos: 5000000 }, du: { seconds: -1, nanos: -500000000 }, w: { value: 0 }, fm: { paths: [ "foo_bar", "a.b_c" ] },
+            st: { fields: { a: { numberValue: 1 }, b: { listValue: { values: [ { boolValue: true }, { nullValue: 0 }, { stringValue: "s" } ] } } } }
+        })).finish();
+        var json = native.binaryToJson(msg, buffer);
+        test.equal(json, "{\"i\":-5,\"l\":\"-3\",\"fooBar\":\"x\\\"y\",\"b\":\"AQL/\",\"d\":0.1,\"c\":\"GREEN\",\"r\":[1,-2],\"m\":{\"7\":\"z\"},\"custom\":\"n\",\"child\":{}," +
+            "\"ts\":\"2001-09-09T01:46:40.005Z\",\"du\":\"-1.500s\",\"st\":{\"a\":1,\"b\":[true,null,\"s\"]},\"w\":0,\"fm\":\"fooBar,a.bC\"}", "should print canonical proto3 JSON");
+        test.equal(native.binaryToJson(msg, native.jsonToBinary(msg, json)), json, "should parse what it prints");
+        test.equal(JSON.parse(native.binaryToJson(msg, buffer, { enumsAsInts: true })).c, 1, "should print enum numbers on request");
+        var keepCase = protobuf.parse("syntax = \"proto3\"; message Snake { int32 foo_bar = 1; }", { keepCase: true }).root;
+        var snake = native.registerType(keepCase.toJSON(), "Snake");
+        test.equal(native.binaryToJson(snake, Buffer.from([ 8, 1 ])), "{\"fooBar\":1}", "should use lowerCamelCase names by default");
+        test.equal(native.binaryToJson(snake, Buffer.from([ 8, 1 ]), { useProtoNames: true }), "{\"foo_bar\":1}", "should use proto names on request");
+        test.equal(native.binaryToJson(msg, Buffer.alloc(0), { emitDefaults: true }), "{\"i\":0,\"l\":\"0\",\"fooBar\":\"\",\"b\":\"\",\"d\":0,\"c\":\"RED\",\"r\":[],\"m\":{},\"custom\":\"\"}", "should emit defaults on request");
+        test.same(Msg.decode(native.jsonToBinary(msg, "{\"i\":\"12\",\"foo_bar\":\"q\",\"d\":\"-Infinity\",\"r\":[1,\"2\",3e0],\"w\":null,\"s2\":0}")).toJSON(), { i: 12, fooBar: "q", d: "-Infinity", r: [ 1, 2, 3 ], s2: 0 }, "should accept the alternative JSON forms");
+
+        var any = Msg.encode(Msg.fromObject({ any: { type_url: "type.googleapis.com/j.Msg", value: Msg.encode({ i: 7 }).finish() } })).finish();
+        test.equal(native.binaryToJson(msg, any), "{\"any\":{\"@type\":\"type.googleapis.com/j.Msg\",\"i\":7}}", "should expand Any");
+        json = "{\"any\":{\"@type\":\"type.googleapis.com/google.protobuf.Duration\",\"value\":\"1.000000001s\"}}";
+        test.equal(native.binaryToJson(msg, native.jsonToBinary(msg, json)), json, "should wrap well-known types in Any as value");
+
+        function caught(fn) {
+            try {
+                fn();
+            } catch (e) {
+                return e;
+            }
+            return null;
+        }
+        var err = caught(function() { native.jsonToBinary(msg, "{\"child\":{\"ts\":\"2020-13-01T00:00:00Z\"}}"); });
+        test.equal(err.code, "TYPE_MISMATCH", "should reject invalid timestamps");
+        test.equal(err.path, "child.ts", "should report the path of invalid JSON values");
+        test.equal(caught(function() { native.jsonToBinary(msg, "{\"nope\":1}"); }).message, "unknown field 'nope' in j.Msg", "should reject unknown fields");
+        test.same(Msg.decode(native.jsonToBinary(msg, "{\"nope\":1,\"c\":\"BLUE\",\"i\":2}", { ignoreUnknownFields: true })).toJSON(), { i: 2 }, "should ignore unknown fields and enum values on request");
+        test.ok(/multiple values for oneof k/.test(caught(function() { native.jsonToBinary(msg, "{\"s1\":\"a\",\"s2\":1}"); }).message), "should reject several oneof members");
+        test.equal(caught(function() { native.jsonToBinary(msg, "{\"i\":3000000000}"); }).message, "i: int32 value out of range: 3000000000", "should reject out of range integers");
+        test.ok(/^invalid JSON: EOF/.test(caught(function() { native.jsonToBinary(msg, "{\"i\":1"); }).message), "should reject invalid JSON");
+        test.equal(caught(function() { native.binaryToJson(msg, Buffer.from([ 26, 1, 255 ])); }).code, "INVALID_UTF8", "should honour the utf8 policy");
+        test.equal(native.binaryToJson(msg, Buffer.from([ 26, 1, 255 ]), { utf8: "replace" }), "{\"fooBar\":\"\ufffd\"}", "should replace invalid utf8 on request");
+        test.equal(caught(function() { native.jsonToBinary(msg, "{\"fooBar\":\"\ud800\"}"); }).code, "INVALID_UTF8", "should reject lone surrogates");
+
+        test.end();
+    });
+
+    test.test(test.name + " - text format", function(test) {
+        var native = protobuf.native;
+        var text = new protobuf.Root();
+        text.addJSON(protobuf.common.get("google/protobuf/any.proto").nested);
+        protobuf.parse("syntax = \"proto2\"; package t; enum Color { RED = 0; GREEN = 1; }\n\
+message Msg { optional int32 i = 1; optional int64 l = 2; optional string s = 3; optional bytes b = 4; optional double d = 5; optional Color c = 6;\n\
+    repeated int32 r = 7 [packed = true]; map<string, Msg> m = 8; optional Msg child = 9; repeated string rs = 10; optional google.protobuf.Any any = 11;\n\
+    optional group Grp = 12 { optional int32 x = 1; } extensions 100 to 200; }\n\
+extend Msg { optional string ext = 100; }", text, { keepCase: true });
+        var Msg = text.lookupType("t.Msg");
+        var msg = native.registerType(text.toJSON(), "t.Msg");
+
+        var buffer = Msg.encode(Msg.fromObject({
+            i: -5, l: "-3", s: "h\u00e9\n\"", b: Buffer.from([ 0, 255, 65 ]), d: 1.5, c: 1, r: [ 1, 2 ], m: { a: { i: 1 } }, child: { child: { s: "x" } }, rs: [ "a", "b" ],
+            any: { type_url: "type.googleapis.com/t.Msg", value: Msg.encode({ i: 9 }).finish() }, ".t.ext": "E"
+        })).finish();
+        var expected = [
+            "i: -5",
+            "l: -3",
+            "s: \"h\u00e9\\n\\\"\"",
+            "b: \"\\000\\377A\"",
+            "d: 1.5",
+            "c: GREEN",
+            "r: 1",
+            "r: 2",
+            "m {",
+            "  key: \"a\"",
+            "  value {",
+            "    i: 1",
+            "  }",
+            "}",
+            "child {",
+            "  child {",
+            "    s: \"x\"",
+            "  }",
+            "}",
+            "rs: \"a\"",
+            "rs: \"b\"",
+            "any {",
+            "  [type.googleapis.com/t.Msg] {",
+            "    i: 9",
+            "  }",
+            "}",
+            "[t.ext]: \"E\"",
+            ""
+        ].join("\n");
+        test.equal(native.toTextFormat(msg, buffer), expected, "should print the text format");
+        test.equal(native.toTextFormat(msg, native.fromTextFormat(msg, expected)), expected, "should parse what it prints");
+        test.equal(native.toTextFormat(msg, Msg.encode({ i: 1, child: { i: 2 } }).finish(), { singleLine: true }), "i: 1 child { i: 2 }", "should print on a single line on request");
+        test.equal(native.toTextFormat(msg, Buffer.from([ 0x63, 0x08, 0x04, 0x64, 0xa8, 0x1f, 0x05, 0xc5, 0x1f, 0x01, 0x00, 0x00, 0x00 ]), { singleLine: true }), "Grp { x: 4 } 501: 5 504: 0x00000001", "should print groups by type name and unknown fields by number");
+
+        var parsed = native.fromTextFormat(msg, "# comment\ni: 0x10, l: -017; d: -inf r: [1, 2] r: 3 rs: [\"a\", 'b' \"c\"]\n" +
+            "m < key: \"k\" value < i: 1 > > c: 1 Grp { x: 1 } [t.ext]: \"z\" any { [type.googleapis.com/t.Msg] { i: 3 } }");
+        test.equal(native.toTextFormat(msg, parsed, { singleLine: true }),
+            "i: 16 l: -15 d: -inf c: GREEN r: 1 r: 2 r: 3 m { key: \"k\" value { i: 1 } } rs: \"a\" rs: \"bc\" any { [type.googleapis.com/t.Msg] { i: 3 } } Grp { x: 1 } [t.ext]: \"z\"",
+            "should accept the alternative text format forms");
+        test.same(Msg.decode(native.fromTextFormat(msg, "i: 1 child { i: 2 } r: [3]")).toJSON(), { i: 1, r: [ 3 ], child: { i: 2 } }, "should write fields protobuf.js decodes");
+
+        function caught(fn) {
+            try {
+                fn();
+            } catch (e) {
+                return e;
+            }
+            return null;
+        }
+        var err = caught(function() { native.fromTextFormat(msg, "i: 1\nchild {\n  i: \"x\"\n}"); });
+        test.equal(err.code, "MALFORMED", "should reject invalid text");
+        test.equal(err.message, "3:6: expected integer, got string", "should report the line and column");
+        test.equal(err.offset, 18, "should report the offset in the text");
+        test.equal(caught(function() { native.fromTextFormat(msg, "nope: 1"); }).message, "1:1: unknown field 'nope' in t.Msg", "should reject unknown fields");
+        test.equal(caught(function() { native.fromTextFormat(msg, "i: 1 i: 2"); }).message, "1:6: non-repeated field 'i' is specified multiple times", "should reject repeated singular fields");
+        test.equal(caught(function() { native.fromTextFormat(msg, "child { i: 1"); }).message, "1:13: expected field name or '}', got end of input", "should reject unterminated messages");
+        test.equal(caught(function() { native.fromTextFormat(msg, "i: 3000000000"); }).code, "TYPE_MISMATCH", "should reject out of range integers");
+        test.equal(caught(function() { native.fromTextFormat(msg, "any { [type.googleapis.com/x.Y] {} }"); }).message, "1:7: unknown type in google.protobuf.Any: type.googleapis.com/x.Y", "should reject unknown Any types");
+        test.equal(caught(function() { native.fromTextFormat(msg, "s: \"\\377\""); }).code, "INVALID_UTF8", "should reject invalid utf8 in strings");
+        test.same(Msg.decode(native.fromTextFormat(msg, "s: \"\\377\"", { utf8: "replace" })).s, "\ufffd", "should replace invalid utf8 on request");
+        test.equal(native.toTextFormat(msg, native.fromTextFormat(msg, "s: \"\\377\"", { utf8: "bytes" }), { utf8: "bytes" }), "s: \"\\377\"\n", "should keep invalid utf8 on request");
+
+        test.end();
+    });
+
+    test.test(test.name + " - well-known types", function(test) {
+        var native = protobuf.native;
+        var wkt = new protobuf.Root();
+        [ "duration", "timestamp", "struct", "wrappers", "field_mask", "empty" ].forEach(function(name) {
+            wkt.addJSON(protobuf.common.get("google/protobuf/" + name + ".proto").nested);
+        });
+        protobuf.parse("syntax = \"proto3\"; package w;\n\
+message Msg { google.protobuf.Timestamp ts = 1; google.protobuf.Duration du = 2; google.protobuf.Int32Value i = 3; google.protobuf.BytesValue b = 4; google.protobuf.Int64Value l = 5;\n\
+    google.protobuf.Struct st = 6; google.protobuf.Value v = 7; google.protobuf.ListValue lv = 8; google.protobuf.FieldMask fm = 9; google.protobuf.Empty e = 10;\n\
+    repeated google.protobuf.Timestamp tss = 11; map<string, google.protobuf.Duration> dus = 12; }", wkt);
+        var Msg = wkt.lookupType("w.Msg");
+        var msg = native.registerType(wkt.toJSON(), "w.Msg");
+        function caught(fn) {
+            try {
+                fn();
+            } catch (e) {
+                return e;
+            }
+            return null;
+        }
+
+        var buffer = Msg.encode(Msg.fromObject({
+            ts: { seconds: -1, nanos: 500000000 }, du: { seconds: -1, nanos: -500000000 }, i: { value: 7 }, b: { value: Buffer.from([ 1 ]) }, l: { value: "5" },
+            st: { fields: { a: { numberValue: 1 }, b: { listValue: { values: [ { boolValue: true }, { nullValue: 0 }, { structValue: { fields: { c: { stringValue: "d" } } } } ] } } } },
+            v: { stringValue: "s" }, lv: { values: [ { numberValue: 2 } ] }, fm: { paths: [ "a", "b.c" ] }, e: {}, tss: [ { seconds: 1 } ], dus: { k: { seconds: 2 } }
+        })).finish();
+        var decoded = native.decode(msg, buffer, { wellKnownTypes: true });
+        test.ok(decoded.ts instanceof Date, "should decode Timestamps as Dates");
+        test.equal(decoded.ts.getTime(), -500, "should keep the second of instants before 1970");
+        test.same([ decoded.du, decoded.i, decoded.b, decoded.l ], [ -1500, 7, Buffer.from([ 1 ]), 5 ], "should decode Durations as milliseconds and wrappers as their value");
+        test.same(decoded.st, { a: 1, b: [ true, null, { c: "d" } ] }, "should decode Structs as plain objects");
+        test.same([ decoded.v, decoded.lv, decoded.fm, decoded.e ], [ "s", [ 2 ], [ "a", "b.c" ], {} ], "should decode Value, ListValue, FieldMask and Empty");
+        test.same([ decoded.tss[0].getTime(), decoded.dus.k ], [ 1000, 2000 ], "should convert repeated and map values");
+        test.same(native.encode(msg, decoded, { wellKnownTypes: true }), Buffer.from(buffer), "should encode the native values");
+
+        decoded = native.decode(msg, buffer, { wellKnownTypes: true, timestamps: "string", durations: "string", longs: "string" });
+        test.same([ decoded.ts, decoded.du, decoded.l, decoded.tss[0] ], [ "1969-12-31T23:59:59.500Z", "-1.500s", "5", "1970-01-01T00:00:01Z" ], "should decode Timestamps and Durations as strings on request");
+        test.same(native.decode(msg, Buffer.from([ 26, 0 ]), { wellKnownTypes: true }).i, 0, "should decode empty wrappers as the default value");
+        test.same(native.decode(msg, Buffer.alloc(0), { wellKnownTypes: true, defaults: true }).ts, null, "should keep missing fields null");
+
+        test.same(Msg.decode(native.encode(msg, { ts: "1970-01-01T00:00:01.5Z", du: "-1.5s", i: 0, fm: "a,b", v: [ 1, null, { x: true } ] }, { wellKnownTypes: true })).toJSON(), {
+            ts: { seconds: "1", nanos: 500000000 }, du: { seconds: "-1", nanos: -500000000 }, i: {}, fm: { paths: [ "a", "b" ] },
+            v: { listValue: { values: [ { numberValue: 1 }, { nullValue: "NULL_VALUE" }, { structValue: { fields: { x: { boolValue: true } } } } ] } }
+        }, "should encode strings, wrapped values and JSON values");
+        test.same(Msg.decode(native.encode(msg, { ts: new Date(-1), du: 1.5 }, { wellKnownTypes: true })).toJSON(), { ts: { seconds: "-1", nanos: 999000000 }, du: { nanos: 1500000 } }, "should keep nanos positive for Dates before 1970");
+        test.same(native.encode(msg, { ts: { seconds: 1 }, i: { value: 2 } }, { wellKnownTypes: true }), Buffer.from([ 10, 2, 8, 1, 26, 2, 8, 2 ]), "should still take the message shape");
+        test.same(native.decode(msg, native.encode(msg, { ts: { seconds: 1 } })).ts, { seconds: 1 }, "should leave well-known types alone by default");
+
+        var err = caught(function() { native.encode(msg, { ts: "yesterday" }, { wellKnownTypes: true }); });
+        test.equal(err.message, "ts: RFC 3339 timestamp expected", "should reject invalid timestamp strings");
+        test.equal(caught(function() { native.encode(msg, { ts: new Date(NaN) }, { wellKnownTypes: true }); }).code, "TYPE_MISMATCH", "should reject invalid Dates");
+        err = caught(function() { native.decode(msg, Msg.encode({ du: { seconds: 1, nanos: -1 } }).finish(), { wellKnownTypes: true }); });
+        test.equal(err.message, "du: invalid google.protobuf.Duration: seconds 1 nanos -1", "should reject Durations with mixed signs");
+        test.throws(function() { native.decode(msg, buffer, { wellKnownTypes: true, timestamps: "iso" }); }, /invalid timestamps option: iso/, "should reject unknown representations");
+
+        test.end();
+    });
+
+    test.test(test.name + " - any", function(test) {
+        var native = protobuf.native;
+        var root = new protobuf.Root();
+        root.addJSON(protobuf.common.get("google/protobuf/any.proto").nested);
+        protobuf.parse("syntax = \"proto3\"; package a;\n\
+message Inner { int32 i = 1; string s = 2; google.protobuf.Any nested = 3; }\n\
+message Msg { google.protobuf.Any any = 1; repeated google.protobuf.Any anys = 2; }", root);
+        var Inner = root.lookupType("a.Inner");
+        var Msg = root.lookupType("a.Msg");
+        var msg = native.registerType(root.toJSON(), "a.Msg");
+        var inner = native.lookupType("a.Inner");
+        function caught(fn) {
+            try {
+                fn();
+            } catch (e) {
+                return e;
+            }
+            return null;
+        }
+
+        var packed = native.packAny(inner, { i: 1, s: "x" });
+        test.same(packed, root.lookupType("google.protobuf.Any").encode({ type_url: "type.googleapis.com/a.Inner", value: Inner.encode({ i: 1, s: "x" }).finish() }).finish(), "should pack with the default type URL");
+        test.same(native.unpackAny(packed), { i: 1, s: "x", "@type": "type.googleapis.com/a.Inner" }, "should unpack registered types");
+
+        var nested = native.packAny(inner, { i: 2, nested: { "@type": "type.googleapis.com/a.Inner", s: "deep" } });
+        test.same(native.unpackAny(nested), { i: 2, nested: { s: "deep", "@type": "type.googleapis.com/a.Inner" }, "@type": "type.googleapis.com/a.Inner" }, "should pack and unpack nested Anys");
+
+        var buffer = Msg.encode(Msg.fromObject({
+            any: { type_url: "type.googleapis.com/a.Inner", value: Inner.encode({ i: 3 }).finish() },
+            anys: [ { type_url: "example.com/x.Unknown", value: Buffer.from([ 8, 1 ]) } ]
+        })).finish();
+        test.same(native.decode(msg, buffer).any, { type_url: "type.googleapis.com/a.Inner", value: Inner.encode({ i: 3 }).finish() }, "should leave Anys as they are by default");
+        var decoded = native.decode(msg, buffer, { unpackAnys: true });
+        test.same(decoded.any, { i: 3, "@type": "type.googleapis.com/a.Inner" }, "should unpack Any fields on request");
+        test.same(decoded.anys, [ { type_url: "example.com/x.Unknown", value: Buffer.from([ 8, 1 ]) } ], "should keep unknown types as raw bytes");
+        test.ok(native.decode(msg, buffer, { unpackAnys: true, maxFields: 7 }), "should count the fields of unknown types once");
+        test.equal(caught(function() { native.decode(msg, buffer, { unpackAnys: true, maxFields: 6 }); }).code, "LIMIT_EXCEEDED", "should count the fields of Anys towards maxFields");
+        test.equal(caught(function() { native.decode(msg, buffer, { unpackAnys: true, unknownAnys: "error" }); }).message, "anys[0]: unknown type in google.protobuf.Any: example.com/x.Unknown", "should reject unknown types on request");
+        test.ok(/unknownAnys/.test(caught(function() { native.decode(msg, buffer, { unknownAnys: "drop" }); }).message), "should reject invalid unknownAnys options");
+        test.same(native.encode(msg, decoded), buffer, "should pack objects with @type when encoding");
+        test.equal(caught(function() { native.encode(msg, { any: { "@type": "example.com/x.Unknown" } }); }).message, "any: unknown type in google.protobuf.Any: example.com/x.Unknown", "should reject packing unknown types");
+
+        native.registerTypeUrl("example.com/custom/inner", inner);
+        var custom = native.packAny(inner, { i: 4 });
+        test.same(native.unpackAny(custom), { i: 4, "@type": "example.com/custom/inner" }, "should pack with registered type URLs");
+        var json = native.binaryToJson(msg, Msg.encode({ any: root.lookupType("google.protobuf.Any").decode(custom) }).finish());
+        test.equal(json, "{\"any\":{\"@type\":\"example.com/custom/inner\",\"i\":4}}", "should resolve registered type URLs in JSON");
+        test.equal(native.toTextFormat(msg, native.jsonToBinary(msg, json)), "any {\n  [example.com/custom/inner] {\n    i: 4\n  }\n}\n", "should resolve registered type URLs in text format");
+        test.equal(caught(function() { native.registerTypeUrl("example.com/y", 100000); }).code, "InvalidArg", "should reject unknown type handles");
+
+        test.end();
+    });
+
+    test.test(test.name + " - descriptor sets", function(test) {
+        var native = protobuf.native;
+        function caught(fn) {
+            try {
+                fn();
+            } catch (e) {
+                return e;
+            }
+            return null;
+        }
+        var main = {
+            name: "main.proto", "package": "s.main", dependency: [ "dep.proto" ], syntax: "proto3", options: { javaPackage: "com.s" },
+            messageType: [ {
+                name: "Msg",
+                field: [
+                    { name: "id", number: 1, label: 1, type: 5, jsonName: "id" },
+                    { name: "tags", number: 2, label: 3, type: 11, typeName: ".s.main.Msg.TagsEntry", jsonName: "tags" },
+                    { name: "opt", number: 3, label: 1, type: 9, oneofIndex: 1, proto3Optional: true, jsonName: "opt" },
+                    { name: "kind", number: 4, label: 1, type: 14, typeName: ".s.main.Msg.Kind", oneofIndex: 0, jsonName: "kindOf" },
+                    { name: "name", number: 5, label: 1, type: 9, oneofIndex: 0, jsonName: "name" },
+                    { name: "dep", number: 6, label: 3, type: 11, typeName: ".s.dep.Dep", jsonName: "dep" }
+                ],
+                nestedType: [ { name: "TagsEntry", field: [ { name: "key", number: 1, label: 1, type: 9 }, { name: "value", number: 2, label: 1, type: 3 } ], options: { mapEntry: true } } ],
+                enumType: [ { name: "Kind", value: [ { name: "ZERO", number: 0 }, { name: "ONE", number: 1 } ] } ],
+                oneofDecl: [ { name: "choice" }, { name: "_opt" } ],
+                reservedRange: [ { start: 10, end: 12 } ],
+                reservedName: [ "old" ]
+            } ],
+            service: [ { name: "Svc", method: [ { name: "Get", inputType: ".s.main.Msg", outputType: ".s.dep.Dep", serverStreaming: true } ] } ]
+        };
+        var dep = {
+            name: "dep.proto", "package": "s.dep", syntax: "proto2",
+            messageType: [ {
+                name: "Dep",
+                field: [
+                    { name: "x", number: 1, label: 2, type: 17, defaultValue: "-7" },
+                    { name: "grp", number: 2, label: 1, type: 10, typeName: ".s.dep.Dep.Grp" },
+                    { name: "b", number: 3, label: 1, type: 12, defaultValue: "a\\001" }
+                ],
+                nestedType: [ { name: "Grp", field: [ { name: "y", number: 1, label: 1, type: 5 } ] } ],
+                extensionRange: [ { start: 100, end: 536870912 } ]
+            } ],
+            extension: [ { name: "ext", number: 100, label: 1, type: 9, extendee: ".s.dep.Dep" } ]
+        };
+        var editions = {
+            name: "editions.proto", "package": "s.ed", syntax: "editions", edition: 1000, options: { features: { fieldPresence: 2 } },
+            messageType: [ { name: "Ed", field: [
+                { name: "a", number: 1, label: 1, type: 5, jsonName: "a" },
+                { name: "b", number: 2, label: 1, type: 5, jsonName: "b", options: { features: { fieldPresence: 1 } } },
+                { name: "child", number: 3, label: 1, type: 10, typeName: ".s.ed.Ed", jsonName: "child" }
+            ] } ]
+        };
+        function encodeSet(files) {
+            return descriptor.FileDescriptorSet.encode(descriptor.FileDescriptorSet.fromObject({ file: files })).finish();
+        }
+
+        var json = native.descriptorSetToJson(encodeSet([ main, dep ]));
+        test.same(Object.keys(json.nested.s.nested), [ "dep", "main" ], "should order files after their imports");
+        test.same(json.nested.s.nested.main.options, { java_package: "com.s" }, "should keep file options on the package");
+        var msg = json.nested.s.nested.main.nested.Msg;
+        test.same(msg.fields.tags, { keyType: "string", type: "int64", id: 2 }, "should turn map entries into map fields");
+        test.same(msg.fields.opt, { type: "string", id: 3, options: { proto3_optional: true } }, "should keep proto3 optional");
+        test.same(msg.fields.kind, { type: ".s.main.Msg.Kind", id: 4, options: { json_name: "kindOf" } }, "should keep fully qualified type names and custom JSON names");
+        test.same(msg.oneofs, { choice: { oneof: [ "kind", "name" ] }, _opt: { oneof: [ "opt" ] } }, "should convert oneofs");
+        test.same(msg.reserved, [ [ 10, 11 ], "old" ], "should convert reserved ranges and names");
+        test.same(Object.keys(msg.nested), [ "Kind" ], "should drop map entry types");
+        test.same(json.nested.s.nested.main.nested.Svc, { methods: { Get: { requestType: ".s.main.Msg", responseType: ".s.dep.Dep", responseStream: true } } }, "should convert services");
+        var depJson = json.nested.s.nested.dep.nested;
+        test.equal(depJson.Dep.edition, "proto2", "should set the edition of top-level elements");
+        test.same(depJson.Dep.fields.x, { rule: "required", type: "sint32", id: 1, options: { "default": -7 } }, "should convert labels and defaults");
+        test.equal(depJson.Dep.fields.b.options["default"], "a\u0001", "should resolve escapes of bytes defaults");
+        test.ok(depJson.Dep.nested.Grp.group, "should mark groups");
+        test.same(depJson.Dep.extensions, [ [ 100, 536870911 ] ], "should convert extension ranges");
+        test.same(depJson.ext, { edition: "proto2", type: "string", id: 100, extend: ".s.dep.Dep" }, "should convert extensions");
+
+        var set = encodeSet([ main, dep, editions ]);
+        test.same(native.registerDescriptorSet(set), native.descriptorSetToJson(set), "should return the registered JSON");
+        var root = protobuf.Root.fromJSON(native.descriptorSetToJson(set));
+        var Msg = root.lookupType("s.main.Msg");
+        var obj = { id: 1, tags: { a: 5 }, opt: "", kind: 1, dep: [ { x: -2, grp: { y: 3 } } ] };
+        test.same(native.encode(native.lookupType("s.main.Msg"), obj), Msg.encode(obj).finish(), "should encode registered types like protobuf.js");
+        test.same(native.decode(native.lookupType("s.main.Msg"), Msg.encode(obj).finish(), { longs: "number" }), Msg.toObject(Msg.decode(Msg.encode(obj).finish()), { longs: Number }), "should decode registered types like protobuf.js");
+        var Ed = root.lookupType("s.ed.Ed");
+        test.same(native.descriptorSetToJson(set).nested.s.nested.ed.nested.Ed.options, { features: { field_presence: "IMPLICIT" } }, "should keep file features on top-level elements");
+        test.same(native.encode(native.lookupType("s.ed.Ed"), { a: 0, b: 0, child: { a: 1 } }), Buffer.from([ 8, 0, 16, 0, 27, 8, 1, 28 ]), "should encode delimited fields as groups");
+        test.same(native.encode(native.lookupType("s.ed.Ed"), { a: 0, b: 0, child: { a: 1 } }), Ed.encode({ a: 0, b: 0, child: { a: 1 } }).finish(), "should encode editions like protobuf.js");
+
+        test.equal(caught(function() { native.descriptorSetToJson(encodeSet([ main ])); }).message, "missing dep.proto, imported by main.proto (build the set with its imports)", "should reject missing imports");
+        test.equal(caught(function() { native.descriptorSetToJson(encodeSet([ dep, dep ])); }).message, "duplicate file dep.proto in descriptor set", "should reject duplicate files");
+        test.equal(caught(function() { native.descriptorSetToJson(Buffer.from([ 10, 5, 8 ])); }).code, "TRUNCATED", "should reject truncated sets");
+
+        test.end();
+    });
+
+    test.test(test.name + " - parse proto", function(test) {
+        var native = protobuf.native;
+        function caught(fn) {
+            try {
+                fn();
+            } catch (e) {
+                return e;
+            }
+            return null;
+        }
+        var sources = {
+            "shop/order.proto": [
+                "syntax = \"proto3\";",
+                "package shop;",
+                "import \"shop/common.proto\";",
+                "import \"google/protobuf/timestamp.proto\";",
+                "option java_package = \"com.shop\";",
+                "message Order {",
+                "    // The order id",
+                "    uint64 order_id = 1;",
+                "    repeated Item items = 2 [packed = false];",
+                "    map<string, int32> stock_by_sku = 3;",
+                "    optional string note = 4; // free text",
+                "    oneof payment { string card = 5; Money cash = 6; }",
+                "    google.protobuf.Timestamp created = 7;",
+                "    reserved 8 to 10, 20 to max;",
+                "    reserved \"legacy\";",
+                "    message Item { string sku = 1; int32 count = 2 [deprecated = true]; }",
+                "}",
+                "service Orders {",
+                "    rpc Watch (Order) returns (stream Order) {",
+                "        option (shop.http) = { get: \"/orders\" additional: \"/o\" };",
+                "    }",
+                "}"
+            ].join("\n"),
+            "shop/common.proto": [
+                "syntax = \"proto2\";",
+                "package shop;",
+                "import \"google/protobuf/descriptor.proto\";",
+                "message Money { required int64 units = 1; optional Currency currency = 2 [default = EUR]; optional group Extra = 3 { optional string memo = 4; } extensions 100 to 199; }",
+                "enum Currency { option allow_alias = true; EUR = 1; EURO = 1 [deprecated = true]; USD = 2; reserved 5; }",
+                "extend Money { optional int32 points = 100; }",
+                "extend google.protobuf.MethodOptions { optional Http http = 72295728; }",
+                "message Http { optional string get = 1; repeated string additional = 2; }"
+            ].join("\n"),
+            "google/protobuf/descriptor.proto": "syntax = \"proto2\"; package google.protobuf; message MethodOptions { extensions 1000 to max; }",
+            "ed.proto": [
+                "edition = \"2023\";",
+                "package ed;",
+                "option features.field_presence = IMPLICIT;",
+                "message Ed { int32 a = 1; int32 b = 2 [features.field_presence = EXPLICIT]; Ed child = 3 [features.message_encoding = DELIMITED]; }"
+            ].join("\n")
+        };
+
+        var json = native.parseProto([ "shop/order.proto", "ed.proto" ], { files: sources });
+        var shop = json.nested.shop;
+        test.same(shop.options, { java_package: "com.shop" }, "should keep file options on the package");
+        test.same(shop.nested.Order.fields.orderId, { type: "uint64", id: 1 }, "should convert field names to camel case");
+        test.same(shop.nested.Order.fields.stockBySku, { keyType: "string", type: "int32", id: 3 }, "should parse map fields");
+        test.same(shop.nested.Order.fields.note, { type: "string", id: 4, options: { proto3_optional: true } }, "should parse proto3 optional fields");
+        test.same(shop.nested.Order.oneofs, { payment: { oneof: [ "card", "cash" ] }, _note: { oneof: [ "note" ] } }, "should parse oneofs");
+        test.same(shop.nested.Order.reserved, [ [ 8, 10 ], [ 20, 536870911 ], "legacy" ], "should parse reserved ranges and names");
+        test.same(shop.nested.Orders.methods.Watch, {
+            requestType: "Order", responseType: "Order", responseStream: true,
+            options: { "(shop.http).get": "/orders", "(shop.http).additional": "/o" },
+            parsedOptions: [ { "(shop.http)": { get: "/orders", additional: "/o" } } ]
+        }, "should parse services with aggregate options");
+        test.same(shop.nested.Money.fields.extra, { type: "Extra", id: 3 }, "should parse groups");
+        test.ok(shop.nested.Money.nested.Extra.group, "should mark group types");
+        test.same(shop.nested.Currency.valuesOptions, { EURO: { deprecated: true } }, "should keep enum value options");
+        test.same(shop.nested.points, { edition: "proto2", type: "int32", id: 100, extend: "Money" }, "should parse extensions");
+        test.same(json.nested.google.nested.protobuf.nested.Timestamp, protobuf.common["google/protobuf/timestamp.proto"].nested.google.nested.protobuf.nested.Timestamp, "should resolve the common google/protobuf files");
+        test.same(json.nested.ed.nested.Ed.options, { features: { field_presence: "IMPLICIT" } }, "should keep file features on top-level elements");
+
+        var expected = new protobuf.Root();
+        [ "shop/order.proto", "shop/common.proto", "ed.proto" ].forEach(function(name) {
+            protobuf.parse(sources[name], expected, { keepCase: true });
+        });
+        var actual = native.parseProto([ "shop/order.proto", "ed.proto" ], { files: sources, keepCase: true });
+        delete actual.nested.google;
+        test.same(actual, JSON.parse(JSON.stringify(expected.toJSON())), "should parse like protobuf.parse");
+
+        var loaded = protobuf.loadSync("tests/data/options_test.proto");
+        test.same(native.parseProto("options_test.proto", { includePaths: [ "tests/data" ] }), JSON.parse(JSON.stringify(loaded.toJSON())), "should resolve imports through include paths like protobuf.load");
+
+        var comments = native.parseProto("c.proto", { keepComments: true, files: Object.assign({ "c.proto": sources["shop/order.proto"] + "\n// the shop\n/* enum */\nenum E { A = 0; // zero\n}" }, sources) });
+        test.equal(comments.nested.shop.nested.Order.fields.orderId.comment, "The order id", "should keep leading comments");
+        test.equal(comments.nested.shop.nested.Order.fields.note.comment, "free text", "should keep trailing comments");
+        test.equal(comments.nested.shop.nested.Order.fields.items.comment, null, "should set a null comment without one");
+        test.equal(comments.nested.shop.nested.E.comment, "the shop\nenum", "should join comment blocks");
+        test.same(comments.nested.shop.nested.E.comments, { A: "zero" }, "should keep enum value comments");
+
+        var registered = native.registerProto([ "shop/order.proto", "ed.proto" ], { files: sources });
+        var root = protobuf.Root.fromJSON(registered);
+        var Order = root.lookupType("shop.Order");
+        var order = { orderId: 7, items: [ { sku: "a", count: 2 } ], stockBySku: { a: 1 }, note: "", cash: { units: 3, extra: { memo: "m" } }, created: { seconds: 5 } };
+        test.same(native.encode(native.lookupType("shop.Order"), order), Order.encode(order).finish(), "should register parsed types");
+        test.same(native.encode(native.lookupType("ed.Ed"), { a: 0, b: 0, child: { a: 1 } }), root.lookupType("ed.Ed").encode({ a: 0, b: 0, child: { a: 1 } }).finish(), "should register edition features");
+
+        function parseError(source) {
+            return caught(function() { native.parseProto("bad.proto", { files: { "bad.proto": source } }); });
+        }
+        var error = parseError("syntax = \"proto3\";\nmessage A {\n    int32 a = 1\n}");
+        test.equal(error.message, "bad.proto:4:1: expected ';', got '}'", "should locate syntax errors");
+        test.equal(error.code, "MALFORMED", "should use the MALFORMED code");
+        test.equal(parseError("syntax = \"proto3\";\nimport \"missing.proto\";").message, "bad.proto:2:8: import \"missing.proto\" not found", "should locate missing imports");
+        test.equal(parseError("syntax = \"proto3\";\nimport weak \"missing.proto\";"), null, "should skip missing weak imports");
+        test.equal(parseError("syntax = \"proto3\";\nmessage A { int32 a = 1; int32 b = 1; }").message, "bad.proto:2:32: field number 1 is already used by 'a'", "should reject duplicate field numbers");
+        test.equal(parseError("syntax = \"proto3\";\nmessage A { reserved 2; int32 a = 2; }").message, "bad.proto:2:31: field 'a' uses reserved number 2", "should reject reserved numbers");
+        test.equal(parseError("message A { int32 a = 1; }").message, "bad.proto:1:13: missing label before 'int32'", "should require labels in proto2");
+        test.equal(parseError("edition = \"2023\";\nmessage A { optional int32 a = 1; }").message, "bad.proto:2:13: 'optional' is not allowed in editions, use features.field_presence", "should reject labels of editions");
+        test.equal(parseError("enum E { A = 0; B = 0; }").message, "bad.proto:1:17: 'B' reuses value 0 of 'A' without option allow_alias", "should reject enum aliases");
+        test.equal(parseError("message A { /* open").message, "bad.proto:1:13: unterminated comment", "should reject unterminated comments");
+        test.equal(caught(function() { native.parseProto("none.proto", { files: {} }); }).message, "file not found: none.proto", "should reject missing files");
+
+        test.end();
+    });
+
+    test.test(test.name + " - descriptor pool", function(test) {
+        var native = protobuf.native;
+        var source = [
+            "syntax = \"proto2\";",
+            "package pool;",
+            "import \"google/protobuf/descriptor.proto\";",
+            "extend google.protobuf.MessageOptions { optional string table = 50000; }",
+            "extend google.protobuf.FieldOptions { optional bool secret = 50001; }",
+            "message User {",
+            "    option (table) = \"users\";",
+            "    required string user_name = 1 [json_name = \"login\"];",
+            "    optional int32 age = 2 [default = 18, (secret) = true];",
+            "    repeated Role roles = 3 [packed = true];",
+            "    map<string, Address> addresses = 4;",
+            "    oneof contact { string email = 5; string phone = 6; }",
+            "    message Address { optional string city = 1; }",
+            "    enum Role { GUEST = 0; ADMIN = 1 [deprecated = true]; }",
+            "    extensions 100 to 199;",
+            "}",
+            "extend User { optional string nickname = 100; }",
+            "service Users {",
+            "    option deprecated = true;",
+            "    rpc Get (User) returns (stream User.Address) { option idempotency_level = NO_SIDE_EFFECTS; }",
+            "}"
+        ].join("\n");
+        native.registerProto("pool.proto", { files: {
+            "pool.proto": source,
+            "google/protobuf/descriptor.proto": "syntax = \"proto2\"; package google.protobuf; message MessageOptions { extensions 1000 to max; } message FieldOptions { extensions 1000 to max; }"
+        } });
+        var pool = new native.DescriptorPool();
+
+        var user = pool.findMessage("pool.User");
+        test.equal(user.name, "User", "should find messages by full name");
+        test.equal(user.fullName, "pool.User", "should return the full name");
+        test.equal(user.handle, native.lookupType("pool.User"), "should return the codec handle");
+        test.same(pool.findMessage(".pool.User"), user, "should accept a leading dot");
+        test.same(user.options, { "(table)": "users" }, "should keep custom message options");
+        test.same(user.fields.map(function(field) { return field.name; }), [ "userName", "age", "roles", "addresses", "email", "phone" ], "should list fields by number without extensions");
+        test.same(user.fields[0], { name: "userName", number: 1, label: "required", type: "string", jsonName: "login", options: { json_name: "login" } }, "should describe labels and JSON names");
+        test.same(user.fields[1], { name: "age", number: 2, label: "optional", type: "int32", jsonName: "age", defaultValue: 18, options: { "default": 18, "(secret)": true } }, "should describe defaults and custom field options");
+        test.same(user.fields[2], { name: "roles", number: 3, label: "repeated", type: "enum", typeName: "pool.User.Role", jsonName: "roles", packed: true, options: { packed: true } }, "should describe enum fields");
+        test.same(user.fields[3], { name: "addresses", number: 4, label: "repeated", type: "message", typeName: "pool.User.Address", keyType: "string", jsonName: "addresses" }, "should describe map fields");
+        test.equal(user.fields[4].oneof, "contact", "should name the oneof of fields");
+        test.same(user.oneofs, [ { name: "contact", fields: [ "email", "phone" ] } ], "should list oneofs");
+
+        test.same(pool.nested("pool.User"), [
+            { name: "Address", fullName: "pool.User.Address", kind: "message" },
+            { name: "Role", fullName: "pool.User.Role", kind: "enum" }
+        ], "should walk nested types");
+        test.ok(pool.nested().some(function(element) { return element.name === "pool" && element.kind === "namespace"; }), "should list namespaces at the root");
+        test.same(pool.nested("pool").map(function(element) { return element.kind; }), [ "message", "service" ], "should list the types and services of a package");
+
+        test.same(pool.findEnum("pool.User.Role"), {
+            name: "Role", fullName: "pool.User.Role",
+            values: [ { name: "GUEST", number: 0 }, { name: "ADMIN", number: 1, options: { deprecated: true } } ]
+        }, "should find enums with value options");
+        test.same(pool.findService("pool.Users"), {
+            name: "Users", fullName: "pool.Users",
+            methods: [ { name: "Get", inputType: "pool.User", outputType: "pool.User.Address", clientStreaming: false, serverStreaming: true, options: { idempotency_level: "NO_SIDE_EFFECTS" } } ],
+            options: { deprecated: true }
+        }, "should find services with resolved method types");
+
+        test.same(pool.findExtensions("pool.User"), [
+            { name: "pool.nickname", number: 100, label: "optional", type: "string", jsonName: "[pool.nickname]", extendee: "pool.User" }
+        ], "should find the extensions of an extendee");
+        test.same(pool.findExtensions("google.protobuf.FieldOptions").map(function(field) { return field.name; }), [ "pool.secret" ], "should find custom option extensions");
+
+        test.equal(pool.findMessage("pool.User.Role"), null, "should not return enums as messages");
+        test.equal(pool.findEnum("pool.Missing"), null, "should return null for unknown enums");
+        test.equal(pool.findService("pool.User"), null, "should return null for unknown services");
+        var anonymous = native.registerType({ fields: { n: { type: "uint32", id: 1 } } });
+        test.same(pool.message(anonymous).fields.map(function(field) { return field.name; }), [ "n" ], "should describe types registered without a name by handle");
+        var error;
+        try {
+            pool.findExtensions("pool.Missing");
+        } catch (e) {
+            error = e;
+        }
+        test.equal(error && error.message, "no such message type: pool.Missing", "should reject unknown extendees");
+
+        test.end();
+    });
+
+    test.end();
+});